@@ -0,0 +1,307 @@
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::diagnostic::Diagnostic;
+use crate::lowering::lua::{Imported, LuaModule, Lowering};
+use crate::lowering::source_map::SourceMap;
+use crate::parsing::proc::ast::Mod;
+use crate::prelude;
+use crate::project::cache::{self, Cache, Entry};
+use crate::project::graph::{Graph, Parsed};
+use crate::project::manifest::{self, Manifest};
+use crate::project::stored;
+use crate::project::watch::snapshot;
+use crate::typing::checker::Checker;
+
+// How often watching looks for changes.
+const WATCH_INTERVAL_MS : u64 = 300;
+
+pub fn build(args : &[String]) -> i32 {
+    let verbose = args.iter().any(|a| a == "--verbose");
+    let watching = args.iter().any(|a| a == "--watch");
+    let args = args.iter().filter(|a| *a != "--verbose" && *a != "--watch").cloned().collect::<Vec<String>>();
+    match (&args[..], watching) {
+        ([], false) => build_project(Path::new("."), verbose),
+        ([], true) => watch(Path::new("."), verbose),
+        ([dir], false) if Path::new(dir).is_dir() => build_project(Path::new(dir), verbose),
+        ([dir], true) if Path::new(dir).is_dir() => watch(Path::new(dir), verbose),
+        ([path], false) => build_file(path, &Path::new(path).with_extension("lua").to_string_lossy()),
+        ([path, flag, output], false) if flag == "-o" => build_file(path, output),
+        _ => {
+            eprintln!("usage: hyper build [<file> [-o <output>] | [--verbose] [--watch] <project directory>]");
+            1
+        },
+    }
+}
+
+fn build_file(path : &str, output : &str) -> i32 {
+    let source = match super::read_source(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+
+    let module = match super::parse_source(path, &source) {
+        Ok(module) => module,
+        Err(code) => return code,
+    };
+
+    let mut checker = Checker::new();
+    checker.check_mod(&module);
+    let warnings = checker.take_warnings();
+    if !report(&warnings, &checker.take_errors(), path, &source) {
+        return 1;
+    }
+
+    let lua = lower_checked(&module, checker, None, false, vec![]);
+    let map = source_map(Path::new(output), path, &source, &lua);
+    let prelude_path = Path::new(output).with_file_name(format!("{}.lua", prelude::MODULE_NAME));
+    let written = write_module(Path::new(output), lua.code, map)
+        .and_then(|_| write(&prelude_path, prelude::lua(None)));
+    match written {
+        Ok(()) => 0,
+        Err(code) => code,
+    }
+}
+
+// Builds the package whose hyper.toml is in `dir` or above it, writing each
+// module to the output directory as the path Lua requires it by, e.g.
+// `geometry/shapes.lua` for `require("geometry.shapes")`. The entry module
+// ends by calling its main, so running it runs the project.
+//
+// Modules are only checked and lowered again when their source or the
+// exports of a module they use changed since the last build; otherwise
+// what the cache kept of them is used.
+fn build_project(dir : &Path, verbose : bool) -> i32 {
+    let manifest = match find_manifest(dir) {
+        Ok(path) => path,
+        Err(code) => return code,
+    };
+    let graph = match Manifest::load(&manifest).and_then(|m| Graph::load(m, Parsed::new())) {
+        Ok(graph) => graph,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        },
+    };
+    match build_graph(&graph, verbose) {
+        Ok(_) => 0,
+        Err(code) => code,
+    }
+}
+
+fn find_manifest(dir : &Path) -> Result<PathBuf, i32> {
+    manifest::find(dir).ok_or_else(|| {
+        eprintln!("{}: no {} here or in any directory above", dir.display(), manifest::FILE_NAME);
+        1
+    })
+}
+
+// Builds the project again whenever one of its packages' manifests or
+// modules changes, until interrupted. Each build clears the terminal first,
+// leaving only its diagnostics and a line saying how it went.
+fn watch(dir : &Path, verbose : bool) -> i32 {
+    let manifest = match find_manifest(dir) {
+        Ok(path) => path,
+        Err(code) => return code,
+    };
+    let mut watched = vec![manifest.clone()];
+    let mut parsed = Parsed::new();
+    let mut last = None;
+    loop {
+        let now = snapshot(&watched);
+        if last.as_ref() == Some(&now) {
+            std::thread::sleep(Duration::from_millis(WATCH_INTERVAL_MS));
+            continue;
+        }
+
+        print!("\x1b[2J\x1b[H");
+        let _ = std::io::stdout().flush();
+        let before = watched.clone();
+        watched.truncate(1);
+        let loaded = Manifest::load(&manifest).and_then(|m| {
+            watched.push(m.source.clone());
+            Graph::load(m, std::mem::take(&mut parsed))
+        });
+        let status = match loaded {
+            Ok(graph) => {
+                for package in graph.packages.iter().skip(1) {
+                    watched.push(package.root.join(manifest::FILE_NAME));
+                    watched.push(package.source.clone());
+                }
+                let status = match build_graph(&graph, verbose) {
+                    Ok(rebuilt) => format!("parsed {} and rebuilt {} of {} modules", graph.reparsed, rebuilt, graph.modules.len()),
+                    Err(_) => "build failed".to_string(),
+                };
+                parsed = graph.into_parsed();
+                status
+            },
+            Err(e) => {
+                eprintln!("{}", e);
+                "build failed".to_string()
+            },
+        };
+        println!("{}; watching for changes", status);
+        last = Some(if watched == before { now } else { snapshot(&watched) });
+    }
+}
+
+// Builds what's been loaded, returning how many modules had to be checked
+// and lowered again.
+fn build_graph(graph : &Graph, verbose : bool) -> Result<usize, i32> {
+    if !graph.errors.is_empty() {
+        for (file, error) in graph.errors.iter() {
+            let file = &graph.files[*file];
+            eprintln!("{}", error.render(&file.path, &file.source));
+        }
+        return Err(1);
+    }
+
+    let cache = Cache::new(graph.packages[0].root.join(cache::DIR_NAME));
+    let signatures = graph.modules.iter().map(|m| cache::hash(&[&cache::signature(&m.module)])).collect::<Vec<u64>>();
+    let mut plans = vec![];
+    for (index, module) in graph.modules.iter().enumerate() {
+        let file = &graph.files[module.file];
+        // The entry is lowered with a call to its main.
+        let entry = if index == 0 { "entry" } else { "" };
+        let source = cache::hash(&[&graph.lua_name(index), entry, &file.source]);
+        let uses = module.uses.iter().flatten().map(|t| format!("{} {:x}", graph.lua_name(*t), signatures[*t])).collect::<Vec<String>>();
+        let imports = cache::hash(&uses.iter().map(|u| u.as_str()).collect::<Vec<&str>>());
+        let plan = match cache.load(&graph.lua_name(index)) {
+            None => Plan::Rebuild("it wasn't built before"),
+            Some(entry) if entry.source != source => Plan::Rebuild("its source changed"),
+            Some(entry) if entry.imports != imports => Plan::Rebuild("a module it uses changed its exports"),
+            Some(entry) => Plan::Fresh(entry),
+        };
+        plans.push((source, imports, plan));
+    }
+
+    let mut checkers = vec![];
+    let mut failed = false;
+    for (module, (_, _, plan)) in graph.modules.iter().zip(plans.iter()) {
+        let file = &graph.files[module.file];
+        if let Plan::Fresh(entry) = plan {
+            report(&entry.warnings, &[], &file.path, &file.source);
+            checkers.push(None);
+            continue;
+        }
+        let mut checker = Checker::new();
+        for (u, target) in module.module.uses.iter().zip(module.uses.iter()) {
+            if let Some(target) = target {
+                checker.declare_imports(u, &graph.modules[*target].module);
+            }
+        }
+        checker.check_mod(&module.module);
+        let warnings = checker.take_warnings();
+        failed |= !report(&warnings, &checker.take_errors(), &file.path, &file.source);
+        checkers.push(Some((checker, warnings)));
+    }
+    if failed {
+        return Err(1);
+    }
+
+    let output = &graph.packages[0].output;
+    let mut rebuilt = 0;
+    for (index, ((module, checked), (source, imports, plan))) in graph.modules.iter().zip(checkers).zip(plans).enumerate() {
+        let path = output.join(graph.lua_name(index).replace('.', "/")).with_extension("lua");
+        let entry = match (plan, checked) {
+            (Plan::Fresh(entry), _) => {
+                if verbose {
+                    eprintln!("{}: up to date", graph.display_name(index));
+                }
+                entry
+            },
+            (Plan::Rebuild(reason), Some((checker, warnings))) => {
+                if verbose {
+                    eprintln!("{}: rebuilt, {}", graph.display_name(index), reason);
+                }
+                rebuilt += 1;
+                let imports_lua = module.module.uses.iter().zip(module.uses.iter()).map(|(u, target)| {
+                    target.map(|t| Imported::new(graph.lua_name(t), u, &graph.modules[t].module))
+                }).collect();
+                let lua = lower_checked(&module.module, checker, Some(graph.lua_name(index)), index == 0, imports_lua);
+                let file = &graph.files[module.file];
+                let map = source_map(&path, &file.path, &file.source, &lua);
+                let module = stored::module_json(&module.module);
+                let entry = Entry { source, imports, lua: lua.code, map, warnings, parsed: cache::hash(&[&file.source]), module };
+                // A cache that can't be written only costs the next build time.
+                if let Err(e) = cache.store(&graph.lua_name(index), &entry) {
+                    eprintln!("warning: cannot cache {}: {}", graph.display_name(index), e);
+                }
+                entry
+            },
+            (Plan::Rebuild(_), None) => unreachable!("modules to rebuild are checked"),
+        };
+        write_module(&path, entry.lua, entry.map)?;
+    }
+    if verbose {
+        eprintln!("rebuilt {} of {} modules", rebuilt, graph.modules.len());
+    }
+    let prelude_path = output.join(format!("{}.lua", prelude::MODULE_NAME));
+    write(&prelude_path, prelude::lua(Some(&graph.packages[0].lua)))?;
+    Ok(rebuilt)
+}
+
+// What a project build does with a module.
+enum Plan {
+    Fresh(Entry),
+    // Why the cached entry can't be used.
+    Rebuild(&'static str),
+}
+
+// Prints what checking found, returning whether the module can be lowered.
+fn report(warnings : &[Diagnostic], errors : &[Diagnostic], path : &str, source : &str) -> bool {
+    for warning in warnings {
+        eprintln!("{}", Diagnostic::new(warning.start, format!("warning: {}", warning.message)).render(path, source));
+    }
+    for error in errors {
+        eprintln!("{}", error.render(path, source));
+    }
+    errors.is_empty()
+}
+
+// `module_name` is the name Lua requires a project's module by, and
+// `entry` whether it's the one the project runs.
+fn lower_checked<'a>(module : &'a Mod, checker : Checker, module_name : Option<String>, entry : bool, imports : Vec<Option<Imported<'a>>>) -> LuaModule {
+    let mut lowering = Lowering::new().with_collections(checker.collections())
+                                      .with_method_calls(checker.method_calls())
+                                      .with_arrangements(checker.arrangements())
+                                      .with_try_kinds(checker.try_kinds())
+                                      .with_assigned_loop_vars(checker.assigned_loop_vars())
+                                      .with_imports(imports)
+                                      .with_entry(entry);
+    if let Some(module_name) = module_name {
+        lowering = lowering.with_module_name(module_name);
+    }
+    lowering.lower_mod(module);
+    lowering.finish()
+}
+
+fn source_map(output : &Path, path : &str, source : &str, lua : &LuaModule) -> String {
+    let file = output.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+    SourceMap::new(&file, path, source, &lua.lines).to_json().to_string()
+}
+
+// Writes the Lua with its source map next to it, leaving files that are
+// already up to date alone.
+fn write_module(output : &Path, code : String, map : String) -> Result<(), i32> {
+    let map_path = PathBuf::from(format!("{}.map", output.display()));
+    for (path, contents) in [(output, code), (map_path.as_path(), map)] {
+        if std::fs::read_to_string(path).ok().as_deref() != Some(contents.as_str()) {
+            write(path, contents)?;
+        }
+    }
+    Ok(())
+}
+
+fn write(path : &Path, contents : String) -> Result<(), i32> {
+    let written = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => std::fs::create_dir_all(dir).and_then(|_| std::fs::write(path, contents)),
+        _ => std::fs::write(path, contents),
+    };
+    written.map_err(|e| {
+        eprintln!("{}: {}", path.display(), e);
+        1
+    })
+}
@@ -0,0 +1,219 @@
+
+use std::path::Path;
+
+use parse_input::ParseError;
+
+use crate::diagnostic::{line_column, Diagnostic};
+use crate::json::Json;
+use crate::lint::config::{Levels, Level};
+use crate::lint::rules::lint as find;
+use crate::parsing::proc::ast::Mod;
+use crate::parsing::proc::parser::parse;
+use crate::project::graph::{Graph, Parsed};
+use crate::project::manifest::{self, Manifest};
+use crate::typing::checker::Checker;
+
+const USAGE : &str = "usage: hyper check [<file> | <project directory>] [--message-format human|json]";
+
+// Runs everything a build does short of lowering, writing nothing.
+pub fn check(args : &[String]) -> i32 {
+    let (json, args) = match args.iter().position(|a| a == "--message-format") {
+        Some(at) => {
+            let json = match args.get(at + 1).map(|f| f.as_str()) {
+                Some("json") => true,
+                Some("human") => false,
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return 1;
+                },
+            };
+            (json, args[..at].iter().chain(args[at + 2..].iter()).cloned().collect::<Vec<String>>())
+        },
+        None => (false, args.to_vec()),
+    };
+    let mut output = Output { json, failed: false };
+    match &args[..] {
+        [] => output.project(Path::new(".")),
+        [dir] if Path::new(dir).is_dir() => output.project(Path::new(dir)),
+        [path] => output.file(path),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 1;
+        },
+    }
+    if output.failed { 1 } else { 0 }
+}
+
+enum Severity {
+    Error,
+    Warning,
+}
+
+// Prints what checking finds as it goes, either for people, like a build
+// does, or as a JSON object on a line of its own for each problem:
+//
+//     {"file":"src/main.hy","line":3,"column":5,"severity":"warning",
+//      "message":"let x is never used","rule":"unused_let"}
+//
+// `line` and `column` are null for problems with the file as a whole, and
+// `rule` for anything that isn't a lint.
+struct Output {
+    json : bool,
+    failed : bool,
+}
+
+impl Output {
+    fn emit(&mut self, path : &str, source : &str, severity : Severity, diagnostic : &Diagnostic, rule : Option<&str>) {
+        let severity = match severity {
+            Severity::Error => {
+                self.failed = true;
+                "error"
+            },
+            Severity::Warning => "warning",
+        };
+        if !self.json {
+            let rule = rule.map(|r| format!(" [{}]", r)).unwrap_or_default();
+            let message = format!("{}: {}{}", severity, diagnostic.message, rule);
+            eprintln!("{}", Diagnostic::new(diagnostic.start, message).render(path, source));
+            return;
+        }
+        println!("{}", to_json(path, source, severity, diagnostic, rule));
+    }
+
+    // Problems that keep checking from starting, like a manifest that can't
+    // be read, have no place in a file to point at.
+    fn fail(&mut self, path : &str, message : String) {
+        self.emit(path, "", Severity::Error, &Diagnostic::new(None, message), None);
+    }
+
+    fn file(&mut self, path : &str) {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => return self.fail(path, e.to_string()),
+        };
+        let module = match parse(&source) {
+            Ok(module) => module,
+            Err(e) => return self.emit(path, &source, Severity::Error, &parse_diagnostic(e), None),
+        };
+        let mut checker = Checker::new();
+        checker.check_mod(&module);
+        self.checked(&mut checker, path, &source);
+        if let Ok(levels) = self.levels(Path::new(path)) {
+            self.lint(&module, levels, path, &source);
+        }
+    }
+
+    // Checks every module of the package whose hyper.toml is in `dir` or
+    // above it, and of its dependencies, linting only the package's own.
+    fn project(&mut self, dir : &Path) {
+        let manifest = match manifest::find(dir) {
+            Some(path) => path,
+            None => return self.fail(&dir.display().to_string(), format!("no {} here or in any directory above", manifest::FILE_NAME)),
+        };
+        let graph = match Manifest::load(&manifest).and_then(|m| Graph::load(m, Parsed::new())) {
+            Ok(graph) => graph,
+            Err(e) => return self.fail(&manifest.display().to_string(), e),
+        };
+        for (file, error) in graph.errors.iter() {
+            let file = &graph.files[*file];
+            self.emit(&file.path, &file.source, Severity::Error, error, None);
+        }
+        if !graph.errors.is_empty() {
+            return;
+        }
+
+        let levels = self.levels(&manifest);
+        for module in graph.modules.iter() {
+            let file = &graph.files[module.file];
+            let mut checker = Checker::new();
+            for (u, target) in module.module.uses.iter().zip(module.uses.iter()) {
+                if let Some(target) = target {
+                    checker.declare_imports(u, &graph.modules[*target].module);
+                }
+            }
+            checker.check_mod(&module.module);
+            self.checked(&mut checker, &file.path, &file.source);
+            if let (0, Ok(levels)) = (module.package, &levels) {
+                self.lint(&module.module, levels.clone(), &file.path, &file.source);
+            }
+        }
+    }
+
+    fn checked(&mut self, checker : &mut Checker, path : &str, source : &str) {
+        for warning in checker.take_warnings() {
+            self.emit(path, source, Severity::Warning, &warning, None);
+        }
+        for error in checker.take_errors() {
+            self.emit(path, source, Severity::Error, &error, None);
+        }
+    }
+
+    fn levels(&mut self, path : &Path) -> Result<Levels, ()> {
+        let mut levels = Levels::new();
+        if let Some(manifest) = manifest::find(path) {
+            let configured = std::fs::read_to_string(&manifest)
+                .map_err(|e| e.to_string())
+                .and_then(|s| crate::toml::parse(&s))
+                .and_then(|t| levels.configure(&t));
+            if let Err(e) = configured {
+                self.fail(&manifest.display().to_string(), e);
+                return Err(());
+            }
+        }
+        Ok(levels)
+    }
+
+    fn lint(&mut self, module : &Mod, mut levels : Levels, path : &str, source : &str) {
+        for error in levels.attributes(module) {
+            self.emit(path, source, Severity::Warning, &error, None);
+        }
+        let mut findings = find(module);
+        findings.sort_by_key(|f| f.start);
+        for finding in findings {
+            let severity = match levels.level(&finding) {
+                Level::Allow => continue,
+                Level::Warn => Severity::Warning,
+                Level::Deny => Severity::Error,
+            };
+            let diagnostic = Diagnostic::new(finding.start, finding.message);
+            self.emit(path, source, severity, &diagnostic, Some(finding.rule.name()));
+        }
+    }
+}
+
+fn to_json(path : &str, source : &str, severity : &str, diagnostic : &Diagnostic, rule : Option<&str>) -> Json {
+    let (line, column) = match diagnostic.start.map(|s| line_column(source, s)) {
+        Some((line, column)) => (Json::Number(line as f64), Json::Number(column as f64)),
+        None => (Json::Null, Json::Null),
+    };
+    let text = |s : &str| Json::String(s.to_string());
+    Json::Object(vec![ ("file".to_string(), text(path))
+                     , ("line".to_string(), line)
+                     , ("column".to_string(), column)
+                     , ("severity".to_string(), text(severity))
+                     , ("message".to_string(), text(&diagnostic.message))
+                     , ("rule".to_string(), rule.map(text).unwrap_or(Json::Null))
+                     ])
+}
+
+fn parse_diagnostic(error : ParseError) -> Diagnostic {
+    match error {
+        ParseError::ErrorAt(offset, message) => Diagnostic::new(Some(offset), message),
+        e => Diagnostic::new(None, format!("{:?}", e)),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_write_diagnostics_as_json() {
+        let source = "fun main() {\n    let x = 1;\n}";
+        let lint = to_json("main.hy", source, "warning", &Diagnostic::new(Some(21), "let x is never used".to_string()), Some("unused_let"));
+        assert_eq!( lint.to_string(), r#"{"file":"main.hy","line":2,"column":9,"severity":"warning","message":"let x is never used","rule":"unused_let"}"# );
+        let error = to_json("main.hy", source, "error", &Diagnostic::new(None, "cannot read".to_string()), None);
+        assert_eq!( error.to_string(), r#"{"file":"main.hy","line":null,"column":null,"severity":"error","message":"cannot read","rule":null}"# );
+    }
+}
@@ -0,0 +1,61 @@
+
+use std::path::Path;
+
+use crate::diagnostic::Diagnostic;
+use crate::lint::config::{Levels, Level};
+use crate::lint::rules::lint as find;
+use crate::project::manifest;
+use crate::toml;
+
+pub fn lint(args : &[String]) -> i32 {
+    let path = match args {
+        [path] => path,
+        _ => {
+            eprintln!("usage: hyper lint <file>");
+            return 1;
+        },
+    };
+
+    let source = match super::read_source(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+
+    let module = match super::parse_source(path, &source) {
+        Ok(module) => module,
+        Err(code) => return code,
+    };
+
+    let mut levels = Levels::new();
+    if let Some(manifest_path) = manifest::find(Path::new(path)) {
+        let manifest = manifest_path.to_string_lossy().to_string();
+        let configured = super::read_source(&manifest)
+            .and_then(|s| toml::parse(&s).and_then(|t| levels.configure(&t)).map_err(|e| {
+                eprintln!("{}: {}", manifest, e);
+                1
+            }));
+        if let Err(code) = configured {
+            return code;
+        }
+    }
+    for error in levels.attributes(&module) {
+        eprintln!("{}", Diagnostic::new(error.start, format!("warning: {}", error.message)).render(path, &source));
+    }
+
+    let mut findings = find(&module);
+    findings.sort_by_key(|f| f.start);
+    let mut denied = false;
+    for finding in findings {
+        let level = match levels.level(&finding) {
+            Level::Allow => continue,
+            Level::Warn => "warning",
+            Level::Deny => {
+                denied = true;
+                "error"
+            },
+        };
+        let message = format!("{}: {} [{}]", level, finding.message, finding.rule.name());
+        eprintln!("{}", Diagnostic::new(finding.start, message).render(path, &source));
+    }
+    if denied { 1 } else { 0 }
+}
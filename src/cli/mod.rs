@@ -0,0 +1,71 @@
+
+mod run;
+mod repl;
+mod build;
+mod trace;
+mod lint;
+mod check;
+
+use parse_input::ParseError;
+
+use crate::diagnostic::Diagnostic;
+use crate::parsing::proc::ast::Mod;
+use crate::parsing::proc::parser::parse;
+
+const USAGE : &str = "usage: hyper <command> [args]
+
+commands:
+    run <file>      interpret a program by calling its main function
+    repl            evaluate definitions, statements and expressions interactively
+    build [<file>]  compile to Lua, writing a source map next to the output; without
+                    a file, builds the project described by hyper.toml, only rebuilding
+                    modules that changed; --verbose lists what was rebuilt, and
+                    --watch builds again whenever a module changes
+    check [<file>]  check a file, or without one the project, for errors without writing
+                    anything; --message-format json prints one JSON object per problem
+    lint <file>     check for unused names and other mistakes, as hyper.toml configures
+    trace           rewrite a Lua traceback on stdin to hyper source locations";
+
+pub fn dispatch(args : &[String]) -> i32 {
+    match args.first().map(|a| a.as_str()) {
+        Some("run") => run::run(&args[1..]),
+        Some("repl") => repl::repl(&args[1..]),
+        Some("build") => build::build(&args[1..]),
+        Some("trace") => trace::trace(&args[1..]),
+        Some("lint") => lint::lint(&args[1..]),
+        Some("check") => check::check(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            1
+        },
+    }
+}
+
+fn read_source(path : &str) -> Result<String, i32> {
+    match std::fs::read_to_string(path) {
+        Ok(source) => Ok(source),
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            Err(1)
+        },
+    }
+}
+
+fn parse_source(path : &str, source : &str) -> Result<Mod, i32> {
+    match parse(source) {
+        Ok(module) => Ok(module),
+        Err(e) => {
+            report_parse_error(path, source, &e);
+            Err(1)
+        },
+    }
+}
+
+fn report_parse_error(path : &str, source : &str, error : &ParseError) {
+    match error {
+        ParseError::ErrorAt(offset, message) => {
+            eprintln!("{}", Diagnostic::new(Some(*offset), message.clone()).render(path, source));
+        },
+        e => eprintln!("{}: {:?}", path, e),
+    }
+}
@@ -0,0 +1,500 @@
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use parse_input::{Input, ParseError};
+
+use crate::diagnostic::Diagnostic;
+use crate::eval::interpreter::Interpreter;
+use crate::eval::value::{lookup, Env, Value};
+use crate::parsing::proc::ast::*;
+use crate::project::graph::{self, Graph};
+use crate::project::manifest::{self, Manifest};
+use crate::parsing::proc::statement::{self, parse_expr};
+use crate::parsing::proc::top_level::parse_top_level;
+use crate::typing::checker::Checker;
+use crate::typing::types::Ty;
+
+enum Entry {
+    TopLevel(TopLevel),
+    Statements(Vec<Statement>),
+    Expr(Expr),
+}
+
+pub struct Repl {
+    checker : Checker,
+    interpreter : Interpreter<'static>,
+    // Where to look for the hyper.toml a `use` is resolved against.
+    dir : PathBuf,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl::in_dir(Path::new("."))
+    }
+
+    pub fn in_dir(dir : &Path) -> Self {
+        Repl { checker: Checker::new(), interpreter: Interpreter::new(), dir: dir.to_path_buf() }
+    }
+
+    pub fn submit(&mut self, source : &str) -> Vec<String> {
+        // Closures defined in the session point into the AST, so every entry
+        // has to live as long as the session does.
+        let entry : &'static Entry = match parse_entry(source) {
+            Ok(entry) => Box::leak(Box::new(entry)),
+            Err(ParseError::ErrorAt(offset, message)) => return vec![Diagnostic::new(Some(offset), message).render("repl", source)],
+            Err(e) => return vec![format!("repl: {:?}", e)],
+        };
+
+        match entry {
+            Entry::TopLevel(item) => self.define(item, source),
+            Entry::Statements(statements) => self.run(statements, source),
+            Entry::Expr(expr) => self.eval(expr, source),
+        }
+    }
+
+    // Checks an entry, leaving the session as it was if that fails.
+    fn check<T>(&mut self, source : &str, f : impl FnOnce(&mut Checker) -> T) -> Result<T, Vec<String>> {
+        let snapshot = self.checker.clone();
+        let result = f(&mut self.checker);
+        let errors = self.checker.take_errors();
+        if errors.is_empty() {
+            Ok(result)
+        }
+        else {
+            self.checker = snapshot;
+            Err(errors.iter().map(|e| e.render("repl", source)).collect())
+        }
+    }
+
+    fn define(&mut self, item : &'static TopLevel, source : &str) -> Vec<String> {
+        match item {
+            TopLevel::FunDef { def, .. } => {
+                let checked = self.check(source, |c| {
+                    c.declare_fun(def);
+                    c.check_fun_def(def);
+                });
+                if let Err(errors) = checked {
+                    return errors;
+                }
+                self.interpreter.load_fun_def(def);
+                let t = match self.checker.global(&def.name.value) {
+                    Some(scheme) => self.checker.resolve(&scheme.ty),
+                    None => Ty::Any,
+                };
+                vec![format!("{} : {}", def.name.value, t)]
+            },
+            TopLevel::StructDef { def, .. } => {
+                let checked = self.check(source, |c| {
+                    c.declare_struct(def);
+                    c.check_struct_defaults(def);
+                });
+                if let Err(errors) = checked {
+                    return errors;
+                }
+                self.interpreter.load_struct_def(def);
+                vec![format!("struct {}", def.name.value)]
+            },
+            TopLevel::EnumDef { def, .. } => {
+                self.checker.declare_enum(def);
+                self.interpreter.load_enum_def(def);
+                vec![format!("enum {}", def.name.value)]
+            },
+            TopLevel::ExternFunDef { def, .. } => {
+                self.checker.declare_extern_fun(def);
+                self.interpreter.load_extern_fun_def(def);
+                let t = match self.checker.global(&def.name.value) {
+                    Some(scheme) => self.checker.resolve(&scheme.ty),
+                    None => Ty::Any,
+                };
+                vec![format!("{} : {}", def.name.value, t)]
+            },
+            TopLevel::ExternStructDef { def, .. } => {
+                self.checker.declare_extern_struct(def);
+                vec![format!("struct {}", def.name.value)]
+            },
+            TopLevel::TypeAlias { def, .. } => {
+                if let Err(errors) = self.check(source, |c| c.declare_type_alias(def)) {
+                    return errors;
+                }
+                vec![format!("type {}", def.name.value)]
+            },
+            TopLevel::ConstDef { def, .. } => {
+                let checked = self.check(source, |c| {
+                    c.declare_const(def);
+                    c.check_const_def(def);
+                });
+                if let Err(errors) = checked {
+                    return errors;
+                }
+                if let Err(e) = self.interpreter.load_const_def(def) {
+                    return vec![format!("runtime error: {}", e.message)];
+                }
+                let t = match self.checker.global(&def.name.value) {
+                    Some(scheme) => self.checker.resolve(&scheme.ty),
+                    None => Ty::Any,
+                };
+                let v = self.interpreter.global(&def.name.value).unwrap_or(Value::Unit);
+                vec![format!("{} : {} = {}", def.name.value, t, show(&v))]
+            },
+            TopLevel::InterfaceDef { def, .. } => {
+                if let Err(errors) = self.check(source, |c| c.declare_interface(def)) {
+                    return errors;
+                }
+                self.interpreter.load_interface_def(def);
+                vec![format!("interface {}", def.name.value)]
+            },
+            TopLevel::ImplDef(def) => {
+                let checked = self.check(source, |c| {
+                    c.declare_impl(def);
+                    c.check_impl(def);
+                });
+                if let Err(errors) = checked {
+                    return errors;
+                }
+                self.interpreter.load_impl_def(def);
+                match &def.interface {
+                    Some(interface) => vec![format!("impl {} for {}", interface.value, def.target.value)],
+                    None => vec![format!("impl {}", def.target.value)],
+                }
+            },
+            TopLevel::Import(u) => self.import(u, source),
+        }
+    }
+
+    // Resolves a `use` against the package whose hyper.toml is in the
+    // session's directory or above it, as though it were written in that
+    // package's source.
+    fn import(&mut self, u : &'static Use, source : &str) -> Vec<String> {
+        let at = u.namespace[0].start;
+        let manifest = match manifest::find(&self.dir) {
+            Some(path) => path,
+            None => {
+                let message = format!("use needs a {} here or in a directory above", manifest::FILE_NAME);
+                return vec![Diagnostic::new(Some(at), message).render("repl", source)];
+            },
+        };
+        // What's loaded is used by the session's closures from then on.
+        let (graph, target) = match Manifest::load(&manifest).and_then(|m| Graph::load_use(m, u)) {
+            Ok((graph, target)) => (&*Box::leak(Box::new(graph)), target),
+            Err(e) => return vec![e],
+        };
+        let mut errors = graph.errors.iter().map(|(file, error)| {
+            let file = &graph.files[*file];
+            error.render(&file.path, &file.source)
+        }).collect::<Vec<String>>();
+        let target = match target {
+            Some(target) if errors.is_empty() => target,
+            _ => return errors,
+        };
+
+        for module in graph.modules.iter() {
+            let file = &graph.files[module.file];
+            let mut checker = Checker::new();
+            for (u, target) in module.module.uses.iter().zip(module.uses.iter()) {
+                if let Some(target) = target {
+                    checker.declare_imports(u, &graph.modules[*target].module);
+                }
+            }
+            checker.check_mod(&module.module);
+            errors.extend(checker.take_errors().iter().map(|e| e.render(&file.path, &file.source)));
+        }
+        let exported = graph::exports(&graph.modules[target].module).collect::<Vec<&String>>();
+        for import in u.imports.iter() {
+            if let Import::Item(name) = import {
+                if !exported.contains(&&name.value) {
+                    let message = format!("{} has no export {}", graph.display_name(target), name.value);
+                    errors.push(Diagnostic::new(Some(name.start), message).render("repl", source));
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return errors;
+        }
+        if let Err(errors) = self.check(source, |c| c.declare_imports(u, &graph.modules[target].module)) {
+            return errors;
+        }
+
+        let env = match self.load_module(graph, target, &mut HashMap::new()) {
+            Ok(env) => env,
+            Err(message) => return vec![format!("runtime error: {}", message)],
+        };
+        let mut output = vec![];
+        for (name, value) in imported(u, &graph.modules[target].module, &env) {
+            match self.checker.global(&name) {
+                Some(scheme) => output.push(format!("{} : {}", name, self.checker.resolve(&scheme.ty))),
+                None => output.push(name.clone()),
+            }
+            self.interpreter.import(&name, value);
+        }
+        output
+    }
+
+    // Loads a module of the graph after the modules it uses, each once.
+    fn load_module(&mut self, graph : &'static Graph, index : usize, loaded : &mut HashMap<usize, Env<'static>>) -> Result<Env<'static>, String> {
+        if let Some(env) = loaded.get(&index) {
+            return Ok(env.clone());
+        }
+        let module = &graph.modules[index];
+        let mut imports = vec![];
+        for (u, target) in module.module.uses.iter().zip(module.uses.iter()) {
+            if let Some(target) = target {
+                let env = self.load_module(graph, *target, loaded)?;
+                imports.extend(imported(u, &graph.modules[*target].module, &env));
+            }
+        }
+        let env = self.interpreter.load_module(&module.module, imports).map_err(|e| e.message)?;
+        loaded.insert(index, env.clone());
+        Ok(env)
+    }
+
+    fn run(&mut self, statements : &'static [Statement], source : &str) -> Vec<String> {
+        if let [Statement::Expr(expr)] = statements {
+            return self.eval(expr, source);
+        }
+
+        let checked = self.check(source, |c| {
+            for statement in statements {
+                c.check_top_statement(statement);
+            }
+        });
+        if let Err(errors) = checked {
+            return errors;
+        }
+
+        if let Err(e) = self.interpreter.run_statements(statements) {
+            return vec![format!("runtime error: {}", e.message)];
+        }
+
+        let mut output = vec![];
+        for statement in statements {
+            let names = match statement {
+                Statement::Let { pattern, .. } => pattern.names(),
+                _ => vec![],
+            };
+            for name in names {
+                let t = self.checker.local(&name.value).unwrap_or(Ty::Any);
+                let v = self.interpreter.global(&name.value).unwrap_or(Value::Unit);
+                output.push(format!("{} : {} = {}", name.value, t, show(&v)));
+            }
+        }
+        output
+    }
+
+    fn eval(&mut self, expr : &'static Expr, source : &str) -> Vec<String> {
+        let t = match self.check(source, |c| c.infer(expr)) {
+            Ok(t) => t,
+            Err(errors) => return errors,
+        };
+
+        match self.interpreter.eval(expr) {
+            Ok(Value::Unit) => vec![],
+            Ok(v) => vec![format!("{} : {}", show(&v), t)],
+            Err(e) => vec![format!("runtime error: {}", e.message)],
+        }
+    }
+}
+
+// The values a `use` brings in from the globals its module was loaded into.
+// Types have none, but an interface brings in its methods.
+fn imported(u : &Use, target : &Mod, env : &Env<'static>) -> Vec<(String, Value<'static>)> {
+    let mut names = graph::exports(target).filter(|e| u.imports.iter().any(|i| match i {
+        Import::Everything => true,
+        Import::Item(n) => n.value == **e,
+    })).cloned().collect::<Vec<String>>();
+    let methods = target.interface_defs.iter()
+        .filter(|d| names.contains(&d.name.value))
+        .flat_map(|d| d.methods.iter().map(|m| m.name.value.clone()))
+        .collect::<Vec<String>>();
+    names.extend(methods);
+    names.into_iter().filter_map(|name| lookup(env, &name).map(|v| (name, v))).collect()
+}
+
+fn show(v : &Value) -> String {
+    match v {
+        Value::String(s) => format!("{:?}", s),
+        v => v.to_string(),
+    }
+}
+
+fn parse_complete<T>(source : &str, parser : fn(&mut Input) -> Result<T, ParseError>) -> Result<T, ParseError> {
+    let ci = source.char_indices().collect::<Vec<(usize, char)>>();
+    let mut input = Input::new(&ci);
+    let result = parser(&mut input)?;
+    input.expect_end()?;
+    Ok(result)
+}
+
+fn parse_entry(source : &str) -> Result<Entry, ParseError> {
+    fn parse_statements(input : &mut Input) -> Result<Vec<Statement>, ParseError> {
+        let statements = statement::parse_statements(input)?;
+        if statements.is_empty() {
+            Err(ParseError::ErrorAt(0, "Expected statement".to_string()))
+        }
+        else {
+            Ok(statements)
+        }
+    }
+
+    let top_level_error = match parse_complete(source, parse_top_level) {
+        Ok(item) => return Ok(Entry::TopLevel(item)),
+        Err(e) => e,
+    };
+    let statements_error = match parse_complete(source, parse_statements) {
+        Ok(statements) => return Ok(Entry::Statements(statements)),
+        Err(e) => e,
+    };
+    let expr_error = match parse_complete(source, parse_expr) {
+        Ok(expr) => return Ok(Entry::Expr(expr)),
+        Err(e) => e,
+    };
+
+    // Report whichever reading of the input got the furthest.
+    let furthest = |e : &ParseError| match e {
+        ParseError::ErrorAt(offset, _) => *offset,
+        _ => usize::MAX,
+    };
+    let errors = vec![top_level_error, statements_error, expr_error];
+    Err(errors.into_iter().max_by_key(furthest).unwrap())
+}
+
+fn needs_more(source : &str) -> bool {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escape = false;
+    for c in source.chars() {
+        if in_string {
+            match c {
+                _ if escape => escape = false,
+                '\\' => escape = true,
+                '"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            _ => (),
+        }
+    }
+    depth > 0
+}
+
+fn prompt(p : &str) {
+    print!("{}", p);
+    let _ = io::stdout().flush();
+}
+
+pub fn repl(args : &[String]) -> i32 {
+    if !args.is_empty() {
+        eprintln!("usage: hyper repl");
+        return 1;
+    }
+
+    let mut repl = Repl::new();
+    let mut buffer = String::new();
+    prompt("> ");
+    for line in io::stdin().lock().lines() {
+        match line {
+            Ok(line) => buffer.push_str(&line),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            },
+        }
+        buffer.push('\n');
+
+        if needs_more(&buffer) {
+            prompt("| ");
+            continue;
+        }
+
+        if !buffer.trim().is_empty() {
+            for output in repl.submit(&buffer) {
+                println!("{}", output);
+            }
+        }
+        buffer.clear();
+        prompt("> ");
+    }
+    println!();
+    0
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_print_let_with_type() {
+        let mut repl = Repl::new();
+        let output = repl.submit("let x = add(1, 2);");
+        assert_eq!( output, vec!["x : number = 3"] );
+    }
+
+    #[test]
+    fn should_keep_state_between_inputs() {
+        let mut repl = Repl::new();
+        repl.submit("let x = [1, 2];");
+        repl.submit("push(x, 3);");
+        let output = repl.submit("x");
+        assert_eq!( output, vec!["[1, 2, 3] : List<number>"] );
+    }
+
+    #[test]
+    fn should_define_and_call_function() {
+        let mut repl = Repl::new();
+        let output = repl.submit("fun id<T>(x : T) -> T {\n    return x;\n}\n");
+        assert_eq!( output, vec!["id : fun(T) -> T"] );
+        let output = repl.submit(r#"id("hi")"#);
+        assert_eq!( output, vec![r#""hi" : string"#] );
+    }
+
+    #[test]
+    fn should_report_type_error() {
+        let mut repl = Repl::new();
+        let output = repl.submit(r#"add(1, "two")"#);
+        assert_eq!( output, vec!["repl:1:8: expected number but found string"] );
+    }
+
+    #[test]
+    fn should_forget_entries_that_fail_to_check() {
+        let mut repl = Repl::in_dir(Path::new("/"));
+        let output = repl.submit(r#"fun f() -> number { return "one"; }"#);
+        assert_eq!( output, vec!["repl:1:28: expected number but found string"] );
+        assert_eq!( repl.submit("f()"), vec!["repl:1:1: unknown variable f"] );
+        repl.submit(r#"let x = 1; let y : string = x;"#);
+        assert_eq!( repl.submit("x"), vec!["repl:1:1: unknown variable x"] );
+        assert_eq!( repl.submit("use shapes::{nope};"), vec!["repl:1:5: use needs a hyper.toml here or in a directory above"] );
+        assert_eq!( repl.submit("let z = 2;"), vec!["z : number = 2"] );
+    }
+
+    #[test]
+    fn should_use_modules_of_the_surrounding_package() {
+        let root = std::env::temp_dir().join(format!("hyper_repl_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("src/geo")).unwrap();
+        std::fs::write(root.join("hyper.toml"), "[package]\nname = \"app\"").unwrap();
+        std::fs::write(root.join("src/geo/shapes.hy"), "use geo::units::{*};\npub fun area(side : number) -> number { return scale(mul(side, side)); }\nfun hidden() { }").unwrap();
+        std::fs::write(root.join("src/geo/units.hy"), "pub const FACTOR : number = 2;\npub fun scale(x : number) -> number { return mul(x, FACTOR); }").unwrap();
+
+        let mut repl = Repl::in_dir(&root.join("src"));
+        assert_eq!( repl.submit("use geo::shapes::{area};"), vec!["area : fun(number) -> number"] );
+        assert_eq!( repl.submit("area(3)"), vec!["18 : number"] );
+        assert_eq!( repl.submit("scale(1)"), vec!["repl:1:1: unknown variable scale"] );
+        assert_eq!( repl.submit("use geo::shapes::{hidden};"), vec!["repl:1:19: geo::shapes has no export hidden"] );
+        assert!( repl.submit("use geo::missing::{*};")[0].contains("cannot read module geo::missing") );
+    }
+
+    #[test]
+    fn should_need_more_with_unbalanced_braces() {
+        assert!( needs_more("fun f() {\n") );
+        assert!( needs_more("let x = [1,\n") );
+        assert!( !needs_more("let x = \"{\";\n") );
+        assert!( !needs_more("fun f() { }\n") );
+    }
+}
@@ -0,0 +1,32 @@
+
+use crate::eval::interpreter::Interpreter;
+
+pub fn run(args : &[String]) -> i32 {
+    let path = match args {
+        [path] => path,
+        _ => {
+            eprintln!("usage: hyper run <file>");
+            return 1;
+        },
+    };
+
+    let source = match super::read_source(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+
+    let module = match super::parse_source(path, &source) {
+        Ok(module) => module,
+        Err(code) => return code,
+    };
+
+    let mut interpreter = Interpreter::new();
+    let result = interpreter.load(&module).and_then(|_| interpreter.call("main", vec![]));
+    match result {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}: runtime error: {}", path, e.message);
+            1
+        },
+    }
+}
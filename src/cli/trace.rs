@@ -0,0 +1,26 @@
+
+use std::io::Read;
+
+use crate::json;
+use crate::lowering::source_map::{SourceMap, rewrite_traceback};
+
+fn load_map(lua_path : &str) -> Option<SourceMap> {
+    let text = std::fs::read_to_string(format!("{}.map", lua_path)).ok()?;
+    SourceMap::from_json(&json::parse(&text).ok()?)
+}
+
+pub fn trace(args : &[String]) -> i32 {
+    if !args.is_empty() {
+        eprintln!("usage: hyper trace < traceback");
+        return 1;
+    }
+
+    let mut text = String::new();
+    if let Err(e) = std::io::stdin().read_to_string(&mut text) {
+        eprintln!("{}", e);
+        return 1;
+    }
+
+    print!("{}", rewrite_traceback(&text, &mut load_map));
+    0
+}
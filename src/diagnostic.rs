@@ -0,0 +1,34 @@
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub start : Option<usize>,
+    pub message : String,
+}
+
+impl Diagnostic {
+    pub fn new(start : Option<usize>, message : String) -> Self {
+        Diagnostic { start, message }
+    }
+
+    pub fn render(&self, path : &str, source : &str) -> String {
+        match self.start {
+            Some(offset) => {
+                let (line, column) = line_column(source, offset);
+                format!("{}:{}:{}: {}", path, line, column, self.message)
+            },
+            None => format!("{}: {}", path, self.message),
+        }
+    }
+}
+
+// Offsets inside a character count as its start.
+pub fn line_column(source : &str, offset : usize) -> (usize, usize) {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
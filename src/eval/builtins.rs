@@ -0,0 +1,373 @@
+
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use super::value::*;
+
+pub fn builtins() -> Vec<(&'static str, BuiltinFn)> {
+    vec![ ("print", print)
+        , ("add", add)
+        , ("sub", sub)
+        , ("mul", mul)
+        , ("div", div)
+        , ("mod", modulo)
+        , ("eq", eq)
+        , ("neq", neq)
+        , ("lt", lt)
+        , ("le", le)
+        , ("gt", gt)
+        , ("ge", ge)
+        , ("not", not)
+        , ("and", and)
+        , ("or", or)
+        , ("concat", concat)
+        , ("to_string", to_string)
+        , ("len", len)
+        , ("push", push)
+        , ("iter", iter)
+        , ("get", get)
+        , ("pop", pop)
+        , ("is_ok", is_ok)
+        , ("unwrap", unwrap)
+        , ("unwrap_err", unwrap_err)
+        , ("is_some", is_some)
+        , ("is_none", is_none)
+        , ("value_or", value_or)
+        , ("substring", substring)
+        , ("split", split)
+        , ("upper", upper)
+        , ("lower", lower)
+        , ("trim", trim)
+        , ("find", find)
+        , ("keys", keys)
+        , ("values", values)
+        , ("has_key", has_key)
+        , ("remove_key", remove_key)
+        ]
+}
+
+fn error<T>(message : String) -> Result<T, RuntimeError> {
+    Err(RuntimeError { message })
+}
+
+fn arity<'a>(name : &str, args : &[Value<'a>], expected : usize) -> Result<(), RuntimeError> {
+    if args.len() != expected {
+        error(format!("{} expects {} arguments but found {}", name, expected, args.len()))
+    }
+    else {
+        Ok(())
+    }
+}
+
+fn numbers<'a>(name : &str, args : &[Value<'a>]) -> Result<(f64, f64), RuntimeError> {
+    arity(name, args, 2)?;
+    match (&args[0], &args[1]) {
+        (Value::Number(a), Value::Number(b)) => Ok((*a, *b)),
+        (a, b) => error(format!("{} expects numbers but found {} and {}", name, a.type_name(), b.type_name())),
+    }
+}
+
+fn string<'a>(name : &str, arg : &Value<'a>) -> Result<String, RuntimeError> {
+    match arg {
+        Value::String(s) => Ok(s.clone()),
+        v => error(format!("{} expects string but found {}", name, v.type_name())),
+    }
+}
+
+fn index<'a>(name : &str, arg : &Value<'a>) -> Result<usize, RuntimeError> {
+    match arg {
+        Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+        Value::Number(n) => error(format!("index {} out of range", n)),
+        v => error(format!("{} expects number but found {}", name, v.type_name())),
+    }
+}
+
+fn bools<'a>(name : &str, args : &[Value<'a>]) -> Result<(bool, bool), RuntimeError> {
+    arity(name, args, 2)?;
+    match (&args[0], &args[1]) {
+        (Value::Bool(a), Value::Bool(b)) => Ok((*a, *b)),
+        (a, b) => error(format!("{} expects bools but found {} and {}", name, a.type_name(), b.type_name())),
+    }
+}
+
+fn print<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let line = args.iter().map(|a| a.to_string()).collect::<Vec<String>>().join("\t");
+    println!("{}", line);
+    Ok(Value::Unit)
+}
+
+fn add<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let (a, b) = numbers("add", &args)?;
+    Ok(Value::Number(a + b))
+}
+
+fn sub<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let (a, b) = numbers("sub", &args)?;
+    Ok(Value::Number(a - b))
+}
+
+fn mul<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let (a, b) = numbers("mul", &args)?;
+    Ok(Value::Number(a * b))
+}
+
+fn div<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let (a, b) = numbers("div", &args)?;
+    Ok(Value::Number(a / b))
+}
+
+// Matches Lua's `%`, which takes the sign of the divisor.
+fn modulo<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let (a, b) = numbers("mod", &args)?;
+    Ok(Value::Number(a - (a / b).floor() * b))
+}
+
+fn eq<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("eq", &args, 2)?;
+    Ok(Value::Bool(args[0].equals(&args[1])))
+}
+
+fn neq<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("neq", &args, 2)?;
+    Ok(Value::Bool(!args[0].equals(&args[1])))
+}
+
+fn lt<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let (a, b) = numbers("lt", &args)?;
+    Ok(Value::Bool(a < b))
+}
+
+fn le<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let (a, b) = numbers("le", &args)?;
+    Ok(Value::Bool(a <= b))
+}
+
+fn gt<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let (a, b) = numbers("gt", &args)?;
+    Ok(Value::Bool(a > b))
+}
+
+fn ge<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let (a, b) = numbers("ge", &args)?;
+    Ok(Value::Bool(a >= b))
+}
+
+fn not<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("not", &args, 1)?;
+    match &args[0] {
+        Value::Bool(b) => Ok(Value::Bool(!b)),
+        v => error(format!("not expects bool but found {}", v.type_name())),
+    }
+}
+
+fn and<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let (a, b) = bools("and", &args)?;
+    Ok(Value::Bool(a && b))
+}
+
+fn or<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let (a, b) = bools("or", &args)?;
+    Ok(Value::Bool(a || b))
+}
+
+fn concat<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    let mut ret = String::new();
+    for arg in args {
+        match arg {
+            Value::String(s) => ret.push_str(&s),
+            v @ Value::Number(_) => ret.push_str(&v.to_string()),
+            v => return error(format!("concat expects strings or numbers but found {}", v.type_name())),
+        }
+    }
+    Ok(Value::String(ret))
+}
+
+fn to_string<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("to_string", &args, 1)?;
+    Ok(Value::String(args[0].to_string()))
+}
+
+fn len<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("len", &args, 1)?;
+    match &args[0] {
+        Value::List(vs) => Ok(Value::Number(vs.borrow().len() as f64)),
+        Value::String(s) => Ok(Value::Number(s.len() as f64)),
+        v => error(format!("len expects list or string but found {}", v.type_name())),
+    }
+}
+
+fn push<'a>(mut args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("push", &args, 2)?;
+    let item = args.pop().unwrap();
+    match args.pop().unwrap() {
+        Value::List(vs) => { vs.borrow_mut().push(item); Ok(Value::Unit) },
+        v => error(format!("push expects list but found {}", v.type_name())),
+    }
+}
+
+
+fn iter<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("iter", &args, 1)?;
+    match &args[0] {
+        Value::List(vs) => Ok(Value::Generator(Rc::new(RefCell::new(Generator::Items(vs.borrow().clone().into_iter()))))),
+        v @ Value::Generator(_) => Ok(v.clone()),
+        v => error(format!("iter expects list or generator but found {}", v.type_name())),
+    }
+}
+
+fn get<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("get", &args, 2)?;
+    let i = index("get", &args[1])?;
+    let item = match &args[0] {
+        Value::List(vs) => vs.borrow().get(i).cloned(),
+        v => return error(format!("get expects list but found {}", v.type_name())),
+    };
+    match item {
+        Some(item) => Ok(item),
+        None => error(format!("index {} out of range", i)),
+    }
+}
+
+fn pop<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("pop", &args, 1)?;
+    match &args[0] {
+        Value::List(vs) => match vs.borrow_mut().pop() {
+            Some(item) => Ok(item),
+            None => error("pop from empty list".to_string()),
+        },
+        v => error(format!("pop expects list but found {}", v.type_name())),
+    }
+}
+
+fn is_ok<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("is_ok", &args, 1)?;
+    match &args[0] {
+        Value::Okay(_) => Ok(Value::Bool(true)),
+        Value::Error(_) => Ok(Value::Bool(false)),
+        v => error(format!("is_ok expects result but found {}", v.type_name())),
+    }
+}
+
+fn unwrap<'a>(mut args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("unwrap", &args, 1)?;
+    match args.pop().unwrap() {
+        Value::Okay(v) => Ok(*v),
+        Value::Error(_) => error("unwrap called on Err".to_string()),
+        v => error(format!("unwrap expects result but found {}", v.type_name())),
+    }
+}
+
+fn unwrap_err<'a>(mut args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("unwrap_err", &args, 1)?;
+    match args.pop().unwrap() {
+        Value::Error(v) => Ok(*v),
+        Value::Okay(_) => error("unwrap_err called on Ok".to_string()),
+        v => error(format!("unwrap_err expects result but found {}", v.type_name())),
+    }
+}
+
+fn is_some<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("is_some", &args, 1)?;
+    match &args[0] {
+        Value::Some(_) => Ok(Value::Bool(true)),
+        Value::None => Ok(Value::Bool(false)),
+        v => error(format!("is_some expects option but found {}", v.type_name())),
+    }
+}
+
+fn is_none<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("is_none", &args, 1)?;
+    match &args[0] {
+        Value::Some(_) => Ok(Value::Bool(false)),
+        Value::None => Ok(Value::Bool(true)),
+        v => error(format!("is_none expects option but found {}", v.type_name())),
+    }
+}
+
+fn value_or<'a>(mut args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("value_or", &args, 2)?;
+    let default = args.pop().unwrap();
+    match args.pop().unwrap() {
+        Value::Some(v) => Ok(*v),
+        Value::None => Ok(default),
+        v => error(format!("value_or expects option but found {}", v.type_name())),
+    }
+}
+
+// String positions are byte offsets, as they are in Lua.
+fn substring<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("substring", &args, 3)?;
+    let s = string("substring", &args[0])?;
+    let start = index("substring", &args[1])?.min(s.len());
+    let stop = index("substring", &args[2])?.min(s.len());
+    if start >= stop {
+        return Ok(Value::String(String::new()));
+    }
+    Ok(Value::String(String::from_utf8_lossy(&s.as_bytes()[start..stop]).into_owned()))
+}
+
+fn split<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("split", &args, 2)?;
+    let s = string("split", &args[0])?;
+    let separator = string("split", &args[1])?;
+    if separator.is_empty() {
+        return error("split with empty separator".to_string());
+    }
+    let items = s.split(separator.as_str()).map(|item| Value::String(item.to_string())).collect();
+    Ok(Value::List(Rc::new(RefCell::new(items))))
+}
+
+fn upper<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("upper", &args, 1)?;
+    Ok(Value::String(string("upper", &args[0])?.to_uppercase()))
+}
+
+fn lower<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("lower", &args, 1)?;
+    Ok(Value::String(string("lower", &args[0])?.to_lowercase()))
+}
+
+fn trim<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("trim", &args, 1)?;
+    Ok(Value::String(string("trim", &args[0])?.trim().to_string()))
+}
+
+fn find<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("find", &args, 2)?;
+    let s = string("find", &args[0])?;
+    let part = string("find", &args[1])?;
+    Ok(Value::Number(s.find(part.as_str()).map(|i| i as f64).unwrap_or(-1.0)))
+}
+
+fn map<'a>(name : &str, arg : &Value<'a>) -> Result<MapRef<'a>, RuntimeError> {
+    match arg {
+        Value::Map(m) => Ok(m.clone()),
+        v => error(format!("{} expects map but found {}", name, v.type_name())),
+    }
+}
+
+fn keys<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("keys", &args, 1)?;
+    let items = map("keys", &args[0])?.borrow().iter().map(|(k, _)| k.clone()).collect();
+    Ok(Value::List(Rc::new(RefCell::new(items))))
+}
+
+fn values<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("values", &args, 1)?;
+    let items = map("values", &args[0])?.borrow().iter().map(|(_, v)| v.clone()).collect();
+    Ok(Value::List(Rc::new(RefCell::new(items))))
+}
+
+fn has_key<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("has_key", &args, 2)?;
+    let entries = map("has_key", &args[0])?;
+    let found = map_get(&entries.borrow(), &args[1]).is_some();
+    Ok(Value::Bool(found))
+}
+
+fn remove_key<'a>(args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+    arity("remove_key", &args, 2)?;
+    map("remove_key", &args[0])?.borrow_mut().retain(|(k, _)| !k.equals(&args[1]));
+    Ok(Value::Unit)
+}
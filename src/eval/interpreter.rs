@@ -0,0 +1,1357 @@
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use parse_input::PSym;
+
+use crate::parsing::proc::ast::*;
+use crate::prelude;
+use crate::typing::arguments::arrange;
+use super::value::*;
+use super::builtins::builtins;
+
+enum Flow<'a> {
+    Normal,
+    Break(Option<&'a str>),
+    Continue(Option<&'a str>),
+    Return(Value<'a>),
+}
+
+// `?` leaves the enclosing function from inside an expression, so
+// expression evaluation can unwind with a return value as well as an error.
+enum Unwind<'a> {
+    Error(RuntimeError),
+    Return(Value<'a>),
+}
+
+impl<'a> From<RuntimeError> for Unwind<'a> {
+    fn from(e : RuntimeError) -> Self {
+        Unwind::Error(e)
+    }
+}
+
+fn error<'a, T>(message : String) -> Result<T, Unwind<'a>> {
+    Err(Unwind::Error(RuntimeError { message }))
+}
+
+fn list_index<'a>(index : &Value<'a>) -> Result<usize, Unwind<'a>> {
+    match index {
+        Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+        Value::Number(n) => error(format!("index {} out of range", n)),
+        v => error(format!("expected number but found {}", v.type_name())),
+    }
+}
+
+fn bind<'a>(env : &Env<'a>, pattern : &Pattern, value : Value<'a>) -> Result<(), Unwind<'a>> {
+    match (pattern, value) {
+        (Pattern::Name(name), value) => {
+            define(env, &name.value, value);
+            Ok(())
+        },
+        (Pattern::Tuple(ps), Value::Tuple(vs)) if ps.len() == vs.len() => {
+            for (p, v) in ps.iter().zip(vs.iter()) {
+                bind(env, p, v.clone())?;
+            }
+            Ok(())
+        },
+        (Pattern::Tuple(ps), v) => error(format!("cannot destructure {} into {} values", v.type_name(), ps.len())),
+    }
+}
+
+// Decides what a loop does once its body ends with `flow`: `None` runs it
+// again, anything else leaves the loop with that flow.
+fn after_body<'a>(label : &Option<PSym>, flow : Flow<'a>) -> Option<Flow<'a>> {
+    let targets_this = |target : Option<&str>| match (target, label) {
+        (None, _) => true,
+        (Some(target), Some(label)) => target == label.value,
+        (Some(_), None) => false,
+    };
+    match flow {
+        Flow::Normal => None,
+        Flow::Continue(target) if targets_this(target) => None,
+        Flow::Break(target) if targets_this(target) => Some(Flow::Normal),
+        flow => Some(flow),
+    }
+}
+
+fn numbered<'a>(items : &[Value<'a>]) -> Vec<(Value<'a>, Value<'a>)> {
+    items.iter().enumerate().map(|(i, v)| (Value::Number(i as f64), v.clone())).collect()
+}
+
+// Binds the key and value of a foreach entry. A map on its own iterates
+// over its keys.
+fn bind_entry<'a>(scope : &Env<'a>, var : &Pattern, value : &Option<Pattern>, (k, v, is_map) : (Value<'a>, Value<'a>, bool)) -> Result<(), Unwind<'a>> {
+    match value {
+        Some(value) => {
+            bind(scope, var, k)?;
+            bind(scope, value, v)
+        },
+        None if is_map => bind(scope, var, k),
+        None => bind(scope, var, v),
+    }
+}
+
+fn block<'a>(statements : &'a [Statement], env : Env<'a>) -> Pending<'a> {
+    Pending::Block { statements, next: 0, env, chain_done: true }
+}
+
+// What running the next statement of a suspended block comes to.
+enum Step<'a> {
+    Next,
+    Enter(Pending<'a>),
+    End(Flow<'a>),
+    Yield(Value<'a>),
+}
+
+pub struct Interpreter<'a> {
+    globals : Env<'a>,
+    // The frame the prelude is loaded into, which every module's globals
+    // sit on.
+    prelude : Env<'a>,
+    enums : HashMap<String, &'a EnumDef>,
+    structs : HashMap<String, &'a StructDef>,
+    // Methods by interface and implementing type name.
+    impls : HashMap<(String, String), HashMap<String, Value<'a>>>,
+    // Methods by struct name and method name.
+    methods : HashMap<(String, String), Value<'a>>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new() -> Self {
+        let builtin_env = new_env(None);
+        for (name, func) in builtins() {
+            define(&builtin_env, name, Value::Builtin { name, func });
+        }
+        // The prelude gets a frame of its own so that user definitions
+        // shadow its names without changing what the prelude calls.
+        let prelude = new_env(Some(builtin_env));
+        let mut interpreter = Interpreter { globals: prelude.clone(), prelude, enums: HashMap::new(), structs: HashMap::new(), impls: HashMap::new(), methods: HashMap::new() };
+        interpreter.load(prelude::module()).expect("prelude failed to load");
+        interpreter.globals = new_env(Some(interpreter.prelude.clone()));
+        interpreter
+    }
+
+    // Loads a module into globals of its own, with what its uses import
+    // already defined there, and gives those globals back so that what it
+    // exports can be imported elsewhere.
+    pub fn load_module(&mut self, module : &'a Mod, imports : Vec<(String, Value<'a>)>) -> Result<Env<'a>, RuntimeError> {
+        let env = new_env(Some(self.prelude.clone()));
+        for (name, value) in imports {
+            define(&env, &name, value);
+        }
+        let outer = std::mem::replace(&mut self.globals, env);
+        let loaded = self.load(module);
+        let env = std::mem::replace(&mut self.globals, outer);
+        loaded.map(|()| env)
+    }
+
+    pub fn import(&mut self, name : &str, value : Value<'a>) {
+        define(&self.globals, name, value);
+    }
+
+    pub fn load(&mut self, module : &'a Mod) -> Result<(), RuntimeError> {
+        for def in module.extern_fun_defs.iter() {
+            self.load_extern_fun_def(def);
+        }
+        for def in module.fun_defs.iter() {
+            self.load_fun_def(def);
+        }
+        for def in module.enum_defs.iter() {
+            self.load_enum_def(def);
+        }
+        for def in module.struct_defs.iter() {
+            self.load_struct_def(def);
+        }
+        for def in module.interface_defs.iter() {
+            self.load_interface_def(def);
+        }
+        for def in module.impl_defs.iter() {
+            self.load_impl_def(def);
+        }
+        // Consts may call the functions above and use the consts before them.
+        for def in module.const_defs.iter() {
+            self.load_const_def(def)?;
+        }
+        Ok(())
+    }
+
+    pub fn load_const_def(&mut self, def : &'a ConstDef) -> Result<(), RuntimeError> {
+        let value = self.eval(&def.value)?;
+        define(&self.globals, &def.name.value, value);
+        Ok(())
+    }
+
+    pub fn load_fun_def(&mut self, def : &'a FunDef) {
+        let closure = self.fun_closure(def);
+        define(&self.globals, &def.name.value, closure);
+    }
+
+    pub fn load_interface_def(&mut self, def : &'a InterfaceDef) {
+        for method in def.methods.iter() {
+            define(&self.globals, &method.name.value, Value::Method { interface: &def.name.value, name: &method.name.value });
+        }
+    }
+
+    pub fn load_impl_def(&mut self, def : &'a ImplDef) {
+        match &def.interface {
+            Some(interface) => {
+                let methods = def.funs.iter().map(|f| (f.name.value.clone(), self.fun_closure(f))).collect();
+                self.impls.insert((interface.value.clone(), def.target.value.clone()), methods);
+            },
+            None => {
+                for fun in def.funs.iter() {
+                    let closure = self.fun_closure(fun);
+                    self.methods.insert((def.target.value.clone(), fun.name.value.clone()), closure);
+                }
+            },
+        }
+    }
+
+    fn fun_closure(&self, def : &'a FunDef) -> Value<'a> {
+        let closure = Closure { params: &def.params
+                              , body: Body::Statements(&def.definition)
+                              , env: self.globals.clone()
+                              , generator: contains_yield(&def.definition)
+                              };
+        Value::Closure(Rc::new(closure))
+    }
+
+    pub fn load_extern_fun_def(&mut self, def : &'a ExternFunDef) {
+        define(&self.globals, &def.name.value, Value::Extern { path: &def.path.value });
+    }
+
+    pub fn load_enum_def(&mut self, def : &'a EnumDef) {
+        self.enums.insert(def.name.value.clone(), def);
+    }
+
+    pub fn load_struct_def(&mut self, def : &'a StructDef) {
+        self.structs.insert(def.name.value.clone(), def);
+    }
+
+    pub fn call(&mut self, name : &str, args : Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError> {
+        let func = match lookup(&self.globals, name) {
+            Some(f) => f,
+            None => return Err(RuntimeError { message: format!("unknown function {}", name) }),
+        };
+        match self.apply(func, args) {
+            Ok(v) => Ok(v),
+            Err(Unwind::Return(v)) => Ok(v),
+            Err(Unwind::Error(e)) => Err(e),
+        }
+    }
+
+    pub fn run_statements(&mut self, statements : &'a [Statement]) -> Result<(), RuntimeError> {
+        let env = self.globals.clone();
+        let message = match self.exec_block(statements, &env) {
+            Ok(Flow::Normal) => return Ok(()),
+            Ok(Flow::Break(_) | Flow::Continue(_)) => "break outside of loop",
+            Ok(Flow::Return(_)) | Err(Unwind::Return(_)) => "return outside of function",
+            Err(Unwind::Error(e)) => return Err(e),
+        };
+        Err(RuntimeError { message: message.to_string() })
+    }
+
+    pub fn eval(&mut self, expr : &'a Expr) -> Result<Value<'a>, RuntimeError> {
+        let env = self.globals.clone();
+        match self.eval_expr(expr, &env) {
+            Ok(v) => Ok(v),
+            Err(Unwind::Return(_)) => Err(RuntimeError { message: "? outside of function".to_string() }),
+            Err(Unwind::Error(e)) => Err(e),
+        }
+    }
+
+    pub fn global(&self, name : &str) -> Option<Value<'a>> {
+        lookup(&self.globals, name)
+    }
+
+    fn apply(&mut self, func : Value<'a>, args : Vec<Value<'a>>) -> Result<Value<'a>, Unwind<'a>> {
+        let closure = match func {
+            Value::Closure(c) => c,
+            Value::Builtin { func, .. } => return Ok(func(args)?),
+            Value::Extern { path } => return error(format!("cannot call extern {} outside of Lua", path)),
+            Value::Method { interface, name } => {
+                let type_name = match args.first() {
+                    Some(Value::Struct(s)) => s.borrow().name.clone().unwrap_or_else(|| "struct".to_string()),
+                    Some(v) => v.type_name().to_string(),
+                    None => return error(format!("{} expects self", name)),
+                };
+                let method = self.impls.get(&(interface.to_string(), type_name.clone()))
+                                       .and_then(|methods| methods.get(name))
+                                       .cloned();
+                return match method {
+                    Some(m) => self.apply(m, args),
+                    None => error(format!("{} does not implement {}", type_name, interface)),
+                };
+            },
+            v => return error(format!("cannot call {}", v.type_name())),
+        };
+        self.call_closure(closure, args.into_iter().map(Some).collect())
+    }
+
+    // Arguments that are None take the parameter's default, which is
+    // evaluated after the parameters before it are bound.
+    fn call_closure(&mut self, closure : Rc<Closure<'a>>, mut args : Vec<Option<Value<'a>>>) -> Result<Value<'a>, Unwind<'a>> {
+        // The arguments left over go into a variadic parameter's list.
+        let rest = match closure.params.last() {
+            Some(param) if param.variadic => {
+                let fixed = closure.params.len() - 1;
+                if args.len() < fixed {
+                    return error(format!("expected at least {} arguments but found {}", fixed, args.len()));
+                }
+                let items = args.split_off(fixed).into_iter().flatten().collect();
+                Some((&param.name.value, Value::List(Rc::new(RefCell::new(items)))))
+            },
+            _ => None,
+        };
+        if rest.is_none() && closure.params.len() != args.len() {
+            return error(format!("expected {} arguments but found {}", closure.params.len(), args.len()));
+        }
+
+        let env = new_env(Some(closure.env.clone()));
+        if let Some((name, list)) = rest {
+            define(&env, name, list);
+        }
+        for (param, arg) in closure.params.iter().zip(args) {
+            let value = match (arg, &param.default) {
+                (Some(v), _) => v,
+                (None, Some(default)) => self.eval_expr(default, &env)?,
+                (None, None) => return error(format!("missing argument {}", param.name.value)),
+            };
+            define(&env, &param.name.value, value);
+        }
+
+        let statements = match closure.body {
+            Body::Expr(e) => return self.eval_expr(e, &env),
+            Body::Statements(ss) => ss,
+        };
+
+        if closure.generator {
+            let body = Generator::Suspended(vec![block(statements, env)]);
+            return Ok(Value::Generator(Rc::new(RefCell::new(body))));
+        }
+
+        match self.exec_block(statements, &env) {
+            Ok(Flow::Normal) => Ok(Value::Unit),
+            Ok(Flow::Return(v)) => Ok(v),
+            Ok(Flow::Break(_) | Flow::Continue(_)) => error("break outside of loop".to_string()),
+            Err(Unwind::Return(v)) => Ok(v),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn exec_block(&mut self, statements : &'a [Statement], env : &Env<'a>) -> Result<Flow<'a>, Unwind<'a>> {
+        // Whether the current if/elseif/else chain has already run a branch.
+        let mut chain_done = true;
+        let mut env = env.clone();
+        for statement in statements {
+            // A `let` of a name the block already has shadows it, as a new
+            // `local` does in Lua, so lambdas that captured the old one
+            // keep it. Globals are redefined in place, since functions
+            // defined earlier look them up there.
+            if let Statement::Let { pattern, .. } = statement {
+                if !Rc::ptr_eq(&env, &self.globals) && pattern.names().iter().any(|n| defines(&env, &n.value)) {
+                    env = new_env(Some(env));
+                }
+            }
+            let env = &env;
+            let flow = match statement {
+                Statement::If { test, statements, .. } => {
+                    chain_done = self.test(test, env)?;
+                    if chain_done {
+                        self.exec_block(statements, &new_env(Some(env.clone())))?
+                    }
+                    else {
+                        Flow::Normal
+                    }
+                },
+                Statement::ElseIf { test, statements, .. } if !chain_done => {
+                    chain_done = self.test(test, env)?;
+                    if chain_done {
+                        self.exec_block(statements, &new_env(Some(env.clone())))?
+                    }
+                    else {
+                        Flow::Normal
+                    }
+                },
+                Statement::Else(statements) if !chain_done => {
+                    chain_done = true;
+                    self.exec_block(statements, &new_env(Some(env.clone())))?
+                },
+                Statement::ElseIf { .. } | Statement::Else(_) => Flow::Normal,
+                statement => {
+                    chain_done = true;
+                    self.exec_statement(statement, env)?
+                },
+            };
+
+            match flow {
+                Flow::Normal => (),
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec_statement(&mut self, statement : &'a Statement, env : &Env<'a>) -> Result<Flow<'a>, Unwind<'a>> {
+        match statement {
+            Statement::Expr(e) => {
+                self.eval_expr(e, env)?;
+                Ok(Flow::Normal)
+            },
+            Statement::Return(None) => Ok(Flow::Return(Value::Unit)),
+            Statement::Return(Some(e)) => Ok(Flow::Return(self.eval_expr(e, env)?)),
+            // Generator bodies are run by `resume`, which takes their yields.
+            Statement::Yield(_) => error("yield outside of generator".to_string()),
+            Statement::Let { pattern, expr, .. } => {
+                let v = self.eval_expr(expr, env)?;
+                bind(env, pattern, v)?;
+                Ok(Flow::Normal)
+            },
+            Statement::Set { target, new_value } => {
+                let v = self.eval_expr(new_value, env)?;
+                self.set(target, v, env)?;
+                Ok(Flow::Normal)
+            },
+            Statement::Break { label, .. } => Ok(Flow::Break(label.as_ref().map(|l| l.value.as_str()))),
+            Statement::Continue { label, .. } => Ok(Flow::Continue(label.as_ref().map(|l| l.value.as_str()))),
+            Statement::While { label, test, statements } => {
+                while self.test(test, env)? {
+                    let flow = self.exec_block(statements, &new_env(Some(env.clone())))?;
+                    if let Some(flow) = after_body(label, flow) {
+                        return Ok(flow);
+                    }
+                }
+                Ok(Flow::Normal)
+            },
+            Statement::Loop { label, statements } => {
+                loop {
+                    let flow = self.exec_block(statements, &new_env(Some(env.clone())))?;
+                    if let Some(flow) = after_body(label, flow) {
+                        return Ok(flow);
+                    }
+                }
+            },
+            Statement::Foreach { label, var, value, items, statements } => {
+                let mut items = self.items(items, env)?;
+                while let Some(entry) = self.next_entry(&mut items)? {
+                    let scope = new_env(Some(env.clone()));
+                    bind_entry(&scope, var, value, entry)?;
+                    let flow = self.exec_block(statements, &scope)?;
+                    if let Some(flow) = after_body(label, flow) {
+                        return Ok(flow);
+                    }
+                }
+                Ok(Flow::Normal)
+            },
+            Statement::If { .. } | Statement::ElseIf { .. } | Statement::Else(_) => self.exec_block(std::slice::from_ref(statement), env),
+        }
+    }
+
+    fn items(&mut self, items : &'a Expr, env : &Env<'a>) -> Result<Items<'a>, Unwind<'a>> {
+        match self.eval_expr(items, env)? {
+            Value::List(vs) => Ok(Items::Entries { entries: numbered(&vs.borrow()).into_iter(), is_map: false }),
+            Value::Generator(generator) => Ok(Items::Generator { generator, index: 0 }),
+            Value::Map(entries) => Ok(Items::Entries { entries: entries.borrow().clone().into_iter(), is_map: true }),
+            v => error(format!("cannot iterate over {}", v.type_name())),
+        }
+    }
+
+    fn next_entry(&mut self, items : &mut Items<'a>) -> Result<Option<(Value<'a>, Value<'a>, bool)>, Unwind<'a>> {
+        match items {
+            Items::Entries { entries, is_map } => Ok(entries.next().map(|(k, v)| (k, v, *is_map))),
+            Items::Generator { generator, index } => match self.resume(generator)? {
+                Some(v) => {
+                    *index += 1;
+                    Ok(Some((Value::Number((*index - 1) as f64), v, false)))
+                },
+                None => Ok(None),
+            },
+        }
+    }
+
+    // Runs a generator up to its next yield, giving None once it has ended.
+    // A `?` that fails ends it as a return would.
+    fn resume(&mut self, generator : &Rc<RefCell<Generator<'a>>>) -> Result<Option<Value<'a>>, Unwind<'a>> {
+        let mut pending = match std::mem::replace(&mut *generator.borrow_mut(), Generator::Running) {
+            Generator::Items(mut items) => {
+                let item = items.next();
+                *generator.borrow_mut() = Generator::Items(items);
+                return Ok(item);
+            },
+            Generator::Suspended(pending) => pending,
+            Generator::Running => return error("generator resumed from inside itself".to_string()),
+            Generator::Done => {
+                *generator.borrow_mut() = Generator::Done;
+                return Ok(None);
+            },
+        };
+        let result = self.run_pending(&mut pending);
+        *generator.borrow_mut() = match result {
+            Ok(Some(_)) => Generator::Suspended(pending),
+            _ => Generator::Done,
+        };
+        match result {
+            Err(Unwind::Return(_)) => Ok(None),
+            result => result,
+        }
+    }
+
+    fn run_pending(&mut self, pending : &mut Vec<Pending<'a>>) -> Result<Option<Value<'a>>, Unwind<'a>> {
+        // How the innermost block or loop ended, for the one around it.
+        let mut ended = None;
+        loop {
+            if let Some(flow) = ended.take() {
+                let flow = match (pending.last(), flow) {
+                    (None, Flow::Break(_) | Flow::Continue(_)) => return error("break outside of loop".to_string()),
+                    (None, _) => return Ok(None),
+                    (Some(Pending::Block { .. }), Flow::Normal) => None,
+                    (Some(Pending::Block { .. }), flow) => Some(flow),
+                    (Some(Pending::Loop { label, .. }), flow) => after_body(label, flow),
+                };
+                if let Some(flow) = flow {
+                    pending.pop();
+                    ended = Some(flow);
+                }
+                continue;
+            }
+            let step = match pending.last_mut() {
+                None => return Ok(None),
+                Some(Pending::Block { statements, next, env, chain_done }) => match statements.get(*next) {
+                    Some(statement) => {
+                        *next += 1;
+                        // A `let` shadows as it does in `exec_block`.
+                        if let Statement::Let { pattern, .. } = statement {
+                            if pattern.names().iter().any(|n| defines(env, &n.value)) {
+                                *env = new_env(Some(env.clone()));
+                            }
+                        }
+                        let env = env.clone();
+                        self.step(statement, &env, chain_done)?
+                    },
+                    None => Step::End(Flow::Normal),
+                },
+                Some(Pending::Loop { kind, statements, env, .. }) => {
+                    let scope = new_env(Some(env.clone()));
+                    let again = match kind {
+                        LoopKind::While(test) => self.test(test, env)?,
+                        LoopKind::Loop => true,
+                        LoopKind::Foreach { var, value, items } => match self.next_entry(items)? {
+                            Some(entry) => {
+                                bind_entry(&scope, var, value, entry)?;
+                                true
+                            },
+                            None => false,
+                        },
+                    };
+                    if again { Step::Enter(block(statements, scope)) } else { Step::End(Flow::Normal) }
+                },
+            };
+            match step {
+                Step::Next => (),
+                Step::Enter(p) => pending.push(p),
+                // The block or loop ran out.
+                Step::End(Flow::Normal) => {
+                    pending.pop();
+                    ended = Some(Flow::Normal);
+                },
+                // A statement broke, continued or returned, which the
+                // block it's in passes on as it ends.
+                Step::End(flow) => ended = Some(flow),
+                Step::Yield(v) => return Ok(Some(v)),
+            }
+        }
+    }
+
+    // Runs a statement of a generator's body, leaving what has blocks of its
+    // own to be entered, as they may yield.
+    fn step(&mut self, statement : &'a Statement, env : &Env<'a>, chain_done : &mut bool) -> Result<Step<'a>, Unwind<'a>> {
+        let branch = match statement {
+            Statement::If { test, statements, .. } => {
+                *chain_done = self.test(test, env)?;
+                Some(statements)
+            },
+            Statement::ElseIf { test, statements, .. } if !*chain_done => {
+                *chain_done = self.test(test, env)?;
+                Some(statements)
+            },
+            Statement::Else(statements) if !*chain_done => {
+                *chain_done = true;
+                Some(statements)
+            },
+            Statement::ElseIf { .. } | Statement::Else(_) => return Ok(Step::Next),
+            _ => None,
+        };
+        if let Some(statements) = branch {
+            return Ok(if *chain_done { Step::Enter(block(statements, new_env(Some(env.clone())))) } else { Step::Next });
+        }
+        *chain_done = true;
+        let (label, kind, statements) = match statement {
+            Statement::Yield(e) => return Ok(Step::Yield(match e {
+                Some(e) => self.eval_expr(e, env)?,
+                None => Value::Unit,
+            })),
+            Statement::While { label, test, statements } => (label, LoopKind::While(test), statements),
+            Statement::Loop { label, statements } => (label, LoopKind::Loop, statements),
+            Statement::Foreach { label, var, value, items, statements } =>
+                (label, LoopKind::Foreach { var, value, items: self.items(items, env)? }, statements),
+            statement => return match self.exec_statement(statement, env)? {
+                Flow::Normal => Ok(Step::Next),
+                flow => Ok(Step::End(flow)),
+            },
+        };
+        Ok(Step::Enter(Pending::Loop { label, kind, statements, env: env.clone() }))
+    }
+
+    fn set(&mut self, target : &'a Expr, value : Value<'a>, env : &Env<'a>) -> Result<(), Unwind<'a>> {
+        match target {
+            Expr::Variable { name, .. } => {
+                if assign(env, &name.value, value) {
+                    Ok(())
+                }
+                else {
+                    error(format!("unknown variable {}", name.value))
+                }
+            },
+            Expr::Dot { object, slot } => {
+                match self.eval_expr(object, env)? {
+                    Value::Struct(s) => {
+                        let mut s = s.borrow_mut();
+                        match s.slots.iter_mut().find(|(name, _)| *name == slot.value) {
+                            Some((_, v)) => { *v = value; Ok(()) },
+                            None => error(format!("unknown slot {}", slot.value)),
+                        }
+                    },
+                    v => error(format!("cannot set slot {} on {}", slot.value, v.type_name())),
+                }
+            },
+            Expr::Index { object, index } => {
+                let object = self.eval_expr(object, env)?;
+                let index = self.eval_expr(index, env)?;
+                match object {
+                    Value::List(items) => {
+                        let i = list_index(&index)?;
+                        let mut items = items.borrow_mut();
+                        // Setting one past the end appends, as it does for a Lua table.
+                        if i < items.len() {
+                            items[i] = value;
+                        }
+                        else if i == items.len() {
+                            items.push(value);
+                        }
+                        else {
+                            return error(format!("index {} out of range", i));
+                        }
+                        Ok(())
+                    },
+                    Value::Map(entries) => {
+                        map_insert(&mut entries.borrow_mut(), index, value);
+                        Ok(())
+                    },
+                    v => error(format!("cannot index {}", v.type_name())),
+                }
+            },
+            _ => error("invalid set target".to_string()),
+        }
+    }
+
+    fn test(&mut self, test : &'a Expr, env : &Env<'a>) -> Result<bool, Unwind<'a>> {
+        match self.eval_expr(test, env)? {
+            Value::Bool(b) => Ok(b),
+            v => error(format!("expected bool but found {}", v.type_name())),
+        }
+    }
+
+    fn number(&mut self, expr : &'a Expr, env : &Env<'a>) -> Result<f64, Unwind<'a>> {
+        match self.eval_expr(expr, env)? {
+            Value::Number(n) => Ok(n),
+            v => error(format!("expected number but found {}", v.type_name())),
+        }
+    }
+
+    fn eval_expr(&mut self, expr : &'a Expr, env : &Env<'a>) -> Result<Value<'a>, Unwind<'a>> {
+        match expr {
+            Expr::Number(n) => match n.value.parse::<f64>() {
+                Ok(n) => Ok(Value::Number(n)),
+                Err(_) => error(format!("invalid number {}", n.value)),
+            },
+            Expr::PString(s) => Ok(Value::String(s.value.clone())),
+            Expr::Interpolate(parts) => {
+                let mut s = String::new();
+                for part in parts {
+                    match part {
+                        StringPart::Text(t) => s.push_str(&t.value),
+                        StringPart::Expr(e) => s.push_str(&self.eval_expr(e, env)?.to_string()),
+                    }
+                }
+                Ok(Value::String(s))
+            },
+            Expr::Bool(b) => Ok(Value::Bool(*b)),
+            Expr::Variable { namespace, name } => {
+                if let Some(last) = namespace.last() {
+                    if let Some(def) = self.enums.get(&last.value) {
+                        if def.items.iter().any(|item| item.value == name.value) {
+                            return Ok(Value::Enum { name: last.value.clone(), case: name.value.clone() });
+                        }
+                    }
+                }
+                match lookup(env, &name.value) {
+                    Some(v) => Ok(v),
+                    None => error(format!("unknown variable {}", name.value)),
+                }
+            },
+            Expr::StatementLambda { params, definition, .. } => {
+                let closure = Closure { params
+                                      , body: Body::Statements(definition)
+                                      , env: env.clone()
+                                      , generator: contains_yield(definition)
+                                      };
+                Ok(Value::Closure(Rc::new(closure)))
+            },
+            Expr::ExprLambda { params, definition, .. } => {
+                let closure = Closure { params
+                                      , body: Body::Expr(definition)
+                                      , env: env.clone()
+                                      , generator: false
+                                      };
+                Ok(Value::Closure(Rc::new(closure)))
+            },
+            Expr::Call { func, params, named } => {
+                let (func, mut args) = match &**func {
+                    // a-f(b) calls f(a, b)
+                    Expr::Dash { object, func } => {
+                        let object = self.eval_expr(object, env)?;
+                        (self.lookup_func(func, env)?, vec![object])
+                    },
+                    Expr::Dot { object, slot } => {
+                        let object = self.eval_expr(object, env)?;
+                        match self.method(&object, &slot.value) {
+                            Some(method) => (method, vec![object]),
+                            None => (self.slot(object, slot)?, vec![]),
+                        }
+                    },
+                    func => (self.eval_expr(func, env)?, vec![]),
+                };
+                let positional = args.len() + params.len();
+                let fixed = |c : &Closure| c.params.iter().filter(|p| !p.variadic).count();
+                match func {
+                    // Arguments are evaluated in parameter order, as they
+                    // are once lowered to Lua.
+                    Value::Closure(c) if !named.is_empty() || fixed(&c) > positional => {
+                        let signature = c.params.iter().filter(|p| !p.variadic).map(|p| (p.name.value.as_str(), p.default.is_some())).collect::<Vec<_>>();
+                        let extra = if fixed(&c) < c.params.len() { positional.saturating_sub(signature.len()) } else { 0 };
+                        let slots = match arrange(&signature, positional - extra, named, expr.start()) {
+                            Ok(slots) => slots,
+                            Err(e) => return error(e.message()),
+                        };
+                        let prefix = args.len();
+                        let exprs = params.iter().chain(named.iter().map(|n| &n.value)).collect::<Vec<&Expr>>();
+                        let mut arranged = vec![];
+                        for slot in slots {
+                            match slot {
+                                Some(i) if i < prefix => arranged.push(Some(args[i].clone())),
+                                Some(i) => arranged.push(Some(self.eval_expr(exprs[i - prefix], env)?)),
+                                None => arranged.push(None),
+                            }
+                        }
+                        let rest = &params[positional - extra - prefix..];
+                        arranged.extend(self.eval_items(rest, env)?.into_iter().map(Some));
+                        self.call_closure(c, arranged)
+                    },
+                    _ if !named.is_empty() => error("only functions declared with fun take named arguments".to_string()),
+                    func => {
+                        args.extend(self.eval_items(params, env)?);
+                        self.apply(func, args)
+                    },
+                }
+            },
+            Expr::Dash { object, func } => {
+                let object = self.eval_expr(object, env)?;
+                let func = self.lookup_func(func, env)?;
+                self.apply(func, vec![object])
+            },
+            Expr::Try(e) => match self.eval_expr(e, env)? {
+                Value::Okay(v) | Value::Some(v) => Ok(*v),
+                Value::Error(v) => Err(Unwind::Return(Value::Error(v))),
+                Value::None => Err(Unwind::Return(Value::None)),
+                v => error(format!("expected result but found {}", v.type_name())),
+            },
+            Expr::Index { object, index } => {
+                let object = self.eval_expr(object, env)?;
+                let index = self.eval_expr(index, env)?;
+                let item = match &object {
+                    Value::List(items) => items.borrow().get(list_index(&index)?).cloned(),
+                    Value::Map(entries) => map_get(&entries.borrow(), &index),
+                    v => return error(format!("cannot index {}", v.type_name())),
+                };
+                match item {
+                    Some(v) => Ok(v),
+                    None if matches!(object, Value::Map(_)) => error(format!("missing key {}", index)),
+                    None => error(format!("index {} out of range", index)),
+                }
+            },
+            Expr::Dot { object, slot } => {
+                let object = self.eval_expr(object, env)?;
+                self.slot(object, slot)
+            },
+            Expr::StructCons { name, slots, base } => {
+                let base = match base {
+                    Some(b) => match self.eval_expr(b, env)? {
+                        Value::Struct(s) => s.borrow().slots.clone(),
+                        v => return error(format!("cannot update {}", v.type_name())),
+                    },
+                    None => vec![],
+                };
+                let mut values = vec![];
+                for slot in slots {
+                    values.push((slot.name.value.clone(), self.eval_expr(&slot.value, env)?));
+                }
+                for (n, v) in base {
+                    if !values.iter().any(|(given, _)| *given == n) {
+                        values.push((n, v));
+                    }
+                }
+                let def = name.as_ref().and_then(|n| self.structs.get(&n.value)).cloned();
+                if let Some(def) = def {
+                    values = self.struct_slots(def, values)?;
+                }
+                let name = name.as_ref().map(|n| n.value.clone());
+                Ok(Value::Struct(Rc::new(RefCell::new(StructValue { name, slots: values }))))
+            },
+            Expr::ListCons(es) => {
+                let values = self.eval_items(es, env)?;
+                Ok(Value::List(Rc::new(RefCell::new(values))))
+            },
+            Expr::Spread(_) => error("...spread is only allowed in calls and lists".to_string()),
+            Expr::Tuple(es) => {
+                let mut values = vec![];
+                for e in es {
+                    values.push(self.eval_expr(e, env)?);
+                }
+                Ok(Value::Tuple(Rc::new(values)))
+            },
+            Expr::Range { start, end, inclusive, step } => {
+                let first = self.number(start, env)?;
+                let last = self.number(end, env)?;
+                let step = match step {
+                    Some(step) => self.number(step, env)?,
+                    None => 1.0,
+                };
+                if step == 0.0 {
+                    return error("range step cannot be zero".to_string());
+                }
+                let mut values = vec![];
+                let mut i = first;
+                while (step > 0.0 && i < last) || (step < 0.0 && i > last) || (*inclusive && i == last) {
+                    values.push(Value::Number(i));
+                    i += step;
+                }
+                Ok(Value::List(Rc::new(RefCell::new(values))))
+            },
+            Expr::MapCons(entries) => {
+                let mut values = vec![];
+                for entry in entries {
+                    let key = self.eval_expr(&entry.key, env)?;
+                    let value = self.eval_expr(&entry.value, env)?;
+                    map_insert(&mut values, key, value);
+                }
+                Ok(Value::Map(Rc::new(RefCell::new(values))))
+            },
+            Expr::ResultCons(ResultValue::Okay(e)) => Ok(Value::Okay(Box::new(self.eval_expr(e, env)?))),
+            Expr::ResultCons(ResultValue::Error(e)) => Ok(Value::Error(Box::new(self.eval_expr(e, env)?))),
+            Expr::OptionCons(OptionValue::Some(e)) => Ok(Value::Some(Box::new(self.eval_expr(e, env)?))),
+            Expr::OptionCons(OptionValue::None) => Ok(Value::None),
+        }
+    }
+
+    // Puts the given slots in declaration order and fills in defaults,
+    // which are evaluated in the global scope.
+    // Evaluates call arguments or list items, splicing in spread lists.
+    fn eval_items(&mut self, es : &'a [Expr], env : &Env<'a>) -> Result<Vec<Value<'a>>, Unwind<'a>> {
+        let mut values = vec![];
+        for e in es {
+            match e {
+                Expr::Spread(e) => match self.eval_expr(e, env)? {
+                    Value::List(items) => values.extend(items.borrow().iter().cloned()),
+                    v => return error(format!("cannot spread {}", v.type_name())),
+                },
+                e => values.push(self.eval_expr(e, env)?),
+            }
+        }
+        Ok(values)
+    }
+
+    fn struct_slots(&mut self, def : &'a StructDef, mut given : Vec<(String, Value<'a>)>) -> Result<Vec<(String, Value<'a>)>, Unwind<'a>> {
+        let mut slots = vec![];
+        for item in def.items.iter() {
+            let value = match given.iter().position(|(n, _)| *n == item.name.value) {
+                Some(i) => given.swap_remove(i).1,
+                None => match &item.default {
+                    Some(e) => {
+                        let globals = self.globals.clone();
+                        self.eval_expr(e, &globals)?
+                    },
+                    None => return error(format!("missing slot {}", item.name.value)),
+                },
+            };
+            slots.push((item.name.value.clone(), value));
+        }
+        Ok(slots)
+    }
+
+    fn slot(&self, object : Value<'a>, slot : &PSym) -> Result<Value<'a>, Unwind<'a>> {
+        match object {
+            Value::Struct(s) => {
+                let s = s.borrow();
+                match s.slots.iter().find(|(name, _)| *name == slot.value) {
+                    Some((_, v)) => Ok(v.clone()),
+                    None => error(format!("unknown slot {}", slot.value)),
+                }
+            },
+            v => error(format!("cannot access slot {} on {}", slot.value, v.type_name())),
+        }
+    }
+
+    // Slots shadow methods, as they do for Lua's __index.
+    fn method(&self, object : &Value<'a>, name : &str) -> Option<Value<'a>> {
+        let s = match object {
+            Value::Struct(s) => s.borrow(),
+            _ => return None,
+        };
+        if s.slots.iter().any(|(n, _)| n == name) {
+            return None;
+        }
+        self.methods.get(&(s.name.clone()?, name.to_string())).cloned()
+    }
+
+    fn lookup_func(&self, func : &PSym, env : &Env<'a>) -> Result<Value<'a>, Unwind<'a>> {
+        match lookup(env, &func.value) {
+            Some(f) => Ok(f),
+            None => error(format!("unknown function {}", func.value)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::proc::parser::parse;
+
+    fn run(input : &str) -> String {
+        let module = parse(input).expect("parse failed");
+        let mut interpreter = Interpreter::new();
+        interpreter.load(&module).expect("load failed");
+        match interpreter.call("main", vec![]) {
+            Ok(v) => format!("{}", v),
+            Err(e) => panic!("runtime error: {}", e.message),
+        }
+    }
+
+    #[test]
+    fn should_call_functions() {
+        let output = run(r#"
+fun double(x : number) -> number {
+    return add(x, x);
+}
+
+fun main() -> number {
+    return double(21);
+}"#);
+        assert_eq!( output, "42" );
+    }
+
+    #[test]
+    fn should_run_if_chain() {
+        let output = run(r#"
+fun pick(x : number) -> string {
+    if lt(x, 0) {
+        return "negative";
+    }
+    elseif eq(x, 0) {
+        return "zero";
+    }
+    else {
+        return "positive";
+    }
+}
+
+fun main() -> List<string> {
+    return [pick(-1), pick(0), pick(1)];
+}"#);
+        assert_eq!( output, r#"["negative", "zero", "positive"]"# );
+    }
+
+    #[test]
+    fn should_run_while_with_set_and_break() {
+        let output = run(r#"
+fun main() -> number {
+    let x = 0;
+    while true {
+        if eq(x, 5) {
+            break;
+        }
+        set x = add(x, 1);
+    }
+    return x;
+}"#);
+        assert_eq!( output, "5" );
+    }
+
+    #[test]
+    fn should_iterate_generator() {
+        let output = run(r#"
+fun numbers() -> number {
+    yield 1;
+    yield 2;
+    yield 3;
+}
+
+fun main() -> number {
+    let total = 0;
+    foreach n in numbers() {
+        set total = add(total, n);
+    }
+    return total;
+}"#);
+        assert_eq!( output, "6" );
+    }
+
+    #[test]
+    fn should_run_generators_lazily() {
+        let output = run(r#"
+fun naturals(log : List<string>) -> number {
+    let n = 0;
+    loop {
+        push(log, "yield {n}");
+        yield n;
+        set n = add(n, 1);
+    }
+}
+
+fun main() -> List<string> {
+    let log = [];
+    foreach n in take(gen_map(naturals(log), |x| mul(x, 10)), 3) {
+        push(log, "got {n}");
+    }
+    foreach n in to_list(take(naturals([]), 2)) {
+        push(log, "listed {n}");
+    }
+    return log;
+}"#);
+        assert_eq!( output, r#"["yield 0", "got 0", "yield 1", "got 10", "yield 2", "got 20", "listed 0", "listed 1"]"# );
+    }
+
+    #[test]
+    fn should_end_generators_on_return_and_break() {
+        let output = run(r#"
+fun upto(n : number) -> number {
+    let i = 0;
+    while true {
+        if gt(i, n) {
+            return;
+        }
+        foreach j in [i] {
+            yield j;
+            break;
+            yield -1;
+        }
+        set i = add(i, 1);
+    }
+}
+
+fun main() -> List<number> {
+    return to_list(upto(3));
+}"#);
+        assert_eq!( output, "[0, 1, 2, 3]" );
+    }
+
+    #[test]
+    fn should_capture_in_closures() {
+        let output = run(r#"
+fun adder(x : number) -> fun(number) -> number {
+    return |y| add(x, y);
+}
+
+fun main() -> number {
+    return adder(1)(2);
+}"#);
+        assert_eq!( output, "3" );
+    }
+
+    #[test]
+    fn should_propagate_error_with_try() {
+        let output = run(r#"
+fun fail() -> Result<number, string> {
+    return Err("bad");
+}
+
+fun main() -> Result<number, string> {
+    let x = fail()?;
+    return Ok(add(x, 1));
+}"#);
+        assert_eq!( output, r#"Err("bad")"# );
+    }
+
+    #[test]
+    fn should_dash_call_with_object_as_first_arg() {
+        let output = run(r#"
+fun main() -> number {
+    let s = new Point { x: 1, y: 2 };
+    set s.x = 10;
+    return s.x-add(s.y);
+}"#);
+        assert_eq!( output, "12" );
+    }
+
+    #[test]
+    fn should_evaluate_enum_case() {
+        let output = run(r#"
+enum Color { Red, Green }
+
+fun main() -> bool {
+    return eq(Color::Green, Color::Green);
+}"#);
+        assert_eq!( output, "true" );
+    }
+
+    #[test]
+    fn should_index_from_zero_and_append_at_end() {
+        let output = run(r#"
+fun main() -> List<number> {
+    let xs = [1, 2];
+    set xs[0] = add(xs[1], 10);
+    set xs[len(xs)] = 3;
+    return xs;
+}"#);
+        assert_eq!( output, "[12, 2, 3]" );
+    }
+
+    #[test]
+    fn should_build_and_iterate_map() {
+        let output = run(r#"
+fun main() -> List<string> {
+    let m = #{ "a": 1, "b": 2 };
+    set m["a"] = 10;
+    set m["c"] = 3;
+    let out = [];
+    foreach k, v in m {
+        push(out, concat(k, v));
+    }
+    foreach i, k in keys(m) {
+        push(out, concat(i, k));
+    }
+    return out;
+}"#);
+        assert_eq!( output, r#"["a10", "b2", "c3", "0a", "1b", "2c"]"# );
+    }
+
+    #[test]
+    fn should_destructure_tuples() {
+        let output = run(r#"
+fun div_mod(a : number, b : number) -> (number, number) {
+    return (div(sub(a, mod(a, b)), b), mod(a, b));
+}
+
+fun main() -> List<number> {
+    let (q, r) = div_mod(17, 5);
+    let out = [q, r];
+    foreach (a, (b, c)) in [(1, (2, 3))] {
+        push(out, add(a, add(b, c)));
+    }
+    let f = |(x, y)| mul(x, y);
+    push(out, f((6, 7)));
+    return out;
+}"#);
+        assert_eq!( output, "[3, 2, 6, 42]" );
+    }
+
+    #[test]
+    fn should_interpolate_strings() {
+        let output = run(r#"
+fun main() -> string {
+    let name = "ann";
+    let items = [1, 2, 3];
+    return "{name} has {len(items)} items, {{ok}} is {eq(name, \"ann\")}";
+}"#);
+        assert_eq!( output, "ann has 3 items, {ok} is true" );
+    }
+
+    #[test]
+    fn should_continue_and_break_labeled_loops() {
+        let output = run(r#"
+fun main() -> List<number> {
+    let out = [];
+    let i = 0;
+    'outer: loop {
+        set i = add(i, 1);
+        if gt(i, 4) {
+            break;
+        }
+        foreach x in [1, 2, 3] {
+            if eq(x, 2) {
+                continue;
+            }
+            if eq(i, 2) {
+                continue 'outer;
+            }
+            if eq(i, 4) {
+                break 'outer;
+            }
+            push(out, add(mul(i, 10), x));
+        }
+    }
+    return out;
+}"#);
+        assert_eq!( output, "[11, 13, 31, 33]" );
+    }
+
+    #[test]
+    fn should_iterate_ranges() {
+        let output = run(r#"
+fun main() -> List<number> {
+    let out = [];
+    foreach i in 0..3 {
+        push(out, i);
+    }
+    foreach i in 10..=4 by -3 {
+        push(out, i);
+    }
+    push(out, len(0.5..3));
+    return out;
+}"#);
+        assert_eq!( output, "[0, 1, 2, 10, 7, 4, 3]" );
+    }
+
+    #[test]
+    fn should_evaluate_consts_at_load() {
+        let output = run(r#"
+const MINUTE : number = 60;
+const HOUR : number = mul(MINUTE, 60);
+fun main() -> number {
+    return HOUR;
+}"#);
+        assert_eq!( output, "3600" );
+    }
+
+    #[test]
+    fn should_dispatch_interface_methods_on_first_argument() {
+        let output = run(r#"
+interface Show {
+    fun show(self) -> string;
+}
+struct Point { x : number, y : number }
+impl Show for Point {
+    fun show(self) -> string {
+        return "({self.x}, {self.y})";
+    }
+}
+impl Show for number {
+    fun show(self) -> string {
+        return "n{self}";
+    }
+}
+fun main() -> List<string> {
+    return [show(new Point { x: 1, y: 2 }), show(3)];
+}"#);
+        assert_eq!( output, r#"["(1, 2)", "n3"]"# );
+    }
+
+    #[test]
+    fn should_call_methods_unless_a_slot_shadows_them() {
+        let output = run(r#"
+struct Counter { count : number, step : fun(number) -> number }
+impl Counter {
+    fun bump(self) -> Counter {
+        set self.count = add(self.count, 1);
+        return self;
+    }
+}
+fun main() -> List<number> {
+    let c = new Counter { count: 0, step: |n| add(n, 10) };
+    c.bump().bump();
+    return [c.count, c.step(1)];
+}"#);
+        assert_eq!( output, "[2, 11]" );
+    }
+
+    #[test]
+    fn should_fill_defaults_and_copy_base() {
+        let output = run(r#"
+struct Config { name : string, retries : number = mul(2, 3), tags : List<string> = [] }
+fun main() -> List<Config> {
+    let a = new Config { name: "a" };
+    let b = new Config { retries: 1, ..a };
+    let c = new Config { tags: ["x"], name: "c" };
+    push(new Config { name: "d" }.tags, "y");
+    return [a, b, c, new Config { name: "e" }];
+}"#);
+        assert_eq!( output, r#"[Config { name: "a", retries: 6, tags: [] }, Config { name: "a", retries: 1, tags: [] }, Config { name: "c", retries: 6, tags: ["x"] }, Config { name: "e", retries: 6, tags: [] }]"# );
+    }
+
+    #[test]
+    fn should_pass_named_and_default_arguments() {
+        let output = run(r#"
+fun connect(host : string, port : number = 80, timeout : number = mul(port, 2)) -> string {
+    return "{host}:{port}/{timeout}";
+}
+fun main() -> List<string> {
+    return [connect("a"), connect("b", 1), connect(timeout: 3, host: "c"), "d"-connect(port: 2)];
+}"#);
+        assert_eq!( output, r#"["a:80/160", "b:1/2", "c:80/3", "d:2/4"]"# );
+    }
+
+    #[test]
+    fn should_collect_variadic_args_and_spread_lists() {
+        let output = run(r#"
+fun join(sep : string = ",", ...parts : List<string>) -> string {
+    let out = "";
+    foreach p in parts {
+        set out = concat(out, sep, p);
+    }
+    return out;
+}
+fun main() -> List<string> {
+    let ws = ["a", "b"];
+    let count = |...xs| len(xs);
+    return [join(), join("-", "x", ...ws, "y"), "{count(...ws, ...ws)}", "{len([0, ...ws, 1])}"];
+}"#);
+        assert_eq!( output, r#"["", "-x-a-b-y", "4", "4"]"# );
+    }
+
+    #[test]
+    fn should_propagate_none_with_try() {
+        let output = run(r#"
+fun half(n : number) -> Option<number> {
+    if eq(mod(n, 2), 0) {
+        return Some(div(n, 2));
+    }
+    return None;
+}
+fun quarter(n : number) -> Option<number> {
+    let h = half(n)?;
+    return half(h);
+}
+fun main() -> List<number> {
+    return [value_or(quarter(8), -1), value_or(quarter(6), -1), value_or(quarter(3), -1)];
+}"#);
+        assert_eq!( output, "[2, -1, -1]" );
+    }
+
+    #[test]
+    fn should_keep_captured_variables_when_shadowed() {
+        let output = run(r#"
+fun main() -> List<number> {
+    let x = 1;
+    let f = || x;
+    let x = 2;
+    set x = 3;
+    let fs = [];
+    foreach i in [4, 5] {
+        push(fs, || i);
+    }
+    return [f(), x, fs[0](), fs[1]()];
+}"#);
+        assert_eq!( output, "[1, 3, 4, 5]" );
+    }
+}
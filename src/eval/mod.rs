@@ -0,0 +1,4 @@
+
+pub mod value;
+pub mod interpreter;
+pub mod builtins;
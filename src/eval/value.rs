@@ -0,0 +1,282 @@
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+use parse_input::PSym;
+
+use crate::parsing::proc::ast::*;
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub message : String,
+}
+
+pub type BuiltinFn = for<'a> fn(Vec<Value<'a>>) -> Result<Value<'a>, RuntimeError>;
+
+pub type Env<'a> = Rc<RefCell<Frame<'a>>>;
+
+pub type ListRef<'a> = Rc<RefCell<Vec<Value<'a>>>>;
+
+// Entries in insertion order; keys are compared with `equals`.
+pub type MapRef<'a> = Rc<RefCell<Vec<(Value<'a>, Value<'a>)>>>;
+
+pub struct Frame<'a> {
+    vars : HashMap<String, Value<'a>>,
+    parent : Option<Env<'a>>,
+}
+
+#[derive(Clone)]
+pub enum Value<'a> {
+    Unit,
+    Number(f64),
+    String(String),
+    Bool(bool),
+    List(ListRef<'a>),
+    Map(MapRef<'a>),
+    Tuple(Rc<Vec<Value<'a>>>),
+    Struct(Rc<RefCell<StructValue<'a>>>),
+    Enum { name : String, case : String },
+    Okay(Box<Value<'a>>),
+    Error(Box<Value<'a>>),
+    Some(Box<Value<'a>>),
+    None,
+    Closure(Rc<Closure<'a>>),
+    Generator(Rc<RefCell<Generator<'a>>>),
+    Builtin { name : &'static str, func : BuiltinFn },
+    // Lua function declared with `extern fun`; only callable from compiled code.
+    Extern { path : &'a str },
+    // Interface method; dispatches on the type of its first argument.
+    Method { interface : &'a str, name : &'a str },
+}
+
+pub struct StructValue<'a> {
+    pub name : Option<String>,
+    pub slots : Vec<(String, Value<'a>)>,
+}
+
+pub enum Body<'a> {
+    Statements(&'a [Statement]),
+    Expr(&'a Expr),
+}
+
+pub struct Closure<'a> {
+    pub params : &'a [FunParam],
+    pub body : Body<'a>,
+    pub env : Env<'a>,
+    pub generator : bool,
+}
+
+// A generator's body runs up to its next yield each time a value is asked
+// for, as a coroutine does in Lua, and is suspended in between.
+pub enum Generator<'a> {
+    // Made from a list by `iter`.
+    Items(std::vec::IntoIter<Value<'a>>),
+    // The blocks and loops the body is in the middle of, innermost last.
+    Suspended(Vec<Pending<'a>>),
+    Running,
+    Done,
+}
+
+pub enum Pending<'a> {
+    // The rest of a block from `next` on. `chain_done` is whether its
+    // current if/elseif/else chain has already run a branch.
+    Block { statements : &'a [Statement], next : usize, env : Env<'a>, chain_done : bool },
+    // A loop about to start its next iteration.
+    Loop { label : &'a Option<PSym>, kind : LoopKind<'a>, statements : &'a [Statement], env : Env<'a> },
+}
+
+pub enum LoopKind<'a> {
+    While(&'a Expr),
+    Loop,
+    Foreach { var : &'a Pattern, value : &'a Option<Pattern>, items : Items<'a> },
+}
+
+// What a foreach has left to go over.
+pub enum Items<'a> {
+    Entries { entries : std::vec::IntoIter<(Value<'a>, Value<'a>)>, is_map : bool },
+    Generator { generator : Rc<RefCell<Generator<'a>>>, index : usize },
+}
+
+pub fn new_env<'a>(parent : Option<Env<'a>>) -> Env<'a> {
+    Rc::new(RefCell::new(Frame { vars: HashMap::new(), parent }))
+}
+
+pub fn define<'a>(env : &Env<'a>, name : &str, value : Value<'a>) {
+    env.borrow_mut().vars.insert(name.to_string(), value);
+}
+
+// Whether `name` is in this frame itself, leaving out its parents.
+pub fn defines(env : &Env, name : &str) -> bool {
+    env.borrow().vars.contains_key(name)
+}
+
+pub fn lookup<'a>(env : &Env<'a>, name : &str) -> Option<Value<'a>> {
+    let frame = env.borrow();
+    match frame.vars.get(name) {
+        Some(v) => Some(v.clone()),
+        None => match &frame.parent {
+            Some(p) => lookup(p, name),
+            None => None,
+        },
+    }
+}
+
+pub fn assign<'a>(env : &Env<'a>, name : &str, value : Value<'a>) -> bool {
+    let mut frame = env.borrow_mut();
+    match frame.vars.get_mut(name) {
+        Some(v) => { *v = value; true },
+        None => match &frame.parent {
+            Some(p) => assign(p, name, value),
+            None => false,
+        },
+    }
+}
+
+pub fn map_get<'a>(entries : &[(Value<'a>, Value<'a>)], key : &Value<'a>) -> Option<Value<'a>> {
+    entries.iter().find(|(k, _)| k.equals(key)).map(|(_, v)| v.clone())
+}
+
+pub fn map_insert<'a>(entries : &mut Vec<(Value<'a>, Value<'a>)>, key : Value<'a>, value : Value<'a>) {
+    match entries.iter_mut().find(|(k, _)| k.equals(&key)) {
+        Some((_, v)) => *v = value,
+        None => entries.push((key, value)),
+    }
+}
+
+impl<'a> Value<'a> {
+    pub fn equals(&self, other : &Value<'a>) -> bool {
+        match (self, other) {
+            (Value::Unit, Value::Unit) => true,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Enum { name: n1, case: c1 }, Value::Enum { name: n2, case: c2 }) => n1 == n2 && c1 == c2,
+            (Value::Okay(a), Value::Okay(b)) => a.equals(b),
+            (Value::Error(a), Value::Error(b)) => a.equals(b),
+            (Value::Some(a), Value::Some(b)) => a.equals(b),
+            (Value::None, Value::None) => true,
+            (Value::List(a), Value::List(b)) => {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| x.equals(y))
+            },
+            (Value::Tuple(a), Value::Tuple(b)) => a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| x.equals(y)),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::Struct(a), Value::Struct(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Generator(a), Value::Generator(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Unit => "unit",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Tuple(_) => "tuple",
+            Value::Struct(_) => "struct",
+            Value::Enum { .. } => "enum",
+            Value::Okay(_) | Value::Error(_) => "result",
+            Value::Some(_) | Value::None => "option",
+            Value::Closure(_) | Value::Builtin { .. } | Value::Extern { .. } | Value::Method { .. } => "function",
+            Value::Generator(_) => "generator",
+        }
+    }
+}
+
+fn fmt_number(n : f64, f : &mut fmt::Formatter) -> fmt::Result {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        write!(f, "{}", n as i64)
+    }
+    else {
+        write!(f, "{}", n)
+    }
+}
+
+impl<'a> fmt::Display for Value<'a> {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        fn items<'a>(f : &mut fmt::Formatter, vs : &[Value<'a>]) -> fmt::Result {
+            for (i, v) in vs.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                match v {
+                    Value::String(s) => write!(f, "{:?}", s)?,
+                    v => write!(f, "{}", v)?,
+                }
+            }
+            Ok(())
+        }
+
+        match self {
+            Value::Unit => write!(f, "()"),
+            Value::Number(n) => fmt_number(*n, f),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::List(vs) => {
+                write!(f, "[")?;
+                items(f, &vs.borrow())?;
+                write!(f, "]")
+            },
+            Value::Tuple(vs) => {
+                write!(f, "(")?;
+                items(f, vs)?;
+                write!(f, ")")
+            },
+            Value::Map(m) => {
+                write!(f, "#{{")?;
+                for (i, (k, v)) in m.borrow().iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    items(f, std::slice::from_ref(k))?;
+                    write!(f, ": ")?;
+                    items(f, std::slice::from_ref(v))?;
+                }
+                write!(f, "}}")
+            },
+            Value::Struct(s) => {
+                let s = s.borrow();
+                match &s.name {
+                    Some(name) => write!(f, "{} {{ ", name)?,
+                    None => write!(f, "new {{ ")?,
+                }
+                for (i, (name, v)) in s.slots.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", name)?;
+                    items(f, std::slice::from_ref(v))?;
+                }
+                write!(f, " }}")
+            },
+            Value::Enum { name, case } => write!(f, "{}::{}", name, case),
+            Value::Okay(v) => {
+                write!(f, "Ok(")?;
+                items(f, std::slice::from_ref(&**v))?;
+                write!(f, ")")
+            },
+            Value::Error(v) => {
+                write!(f, "Err(")?;
+                items(f, std::slice::from_ref(&**v))?;
+                write!(f, ")")
+            },
+            Value::Some(v) => {
+                write!(f, "Some(")?;
+                items(f, std::slice::from_ref(&**v))?;
+                write!(f, ")")
+            },
+            Value::None => write!(f, "None"),
+            Value::Closure(_) => write!(f, "<fun>"),
+            Value::Builtin { name, .. } => write!(f, "<builtin {}>", name),
+            Value::Extern { path } => write!(f, "<extern {}>", path),
+            Value::Method { interface, name } => write!(f, "<method {}.{}>", interface, name),
+            Value::Generator(_) => write!(f, "<generator>"),
+        }
+    }
+}
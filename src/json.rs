@@ -0,0 +1,239 @@
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn get(&self, key : &str) -> Option<&Json> {
+        match self {
+            Json::Object(items) => items.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(n) if *n >= 0.0 => Some(*n as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+fn write_string(f : &mut fmt::Formatter, s : &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(items) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+pub fn parse(s : &str) -> Result<Json, String> {
+    let cs = s.chars().collect::<Vec<char>>();
+    let mut index = 0;
+    let value = parse_value(&cs, &mut index)?;
+    skip_ws(&cs, &mut index);
+    if index != cs.len() {
+        return Err(format!("unexpected trailing input at {}", index));
+    }
+    Ok(value)
+}
+
+fn skip_ws(cs : &[char], index : &mut usize) {
+    while *index < cs.len() && cs[*index].is_whitespace() {
+        *index += 1;
+    }
+}
+
+fn expect(cs : &[char], index : &mut usize, c : char) -> Result<(), String> {
+    skip_ws(cs, index);
+    if *index < cs.len() && cs[*index] == c {
+        *index += 1;
+        Ok(())
+    }
+    else {
+        Err(format!("expected {} at {}", c, index))
+    }
+}
+
+fn parse_value(cs : &[char], index : &mut usize) -> Result<Json, String> {
+    skip_ws(cs, index);
+    match cs.get(*index) {
+        Some('{') => {
+            *index += 1;
+            let mut items = vec![];
+            skip_ws(cs, index);
+            if cs.get(*index) == Some(&'}') {
+                *index += 1;
+                return Ok(Json::Object(items));
+            }
+            loop {
+                skip_ws(cs, index);
+                let key = parse_string(cs, index)?;
+                expect(cs, index, ':')?;
+                items.push((key, parse_value(cs, index)?));
+                skip_ws(cs, index);
+                match cs.get(*index) {
+                    Some(',') => *index += 1,
+                    Some('}') => { *index += 1; return Ok(Json::Object(items)); },
+                    _ => return Err(format!("expected , or }} at {}", index)),
+                }
+            }
+        },
+        Some('[') => {
+            *index += 1;
+            let mut items = vec![];
+            skip_ws(cs, index);
+            if cs.get(*index) == Some(&']') {
+                *index += 1;
+                return Ok(Json::Array(items));
+            }
+            loop {
+                items.push(parse_value(cs, index)?);
+                skip_ws(cs, index);
+                match cs.get(*index) {
+                    Some(',') => *index += 1,
+                    Some(']') => { *index += 1; return Ok(Json::Array(items)); },
+                    _ => return Err(format!("expected , or ] at {}", index)),
+                }
+            }
+        },
+        Some('"') => Ok(Json::String(parse_string(cs, index)?)),
+        Some(c) if *c == '-' || c.is_ascii_digit() => {
+            let start = *index;
+            while *index < cs.len() && (cs[*index].is_ascii_digit() || "+-.eE".contains(cs[*index])) {
+                *index += 1;
+            }
+            let n = cs[start..*index].iter().collect::<String>();
+            n.parse::<f64>().map(Json::Number).map_err(|_| format!("invalid number {}", n))
+        },
+        _ => {
+            for (word, value) in [("true", Json::Bool(true)), ("false", Json::Bool(false)), ("null", Json::Null)] {
+                if cs[*index..].starts_with(&word.chars().collect::<Vec<char>>()) {
+                    *index += word.len();
+                    return Ok(value);
+                }
+            }
+            Err(format!("unexpected input at {}", index))
+        },
+    }
+}
+
+fn parse_string(cs : &[char], index : &mut usize) -> Result<String, String> {
+    if cs.get(*index) != Some(&'"') {
+        return Err(format!("expected string at {}", index));
+    }
+    *index += 1;
+    let mut ret = String::new();
+    while *index < cs.len() {
+        let c = cs[*index];
+        *index += 1;
+        match c {
+            '"' => return Ok(ret),
+            '\\' => {
+                let e = cs.get(*index).ok_or("unterminated string")?;
+                *index += 1;
+                match e {
+                    'n' => ret.push('\n'),
+                    'r' => ret.push('\r'),
+                    't' => ret.push('\t'),
+                    'b' => ret.push('\u{8}'),
+                    'f' => ret.push('\u{c}'),
+                    'u' => {
+                        let hex = cs.get(*index..*index + 4).ok_or("invalid escape")?.iter().collect::<String>();
+                        *index += 4;
+                        let code = u32::from_str_radix(&hex, 16).map_err(|_| "invalid escape".to_string())?;
+                        ret.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                    },
+                    c => ret.push(*c),
+                }
+            },
+            c => ret.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_round_trip() -> Result<(), String> {
+        let input = r#"{"a":[1,2.5,-3],"b":"x\"y\n","c":true,"d":null,"e":{}}"#;
+        let json = parse(input)?;
+        assert_eq!( json.to_string(), input );
+        Ok(())
+    }
+
+    #[test]
+    fn should_get_fields() -> Result<(), String> {
+        let json = parse(r#" { "name" : "hyper", "lines" : [ [1, 2] ] } "#)?;
+        assert_eq!( json.get("name").and_then(|j| j.as_str()), Some("hyper") );
+        assert_eq!( json.get("lines").and_then(|j| j.as_array()).map(|a| a.len()), Some(1) );
+        Ok(())
+    }
+}
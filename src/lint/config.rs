@@ -0,0 +1,152 @@
+use parse_input::PSym;
+
+use crate::diagnostic::Diagnostic;
+use crate::parsing::proc::ast::Mod;
+use crate::toml::Toml;
+use super::rules::{Rule, Finding};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    pub fn from_name(name : &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+// Every rule warns unless told otherwise. An item's attributes win over
+// the module's, which win over the project's `[lints]` table.
+#[derive(Clone)]
+pub struct Levels {
+    project : Vec<(Rule, Level)>,
+    module : Vec<(Rule, Level)>,
+    items : Vec<((usize, usize), Rule, Level)>,
+}
+
+impl Levels {
+    pub fn new() -> Self {
+        Levels { project: vec![], module: vec![], items: vec![] }
+    }
+
+    // Reads `[lints]` from a project file, e.g. `unused_let = "deny"`.
+    pub fn configure(&mut self, manifest : &Toml) -> Result<(), String> {
+        let lints = match manifest.get("lints") {
+            Some(lints) => lints.as_table().ok_or("lints should be a table")?,
+            None => return Ok(()),
+        };
+        for (name, level) in lints {
+            let rule = Rule::from_name(name).ok_or(format!("unknown lint rule {}", name))?;
+            let level = level.as_str()
+                             .and_then(Level::from_name)
+                             .ok_or(format!("the level of {} should be allow, warn or deny", name))?;
+            self.project.push((rule, level));
+        }
+        Ok(())
+    }
+
+    // Takes the module's attributes, returning what's wrong with any of
+    // them.
+    pub fn attributes(&mut self, module : &Mod) -> Vec<Diagnostic> {
+        let mut errors = vec![];
+        for attr in module.lint_attrs.iter() {
+            let level = match Level::from_name(&attr.level.value) {
+                Some(level) => level,
+                None => {
+                    errors.push(unknown("lint level", &attr.level));
+                    continue;
+                },
+            };
+            for name in attr.rules.iter() {
+                match (Rule::from_name(&name.value), attr.item) {
+                    (None, _) => errors.push(unknown("lint rule", name)),
+                    (Some(rule), None) => self.module.push((rule, level)),
+                    (Some(rule), Some(range)) => self.items.push((range, rule, level)),
+                }
+            }
+        }
+        errors
+    }
+
+    pub fn level(&self, finding : &Finding) -> Level {
+        let in_item = |(start, end) : (usize, usize)| finding.start.is_some_and(|s| start <= s && s < end);
+        self.items.iter().rev()
+            .find(|(range, rule, _)| *rule == finding.rule && in_item(*range))
+            .map(|(_, _, level)| *level)
+            .or_else(|| find(&self.module, finding.rule))
+            .or_else(|| find(&self.project, finding.rule))
+            .unwrap_or(Level::Warn)
+    }
+}
+
+// Later settings win.
+fn find(levels : &[(Rule, Level)], rule : Rule) -> Option<Level> {
+    levels.iter().rev().find(|(r, _)| *r == rule).map(|(_, level)| *level)
+}
+
+fn unknown(what : &str, name : &PSym) -> Diagnostic {
+    Diagnostic::new(Some(name.start), format!("unknown {} {}", what, name.value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lint::rules::lint;
+    use crate::parsing::proc::parser::parse;
+    use crate::toml;
+
+    #[test]
+    fn should_let_attributes_override_the_project() -> Result<(), String> {
+        let module = parse(r#"
+#![deny(unused_param)]
+#![allow(unusual)]
+fun main() {
+    let x = 1;
+}
+
+#[allow(unused_let)]
+#[warn(unused_param)]
+fun f(a : number) {
+    let y = 1;
+}
+
+#[deny(unused_let)]
+fun g(b : number) {
+    let z = 1;
+}
+"#).map_err(|e| format!("{:?}", e))?;
+        let mut levels = Levels::new();
+        levels.configure(&toml::parse("[lints]\nunused_let = \"allow\"\nempty_if = 'deny'")?)?;
+        let errors = levels.attributes(&module);
+        assert_eq!( errors.len(), 1 );
+        assert_eq!( errors[0].message, "unknown lint rule unusual" );
+
+        let found = lint(&module).iter().map(|f| (f.message.clone(), levels.level(f))).collect::<Vec<_>>();
+        assert_eq!( found, vec![ ("let x is never used".to_string(), Level::Allow)
+                               , ("parameter a is never used".to_string(), Level::Warn)
+                               , ("let y is never used".to_string(), Level::Allow)
+                               , ("parameter b is never used".to_string(), Level::Deny)
+                               , ("let z is never used".to_string(), Level::Deny)
+                               , ("function f is never used".to_string(), Level::Warn)
+                               , ("function g is never used".to_string(), Level::Warn)
+                               ] );
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_unknown_rules_in_the_project() {
+        let mut levels = Levels::new();
+        let manifest = toml::parse("[lints]\nunused = \"deny\"").expect("invalid toml");
+        assert_eq!( levels.configure(&manifest), Err("unknown lint rule unused".to_string()) );
+        let manifest = toml::parse("[lints]\nunused_let = \"loud\"").expect("invalid toml");
+        assert_eq!( levels.configure(&manifest), Err("the level of unused_let should be allow, warn or deny".to_string()) );
+    }
+}
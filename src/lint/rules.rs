@@ -0,0 +1,486 @@
+use std::collections::HashSet;
+
+use parse_input::PSym;
+
+use crate::parsing::proc::ast::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    UnusedLet,
+    UnusedParam,
+    UnusedImport,
+    DuplicateImport,
+    ShadowedBinding,
+    EmptyIf,
+    UnusedItem,
+}
+
+pub const RULES : [Rule; 7] = [ Rule::UnusedLet
+                              , Rule::UnusedParam
+                              , Rule::UnusedImport
+                              , Rule::DuplicateImport
+                              , Rule::ShadowedBinding
+                              , Rule::EmptyIf
+                              , Rule::UnusedItem
+                              ];
+
+impl Rule {
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::UnusedLet => "unused_let",
+            Rule::UnusedParam => "unused_param",
+            Rule::UnusedImport => "unused_import",
+            Rule::DuplicateImport => "duplicate_import",
+            Rule::ShadowedBinding => "shadowed_binding",
+            Rule::EmptyIf => "empty_if",
+            Rule::UnusedItem => "unused_item",
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<Rule> {
+        RULES.iter().find(|r| r.name() == name).cloned()
+    }
+}
+
+#[derive(Debug)]
+pub struct Finding {
+    pub rule : Rule,
+    pub start : Option<usize>,
+    pub message : String,
+}
+
+// A local, with the rule that reports it when it's never read. Loop
+// variables have none.
+struct Local {
+    name : String,
+    start : usize,
+    unused : Option<Rule>,
+    used : bool,
+}
+
+struct Walker {
+    locals : Vec<Local>,
+    // Where each scope's locals start in `locals`.
+    scopes : Vec<usize>,
+    // Names used that aren't locals, from items other than the one
+    // declaring them.
+    globals : HashSet<String>,
+    item : Option<String>,
+    findings : Vec<Finding>,
+}
+
+// Finds everything any rule reports, whatever level it's at.
+pub fn lint(module : &Mod) -> Vec<Finding> {
+    let mut walker = Walker { locals: vec![], scopes: vec![], globals: HashSet::new(), item: None, findings: vec![] };
+
+    for def in module.fun_defs.iter() {
+        walker.item = Some(def.name.value.clone());
+        for bound in def.bounds.iter() {
+            walker.global(&bound.interface.value);
+        }
+        walker.function(def, true);
+    }
+    for def in module.impl_defs.iter() {
+        // Methods aren't items, and the target isn't used by having them.
+        walker.item = None;
+        if let Some(interface) = &def.interface {
+            walker.global(&interface.value);
+        }
+        // An interface decides what its methods take.
+        for fun in def.funs.iter() {
+            walker.function(fun, def.interface.is_none());
+        }
+    }
+    for def in module.extern_fun_defs.iter() {
+        walker.item = Some(def.name.value.clone());
+        for param in def.params.iter() {
+            walker.ty(&param.param_type);
+        }
+        walker.ty(&def.return_type);
+    }
+    for def in module.struct_defs.iter() {
+        walker.item = Some(def.name.value.clone());
+        for item in def.items.iter() {
+            walker.ty(&item.item_type);
+            if let Some(default) = &item.default {
+                walker.expr(default);
+            }
+        }
+    }
+    for def in module.type_aliases.iter() {
+        walker.item = Some(def.name.value.clone());
+        walker.ty(&def.aliased);
+    }
+    for def in module.const_defs.iter() {
+        walker.item = Some(def.name.value.clone());
+        walker.ty(&def.const_type);
+        walker.expr(&def.value);
+    }
+    for def in module.interface_defs.iter() {
+        walker.item = Some(def.name.value.clone());
+        for method in def.methods.iter() {
+            for param in method.params.iter() {
+                walker.ty(&param.param_type);
+            }
+            walker.ty(&method.return_type);
+        }
+    }
+
+    walker.imports(&module.uses);
+    walker.items(module);
+
+    walker.findings
+}
+
+impl Walker {
+    fn report(&mut self, rule : Rule, start : Option<usize>, message : String) {
+        self.findings.push(Finding { rule, start, message });
+    }
+
+    fn global(&mut self, name : &str) {
+        if self.item.as_deref() != Some(name) {
+            self.globals.insert(name.to_string());
+        }
+    }
+
+    // Annotations never name locals.
+    fn ty(&mut self, t : &Type) {
+        match t {
+            Type::Unit | Type::Infer => (),
+            Type::Simple(name) => self.global(&name.value),
+            Type::Generic(name, args) => {
+                self.global(&name.value);
+                args.iter().for_each(|a| self.ty(a));
+            },
+            Type::Fun(params, ret) => {
+                params.iter().for_each(|p| self.ty(p));
+                self.ty(ret);
+            },
+            Type::Tuple(ts) => ts.iter().for_each(|t| self.ty(t)),
+            Type::Namespace(namespace, t) => {
+                if let Some(first) = namespace.first() {
+                    self.global(&first.value);
+                }
+                if let Type::Generic(_, args) = &**t {
+                    args.iter().for_each(|a| self.ty(a));
+                }
+            },
+        }
+    }
+
+    fn reference(&mut self, name : &str) {
+        match self.locals.iter_mut().rev().find(|l| l.name == name) {
+            Some(local) => local.used = true,
+            None => self.global(name),
+        }
+    }
+
+    fn bind(&mut self, name : &PSym, unused : Option<Rule>) {
+        if name.value.starts_with('_') {
+            return;
+        }
+        if self.locals.iter().any(|l| l.name == name.value) {
+            self.report(Rule::ShadowedBinding, Some(name.start), format!("{} shadows an earlier binding of the same name", name.value));
+        }
+        self.locals.push(Local { name: name.value.clone(), start: name.start, unused, used: false });
+    }
+
+    fn in_scope(&mut self, f : impl FnOnce(&mut Self)) {
+        self.scopes.push(self.locals.len());
+        f(self);
+        let start = self.scopes.pop().unwrap();
+        for local in self.locals.split_off(start) {
+            match local.unused {
+                Some(Rule::UnusedLet) if !local.used =>
+                    self.report(Rule::UnusedLet, Some(local.start), format!("let {} is never used", local.name)),
+                Some(Rule::UnusedParam) if !local.used =>
+                    self.report(Rule::UnusedParam, Some(local.start), format!("parameter {} is never used", local.name)),
+                _ => (),
+            }
+        }
+    }
+
+    fn params(&mut self, params : &[FunParam], unused : Option<Rule>) {
+        for param in params {
+            self.ty(&param.param_type);
+            if param.name.value != "self" {
+                self.bind(&param.name, unused);
+            }
+        }
+        for default in params.iter().filter_map(|p| p.default.as_ref()) {
+            self.expr(default);
+        }
+    }
+
+    fn function(&mut self, def : &FunDef, check_params : bool) {
+        self.ty(&def.return_type);
+        self.in_scope(|w| {
+            w.params(&def.params, if check_params { Some(Rule::UnusedParam) } else { None });
+            w.statements(&def.definition);
+        });
+    }
+
+    fn statements(&mut self, statements : &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn block(&mut self, statements : &[Statement]) {
+        self.in_scope(|w| w.statements(statements));
+    }
+
+    fn statement(&mut self, statement : &Statement) {
+        match statement {
+            Statement::Expr(e) | Statement::Return(Some(e)) | Statement::Yield(Some(e)) => self.expr(e),
+            Statement::Return(None) | Statement::Yield(None) | Statement::Break { .. } | Statement::Continue { .. } => (),
+            Statement::Let { pattern, value_type, expr } => {
+                self.expr(expr);
+                self.ty(value_type);
+                for name in pattern.names() {
+                    self.bind(name, Some(Rule::UnusedLet));
+                }
+            },
+            // Setting a variable doesn't read it.
+            Statement::Set { target, new_value } => {
+                match target {
+                    Expr::Variable { namespace, .. } if namespace.is_empty() => (),
+                    target => self.expr(target),
+                }
+                self.expr(new_value);
+            },
+            Statement::While { test, statements, .. } => {
+                self.expr(test);
+                self.block(statements);
+            },
+            Statement::Loop { statements, .. } | Statement::Else(statements) => self.block(statements),
+            Statement::Foreach { var, value, items, statements, .. } => {
+                self.expr(items);
+                self.in_scope(|w| {
+                    for name in std::iter::once(var).chain(value.iter()).flat_map(|p| p.names()) {
+                        w.bind(name, None);
+                    }
+                    w.statements(statements);
+                });
+            },
+            Statement::If { keyword, test, statements } | Statement::ElseIf { keyword, test, statements } => {
+                self.expr(test);
+                if statements.is_empty() {
+                    self.report(Rule::EmptyIf, Some(keyword.start), "empty if block".to_string());
+                }
+                self.block(statements);
+            },
+        }
+    }
+
+    fn lambda(&mut self, params : &[FunParam], return_type : &Type, body : Result<&[Statement], &Expr>) {
+        self.ty(return_type);
+        self.in_scope(|w| {
+            w.params(params, Some(Rule::UnusedParam));
+            match body {
+                Ok(statements) => w.statements(statements),
+                Err(expr) => w.expr(expr),
+            }
+        });
+    }
+
+    fn expr(&mut self, e : &Expr) {
+        match e {
+            Expr::Number(_) | Expr::PString(_) | Expr::Bool(_) | Expr::OptionCons(OptionValue::None) => (),
+            Expr::Interpolate(parts) => {
+                for part in parts {
+                    if let StringPart::Expr(e) = part {
+                        self.expr(e);
+                    }
+                }
+            },
+            Expr::Variable { namespace, name } => match namespace.first() {
+                Some(first) => self.global(&first.value),
+                None => self.reference(&name.value),
+            },
+            Expr::StatementLambda { params, return_type, definition } => self.lambda(params, return_type, Ok(definition)),
+            Expr::ExprLambda { params, return_type, definition } => self.lambda(params, return_type, Err(definition)),
+            Expr::Call { func, params, named } => {
+                self.expr(func);
+                for p in params {
+                    self.expr(p);
+                }
+                for arg in named {
+                    self.expr(&arg.value);
+                }
+            },
+            Expr::Dash { object, func } => {
+                self.expr(object);
+                self.reference(&func.value);
+            },
+            Expr::Try(e) | Expr::Spread(e) | Expr::Dot { object: e, .. } => self.expr(e),
+            Expr::ResultCons(ResultValue::Okay(e)) | Expr::ResultCons(ResultValue::Error(e)) | Expr::OptionCons(OptionValue::Some(e)) => self.expr(e),
+            Expr::Index { object, index } => {
+                self.expr(object);
+                self.expr(index);
+            },
+            Expr::StructCons { name, slots, base } => {
+                if let Some(name) = name {
+                    self.global(&name.value);
+                }
+                for slot in slots {
+                    self.expr(&slot.value);
+                }
+                if let Some(base) = base {
+                    self.expr(base);
+                }
+            },
+            Expr::ListCons(es) | Expr::Tuple(es) => {
+                for e in es {
+                    self.expr(e);
+                }
+            },
+            Expr::MapCons(entries) => {
+                for entry in entries {
+                    self.expr(&entry.key);
+                    self.expr(&entry.value);
+                }
+            },
+            Expr::Range { start, end, step, .. } => {
+                self.expr(start);
+                self.expr(end);
+                if let Some(step) = step {
+                    self.expr(step);
+                }
+            },
+        }
+    }
+
+    // `*` can't be checked for being used, only for being given twice.
+    fn imports(&mut self, uses : &[Use]) {
+        let mut seen = HashSet::new();
+        for u in uses {
+            let namespace = u.namespace.iter().map(|n| n.value.as_str()).collect::<Vec<&str>>().join("::");
+            for import in u.imports.iter() {
+                let (name, start) = match import {
+                    Import::Everything => ("*", u.namespace.last().map(|n| n.start)),
+                    Import::Item(name) => (name.value.as_str(), Some(name.start)),
+                };
+                let path = format!("{}::{}", namespace, name);
+                if !seen.insert(path.clone()) {
+                    self.report(Rule::DuplicateImport, start, format!("{} is already imported", path));
+                }
+                else if name != "*" && !self.globals.contains(name) {
+                    self.report(Rule::UnusedImport, start, format!("{} is never used", path));
+                }
+            }
+        }
+    }
+
+    fn items(&mut self, module : &Mod) {
+        let items = module.fun_defs.iter().map(|d| ("function", &d.name, &module.fun_exports))
+            .chain(module.extern_fun_defs.iter().map(|d| ("function", &d.name, &module.fun_exports)))
+            .chain(module.struct_defs.iter().map(|d| ("struct", &d.name, &module.struct_exports)))
+            .chain(module.extern_struct_defs.iter().map(|d| ("struct", &d.name, &module.struct_exports)))
+            .chain(module.enum_defs.iter().map(|d| ("enum", &d.name, &module.enum_exports)))
+            .chain(module.type_aliases.iter().map(|d| ("type", &d.name, &module.type_exports)))
+            .chain(module.const_defs.iter().map(|d| ("const", &d.name, &module.const_exports)))
+            .chain(module.interface_defs.iter().map(|d| ("interface", &d.name, &module.interface_exports)))
+            .collect::<Vec<_>>();
+        for (kind, name, exports) in items {
+            if name.value != "main" && !exports.contains(&name.value) && !self.globals.contains(&name.value) {
+                self.report(Rule::UnusedItem, Some(name.start), format!("{} {} is never used", kind, name.value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::proc::parser::parse;
+
+    fn findings(input : &str) -> Vec<(&'static str, String)> {
+        let module = parse(input).expect("parse failed");
+        lint(&module).into_iter().map(|f| (f.rule.name(), f.message)).collect()
+    }
+
+    #[test]
+    fn should_find_unused_locals_and_shadowing() {
+        let found = findings(r#"
+fun main() {
+    let x = 1;
+    let y = 2;
+    let _z = 3;
+    set y = 4;
+    let f = |a : number, b : number| add(a, x);
+    foreach i in [1] {
+        let x = 5;
+        print(x);
+    }
+    f(1, 2);
+}
+
+fun g(n : number, unused : number) -> number {
+    return n;
+}
+pub fun h() { g(1, 2); }
+"#);
+        assert_eq!( found, vec![ ("unused_param", "parameter b is never used".to_string())
+                               , ("shadowed_binding", "x shadows an earlier binding of the same name".to_string())
+                               , ("unused_let", "let y is never used".to_string())
+                               , ("unused_param", "parameter unused is never used".to_string())
+                               ] );
+    }
+
+    #[test]
+    fn should_find_unused_and_duplicate_imports() {
+        let found = findings(r#"
+use a::{*, *};
+use b::{Point, Color, make, unused};
+use b::{make};
+
+fun main() {
+    let p : Point = make();
+    print(p, Color::Red);
+    if eq(1, 2) { }
+}
+"#);
+        assert_eq!( found, vec![ ("empty_if", "empty if block".to_string())
+                               , ("duplicate_import", "a::* is already imported".to_string())
+                               , ("unused_import", "b::unused is never used".to_string())
+                               , ("duplicate_import", "b::make is already imported".to_string())
+                               ] );
+    }
+
+    #[test]
+    fn should_place_empty_ifs_at_their_keyword() {
+        let source = "fun main() {\n    if true { }\n    elseif false { }\n}";
+        let module = parse(source).expect("parse failed");
+        let starts = lint(&module).into_iter().map(|f| f.start).collect::<Vec<Option<usize>>>();
+        assert_eq!( starts, vec![source.find("if"), source.find("elseif")] );
+    }
+
+    #[test]
+    fn should_find_unused_items() {
+        let found = findings(r#"
+struct Point { x : number }
+struct Unused { p : Point }
+enum Color { Red }
+pub enum Shape { Circle }
+const LIMIT : number = 3;
+interface Show { fun show(self) -> string; }
+
+fun main() {
+    print(Color::Red);
+    print(new Point { x: LIMIT });
+}
+
+fun recurse(n : number) -> number {
+    return recurse(n);
+}
+
+impl Show for Point {
+    fun show(self, _x : number) -> string { return "point"; }
+}
+"#);
+        assert_eq!( found, vec![ ("unused_item", "function recurse is never used".to_string())
+                               , ("unused_item", "struct Unused is never used".to_string())
+                               ] );
+    }
+}
@@ -0,0 +1,134 @@
+
+use std::collections::{HashMap, HashSet};
+
+use crate::eval::builtins::builtins;
+use crate::eval::value::Value;
+use crate::parsing::proc::ast::*;
+use super::lua::string_literal;
+
+// Builtins without side effects, which are safe to run at compile time.
+const PURE : &[&str] = &[ "add", "sub", "mul", "div", "mod", "eq", "neq", "lt", "le", "gt", "ge"
+                        , "not", "and", "or", "concat"
+                        ];
+
+// Evaluates a const expression at compile time, using the interpreter's
+// builtins so that the result matches `hyper run`. Gives up on anything
+// that isn't made of literals, earlier consts and pure builtins that
+// `defined` doesn't shadow.
+pub fn fold<'v>(e : &Expr, consts : &HashMap<&str, Value<'v>>, defined : &HashSet<&str>) -> Option<Value<'v>> {
+    match e {
+        Expr::Number(n) => n.value.parse::<f64>().ok().map(Value::Number),
+        Expr::PString(s) => Some(Value::String(s.value.clone())),
+        Expr::Bool(b) => Some(Value::Bool(*b)),
+        Expr::Variable { namespace, name } if namespace.is_empty() => consts.get(name.value.as_str()).cloned(),
+        Expr::Interpolate(parts) => {
+            let mut s = String::new();
+            for part in parts {
+                match part {
+                    StringPart::Text(t) => s.push_str(&t.value),
+                    StringPart::Expr(e) => s.push_str(&printable(fold(e, consts, defined)?)?),
+                }
+            }
+            Some(Value::String(s))
+        },
+        Expr::Call { func, params, named } if named.is_empty() => {
+            let name = match &**func {
+                Expr::Variable { namespace, name } if namespace.is_empty() => name.value.as_str(),
+                _ => return None,
+            };
+            if !PURE.contains(&name) || defined.contains(name) {
+                return None;
+            }
+            let mut args = vec![];
+            for p in params {
+                let arg = fold(p, consts, defined)?;
+                // Lua and Rust write fractions differently.
+                if name == "concat" {
+                    printable(arg.clone())?;
+                }
+                args.push(arg);
+            }
+            let (_, f) = builtins().into_iter().find(|(n, _)| *n == name)?;
+            f(args).ok()
+        },
+        _ => None,
+    }
+}
+
+// The Lua literal for a folded value, if it has an exact one.
+pub fn literal(v : &Value) -> Option<String> {
+    match v {
+        Value::Number(n) if n.is_finite() && *n < 0.0 => Some(format!("({})", n)),
+        Value::Number(n) if n.is_finite() => Some(format!("{}", n)),
+        Value::String(s) => Some(string_literal(s)),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+// Text that Lua's tostring would produce for the same value.
+fn printable(v : Value) -> Option<String> {
+    match v {
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => Some(format!("{}", n)),
+        Value::String(s) => Some(s),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::proc::parser::parse;
+
+    fn fold_consts(input : &str) -> Vec<Option<String>> {
+        let module = parse(input).expect("parse failed");
+        let defined = module.fun_defs.iter().map(|d| d.name.value.as_str()).collect::<HashSet<&str>>();
+        let mut consts = HashMap::new();
+        let mut ret = vec![];
+        for def in module.const_defs.iter() {
+            let v = fold(&def.value, &consts, &defined);
+            ret.push(v.as_ref().and_then(literal));
+            if let Some(v) = v {
+                consts.insert(def.name.value.as_str(), v);
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn should_fold_pure_builtins_and_earlier_consts() {
+        let output = fold_consts(r#"
+const MINUTE : number = 60;
+const HOUR : number = mul(MINUTE, 60);
+const NEG : number = sub(0, 1.5);
+const NAME : string = "hour {HOUR}";
+const THIRD : string = concat("a", div(1, 3));
+const BIG : bool = gt(HOUR, 100);
+const LIMIT : number = limit();
+fun limit() -> number {
+    return 1;
+}
+"#);
+        assert_eq!( output, vec![ Some("60".to_string())
+                                , Some("3600".to_string())
+                                , Some("(-1.5)".to_string())
+                                , Some("\"hour 3600\"".to_string())
+                                , None
+                                , Some("true".to_string())
+                                , None
+                                ] );
+    }
+
+    #[test]
+    fn should_not_fold_shadowed_builtins() {
+        let output = fold_consts(r#"
+const X : number = add(1, 2);
+fun add(a : number, b : number) -> number {
+    return a;
+}
+"#);
+        assert_eq!( output, vec![None] );
+    }
+}
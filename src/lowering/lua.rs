@@ -0,0 +1,2024 @@
+
+use std::collections::{HashMap, HashSet};
+
+use parse_input::PSym;
+
+use crate::parsing::proc::ast::*;
+use crate::prelude;
+use super::fold::{fold, literal};
+use crate::typing::checker::{Collection, TryKind};
+
+pub struct LuaModule {
+    pub code : String,
+    // Source offset for each generated line, indexed from line 1.
+    pub lines : Vec<Option<usize>>,
+}
+
+const KEYWORDS : &[&str] = &[ "and", "break", "do", "else", "elseif", "end", "false", "for", "function"
+                            , "goto", "if", "in", "local", "nil", "not", "or", "repeat", "return", "then"
+                            , "true", "until", "while"
+                            ];
+
+// What a `use` brings in from the module it refers to, when the build
+// found one.
+pub struct Imported<'a> {
+    pub require : String,
+    // The names that are values in Lua.
+    pub names : Vec<&'a str>,
+    // Structs, whose metatables come along.
+    pub tagged : Vec<&'a str>,
+    // The slots of those structs that have defaults, which are functions
+    // on the metatable.
+    pub defaults : Vec<(&'a str, Vec<&'a str>)>,
+}
+
+impl<'a> Imported<'a> {
+    pub fn new(require : String, u : &Use, target : &'a Mod) -> Self {
+        let imported = |name : &str| u.imports.iter().any(|i| match i {
+            Import::Everything => true,
+            Import::Item(n) => n.value == name,
+        });
+        let names = lua_exports(target).into_iter().filter(|(item, _)| imported(item)).map(|(_, name)| name).collect();
+        let tagged = tagged_exports(target).into_iter().filter(|s| imported(s)).collect::<Vec<&str>>();
+        let defaults = target.struct_defs.iter().filter(|d| tagged.contains(&d.name.value.as_str())).map(|d| {
+            (d.name.value.as_str(), d.items.iter().filter(|i| i.default.is_some()).map(|i| i.name.value.as_str()).collect())
+        }).collect();
+        Imported { require, names, tagged, defaults }
+    }
+}
+
+// The module's exports that are values in Lua, with the item each comes
+// from; an interface gives its methods.
+fn lua_exports(module : &Mod) -> Vec<(&str, &str)> {
+    let methods = module.interface_defs.iter()
+                                       .filter(|d| module.interface_exports.contains(&d.name.value))
+                                       .flat_map(|d| d.methods.iter().map(move |m| (d.name.value.as_str(), m.name.value.as_str())));
+    module.fun_exports.iter().chain(module.enum_exports.iter()).chain(module.const_exports.iter())
+                      .map(|e| (e.as_str(), e.as_str()))
+                      .chain(methods)
+                      .collect()
+}
+
+fn tagged_exports(module : &Mod) -> Vec<&str> {
+    module.struct_defs.iter()
+                      .map(|d| d.name.value.as_str())
+                      .filter(|s| module.struct_exports.iter().any(|e| e == s))
+                      .collect()
+}
+
+enum Builtin {
+    Binary(&'static str),
+    Unary(&'static str),
+    Concat,
+    Call(&'static str),
+}
+
+fn builtin(name : &str) -> Option<(Builtin, Option<usize>)> {
+    let b = match name {
+        "add" => (Builtin::Binary("+"), Some(2)),
+        "sub" => (Builtin::Binary("-"), Some(2)),
+        "mul" => (Builtin::Binary("*"), Some(2)),
+        "div" => (Builtin::Binary("/"), Some(2)),
+        "mod" => (Builtin::Binary("%"), Some(2)),
+        "eq" => (Builtin::Binary("=="), Some(2)),
+        "neq" => (Builtin::Binary("~="), Some(2)),
+        "lt" => (Builtin::Binary("<"), Some(2)),
+        "le" => (Builtin::Binary("<="), Some(2)),
+        "gt" => (Builtin::Binary(">"), Some(2)),
+        "ge" => (Builtin::Binary(">="), Some(2)),
+        "and" => (Builtin::Binary("and"), Some(2)),
+        "or" => (Builtin::Binary("or"), Some(2)),
+        "not" => (Builtin::Unary("not "), Some(1)),
+        "len" => (Builtin::Unary("#"), Some(1)),
+        "concat" => (Builtin::Concat, None),
+        "to_string" => (Builtin::Call("tostring"), Some(1)),
+        "push" => (Builtin::Call("table.insert"), Some(2)),
+        "print" => (Builtin::Call("print"), None),
+        _ => return None,
+    };
+    Some(b)
+}
+
+pub fn name(s : &str) -> String {
+    if KEYWORDS.contains(&s) {
+        format!("{}_", s)
+    }
+    else {
+        s.to_string()
+    }
+}
+
+// The metatable of a struct.
+fn tag(s : &str) -> String {
+    format!("__struct_{}", s)
+}
+
+pub fn string_literal(s : &str) -> String {
+    let mut ret = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\{:03}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+pub struct Lowering<'a> {
+    code : String,
+    lines : Vec<Option<usize>>,
+    indent : usize,
+    // Start of the statement being written, used for lines that continue it.
+    current : Vec<Option<usize>>,
+    enums : HashSet<&'a str>,
+    defined : HashSet<&'a str>,
+    scopes : Vec<HashSet<&'a str>>,
+    // Prelude functions reachable from this module through `__prelude`.
+    // Empty while lowering the prelude itself, which sees them as locals.
+    prelude : HashSet<&'static str>,
+    in_prelude : bool,
+    // What the checker found each indexed or iterated expression to be.
+    // Anything missing is dispatched at runtime.
+    collections : HashMap<*const Expr, Collection>,
+    // The `a.f(...)` calls that the checker found to be method calls.
+    method_calls : HashSet<*const Expr>,
+    // Calls with named or left out arguments; see `arranged`.
+    arrangements : HashMap<*const Expr, Vec<Option<usize>>>,
+    // Whether each `?` is applied to a result or to an option, which is
+    // nil when absent. Anything missing is dispatched at runtime.
+    try_kinds : HashMap<*const Expr, TryKind>,
+    // Loop variables that are set. Lua passes the first one back to the
+    // iterator, so these are copied into locals of their own.
+    assigned : HashSet<*const PSym>,
+    tries : HashMap<*const Expr, String>,
+    // Operands evaluated into temporaries ahead of a `?` after them; see
+    // `hoist`.
+    spilled : HashMap<*const Expr, String>,
+    // Lua literals for the consts that could be folded, which are inlined.
+    consts : HashMap<&'a str, String>,
+    // Lua for the default of each slot that has one, by struct.
+    defaults : HashMap<&'a str, Vec<(&'a str, String)>>,
+    // Structs, which are built with a metatable naming them, qualified by
+    // the module so that impls of same-named structs don't collide. It is
+    // also the __index of structs with methods.
+    tagged : HashSet<&'a str>,
+    // The name Lua requires this module by, when it's part of a project.
+    module_name : Option<String>,
+    // Whether this is the module a project runs, which calls its main.
+    entry : bool,
+    // For each `use`, what the build found it to bring in.
+    imports : Vec<Option<Imported<'a>>>,
+    // The Lua local each module a `use` names is in, by its path and by
+    // its last name, e.g. `geometry::shapes` and `shapes`.
+    namespaces : HashMap<String, String>,
+    temps : usize,
+    loops : Vec<LoopTarget<'a>>,
+}
+
+// A loop being lowered. `continue` and breaks out of outer loops become
+// gotos, and their labels are only written when something jumps to them.
+struct LoopTarget<'a> {
+    label : Option<&'a str>,
+    id : usize,
+    continued : bool,
+    broken : bool,
+}
+
+impl<'a> Lowering<'a> {
+    pub fn new() -> Self {
+        Lowering { code: String::new()
+                 , lines: vec![]
+                 , indent: 0
+                 , current: vec![]
+                 , enums: HashSet::new()
+                 , defined: HashSet::new()
+                 , scopes: vec![]
+                 , prelude: prelude::exports().into_iter().collect()
+                 , in_prelude: false
+                 , collections: HashMap::new()
+                 , method_calls: HashSet::new()
+                 , arrangements: HashMap::new()
+                 , try_kinds: HashMap::new()
+                 , assigned: HashSet::new()
+                 , tries: HashMap::new()
+                 , spilled: HashMap::new()
+                 , consts: HashMap::new()
+                 , defaults: HashMap::new()
+                 , tagged: HashSet::new()
+                 , module_name: None
+                 , entry: false
+                 , imports: vec![]
+                 , namespaces: HashMap::new()
+                 , temps: 0
+                 , loops: vec![]
+                 }
+    }
+
+    pub fn prelude() -> Self {
+        Lowering { prelude: HashSet::new(), in_prelude: true, ..Lowering::new() }
+    }
+
+    pub fn with_collections(self, collections : HashMap<*const Expr, Collection>) -> Self {
+        Lowering { collections, ..self }
+    }
+
+    pub fn with_method_calls(self, method_calls : HashSet<*const Expr>) -> Self {
+        Lowering { method_calls, ..self }
+    }
+
+    pub fn with_arrangements(self, arrangements : HashMap<*const Expr, Vec<Option<usize>>>) -> Self {
+        Lowering { arrangements, ..self }
+    }
+
+    pub fn with_try_kinds(self, try_kinds : HashMap<*const Expr, TryKind>) -> Self {
+        Lowering { try_kinds, ..self }
+    }
+
+    pub fn with_assigned_loop_vars(self, assigned : HashSet<*const PSym>) -> Self {
+        Lowering { assigned, ..self }
+    }
+
+    pub fn with_imports(self, imports : Vec<Option<Imported<'a>>>) -> Self {
+        Lowering { imports, ..self }
+    }
+
+    pub fn with_module_name(self, module_name : String) -> Self {
+        Lowering { module_name: Some(module_name), ..self }
+    }
+
+    pub fn with_entry(self, entry : bool) -> Self {
+        Lowering { entry, ..self }
+    }
+
+    pub fn finish(self) -> LuaModule {
+        LuaModule { code: self.code, lines: self.lines }
+    }
+
+    fn begin(&mut self, start : Option<usize>) {
+        let start = start.or_else(|| self.current.last().cloned().flatten());
+        self.lines.push(start);
+        for _ in 0..self.indent {
+            self.code.push_str("    ");
+        }
+    }
+
+    fn write(&mut self, s : &str) {
+        self.code.push_str(s);
+    }
+
+    fn end(&mut self) {
+        self.code.push('\n');
+    }
+
+    fn line(&mut self, start : Option<usize>, s : &str) {
+        self.begin(start);
+        self.write(s);
+        self.end();
+    }
+
+    fn raw(&mut self, s : &str) {
+        for l in s.lines() {
+            self.line(None, l);
+        }
+    }
+
+    fn temp(&mut self, prefix : &str) -> String {
+        self.temps += 1;
+        format!("__{}{}", prefix, self.temps)
+    }
+
+    pub fn lower_mod(&mut self, module : &'a Mod) {
+        for def in module.enum_defs.iter() {
+            self.enums.insert(&def.name.value);
+        }
+        for def in module.fun_defs.iter() {
+            self.defined.insert(&def.name.value);
+        }
+        for def in module.extern_fun_defs.iter() {
+            self.defined.insert(&def.name.value);
+        }
+        for method in module.interface_defs.iter().flat_map(|d| d.methods.iter()) {
+            self.defined.insert(&method.name.value);
+        }
+        for def in module.struct_defs.iter() {
+            self.tagged.insert(&def.name.value);
+        }
+        for imported in self.imports.iter().flatten() {
+            self.defined.extend(imported.names.iter().cloned());
+            self.tagged.extend(imported.tagged.iter().cloned());
+            for (s, slots) in imported.defaults.iter() {
+                self.defaults.insert(s, slots.iter().map(|slot| (*slot, format!("{}.__default_{}()", tag(s), slot))).collect());
+            }
+        }
+        let mut folded = HashMap::new();
+        for def in module.const_defs.iter() {
+            let value = fold(&def.value, &folded, &self.defined);
+            if let Some(lua) = value.as_ref().and_then(literal) {
+                self.consts.insert(&def.name.value, lua);
+                folded.insert(def.name.value.as_str(), value.unwrap());
+            }
+        }
+        for def in module.const_defs.iter() {
+            self.defined.insert(&def.name.value);
+        }
+        // Defaults that aren't literals are functions on the struct's
+        // metatable, so that each `new` gets a fresh value and sees the
+        // module's globals. Exported structs have one for every default,
+        // as other modules can't see the literals.
+        let mut thunks = vec![];
+        for def in module.struct_defs.iter() {
+            let exported = module.struct_exports.contains(&def.name.value);
+            let mut defaults = vec![];
+            for item in def.items.iter() {
+                let default = match &item.default {
+                    Some(e) => e,
+                    None => continue,
+                };
+                let thunk = format!("{}.__default_{}", tag(&def.name.value), item.name.value);
+                if exported {
+                    thunks.push((thunk.clone(), default));
+                }
+                let lua = match fold(default, &folded, &self.defined).as_ref().and_then(literal) {
+                    Some(lua) => lua,
+                    None => {
+                        if !exported {
+                            thunks.push((thunk.clone(), default));
+                        }
+                        format!("{}()", thunk)
+                    },
+                };
+                defaults.push((item.name.value.as_str(), lua));
+            }
+            self.defaults.insert(&def.name.value, defaults);
+        }
+
+        if self.in_prelude {
+            self.raw(prelude::NATIVE_LUA);
+        }
+        else {
+            self.line(None, &format!("local __prelude = require({})", string_literal(prelude::MODULE_NAME)));
+        }
+        for (u, imported) in module.uses.iter().zip(std::mem::take(&mut self.imports)) {
+            let imported = match imported {
+                Some(imported) => imported,
+                None => continue,
+            };
+            let local = self.temp("use");
+            self.line(Some(u.namespace[0].start), &format!("local {} = require({})", local, string_literal(&imported.require)));
+            let names = imported.names.iter().map(|n| name(n)).collect::<Vec<String>>();
+            if !names.is_empty() {
+                let values = names.iter().map(|n| format!("{}.{}", local, n)).collect::<Vec<String>>();
+                self.line(Some(u.namespace[0].start), &format!("local {} = {}", names.join(", "), values.join(", ")));
+            }
+            for s in imported.tagged {
+                self.line(Some(u.namespace[0].start), &format!("local {0} = {1}.{0}", tag(s), local));
+            }
+            let path = u.namespace.iter().map(|n| n.value.as_str()).collect::<Vec<&str>>();
+            for key in [path.join("::"), path[path.len() - 1].to_string()] {
+                self.namespaces.entry(key).or_insert_with(|| local.clone());
+            }
+        }
+        self.line(None, "local __module = {}");
+
+        for def in module.extern_fun_defs.iter() {
+            let value = match &def.module {
+                Some(module) => format!("require({}).{}", string_literal(&module.value), def.path.value),
+                None => def.path.value.clone(),
+            };
+            self.line(Some(def.name.start), &format!("local {} = {}", name(&def.name.value), value));
+        }
+
+        for def in module.enum_defs.iter() {
+            self.begin(Some(def.name.start));
+            self.write(&format!("local {} = {{ ", name(&def.name.value)));
+            let cases = def.items.iter().map(|i| format!("{} = {}", name(&i.value), string_literal(&format!("{}::{}", def.name.value, i.value)))).collect::<Vec<String>>();
+            self.write(&cases.join(", "));
+            self.write(" }");
+            self.end();
+        }
+
+        for def in module.struct_defs.iter() {
+            let qualified = match &self.module_name {
+                Some(module_name) => format!("{}.{}", module_name, def.name.value),
+                None => def.name.value.clone(),
+            };
+            self.line(Some(def.name.start), &format!("local {} = {{ __name = {} }}", tag(&def.name.value), string_literal(&qualified)));
+            if module.impl_defs.iter().any(|i| i.interface.is_none() && i.target.value == def.name.value) {
+                self.line(Some(def.name.start), &format!("{0}.__index = {0}", tag(&def.name.value)));
+            }
+        }
+
+        for def in module.interface_defs.iter() {
+            for method in def.methods.iter() {
+                let lua = format!( "local {} = {}({}, {})"
+                                 , name(&method.name.value)
+                                 , self.runtime("__method")
+                                 , string_literal(&def.name.value)
+                                 , string_literal(&method.name.value)
+                                 );
+                self.line(Some(method.name.start), &lua);
+            }
+        }
+
+        if !module.fun_defs.is_empty() || !module.const_defs.is_empty() {
+            let names = module.const_defs.iter().map(|d| name(&d.name.value))
+                                          .chain(module.fun_defs.iter().map(|d| name(&d.name.value)))
+                                          .collect::<Vec<String>>();
+            self.line(None, &format!("local {}", names.join(", ")));
+        }
+
+        for (thunk, default) in thunks {
+            self.current.push(default.start());
+            self.begin(default.start());
+            self.write(&format!("function {}() return ", thunk));
+            self.expr(default);
+            self.write(" end");
+            self.end();
+            self.current.pop();
+        }
+
+        for def in module.fun_defs.iter() {
+            self.current.push(Some(def.name.start));
+            self.begin(Some(def.name.start));
+            self.write(&format!("function {}", name(&def.name.value)));
+            self.function(&def.params, &def.definition);
+            self.end();
+            self.current.pop();
+        }
+
+        for def in module.impl_defs.iter() {
+            let interface = match &def.interface {
+                Some(interface) => interface,
+                None => {
+                    for fun in def.funs.iter() {
+                        self.current.push(Some(fun.name.start));
+                        self.begin(Some(fun.name.start));
+                        self.write(&format!("function {}.{}", tag(&def.target.value), name(&fun.name.value)));
+                        self.function(&fun.params, &fun.definition);
+                        self.end();
+                        self.current.pop();
+                    }
+                    continue;
+                },
+            };
+            // Structs are named by their metatable, which may come from
+            // another module; other types by their Lua type.
+            let target = if self.tagged.contains(def.target.value.as_str()) {
+                format!("{}.__name", tag(&def.target.value))
+            }
+            else {
+                string_literal(&def.target.value)
+            };
+            self.line(Some(interface.start), &format!( "{}({}, {}, {{"
+                                                     , self.runtime("__impl")
+                                                     , string_literal(&interface.value)
+                                                     , target
+                                                     ));
+            self.indent += 1;
+            for fun in def.funs.iter() {
+                self.current.push(Some(fun.name.start));
+                self.begin(Some(fun.name.start));
+                self.write(&format!("{} = function", name(&fun.name.value)));
+                self.function(&fun.params, &fun.definition);
+                self.write(",");
+                self.end();
+                self.current.pop();
+            }
+            self.indent -= 1;
+            self.line(None, "})");
+        }
+
+        // Consts that couldn't be folded run once the functions they may
+        // call exist.
+        for def in module.const_defs.iter() {
+            self.current.push(Some(def.name.start));
+            match self.consts.get(def.name.value.as_str()) {
+                Some(lua) => self.line(Some(def.name.start), &format!("{} = {}", name(&def.name.value), lua)),
+                None => {
+                    self.hoist(&def.value);
+                    self.begin(Some(def.name.start));
+                    self.write(&format!("{} = ", name(&def.name.value)));
+                    self.expr(&def.value);
+                    self.end();
+                },
+            }
+            self.current.pop();
+        }
+
+        let methods = module.interface_defs.iter()
+                                           .filter(|d| module.interface_exports.contains(&d.name.value))
+                                           .flat_map(|d| d.methods.iter().map(|m| &m.name.value));
+        for export in module.fun_exports.iter().chain(module.enum_exports.iter()).chain(module.const_exports.iter()).chain(methods) {
+            self.line(None, &format!("__module.{} = {}", name(export), name(export)));
+        }
+        for export in tagged_exports(module) {
+            self.line(None, &format!("__module.{0} = {0}", tag(export)));
+        }
+        if self.in_prelude {
+            for native in prelude::NATIVES.iter().chain(prelude::INTERNALS.iter()) {
+                self.line(None, &format!("__module.{} = {}", native, native));
+            }
+        }
+        if self.entry && module.fun_defs.iter().any(|d| d.name.value == "main") {
+            self.line(None, "main()");
+        }
+        self.line(None, "return __module");
+    }
+
+    // Writes the parameter list and body; the caller has written the
+    // `function` keyword and writes whatever follows `end`.
+    fn function(&mut self, params : &'a [FunParam], definition : &'a [Statement]) {
+        self.scopes.push(params.iter().map(|p| p.name.value.as_str()).collect());
+        self.write(&format!("({})", param_list(params)));
+        self.end();
+        self.indent += 1;
+        for param in params {
+            if let Some(default) = &param.default {
+                self.begin(default.start());
+                self.write(&format!("if {0} == nil then {0} = ", name(&param.name.value)));
+                self.expr(default);
+                self.write(" end");
+                self.end();
+            }
+            if param.variadic {
+                self.line(Some(param.name.start), &format!("local {} = {{ ... }}", name(&param.name.value)));
+            }
+        }
+        let loops = std::mem::take(&mut self.loops);
+        if contains_yield(definition) {
+            self.line(None, "return coroutine.wrap(function()");
+            self.indent += 1;
+            self.block(definition);
+            self.indent -= 1;
+            self.line(None, "end)");
+        }
+        else {
+            self.block(definition);
+        }
+        self.loops = loops;
+        self.indent -= 1;
+        self.scopes.pop();
+        self.begin(None);
+        self.write("end");
+    }
+
+    fn block(&mut self, statements : &'a [Statement]) {
+        let mut index = 0;
+        while index < statements.len() {
+            let last = index + 1 == statements.len();
+            match &statements[index] {
+                Statement::If { .. } => index = self.if_chain(statements, index),
+                statement => {
+                    self.statement(statement, last);
+                    index += 1;
+                },
+            }
+        }
+    }
+
+    // Lowers an if followed by its elseif/else siblings and returns the
+    // index of the first statement after the chain.
+    fn if_chain(&mut self, statements : &'a [Statement], mut index : usize) -> usize {
+        let mut ends = 1;
+        if let Statement::If { test, statements: body, .. } = &statements[index] {
+            self.current.push(test.start());
+            self.hoist(test);
+            self.begin(test.start());
+            self.write("if ");
+            self.expr(test);
+            self.write(" then");
+            self.end();
+            self.current.pop();
+            self.nested(body);
+        }
+        index += 1;
+
+        while index < statements.len() {
+            match &statements[index] {
+                Statement::ElseIf { test, statements: body, .. } => {
+                    self.current.push(test.start());
+                    if self.has_try(test) {
+                        // Hoisted temporaries need a block of their own.
+                        self.line(None, "else");
+                        self.indent += 1;
+                        ends += 1;
+                        self.hoist(test);
+                        self.begin(test.start());
+                        self.write("if ");
+                    }
+                    else {
+                        self.begin(test.start());
+                        self.write("elseif ");
+                    }
+                    self.expr(test);
+                    self.write(" then");
+                    self.end();
+                    self.current.pop();
+                    self.nested(body);
+                },
+                Statement::Else(body) => {
+                    self.line(None, "else");
+                    self.nested(body);
+                },
+                _ => break,
+            }
+            index += 1;
+        }
+
+        for _ in 1..ends {
+            self.line(None, "end");
+            self.indent -= 1;
+        }
+        self.line(None, "end");
+        index
+    }
+
+    fn nested(&mut self, statements : &'a [Statement]) {
+        self.indent += 1;
+        self.scopes.push(HashSet::new());
+        self.block(statements);
+        self.scopes.pop();
+        self.indent -= 1;
+    }
+
+    fn loop_body(&mut self, label : &'a Option<PSym>, statements : &'a [Statement]) {
+        self.temps += 1;
+        self.loops.push(LoopTarget { label: label.as_ref().map(|l| l.value.as_str()), id: self.temps, continued: false, broken: false });
+        self.nested(statements);
+        let target = self.loops.pop().unwrap();
+        if target.continued {
+            self.indent += 1;
+            self.line(None, &format!("::__continue{}::", target.id));
+            self.indent -= 1;
+        }
+        self.line(None, "end");
+        if target.broken {
+            self.line(None, &format!("::__break{}::", target.id));
+        }
+    }
+
+    // Lowers `break` or `continue`, jumping with goto unless a plain `break`
+    // out of the innermost loop will do.
+    fn loop_control(&mut self, start : Option<usize>, label : &Option<PSym>, is_break : bool) {
+        let index = match label {
+            Some(label) => self.loops.iter().rposition(|l| l.label == Some(label.value.as_str())),
+            None => self.loops.len().checked_sub(1),
+        };
+        let last = self.loops.len().checked_sub(1);
+        let target = match index {
+            Some(index) => &mut self.loops[index],
+            None => return self.line(start, "break"),
+        };
+        let line = if is_break && index == last {
+            "break".to_string()
+        }
+        else if is_break {
+            target.broken = true;
+            format!("goto __break{}", target.id)
+        }
+        else {
+            target.continued = true;
+            format!("goto __continue{}", target.id)
+        };
+        self.line(start, &line);
+    }
+
+    fn runtime(&self, n : &str) -> String {
+        if self.in_prelude {
+            n.to_string()
+        }
+        else {
+            format!("__prelude.{}", n)
+        }
+    }
+
+    // Where a `set` on a collection the checker knows writes to. Lists are
+    // indexed from 0 in hyper and from 1 in Lua.
+    fn place(&mut self, object : &'a Expr, index : &'a Expr) {
+        self.callee(object);
+        match index {
+            _ if self.collection(object) == Some(Collection::Map) => {
+                self.write("[");
+                self.expr(index);
+                self.write("]");
+            },
+            Expr::Number(n) if n.value.parse::<u64>().is_ok() => {
+                let n = n.value.parse::<u64>().unwrap_or_default();
+                self.write(&format!("[{}]", n + 1));
+            },
+            index => {
+                self.write("[");
+                self.expr(index);
+                self.write(" + 1]");
+            },
+        }
+    }
+
+    fn collection(&self, e : &Expr) -> Option<Collection> {
+        self.collections.get(&(e as *const Expr)).cloned()
+    }
+
+    fn bind(&mut self, n : &'a str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(n);
+        }
+    }
+
+    fn is_local(&self, n : &str) -> bool {
+        self.scopes.iter().any(|s| s.contains(n))
+    }
+
+    // Names the Lua value for an unqualified hyper name, going through the
+    // prelude table unless the module or a local shadows it.
+    fn variable(&self, n : &str) -> String {
+        if self.prelude.contains(n) && !self.defined.contains(n) && !self.enums.contains(n) && !self.is_local(n) {
+            format!("__prelude.{}", name(n))
+        }
+        else {
+            name(n)
+        }
+    }
+
+    fn statement(&mut self, statement : &'a Statement, last : bool) {
+        let start = statement.start();
+        self.current.push(start);
+        match statement {
+            Statement::Expr(e) => {
+                self.hoist(e);
+                self.begin(start);
+                if !self.is_call_statement(e) {
+                    self.write("local _ = ");
+                }
+                self.expr(e);
+                self.end();
+            },
+            Statement::Return(e) => {
+                if let Some(e) = e {
+                    self.hoist(e);
+                }
+                self.begin(start);
+                // Lua only allows return as the last statement of a block.
+                if !last {
+                    self.write("do ");
+                }
+                self.write("return");
+                if let Some(e) = e {
+                    self.write(" ");
+                    self.expr(e);
+                }
+                if !last {
+                    self.write(" end");
+                }
+                self.end();
+            },
+            Statement::Yield(e) => {
+                if let Some(e) = e {
+                    self.hoist(e);
+                }
+                self.begin(start);
+                self.write("coroutine.yield(");
+                if let Some(e) = e {
+                    self.expr(e);
+                }
+                self.write(")");
+                self.end();
+            },
+            Statement::Let { pattern: Pattern::Name(n), expr, .. } => {
+                self.hoist(expr);
+                self.begin(start);
+                self.write(&format!("local {} = ", name(&n.value)));
+                self.expr(expr);
+                self.end();
+                self.bind(&n.value);
+            },
+            Statement::Let { pattern, expr, .. } => {
+                self.hoist(expr);
+                let temp = self.temp("tuple");
+                self.begin(start);
+                self.write(&format!("local {} = ", temp));
+                self.expr(expr);
+                self.end();
+                self.destructure(start, pattern, &temp);
+            },
+            Statement::Set { target: Expr::Index { object, index }, new_value } if self.collection(object).is_none() => {
+                self.hoist_all(&[object, index, new_value]);
+                self.begin(start);
+                self.write(&format!("{}(", self.runtime("__set_index")));
+                self.args(vec![object, index, new_value]);
+                self.write(")");
+                self.end();
+            },
+            Statement::Set { target, new_value } => {
+                let mut parts = operands(target);
+                parts.push(new_value);
+                self.hoist_all(&parts);
+                self.begin(start);
+                match target {
+                    Expr::Index { object, index } => self.place(object, index),
+                    target => self.expr(target),
+                }
+                self.write(" = ");
+                self.expr(new_value);
+                self.end();
+            },
+            Statement::Break { label, .. } => self.loop_control(start, label, true),
+            Statement::Continue { label, .. } => self.loop_control(start, label, false),
+            Statement::Loop { label, statements } => {
+                self.line(start, "while true do");
+                self.loop_body(label, statements);
+            },
+            Statement::While { label, test, statements } => {
+                if self.has_try(test) {
+                    self.line(start, "while true do");
+                    self.indent += 1;
+                    self.hoist(test);
+                    self.begin(start);
+                    self.write("if not (");
+                    self.expr(test);
+                    self.write(") then break end");
+                    self.end();
+                    self.indent -= 1;
+                }
+                else {
+                    self.begin(start);
+                    self.write("while ");
+                    self.expr(test);
+                    self.write(" do");
+                    self.end();
+                }
+                self.loop_body(label, statements);
+            },
+            Statement::Foreach { label, var: Pattern::Name(n), value: None, items: items @ Expr::Range { start: first, end, inclusive, step }, statements } => {
+                self.hoist(items);
+                // Lua's `for` includes its limit. Integer starts and steps only
+                // reach integers, so an exclusive end can be rounded to the last
+                // one; anything else stops when it reaches the end, which is
+                // kept in a local unless it's a number.
+                let ascending = match step.as_deref() {
+                    None => Some(true),
+                    Some(Expr::Number(s)) => Some(!s.value.starts_with('-')),
+                    Some(_) => None,
+                };
+                let integral = |e : &Expr| matches!(e, Expr::Number(n) if !n.value.contains('.'));
+                let rounded = ascending.filter(|_| !inclusive && integral(first) && step.as_deref().is_none_or(integral));
+                let stop = match &**end {
+                    _ if *inclusive || rounded.is_some() => None,
+                    Expr::Number(e) => Some(e.value.clone()),
+                    _ => Some(self.temp("stop")),
+                };
+                let number_end = matches!(**end, Expr::Number(_));
+                if let (Some(stop), false) = (&stop, number_end) {
+                    self.begin(start);
+                    self.write(&format!("local {} = ", stop));
+                    self.expr(end);
+                    self.end();
+                }
+                self.begin(start);
+                self.write(&format!("for {} = ", name(&n.value)));
+                self.expr(first);
+                self.write(", ");
+                match (&stop, rounded, &**end) {
+                    (Some(stop), _, _) => self.write(stop),
+                    (None, Some(ascending), Expr::Number(e)) if integral(end) => {
+                        let e = e.value.parse::<i64>().unwrap_or_default();
+                        self.write(&(if ascending { e - 1 } else { e + 1 }).to_string());
+                    },
+                    (None, Some(true), _) => {
+                        self.write("math.ceil(");
+                        self.expr(end);
+                        self.write(") - 1");
+                    },
+                    (None, Some(false), _) => {
+                        self.write("math.floor(");
+                        self.expr(end);
+                        self.write(") + 1");
+                    },
+                    (None, None, _) => self.expr(end),
+                }
+                if let Some(step) = step {
+                    self.write(", ");
+                    self.expr(step);
+                }
+                self.write(" do");
+                self.end();
+                self.scopes.push(HashSet::new());
+                self.indent += 1;
+                if let Some(stop) = &stop {
+                    self.line(start, &format!("if {} == {} then break end", name(&n.value), stop));
+                }
+                self.copy_assigned(start, n);
+                self.indent -= 1;
+                self.loop_body(label, statements);
+                self.scopes.pop();
+            },
+            Statement::Foreach { label, var, value, items, statements } => {
+                self.hoist(items);
+                self.begin(start);
+                let is_map = self.collection(items) == Some(Collection::Map);
+                let k = loop_var(var);
+                match value.as_ref().map(loop_var) {
+                    Some(v) if is_map => self.write(&format!("for {}, {} in pairs(", k, v)),
+                    Some(v) => self.write(&format!("for {}, {} in {}(", k, v, self.runtime("__entries"))),
+                    None if is_map => self.write(&format!("for {} in pairs(", k)),
+                    None => self.write(&format!("for _, {} in {}(", k, self.runtime("iter"))),
+                }
+                self.expr(items);
+                self.write(") do");
+                self.end();
+                self.scopes.push(HashSet::new());
+                self.indent += 1;
+                for (p, n) in std::iter::once((var, k)).chain(value.iter().map(|v| (v, loop_var(v)))) {
+                    match p {
+                        Pattern::Name(n) => self.copy_assigned(start, n),
+                        p => self.destructure(start, p, &n),
+                    }
+                }
+                self.indent -= 1;
+                self.loop_body(label, statements);
+                self.scopes.pop();
+            },
+            Statement::If { .. } | Statement::ElseIf { .. } | Statement::Else(_) => {
+                self.if_chain(std::slice::from_ref(statement), 0);
+            },
+        }
+        self.current.pop();
+    }
+
+    fn copy_assigned(&mut self, start : Option<usize>, n : &'a PSym) {
+        if self.assigned.contains(&(n as *const PSym)) {
+            self.line(start, &format!("local {0} = {0}", name(&n.value)));
+        }
+        self.bind(&n.value);
+    }
+
+    // Binds the names in a pattern from the Lua table held in `source`. Plain
+    // names are left alone since the loop or let already bound them.
+    fn destructure(&mut self, start : Option<usize>, pattern : &'a Pattern, source : &str) {
+        let ps = match pattern {
+            Pattern::Name(n) => return self.bind(&n.value),
+            Pattern::Tuple(ps) => ps,
+        };
+        let targets = ps.iter().map(|p| match p {
+            Pattern::Name(n) => name(&n.value),
+            Pattern::Tuple(_) => self.temp("tuple"),
+        }).collect::<Vec<String>>();
+        let values = (1..=ps.len()).map(|i| format!("{}[{}]", source, i)).collect::<Vec<String>>();
+        self.line(start, &format!("local {} = {}", targets.join(", "), values.join(", ")));
+        for (p, target) in ps.iter().zip(targets) {
+            self.destructure(start, p, &target);
+        }
+    }
+
+    fn is_call_statement(&self, e : &Expr) -> bool {
+        match e {
+            Expr::Call { func, params, .. } => match &**func {
+                Expr::Dash { func, .. } => !self.is_operator(&func.value, params.len() + 1),
+                Expr::Variable { namespace, name } if namespace.is_empty() => !self.is_operator(&name.value, params.len()),
+                _ => true,
+            },
+            Expr::Dash { func, .. } => !self.is_operator(&func.value, 1),
+            _ => false,
+        }
+    }
+
+    fn is_operator(&self, func : &str, arity : usize) -> bool {
+        match self.builtin(func, arity) {
+            Some(Builtin::Call(_)) | None => false,
+            Some(_) => true,
+        }
+    }
+
+    fn builtin(&self, func : &str, arity : usize) -> Option<Builtin> {
+        if self.defined.contains(func) || self.is_local(func) {
+            return None;
+        }
+        match builtin(func) {
+            Some((b, Some(n))) if n == arity => Some(b),
+            Some((b, None)) => Some(b),
+            _ => None,
+        }
+    }
+
+    fn has_try(&self, e : &Expr) -> bool {
+        let mut found = false;
+        visit_unlambda(e, &mut |e| if let Expr::Try(_) = e { found = true; });
+        found
+    }
+
+    // `?` returns from the enclosing function, so its operand is evaluated
+    // into a temporary ahead of the statement that contains it. So that
+    // nothing is evaluated out of order, whatever comes before it goes
+    // into temporaries too.
+    fn hoist(&mut self, e : &'a Expr) {
+        self.hoist_all(&operands(e));
+        let inner = match e {
+            Expr::Try(inner) => inner,
+            _ => return,
+        };
+        let temp = self.temp("try");
+        self.begin(e.start());
+        self.write(&format!("local {} = ", temp));
+        self.expr(inner);
+        self.end();
+        let test = match self.try_kinds.get(&(e as *const Expr)) {
+            Some(TryKind::Result) => format!("not {}.is_ok", temp),
+            Some(TryKind::Option) => format!("{} == nil", temp),
+            None => format!("{}({})", self.runtime("__failed"), temp),
+        };
+        self.line(e.start(), &format!("if {} then return {} end", test, temp));
+        self.tries.insert(e as *const Expr, temp);
+    }
+
+    // Hoists from operands evaluated in the order given.
+    fn hoist_all(&mut self, es : &[&'a Expr]) {
+        let last = match es.iter().rposition(|e| self.has_try(e)) {
+            Some(last) => last,
+            None => return,
+        };
+        for e in es[..last].iter() {
+            self.hoist(e);
+            if !matches!(e, Expr::Number(_) | Expr::PString(_) | Expr::Bool(_) | Expr::StatementLambda { .. } | Expr::ExprLambda { .. }) {
+                let temp = self.temp("arg");
+                self.begin(e.start());
+                self.write(&format!("local {} = ", temp));
+                self.expr(e);
+                self.end();
+                self.spilled.insert(*e as *const Expr, temp);
+            }
+        }
+        self.hoist(es[last]);
+    }
+
+    // The arguments of a call in parameter order, with None where the
+    // parameter's default is used.
+    // Positional arguments past the parameters are for a variadic one.
+    fn arranged(&self, call : &Expr, args : Vec<&'a Expr>, named : &'a [NamedArg]) -> Vec<Option<&'a Expr>> {
+        let positional = args.len();
+        let given = args.into_iter().chain(named.iter().map(|n| &n.value)).collect::<Vec<&Expr>>();
+        match self.arrangements.get(&(call as *const Expr)) {
+            Some(slots) => {
+                let rest = given[slots.len().min(positional)..positional].iter().map(|e| Some(*e));
+                slots.iter().map(|slot| slot.map(|i| given[i])).chain(rest).collect()
+            },
+            None => given.into_iter().map(Some).collect(),
+        }
+    }
+
+    // Left out arguments are nil, which the callee replaces with the
+    // default. Lua only expands the last argument, so anything after a
+    // spread that isn't last is spliced into one list to unpack.
+    fn call_args(&mut self, mut args : Vec<Option<&'a Expr>>) {
+        while let Some(None) = args.last() {
+            args.pop();
+        }
+        let spread = args.iter().position(|a| matches!(a, Some(Expr::Spread(_))));
+        let spliced = match spread {
+            Some(k) if k + 1 < args.len() => args.split_off(k).into_iter().flatten().collect(),
+            _ => vec![],
+        };
+        for (i, arg) in args.iter().enumerate() {
+            if i != 0 {
+                self.write(", ");
+            }
+            match arg {
+                Some(arg) => self.expr(arg),
+                None => self.write("nil"),
+            }
+        }
+        if !spliced.is_empty() {
+            if !args.is_empty() {
+                self.write(", ");
+            }
+            self.write("table.unpack(");
+            self.splice(spliced);
+            self.write(")");
+        }
+    }
+
+    // A new list of the items, with spread lists' items in place.
+    fn splice(&mut self, items : Vec<&'a Expr>) {
+        self.write(&format!("{}(", self.runtime("__splice")));
+        let mut group = vec![];
+        let mut first = true;
+        for item in items.into_iter().map(Some).chain(std::iter::once(None)) {
+            let spread = match item {
+                Some(Expr::Spread(e)) => Some(&**e),
+                Some(e) => {
+                    group.push(e);
+                    continue;
+                },
+                None => None,
+            };
+            if !group.is_empty() {
+                self.write(if first { "{ " } else { ", { " });
+                self.args(std::mem::take(&mut group));
+                self.write(" }");
+                first = false;
+            }
+            if let Some(e) = spread {
+                if !first {
+                    self.write(", ");
+                }
+                self.expr(e);
+                first = false;
+            }
+        }
+        self.write(")");
+    }
+
+    fn call(&mut self, func : &str, args : Vec<&'a Expr>) -> bool {
+        let b = match self.builtin(func, args.len()) {
+            Some(b) => b,
+            None => return false,
+        };
+        match b {
+            Builtin::Binary(op) => {
+                self.write("(");
+                self.expr(args[0]);
+                self.write(&format!(" {} ", op));
+                self.expr(args[1]);
+                self.write(")");
+            },
+            Builtin::Unary(op) => {
+                self.write(&format!("({}", op));
+                self.expr(args[0]);
+                self.write(")");
+            },
+            Builtin::Concat => {
+                self.write("(");
+                for (i, arg) in args.into_iter().enumerate() {
+                    if i != 0 {
+                        self.write(" .. ");
+                    }
+                    self.expr(arg);
+                }
+                self.write(")");
+            },
+            Builtin::Call(f) => {
+                self.write(&format!("{}(", f));
+                self.args(args);
+                self.write(")");
+            },
+        }
+        true
+    }
+
+    fn args(&mut self, args : Vec<&'a Expr>) {
+        for (i, arg) in args.into_iter().enumerate() {
+            if i != 0 {
+                self.write(", ");
+            }
+            self.expr(arg);
+        }
+    }
+
+    fn lambda(&mut self, start : Option<usize>, params : &'a [FunParam], definition : &'a [Statement]) {
+        self.current.push(start);
+        self.write("function");
+        self.function(params, definition);
+        self.current.pop();
+    }
+
+    fn expr(&mut self, e : &'a Expr) {
+        if let Some(temp) = self.spilled.get(&(e as *const Expr)) {
+            let temp = temp.clone();
+            return self.write(&temp);
+        }
+        match e {
+            Expr::Number(n) => self.write(&n.value),
+            Expr::PString(s) => self.write(&string_literal(&s.value)),
+            Expr::Interpolate(parts) => {
+                self.write("(");
+                for (i, part) in parts.iter().enumerate() {
+                    if i != 0 {
+                        self.write(" .. ");
+                    }
+                    match part {
+                        StringPart::Text(t) => self.write(&string_literal(&t.value)),
+                        StringPart::Expr(e @ (Expr::PString(_) | Expr::Interpolate(_))) => self.expr(e),
+                        StringPart::Expr(e) => {
+                            self.write("tostring(");
+                            self.expr(e);
+                            self.write(")");
+                        },
+                    }
+                }
+                self.write(")");
+            },
+            Expr::Bool(b) => self.write(if *b { "true" } else { "false" }),
+            Expr::Variable { namespace, name: n } => {
+                if let Some(lua) = self.consts.get(n.value.as_str()).filter(|_| namespace.is_empty() && !self.is_local(&n.value)) {
+                    self.write(&lua.clone());
+                }
+                else if namespace.is_empty() {
+                    self.write(&self.variable(&n.value));
+                }
+                else {
+                    // Names from a module a `use` names go through its local.
+                    let mut path = namespace.iter().map(|p| name(&p.value)).chain(std::iter::once(name(&n.value))).collect::<Vec<String>>();
+                    for k in (1..=namespace.len()).rev() {
+                        let prefix = namespace[..k].iter().map(|p| p.value.as_str()).collect::<Vec<&str>>().join("::");
+                        if let Some(local) = self.namespaces.get(&prefix) {
+                            path.splice(..k, std::iter::once(local.clone()));
+                            break;
+                        }
+                    }
+                    self.write(&path.join("."));
+                }
+            },
+            Expr::StatementLambda { params, definition, .. } => self.lambda(e.start(), params, definition),
+            Expr::ExprLambda { params, definition, .. } => {
+                self.scopes.push(params.iter().map(|p| p.name.value.as_str()).collect());
+                let rest = params.iter().find(|p| p.variadic).map(|p| format!("local {} = {{ ... }} ", name(&p.name.value)));
+                let params = param_list(params);
+                if self.has_try(definition) {
+                    // Give the hoisted temporaries a function body to live in.
+                    self.current.push(e.start());
+                    self.write(&format!("function({})", params));
+                    self.end();
+                    self.indent += 1;
+                    if let Some(rest) = rest {
+                        self.line(None, rest.trim_end());
+                    }
+                    self.hoist(definition);
+                    self.begin(definition.start());
+                    self.write("return ");
+                    self.expr(definition);
+                    self.end();
+                    self.indent -= 1;
+                    self.begin(None);
+                    self.write("end");
+                    self.current.pop();
+                }
+                else {
+                    self.write(&format!("function({}) {}return ", params, rest.unwrap_or_default()));
+                    self.expr(definition);
+                    self.write(" end");
+                }
+                self.scopes.pop();
+            },
+            Expr::Call { func, params, named } => {
+                let plain = named.is_empty()
+                         && !self.arrangements.contains_key(&(e as *const Expr))
+                         && !params.iter().any(|p| matches!(p, Expr::Spread(_)));
+                match &**func {
+                    Expr::Dash { object, func } => {
+                        let args = std::iter::once(&**object).chain(params.iter()).collect::<Vec<&Expr>>();
+                        if !plain || !self.call(&func.value, args.clone()) {
+                            self.write(&format!("{}(", self.variable(&func.value)));
+                            let args = self.arranged(e, args, named);
+                            self.call_args(args);
+                            self.write(")");
+                        }
+                    },
+                    Expr::Dot { object, slot } if self.method_calls.contains(&(e as *const Expr)) => {
+                        self.callee(object);
+                        self.write(&format!(":{}(", name(&slot.value)));
+                        let args = self.arranged(e, std::iter::once(&**object).chain(params.iter()).collect(), named);
+                        self.call_args(args.into_iter().skip(1).collect());
+                        self.write(")");
+                    },
+                    Expr::Variable { namespace, name: n } if plain && namespace.is_empty() && self.call(&n.value, params.iter().collect()) => (),
+                    func => {
+                        self.callee(func);
+                        self.write("(");
+                        let args = self.arranged(e, params.iter().collect(), named);
+                        self.call_args(args);
+                        self.write(")");
+                    },
+                }
+            },
+            Expr::Dash { object, func } => {
+                if !self.call(&func.value, vec![&**object]) {
+                    self.write(&format!("{}(", self.variable(&func.value)));
+                    self.expr(object);
+                    self.write(")");
+                }
+            },
+            Expr::Try(_) => {
+                let temp = self.tries.get(&(e as *const Expr)).cloned().unwrap_or_default();
+                match self.try_kinds.get(&(e as *const Expr)) {
+                    Some(TryKind::Result) => self.write(&format!("{}.value", temp)),
+                    Some(TryKind::Option) => self.write(&temp),
+                    None => self.write(&format!("{}({})", self.runtime("__value"), temp)),
+                }
+            },
+            Expr::Index { object, index } if self.collection(object).is_none() => {
+                self.write(&format!("{}(", self.runtime("__index")));
+                self.args(vec![object, index]);
+                self.write(")");
+            },
+            // Reads go through the prelude, which fails on missing keys and
+            // indexes rather than giving nil.
+            Expr::Index { object, index } => {
+                let read = match self.collection(object) {
+                    Some(Collection::Map) => "__key",
+                    _ => "get",
+                };
+                self.write(&format!("{}(", self.runtime(read)));
+                self.args(vec![object, index]);
+                self.write(")");
+            },
+            Expr::Dot { object, slot } => {
+                self.callee(object);
+                self.write(&format!(".{}", name(&slot.value)));
+            },
+            Expr::StructCons { name: struct_name, slots, base } => {
+                if let Some(base) = base {
+                    self.write(&format!("{}(", self.runtime("__update")));
+                    self.expr(base);
+                    self.write(", ");
+                }
+                let tagged = struct_name.as_ref().filter(|n| base.is_none() && self.tagged.contains(n.value.as_str()));
+                if tagged.is_some() {
+                    self.write("setmetatable(");
+                }
+                let mut items = vec![];
+                for slot in slots {
+                    items.push(Ok(slot));
+                }
+                if let (Some(n), None) = (struct_name, base) {
+                    let defaults = self.defaults.get(n.value.as_str()).cloned().unwrap_or_default();
+                    for (slot, lua) in defaults {
+                        if !slots.iter().any(|s| s.name.value == slot) {
+                            items.push(Err((slot, lua)));
+                        }
+                    }
+                }
+                self.write("{ ");
+                for (i, item) in items.into_iter().enumerate() {
+                    if i != 0 {
+                        self.write(", ");
+                    }
+                    match item {
+                        Ok(slot) => {
+                            self.write(&format!("{} = ", name(&slot.name.value)));
+                            self.expr(&slot.value);
+                        },
+                        Err((slot, lua)) => self.write(&format!("{} = {}", name(slot), lua)),
+                    }
+                }
+                self.write(" }");
+                if let Some(n) = tagged {
+                    self.write(&format!(", {})", tag(&n.value)));
+                }
+                if base.is_some() {
+                    self.write(")");
+                }
+            },
+            Expr::Range { start, end, inclusive, step } => {
+                self.write(&format!("{}(", self.runtime("__range")));
+                self.args(vec![start, end]);
+                self.write(", ");
+                match step {
+                    Some(step) => self.expr(step),
+                    None => self.write("1"),
+                }
+                self.write(&format!(", {})", inclusive));
+            },
+            Expr::ListCons(es) if es.iter().any(|e| matches!(e, Expr::Spread(_))) => self.splice(es.iter().collect()),
+            Expr::ListCons(es) | Expr::Tuple(es) => {
+                self.write("{ ");
+                self.args(es.iter().collect());
+                self.write(" }");
+            },
+            Expr::Spread(e) => {
+                self.write("table.unpack(");
+                self.expr(e);
+                self.write(")");
+            },
+            Expr::MapCons(entries) => {
+                self.write(&format!("{}({{ ", self.runtime("__map")));
+                for (i, entry) in entries.iter().enumerate() {
+                    if i != 0 {
+                        self.write(", ");
+                    }
+                    self.write("[");
+                    self.expr(&entry.key);
+                    self.write("] = ");
+                    self.expr(&entry.value);
+                }
+                self.write(" })");
+            },
+            Expr::ResultCons(ResultValue::Okay(e)) => {
+                self.write("{ is_ok = true, value = ");
+                self.expr(e);
+                self.write(" }");
+            },
+            Expr::ResultCons(ResultValue::Error(e)) => {
+                self.write("{ is_ok = false, value = ");
+                self.expr(e);
+                self.write(" }");
+            },
+            // The checker keeps options out of the places nil can't go, so
+            // they lower to the value or nil.
+            Expr::OptionCons(OptionValue::Some(e)) => self.expr(e),
+            Expr::OptionCons(OptionValue::None) => self.write("nil"),
+        }
+    }
+
+    // Lua only allows names, calls and parenthesised expressions before
+    // `(` and `.`.
+    fn callee(&mut self, e : &'a Expr) {
+        match e {
+            Expr::Variable { .. } | Expr::Call { .. } | Expr::Dot { .. } | Expr::Index { .. } | Expr::Try(_) => self.expr(e),
+            e => {
+                self.write("(");
+                self.expr(e);
+                self.write(")");
+            },
+        }
+    }
+}
+
+// A variadic parameter is Lua's `...`.
+fn param_list(params : &[FunParam]) -> String {
+    let names = params.iter().map(|p| if p.variadic { "...".to_string() } else { name(&p.name.value) });
+    names.collect::<Vec<String>>().join(", ")
+}
+
+// Loop variables that destructure get a temporary named after where the
+// pattern starts, so the loop head and body agree on it.
+fn loop_var(pattern : &Pattern) -> String {
+    match pattern {
+        Pattern::Name(n) => name(&n.value),
+        p => format!("__item{}", p.start().unwrap_or_default()),
+    }
+}
+
+// Visits an expression and its subexpressions, children first, without
+// entering lambda bodies.
+// What evaluating an expression evaluates first, in order, leaving out
+// what can't be put in a temporary in its place, like the name of a
+// function called.
+fn operands(e : &Expr) -> Vec<&Expr> {
+    fn unspread(e : &Expr) -> &Expr {
+        match e {
+            Expr::Spread(inner) => inner,
+            e => e,
+        }
+    }
+    match e {
+        Expr::Call { func, params, named } => {
+            let func = match &**func {
+                Expr::Dot { object, .. } | Expr::Dash { object, .. } => Some(&**object),
+                Expr::Variable { .. } => None,
+                func => Some(func),
+            };
+            func.into_iter().chain(params.iter().map(unspread)).chain(named.iter().map(|n| &n.value)).collect()
+        },
+        Expr::Try(inner) | Expr::Spread(inner) | Expr::Dot { object: inner, .. } | Expr::Dash { object: inner, .. } => vec![inner],
+        Expr::Index { object, index } => vec![object, index],
+        Expr::StructCons { slots, base, .. } => base.iter().map(|b| &**b).chain(slots.iter().map(|s| &s.value)).collect(),
+        Expr::ListCons(es) | Expr::Tuple(es) => es.iter().map(unspread).collect(),
+        Expr::MapCons(entries) => entries.iter().flat_map(|e| vec![&e.key, &e.value]).collect(),
+        Expr::Range { start, end, step, .. } => vec![&**start, &**end].into_iter().chain(step.iter().map(|s| &**s)).collect(),
+        Expr::Interpolate(parts) => parts.iter().filter_map(|p| match p {
+            StringPart::Expr(e) => Some(e),
+            StringPart::Text(_) => None,
+        }).collect(),
+        Expr::ResultCons(ResultValue::Okay(e)) | Expr::ResultCons(ResultValue::Error(e)) | Expr::OptionCons(OptionValue::Some(e)) => vec![e],
+        _ => vec![],
+    }
+}
+
+pub fn visit_unlambda<'a>(e : &'a Expr, f : &mut dyn FnMut(&'a Expr)) {
+    match e {
+        Expr::Call { func, params, named } => {
+            visit_unlambda(func, f);
+            for p in params.iter().chain(named.iter().map(|n| &n.value)) {
+                visit_unlambda(p, f);
+            }
+        },
+        Expr::Try(inner) | Expr::Spread(inner) => visit_unlambda(inner, f),
+        Expr::Dot { object, .. } | Expr::Dash { object, .. } => visit_unlambda(object, f),
+        Expr::Index { object, index } => {
+            visit_unlambda(object, f);
+            visit_unlambda(index, f);
+        },
+        Expr::StructCons { slots, base, .. } => {
+            for s in slots {
+                visit_unlambda(&s.value, f);
+            }
+            if let Some(base) = base {
+                visit_unlambda(base, f);
+            }
+        },
+        Expr::ListCons(es) | Expr::Tuple(es) => {
+            for e in es {
+                visit_unlambda(e, f);
+            }
+        },
+        Expr::MapCons(entries) => {
+            for entry in entries {
+                visit_unlambda(&entry.key, f);
+                visit_unlambda(&entry.value, f);
+            }
+        },
+        Expr::Range { start, end, step, .. } => {
+            visit_unlambda(start, f);
+            visit_unlambda(end, f);
+            if let Some(step) = step {
+                visit_unlambda(step, f);
+            }
+        },
+        Expr::Interpolate(parts) => {
+            for part in parts {
+                if let StringPart::Expr(e) = part {
+                    visit_unlambda(e, f);
+                }
+            }
+        },
+        Expr::ResultCons(ResultValue::Okay(e)) | Expr::ResultCons(ResultValue::Error(e)) => visit_unlambda(e, f),
+        Expr::OptionCons(OptionValue::Some(e)) => visit_unlambda(e, f),
+        _ => (),
+    }
+    f(e);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::proc::parser::parse;
+    use crate::typing::checker::Checker;
+
+    fn lower_str(input : &str) -> LuaModule {
+        let module = parse(input).expect("parse failed");
+        let mut checker = Checker::new();
+        checker.check_mod(&module);
+        let mut lowering = Lowering::new().with_collections(checker.collections())
+                                          .with_method_calls(checker.method_calls())
+                                          .with_arrangements(checker.arrangements())
+                                          .with_try_kinds(checker.try_kinds())
+                                          .with_assigned_loop_vars(checker.assigned_loop_vars());
+        lowering.lower_mod(&module);
+        lowering.finish()
+    }
+
+    #[test]
+    fn should_lower_builtins_to_operators() {
+        let output = lower_str(r#"
+pub fun f(x : number) -> number {
+    return add(x, mul(x, 2));
+}"#);
+        assert!( output.code.contains("return (x + (x * 2))") );
+        assert!( output.code.contains("__module.f = f") );
+    }
+
+    #[test]
+    fn should_hoist_try() {
+        let output = lower_str(r#"
+fun f() -> Result<number, string> {
+    let r = Ok(2);
+    let x = add(r?, 1);
+    let y = add(g()?, x);
+    return Ok(y);
+}"#);
+        assert!( output.code.contains("local __try1 = r") );
+        assert!( output.code.contains("if not __try1.is_ok then return __try1 end") );
+        assert!( output.code.contains("local x = (__try1.value + 1)") );
+        // g is unknown, so it may return a result or an option.
+        assert!( output.code.contains("local __try2 = g()") );
+        assert!( output.code.contains("if __prelude.__failed(__try2) then return __try2 end") );
+        assert!( output.code.contains("local y = (__prelude.__value(__try2) + x)") );
+    }
+
+    #[test]
+    fn should_hoist_what_comes_before_try() {
+        let output = lower_str(r#"
+fun f(g : fun() -> number, h : fun() -> Result<number, string>) -> Result<number, string> {
+    let x = add(g(), h()?);
+    let y = add(add(x, 2), add(h()?, g()));
+    return Ok(y);
+}"#);
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        let at = |line : &str| lines.iter().position(|l| *l == line).unwrap_or_else(|| panic!( "missing {} in\n{}", line, output.code ));
+        assert!( at("local __arg1 = g()") < at("local __try2 = h()") );
+        assert!( at("local x = (__arg1 + __try2.value)") > at("if not __try2.is_ok then return __try2 end") );
+        assert!( at("local __arg3 = (x + 2)") < at("local __try4 = h()") );
+        assert!( lines.contains(&"local y = (__arg3 + (__try4.value + g()))") );
+    }
+
+    #[test]
+    fn should_lower_if_chain() {
+        let output = lower_str(r#"
+fun f(x : number) -> number {
+    if lt(x, 0) {
+        return 0;
+    }
+    elseif eq(x, 0) {
+        return 1;
+    }
+    else {
+        return 2;
+    }
+}"#);
+        assert!( output.code.contains("if (x < 0) then") );
+        assert!( output.code.contains("elseif (x == 0) then") );
+        assert!( output.code.contains("else\n") );
+    }
+
+    #[test]
+    fn should_map_every_line() {
+        let output = lower_str(r#"
+fun f() {
+    print(1);
+}"#);
+        assert_eq!( output.lines.len(), output.code.lines().count() );
+        let line = output.code.lines().position(|l| l.trim() == "print(1)").unwrap();
+        assert_eq!( output.lines[line], Some(15) );
+    }
+
+    #[test]
+    fn should_wrap_generator_in_coroutine() {
+        let output = lower_str(r#"
+fun f() -> number {
+    yield 1;
+}"#);
+        assert!( output.code.contains("return coroutine.wrap(function()") );
+        assert!( output.code.contains("coroutine.yield(1)") );
+    }
+
+    #[test]
+    fn should_reach_prelude_unless_shadowed() {
+        let output = lower_str(r#"
+fun reverse(x : number) -> number {
+    return x;
+}
+fun f(items : List<number>) -> number {
+    let xs = map(items, |x| x);
+    let get = |i| reverse(i);
+    foreach x in xs {
+        print(x);
+    }
+    return get(0);
+}"#);
+        assert!( output.code.starts_with("local __prelude = require(\"hyper_prelude\")") );
+        assert!( output.code.contains("local xs = __prelude.map(items, function(x) return x end)") );
+        assert!( output.code.contains("local get = function(i) return reverse(i) end") );
+        assert!( output.code.contains("for _, x in __prelude.iter(xs) do") );
+        assert!( output.code.contains("return get(0)") );
+    }
+
+    #[test]
+    fn should_bind_extern_to_lua_path() {
+        let output = lower_str(r#"
+pub extern fun format(fmt : string, arg : number) -> string = "string.format";
+extern fun encode(value : any) -> string = "encode" from "cjson";
+fun f() -> string {
+    return format("%d", 1);
+}"#);
+        assert!( output.code.contains("local format = string.format") );
+        assert!( output.code.contains("local encode = require(\"cjson\").encode") );
+        assert!( output.code.contains("return format(\"%d\", 1)") );
+        assert!( output.code.contains("__module.format = format") );
+    }
+
+    #[test]
+    fn should_shift_index_to_one_based() {
+        let output = lower_str(r#"
+fun f(i : number) -> number {
+    let xs = [1, 2];
+    set xs[i] = xs[0];
+    return xs[add(i, 1)];
+}"#);
+        assert!( output.code.contains("xs[i + 1] = __prelude.get(xs, 0)") );
+        assert!( output.code.contains("return __prelude.get(xs, (i + 1))") );
+    }
+
+    #[test]
+    fn should_lower_maps_by_what_the_checker_knows() {
+        let output = lower_str(r#"
+fun f(unknown : any) -> number {
+    let m = #{ "a": 1 };
+    set m["b"] = m["a"];
+    set unknown[0] = 2;
+    foreach k, v in m {
+        print(k, v);
+    }
+    foreach k in unknown {
+        print(k);
+    }
+    return unknown[1];
+}"#);
+        assert!( output.code.contains("local m = __prelude.__map({ [\"a\"] = 1 })") );
+        assert!( output.code.contains("m[\"b\"] = __prelude.__key(m, \"a\")") );
+        assert!( output.code.contains("__prelude.__set_index(unknown, 0, 2)") );
+        assert!( output.code.contains("for k, v in pairs(m) do") );
+        assert!( output.code.contains("for _, k in __prelude.iter(unknown) do") );
+        assert!( output.code.contains("return __prelude.__index(unknown, 1)") );
+    }
+
+    #[test]
+    fn should_lower_tuples_to_tables() {
+        let output = lower_str(r#"
+fun f() -> (number, number) {
+    let (a, (b, c)) = (1, (2, 3));
+    foreach (x, y) in [(a, b)] {
+        print(x, y, c);
+    }
+    return (a, c);
+}"#);
+        assert!( output.code.contains("local __tuple1 = { 1, { 2, 3 } }") );
+        assert!( output.code.contains("local a, __tuple2 = __tuple1[1], __tuple1[2]") );
+        assert!( output.code.contains("local b, c = __tuple2[1], __tuple2[2]") );
+        assert!( output.code.contains("for _, __item") );
+        assert!( output.code.contains("local x, y = __item") );
+        assert!( output.code.contains("return { a, c }") );
+    }
+
+    #[test]
+    fn should_lower_interpolation_to_concatenation() {
+        let output = lower_str(r#"
+fun f(name : string, count : number) -> string {
+    return "user {name} has {count} items";
+}"#);
+        assert!( output.code.contains(r#"return ("user " .. tostring(name) .. " has " .. tostring(count) .. " items")"#) );
+    }
+
+    #[test]
+    fn should_lower_loop_control_to_goto() {
+        let output = lower_str(r#"
+fun f(xs : List<number>) {
+    'outer: loop {
+        foreach x in xs {
+            if eq(x, 1) {
+                continue;
+            }
+            if eq(x, 2) {
+                continue 'outer;
+            }
+            if eq(x, 3) {
+                break 'outer;
+            }
+            break;
+        }
+    }
+}"#);
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"while true do") );
+        assert!( lines.contains(&"goto __continue2") );
+        assert!( lines.contains(&"goto __continue1") );
+        assert!( lines.contains(&"goto __break1") );
+        assert!( lines.contains(&"::__continue1::") );
+        assert!( lines.contains(&"::__continue2::") );
+        assert!( lines.contains(&"::__break1::") );
+        assert!( !lines.contains(&"::__break2::") );
+    }
+
+    #[test]
+    fn should_lower_range_foreach_to_numeric_for() {
+        let output = lower_str(r#"
+fun f(xs : List<number>, n : number) -> List<number> {
+    foreach i in 0..10 {
+        print(i);
+    }
+    foreach i in 0..len(xs) {
+        print(i);
+    }
+    foreach i in 10..0 by -2 {
+        print(i);
+    }
+    foreach i in n..=10 by 0.5 {
+        print(i);
+    }
+    foreach i in n..len(xs) {
+        print(i);
+    }
+    foreach i in 0..3 by 0.5 {
+        print(i);
+    }
+    foreach i in n..10 {
+        print(i);
+    }
+    return 0..n;
+}"#);
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"for i = 0, 9 do") );
+        assert!( lines.contains(&"for i = 0, math.ceil((#xs)) - 1 do") );
+        assert!( lines.contains(&"for i = 10, 1, -2 do") );
+        assert!( lines.contains(&"for i = n, 10, 0.5 do") );
+        let stop = lines.iter().find_map(|l| l.strip_prefix("local ")?.strip_suffix(" = (#xs)")).expect("missing stop");
+        assert!( lines.contains(&format!("for i = n, {} do", stop).as_str()) );
+        assert!( lines.contains(&format!("if i == {} then break end", stop).as_str()) );
+        assert!( lines.contains(&"for i = 0, 3, 0.5 do") );
+        assert!( lines.contains(&"for i = n, 10 do") );
+        assert_eq!( lines.iter().filter(|l| **l == "if i == 3 then break end").count(), 1 );
+        assert_eq!( lines.iter().filter(|l| **l == "if i == 10 then break end").count(), 1 );
+        assert!( lines.contains(&"return __prelude.__range(0, n, 1, false)") );
+    }
+
+    #[test]
+    fn should_inline_folded_consts() {
+        let output = lower_str(r#"
+const MINUTE : number = 60;
+pub const HOUR : number = mul(MINUTE, 60);
+const START : number = now();
+fun now() -> number {
+    return HOUR;
+}
+fun f(MINUTE : number) -> number {
+    return add(MINUTE, START);
+}"#);
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"local MINUTE, HOUR, START, now, f") );
+        assert!( lines.contains(&"return 3600") );
+        assert!( lines.contains(&"return (MINUTE + START)") );
+        assert!( lines.contains(&"HOUR = 3600") );
+        assert!( lines.contains(&"START = now()") );
+        assert!( lines.contains(&"__module.HOUR = HOUR") );
+    }
+
+    #[test]
+    fn should_lower_impls_to_runtime_dispatch() {
+        let output = lower_str(r#"
+pub interface Show {
+    fun show(self) -> string;
+}
+struct Point { x : number }
+struct Plain { x : number }
+impl Show for Point {
+    fun show(self) -> string {
+        return "point";
+    }
+}
+fun f() -> string {
+    let p = new Plain { x: 2 };
+    return show(new Point { x: 1 });
+}"#);
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"local __struct_Point = { __name = \"Point\" }") );
+        assert!( lines.contains(&"local show = __prelude.__method(\"Show\", \"show\")") );
+        assert!( lines.contains(&"local __struct_Plain = { __name = \"Plain\" }") );
+        assert!( lines.contains(&"local p = setmetatable({ x = 2 }, __struct_Plain)") );
+        assert!( lines.contains(&"return show(setmetatable({ x = 1 }, __struct_Point))") );
+        assert!( lines.contains(&"__prelude.__impl(\"Show\", __struct_Point.__name, {") );
+        assert!( lines.contains(&"show = function(self)") );
+        assert!( lines.contains(&"end,") );
+        assert!( lines.contains(&"__module.show = show") );
+    }
+
+    #[test]
+    fn should_lower_method_calls_to_colon_calls() {
+        let output = lower_str(r#"
+struct Point { x : number, f : fun() -> number }
+impl Point {
+    fun get(self) -> number {
+        return self.x;
+    }
+}
+fun f(p : Point) -> number {
+    let g = |q| q.get();
+    return add(p.get(), p.f());
+}"#);
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"__struct_Point.__index = __struct_Point") );
+        assert!( lines.contains(&"function __struct_Point.get(self)") );
+        assert!( lines.contains(&"local g = function(q) return q:get() end") );
+        assert!( lines.contains(&"return (p:get() + p.f())") );
+    }
+
+    #[test]
+    fn should_fill_defaults_and_lower_updates() {
+        let output = lower_str(r#"
+const N : number = 2;
+struct Config { name : string, retries : number = add(N, 1), tags : List<string> = [] }
+fun f(base : Config) -> Config {
+    let N = 10;
+    let a = new Config { name: "a" };
+    return new Config { retries: N, ..base };
+}"#);
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"function __struct_Config.__default_tags() return {  } end") );
+        assert!( !lines.contains(&"function __struct_Config.__default_retries() return (N + 1) end") );
+        assert!( lines.contains(&"local a = setmetatable({ name = \"a\", retries = 3, tags = __struct_Config.__default_tags() }, __struct_Config)") );
+        assert!( lines.contains(&"return __prelude.__update(base, { retries = N })") );
+    }
+
+    #[test]
+    fn should_lower_named_arguments_to_positional() {
+        let output = lower_str(r#"
+fun connect(host : string, port : number = 80, secure : bool = false) -> string {
+    return host;
+}
+fun f() {
+    connect("a");
+    connect(secure: true, host: "b");
+    connect("c", port: 1);
+}"#);
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"if port == nil then port = 80 end") );
+        assert!( lines.contains(&"if secure == nil then secure = false end") );
+        assert!( lines.contains(&"connect(\"a\")") );
+        assert!( lines.contains(&"connect(\"b\", nil, true)") );
+        assert!( lines.contains(&"connect(\"c\", 1)") );
+    }
+
+    #[test]
+    fn should_lower_variadics_to_lua_varargs() {
+        let output = lower_str(r#"
+fun log(level : string, ...parts : List<string>) {
+}
+fun f(ws : List<string>) {
+    log("a", ...ws);
+    log("a", ...ws, "b");
+    let xs = [1, ...ws];
+}"#);
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"function log(level, ...)") );
+        assert!( lines.contains(&"local parts = { ... }") );
+        assert!( lines.contains(&"log(\"a\", table.unpack(ws))") );
+        assert!( lines.contains(&"log(\"a\", table.unpack(__prelude.__splice(ws, { \"b\" })))") );
+        assert!( lines.contains(&"local xs = __prelude.__splice({ 1 }, ws)") );
+    }
+
+    #[test]
+    fn should_lower_options_to_nil() {
+        let output = lower_str(r#"
+fun f(xs : List<number>) {
+    let o = Some(1);
+    let none = None;
+    let g = || {
+        let x = o?;
+        return Some(add(x, 1));
+    };
+}"#);
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"local o = 1") );
+        assert!( lines.contains(&"local none = nil") );
+        assert!( lines.contains(&"if __try1 == nil then return __try1 end") );
+        assert!( lines.contains(&"local x = __try1") );
+    }
+
+    #[test]
+    fn should_copy_loop_variables_that_are_set() {
+        let output = lower_str(r#"
+fun f(m : Map<string, number>) {
+    foreach k, v in m {
+        set k = concat(k, "!");
+    }
+    foreach i in 0..3 {
+        let g = || { set i = 5; };
+    }
+    foreach x in [1] {
+        print(x);
+    }
+}"#);
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"local k = k") );
+        assert!( lines.contains(&"local i = i") );
+        assert!( !lines.contains(&"local v = v") );
+        assert!( !lines.contains(&"local x = x") );
+    }
+
+    #[test]
+    fn should_require_imported_modules() {
+        let target = parse(r#"
+pub struct Circle { r : number, label : string = "circle" }
+impl Circle {
+    fun area(self) -> number { return self.r; }
+}
+pub fun unit() -> Circle { return new Circle { r: 1 }; }
+pub enum Color { Red }
+pub interface Show { fun show(self) -> string; }
+fun hidden() { }"#).expect("parse failed");
+        let module = parse(r#"
+use geometry::shapes::{*};
+struct Circle2 { r : number }
+impl Show for Circle {
+    fun show(self) -> string { return "circle"; }
+}
+impl Show for number {
+    fun show(self) -> string { return "number"; }
+}
+fun main() {
+    print(new Circle { r: 2 }, unit(), shapes::unit(), geometry::shapes::Color::Red);
+}"#).expect("parse failed");
+        let imports = vec![Some(Imported::new("geometry.shapes".to_string(), &module.uses[0], &target))];
+        let mut lowering = Lowering::new().with_imports(imports).with_module_name("main".to_string()).with_entry(true);
+        lowering.lower_mod(&module);
+        let output = lowering.finish();
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"local __use1 = require(\"geometry.shapes\")") );
+        assert!( lines.contains(&"local unit, Color, show = __use1.unit, __use1.Color, __use1.show") );
+        assert!( lines.contains(&"local __struct_Circle = __use1.__struct_Circle") );
+        assert!( lines.contains(&"print(setmetatable({ r = 2, label = __struct_Circle.__default_label() }, __struct_Circle), unit(), __use1.unit(), __use1.Color.Red)") );
+        assert!( lines.contains(&"local __struct_Circle2 = { __name = \"main.Circle2\" }") );
+        assert!( lines.contains(&"__prelude.__impl(\"Show\", __struct_Circle.__name, {") );
+        assert!( lines.contains(&"__prelude.__impl(\"Show\", \"number\", {") );
+        assert!( lines.ends_with(&["main()", "return __module"]) );
+        let mut lowering = Lowering::new().with_module_name("geometry.shapes".to_string());
+        lowering.lower_mod(&target);
+        let output = lowering.finish();
+        let lines = output.code.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+        assert!( lines.contains(&"function __struct_Circle.__default_label() return \"circle\" end") );
+        assert!( lines.contains(&"local Color = { Red = \"Color::Red\" }") );
+        assert!( lines.contains(&"return setmetatable({ r = 1, label = \"circle\" }, __struct_Circle)") );
+    }
+}
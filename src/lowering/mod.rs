@@ -0,0 +1,4 @@
+
+pub mod lua;
+pub mod fold;
+pub mod source_map;
@@ -0,0 +1,131 @@
+
+use crate::diagnostic::line_column;
+use crate::json::Json;
+
+#[derive(Debug, PartialEq)]
+pub struct Mapping {
+    pub generated : usize,
+    pub line : usize,
+    pub column : usize,
+}
+
+#[derive(Debug)]
+pub struct SourceMap {
+    pub file : String,
+    pub source : String,
+    pub mappings : Vec<Mapping>,
+}
+
+impl SourceMap {
+    pub fn new(file : &str, source_path : &str, source : &str, lines : &[Option<usize>]) -> Self {
+        let mappings = lines.iter()
+                            .enumerate()
+                            .filter_map(|(i, offset)| offset.map(|o| (i + 1, line_column(source, o))))
+                            .map(|(generated, (line, column))| Mapping { generated, line, column })
+                            .collect();
+        SourceMap { file: file.to_string(), source: source_path.to_string(), mappings }
+    }
+
+    // Unmapped lines take the mapping of the closest line above them.
+    pub fn lookup(&self, generated : usize) -> Option<&Mapping> {
+        self.mappings.iter().take_while(|m| m.generated <= generated).last()
+    }
+
+    pub fn to_json(&self) -> Json {
+        let mappings = self.mappings.iter()
+                                    .map(|m| Json::Array(vec![ Json::Number(m.generated as f64)
+                                                             , Json::Number(m.line as f64)
+                                                             , Json::Number(m.column as f64)
+                                                             ]))
+                                    .collect();
+        Json::Object(vec![ ("version".to_string(), Json::Number(1.0))
+                         , ("file".to_string(), Json::String(self.file.clone()))
+                         , ("source".to_string(), Json::String(self.source.clone()))
+                         , ("mappings".to_string(), Json::Array(mappings))
+                         ])
+    }
+
+    pub fn from_json(json : &Json) -> Option<SourceMap> {
+        let file = json.get("file")?.as_str()?.to_string();
+        let source = json.get("source")?.as_str()?.to_string();
+        let mut mappings = vec![];
+        for m in json.get("mappings")?.as_array()? {
+            match m.as_array()? {
+                [generated, line, column] => mappings.push(Mapping { generated: generated.as_usize()?
+                                                                   , line: line.as_usize()?
+                                                                   , column: column.as_usize()?
+                                                                   }),
+                _ => return None,
+            }
+        }
+        Some(SourceMap { file, source, mappings })
+    }
+}
+
+// Rewrites every `path.lua:line` in a Lua error or traceback to the hyper
+// source location, for files that `load_map` can find a source map for.
+pub fn rewrite_traceback(text : &str, load_map : &mut dyn FnMut(&str) -> Option<SourceMap>) -> String {
+    let mut ret = String::new();
+    let mut rest = text;
+    while let Some(index) = rest.find(".lua:") {
+        let path_start = rest[..index].rfind(|c : char| c.is_whitespace() || c == '\'' || c == '"' || c == '(' || c == '<')
+                                      .map(|i| i + 1)
+                                      .unwrap_or(0);
+        let after = &rest[index + ".lua:".len()..];
+        let digits = after.chars().take_while(|c| c.is_ascii_digit()).count();
+        let path = &rest[path_start..index + ".lua".len()];
+
+        let mapped = match after[..digits].parse::<usize>() {
+            Ok(line) => load_map(path).and_then(|map| map.lookup(line).map(|m| format!("{}:{}:{}", map.source, m.line, m.column))),
+            Err(_) => None,
+        };
+
+        match mapped {
+            Some(location) => {
+                ret.push_str(&rest[..path_start]);
+                ret.push_str(&location);
+                rest = &after[digits..];
+            },
+            None => {
+                ret.push_str(&rest[..index + ".lua:".len()]);
+                rest = after;
+            },
+        }
+    }
+    ret.push_str(rest);
+    ret
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn map() -> SourceMap {
+        let source = "fun f() {\n    error();\n}\n";
+        SourceMap::new("f.lua", "f.hy", source, &[None, Some(4), None, Some(14), None])
+    }
+
+    #[test]
+    fn should_map_lines_to_source() {
+        let map = map();
+        assert_eq!( map.lookup(4), Some(&Mapping { generated: 4, line: 2, column: 5 }) );
+        assert_eq!( map.lookup(5), Some(&Mapping { generated: 4, line: 2, column: 5 }) );
+        assert_eq!( map.lookup(1), None );
+    }
+
+    #[test]
+    fn should_round_trip_through_json() {
+        let json = map().to_json();
+        let map = SourceMap::from_json(&json).expect("invalid source map");
+        assert_eq!( map.source, "f.hy" );
+        assert_eq!( map.mappings.len(), 2 );
+    }
+
+    #[test]
+    fn should_rewrite_traceback() {
+        let text = "lua: ./f.lua:4: oops\nstack traceback:\n\t./f.lua:5: in function 'f'\n\tother.lua:3: in main chunk\n";
+        let output = rewrite_traceback(text, &mut |path| if path == "./f.lua" { Some(map()) } else { None });
+        assert_eq!( output, "lua: f.hy:2:5: oops\nstack traceback:\n\tf.hy:2:5: in function 'f'\n\tother.lua:3: in main chunk\n" );
+    }
+}
@@ -1,14 +1,23 @@
-
 extern crate parse_input;
-extern crate parse_type;
 extern crate error_reporter;
 extern crate code_gen;
 
 mod parsing;
+mod diagnostic;
+mod json;
+mod toml;
+mod eval;
+mod typing;
+mod lowering;
+mod lint;
+mod project;
+mod prelude;
+mod cli;
 
 use code_gen::lua_5_2::{ast};
 
 
 fn main() {
-    println!("Hello, world!");
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    std::process::exit(cli::dispatch(&args));
 }
@@ -1,6 +1,5 @@
 
 use parse_input::PSym;
-use parse_type::Type;
 
 #[derive(Debug)]
 pub struct Use {
@@ -14,10 +13,24 @@ pub enum Import {
     Item(PSym),
 }
 
+// An annotation as written. `Unit` is what a missing return type means, and
+// `Infer` a missing annotation anywhere else.
+#[derive(Debug, Clone)]
+pub enum Type {
+    Unit,
+    Infer,
+    Simple(PSym),
+    Generic(PSym, Vec<Type>),
+    Fun(Vec<Type>, Box<Type>),
+    Tuple(Vec<Type>),
+    Namespace(Vec<PSym>, Box<Type>),
+}
+
 #[derive(Debug)]
 pub enum Expr {
     Number(PSym),
     PString(PSym),  
+    Interpolate(Vec<StringPart>),
     Bool(bool),
     Variable { namespace : Vec<PSym>, name : PSym },
     StatementLambda { params : Vec<FunParam>
@@ -28,13 +41,29 @@ pub enum Expr {
                , return_type : Type
                , definition : Box<Expr>
                },
-    Call { func : Box<Expr>, params : Vec<Expr> },
+    // Named arguments come after the positional ones.
+    Call { func : Box<Expr>, params : Vec<Expr>, named : Vec<NamedArg> },
     Try(Box<Expr>),
     Dot { object : Box<Expr>, slot : PSym },
     Dash { object : Box<Expr>, func : PSym },
-    StructCons { name : Option<PSym>, slots : Vec<StructSlot> },
+    Index { object : Box<Expr>, index : Box<Expr> },
+    // `..base` copies the slots that aren't given from `base`.
+    StructCons { name : Option<PSym>, slots : Vec<StructSlot>, base : Option<Box<Expr>> },
     ListCons(Vec<Expr>),
+    // `...xs` splices a list into a call's arguments or a list.
+    Spread(Box<Expr>),
+    MapCons(Vec<MapEntry>),
+    Tuple(Vec<Expr>),
+    // `start..end` or `start..=end`, optionally followed by `by step`.
+    Range { start : Box<Expr>, end : Box<Expr>, inclusive : bool, step : Option<Box<Expr>> },
     ResultCons(ResultValue),
+    OptionCons(OptionValue),
+}
+
+#[derive(Debug)]
+pub enum StringPart {
+    Text(PSym),
+    Expr(Expr),
 }
 
 #[derive(Debug)]
@@ -42,13 +71,17 @@ pub enum Statement {
     Expr(Expr),
     Return(Option<Expr>),    
     Yield(Option<Expr>),
-    Let { name : PSym, value_type : Type, expr : Expr },
+    Let { pattern : Pattern, value_type : Type, expr : Expr },
     Set { target : Expr, new_value : Expr },
-    Break,
-    While { test : Expr, statements : Vec<Statement> },
-    Foreach { var : PSym, items : Expr, statements : Vec<Statement> },
-    If { test : Expr, statements : Vec<Statement> },
-    ElseIf { test : Expr, statements : Vec<Statement> },
+    // Loop control with an optional `'label` naming an enclosing loop.
+    Break { keyword : PSym, label : Option<PSym> },
+    Continue { keyword : PSym, label : Option<PSym> },
+    While { label : Option<PSym>, test : Expr, statements : Vec<Statement> },
+    // With `value`, iterates over index/item or key/value pairs.
+    Foreach { label : Option<PSym>, var : Pattern, value : Option<Pattern>, items : Expr, statements : Vec<Statement> },
+    Loop { label : Option<PSym>, statements : Vec<Statement> },
+    If { keyword : PSym, test : Expr, statements : Vec<Statement> },
+    ElseIf { keyword : PSym, test : Expr, statements : Vec<Statement> },
     Else(Vec<Statement>),
 }
 
@@ -60,7 +93,27 @@ pub struct Mod {
     pub fun_exports : Vec<String>,
     pub struct_exports : Vec<String>,
     pub enum_exports : Vec<String>,
+    pub extern_fun_defs : Vec<ExternFunDef>,
+    pub extern_struct_defs : Vec<ExternStructDef>,
+    pub type_aliases : Vec<TypeAlias>,
+    pub type_exports : Vec<String>,
+    pub const_defs : Vec<ConstDef>,
+    pub const_exports : Vec<String>,
+    pub interface_defs : Vec<InterfaceDef>,
+    pub interface_exports : Vec<String>,
+    pub impl_defs : Vec<ImplDef>,
     pub uses : Vec<Use>,
+    pub lint_attrs : Vec<LintAttr>,
+}
+
+// `#[allow(unused_let)]` before an item, or `#![deny(...)]` at the top of
+// a module for all of it. The level is allow, warn or deny.
+#[derive(Debug)]
+pub struct LintAttr {
+    pub level : PSym,
+    pub rules : Vec<PSym>,
+    // The offsets from the item's name up to the next item's.
+    pub item : Option<(usize, usize)>,
 }
 
 #[derive(Debug)]
@@ -68,6 +121,12 @@ pub enum TopLevel {
     FunDef { def : FunDef, public : bool },
     EnumDef { def : EnumDef, public : bool },
     StructDef { def : StructDef, public : bool },
+    ExternFunDef { def : ExternFunDef, public : bool },
+    ExternStructDef { def : ExternStructDef, public : bool },
+    TypeAlias { def : TypeAlias, public : bool },
+    ConstDef { def : ConstDef, public : bool },
+    InterfaceDef { def : InterfaceDef, public : bool },
+    ImplDef(ImplDef),
     Import(Use),
 }
 
@@ -88,6 +147,10 @@ pub struct StructDef {
 pub struct StructItem {
     pub name : PSym,
     pub item_type : Type,
+    // Only `pub` slots are visible outside the module.
+    pub public : bool,
+    // Used by `new` when the slot isn't given.
+    pub default : Option<Expr>,
 }
 
 #[derive(Debug)]
@@ -96,24 +159,215 @@ pub struct StructSlot {
     pub value : Expr,
 }
 
+#[derive(Debug)]
+pub enum Pattern {
+    Name(PSym),
+    Tuple(Vec<Pattern>),
+}
+
+#[derive(Debug)]
+pub struct MapEntry {
+    pub key : Expr,
+    pub value : Expr,
+}
+
 #[derive(Debug)]
 pub enum ResultValue {
     Okay(Box<Expr>),
     Error(Box<Expr>),
 }
 
+#[derive(Debug)]
+pub enum OptionValue {
+    Some(Box<Expr>),
+    None,
+}
+
 #[derive(Debug)]
 pub struct FunDef {
     pub name : PSym, 
     pub type_params : Vec<PSym>, 
+    pub bounds : Vec<TypeBound>,
     pub params : Vec<FunParam>,
     pub return_type : Type,
     pub definition : Vec<Statement>,
 }
 
+// A Lua function, referred to by its dotted path, e.g. `string.format`.
+// With `from "cjson"`, the path is looked up in what `require("cjson")`
+// returns instead of among the globals.
+#[derive(Debug)]
+pub struct ExternFunDef {
+    pub name : PSym,
+    pub type_params : Vec<PSym>,
+    pub params : Vec<FunParam>,
+    pub return_type : Type,
+    pub path : PSym,
+    pub module : Option<PSym>,
+}
+
+// An opaque Lua table.
+#[derive(Debug)]
+pub struct ExternStructDef {
+    pub name : PSym,
+    pub type_params : Vec<PSym>,
+}
+
+#[derive(Debug)]
+pub struct TypeAlias {
+    pub name : PSym,
+    pub type_params : Vec<PSym>,
+    pub aliased : Type,
+}
+
+#[derive(Debug)]
+pub struct ConstDef {
+    pub name : PSym,
+    pub const_type : Type,
+    pub value : Expr,
+}
+
+// `T : Ord` on a type parameter.
+#[derive(Debug)]
+pub struct TypeBound {
+    pub param : PSym,
+    pub interface : PSym,
+}
+
+// Methods take `self` first, which has the implementing type `Self`.
+#[derive(Debug)]
+pub struct InterfaceDef {
+    pub name : PSym,
+    pub methods : Vec<InterfaceMethod>,
+}
+
+#[derive(Debug)]
+pub struct InterfaceMethod {
+    pub name : PSym,
+    pub params : Vec<FunParam>,
+    pub return_type : Type,
+}
+
+// Without an interface, the functions are methods of the target struct.
+#[derive(Debug)]
+pub struct ImplDef {
+    pub interface : Option<PSym>,
+    pub target : PSym,
+    pub funs : Vec<FunDef>,
+}
+
 #[derive(Debug)]
 pub struct FunParam {
     pub name : PSym,
     pub param_type : Type,
+    // Used when a call leaves the parameter out. Only `fun` definitions
+    // have them.
+    pub default : Option<Expr>,
+    // `...rest : List<T>` takes the arguments left over, and can only be
+    // the last parameter.
+    pub variadic : bool,
+}
+
+#[derive(Debug)]
+pub struct NamedArg {
+    pub name : PSym,
+    pub value : Expr,
+}
+
+
+impl Expr {
+    pub fn start(&self) -> Option<usize> {
+        match self {
+            Expr::Number(s) | Expr::PString(s) => Some(s.start),
+            Expr::Interpolate(parts) => parts.first().and_then(|p| match p {
+                StringPart::Text(s) => Some(s.start),
+                StringPart::Expr(e) => e.start(),
+            }),
+            Expr::Bool(_) => None,
+            Expr::Variable { namespace, name } => Some(namespace.first().unwrap_or(name).start),
+            Expr::StatementLambda { params, definition, .. } =>
+                params.first().map(|p| p.name.start).or_else(|| definition.iter().find_map(|s| s.start())),
+            Expr::ExprLambda { params, definition, .. } =>
+                params.first().map(|p| p.name.start).or_else(|| definition.start()),
+            Expr::Call { func, .. } => func.start(),
+            Expr::Try(e) => e.start(),
+            Expr::Dot { object, .. } | Expr::Dash { object, .. } | Expr::Index { object, .. } => object.start(),
+            Expr::StructCons { name: Some(name), .. } => Some(name.start),
+            Expr::StructCons { name: None, slots, base } => slots.first().map(|s| s.name.start).or_else(|| base.as_ref()?.start()),
+            Expr::ListCons(es) => es.iter().find_map(|e| e.start()),
+            Expr::Spread(e) => e.start(),
+            Expr::MapCons(entries) => entries.iter().find_map(|e| e.key.start()),
+            Expr::Tuple(es) => es.iter().find_map(|e| e.start()),
+            Expr::Range { start, .. } => start.start(),
+            Expr::ResultCons(ResultValue::Okay(e)) | Expr::ResultCons(ResultValue::Error(e)) => e.start(),
+            Expr::OptionCons(OptionValue::Some(e)) => e.start(),
+            Expr::OptionCons(OptionValue::None) => None,
+        }
+    }
+}
+
+impl TopLevel {
+    pub fn start(&self) -> usize {
+        match self {
+            TopLevel::FunDef { def, .. } => def.name.start,
+            TopLevel::EnumDef { def, .. } => def.name.start,
+            TopLevel::StructDef { def, .. } => def.name.start,
+            TopLevel::ExternFunDef { def, .. } => def.name.start,
+            TopLevel::ExternStructDef { def, .. } => def.name.start,
+            TopLevel::TypeAlias { def, .. } => def.name.start,
+            TopLevel::ConstDef { def, .. } => def.name.start,
+            TopLevel::InterfaceDef { def, .. } => def.name.start,
+            TopLevel::ImplDef(def) => def.interface.as_ref().unwrap_or(&def.target).start,
+            TopLevel::Import(u) => u.namespace[0].start,
+        }
+    }
+}
+
+impl Pattern {
+    pub fn start(&self) -> Option<usize> {
+        match self {
+            Pattern::Name(name) => Some(name.start),
+            Pattern::Tuple(ps) => ps.iter().find_map(|p| p.start()),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&PSym> {
+        match self {
+            Pattern::Name(name) => vec![name],
+            Pattern::Tuple(ps) => ps.iter().flat_map(|p| p.names()).collect(),
+        }
+    }
+}
+
+impl Statement {
+    pub fn start(&self) -> Option<usize> {
+        match self {
+            Statement::Expr(e) => e.start(),
+            Statement::Return(e) | Statement::Yield(e) => e.as_ref().and_then(|e| e.start()),
+            Statement::Let { pattern, .. } => pattern.start(),
+            Statement::Set { target, .. } => target.start(),
+            Statement::Break { keyword, .. } | Statement::Continue { keyword, .. } => Some(keyword.start),
+            Statement::Loop { label, statements } =>
+                label.as_ref().map(|l| l.start).or_else(|| statements.iter().find_map(|s| s.start())),
+            Statement::If { keyword, .. } | Statement::ElseIf { keyword, .. } => Some(keyword.start),
+            Statement::While { test, .. } => test.start(),
+            Statement::Foreach { var, .. } => var.start(),
+            Statement::Else(statements) => statements.iter().find_map(|s| s.start()),
+        }
+    }
+}
+
+// Yields inside nested lambdas belong to those lambdas.
+pub fn contains_yield(statements : &[Statement]) -> bool {
+    statements.iter().any(|s| match s {
+        Statement::Yield(_) => true,
+        Statement::While { statements, .. }
+        | Statement::Foreach { statements, .. }
+        | Statement::Loop { statements, .. }
+        | Statement::If { statements, .. }
+        | Statement::ElseIf { statements, .. }
+        | Statement::Else(statements) => contains_yield(statements),
+        _ => false,
+    })
 }
 
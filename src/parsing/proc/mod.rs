@@ -1,5 +1,5 @@
 
 pub mod parser;
 pub mod ast;
-mod statement;
-mod top_level;
+pub mod statement;
+pub mod top_level;
@@ -2,14 +2,28 @@
 use parse_input::{Input, ParseError};
 
 use super::ast::*;
-use super::top_level::parse_top_level;
+use super::top_level::{parse_top_level, parse_lint_attr};
 
 
 pub fn parse(s : &str) -> Result<Mod, ParseError> {
     let ci = s.char_indices().collect::<Vec<(usize, char)>>();
     let mut input = Input::new(&ci);
 
-    let top_level_items = input.zero_or_more(parse_top_level)?; 
+    let module_attrs = input.zero_or_more(|i| parse_lint_attr(i, true))?;
+    let mut top_level_items = vec![];
+    while input.expect_end().is_err() {
+        let attrs = input.zero_or_more(|i| parse_lint_attr(i, false))?;
+        top_level_items.push((attrs, parse_top_level(&mut input)?));
+    }
+    let mut lint_attrs = module_attrs.into_iter()
+                                     .map(|(level, rules)| LintAttr { level, rules, item: None })
+                                     .collect::<Vec<LintAttr>>();
+    // An item's attributes cover it up to where the next item starts.
+    let starts = top_level_items.iter()
+                                .map(|(_, item)| item.start())
+                                .chain(std::iter::once(usize::MAX))
+                                .collect::<Vec<usize>>();
+
     let mut fun_defs = vec![];
     let mut fun_exports = vec![];
 
@@ -19,9 +33,25 @@ pub fn parse(s : &str) -> Result<Mod, ParseError> {
     let mut enum_defs = vec![];
     let mut enum_exports = vec![];
 
+    let mut extern_fun_defs = vec![];
+    let mut extern_struct_defs = vec![];
+
+    let mut type_aliases = vec![];
+    let mut type_exports = vec![];
+
+    let mut const_defs = vec![];
+    let mut const_exports = vec![];
+
+    let mut interface_defs = vec![];
+    let mut interface_exports = vec![];
+
+    let mut impl_defs = vec![];
+
     let mut uses = vec![];
 
-    for item in top_level_items.into_iter() {
+    for (index, (attrs, item)) in top_level_items.into_iter().enumerate() {
+        let item_range = (starts[index], starts[index + 1]);
+        lint_attrs.extend(attrs.into_iter().map(|(level, rules)| LintAttr { level, rules, item: Some(item_range) }));
         match item {
             TopLevel::FunDef { def, public: true } => {
                 fun_exports.push(def.name.value.clone());
@@ -44,6 +74,44 @@ pub fn parse(s : &str) -> Result<Mod, ParseError> {
             TopLevel::EnumDef { def, public: false } => {
                 enum_defs.push(def);
             },
+            TopLevel::ExternFunDef { def, public: true } => {
+                fun_exports.push(def.name.value.clone());
+                extern_fun_defs.push(def);
+            },
+            TopLevel::ExternFunDef { def, public: false } => {
+                extern_fun_defs.push(def);
+            },
+            TopLevel::ExternStructDef { def, public: true } => {
+                struct_exports.push(def.name.value.clone());
+                extern_struct_defs.push(def);
+            },
+            TopLevel::ExternStructDef { def, public: false } => {
+                extern_struct_defs.push(def);
+            },
+            TopLevel::TypeAlias { def, public: true } => {
+                type_exports.push(def.name.value.clone());
+                type_aliases.push(def);
+            },
+            TopLevel::TypeAlias { def, public: false } => {
+                type_aliases.push(def);
+            },
+            TopLevel::ConstDef { def, public: true } => {
+                const_exports.push(def.name.value.clone());
+                const_defs.push(def);
+            },
+            TopLevel::ConstDef { def, public: false } => {
+                const_defs.push(def);
+            },
+            TopLevel::InterfaceDef { def, public: true } => {
+                interface_exports.push(def.name.value.clone());
+                interface_defs.push(def);
+            },
+            TopLevel::InterfaceDef { def, public: false } => {
+                interface_defs.push(def);
+            },
+            TopLevel::ImplDef(def) => {
+                impl_defs.push(def);
+            },
             TopLevel::Import(u) => {
                 uses.push(u);
             },
@@ -58,7 +126,17 @@ pub fn parse(s : &str) -> Result<Mod, ParseError> {
             , struct_exports
             , enum_defs
             , enum_exports
+            , extern_fun_defs
+            , extern_struct_defs
+            , type_aliases
+            , type_exports
+            , const_defs
+            , const_exports
+            , interface_defs
+            , interface_exports
+            , impl_defs
             , uses
+            , lint_attrs
             } )
 }
 
@@ -103,4 +181,91 @@ pub struct Name<Type, Type> {
 
         Ok(())
     }
+
+    #[test]
+    fn should_collect_extern_alias_and_const_items() -> Result<(), ParseError> {
+        let input = r#"
+pub extern fun format(fmt : string, arg : any) -> string = "string.format";
+extern struct File;
+
+pub type Callback<T> = fun(T) -> string;
+type Id = number;
+pub const LIMIT : number = mul(60, 60);
+"#;
+
+        let module = parse(input)?;
+
+        assert_eq!( module.fun_exports, vec!["format"] );
+        assert_eq!( module.extern_fun_defs.len(), 1 );
+        assert_eq!( module.extern_struct_defs.len(), 1 );
+        assert_eq!( module.type_aliases.len(), 2 );
+        assert_eq!( module.type_exports, vec!["Callback"] );
+        assert_eq!( module.const_defs.len(), 1 );
+        assert_eq!( module.const_exports, vec!["LIMIT"] );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_the_error_of_the_item_that_was_started() {
+        let cases = [ (r#"extern fun f() -> string = "1bad";"#, "Expected Lua path", "\"1bad")
+                    , ("const X : number = ;", "Expected expression", ";")
+                    , (r#"fun f() { let s = "{a"; }"#, "Unterminated interpolation in string", "{a")
+                    , ("fun f(...xs : List<number>, y : number) { }", "variadic parameter xs must come last", "xs")
+                    , ("struct S { x : number }\nfun f() { if true { return 1 } }", "expected ;", "} }")
+                    , ("fun f() { } }", "expected end", "}")
+                    ];
+        for (input, message, at) in cases.iter() {
+            match parse(input) {
+                Err(ParseError::ErrorAt(offset, m)) => {
+                    assert_eq!( m, *message );
+                    assert_eq!( offset, input.rfind(at).unwrap(), "{}", input );
+                },
+                r => panic!( "expected error for {} but found {:?}", input, r ),
+            }
+        }
+    }
+
+    #[test]
+    fn should_place_interpolations_after_wide_characters_and_escapes() -> Result<(), ParseError> {
+        let input = "fun f() {\n    let s = \"éé\\t{zz}\";\n    let t = \"\\\"\t{yy}\";\n}";
+
+        let module = parse(input)?;
+
+        let names = module.fun_defs[0].definition.iter().map(|s| match s {
+            Statement::Let { expr: Expr::Interpolate(parts), .. } => match &parts[1] {
+                StringPart::Expr(Expr::Variable { name, .. }) => name.clone(),
+                p => panic!( "expected variable but found {:?}", p ),
+            },
+            s => panic!( "expected interpolated let but found {:?}", s ),
+        }).collect::<Vec<_>>();
+        assert_eq!( names[0].start, input.find("zz").unwrap() );
+        assert_eq!( names[1].start, input.find("yy").unwrap() );
+        assert_eq!( crate::diagnostic::line_column(input, input.find('é').unwrap() + 1), (2, 14) );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_lint_attributes() -> Result<(), ParseError> {
+        let input = r#"
+#![deny(unused_let)]
+use a::{b};
+#[allow(unused_param, shadowed_binding)]
+#[warn(unused_let)]
+fun f(x : number) { }
+fun g() { }
+"#;
+
+        let module = parse(input)?;
+
+        let attrs = module.lint_attrs.iter()
+                                     .map(|a| (a.level.value.as_str(), a.rules.len(), a.item))
+                                     .collect::<Vec<_>>();
+        let f = module.fun_defs[0].name.start;
+        let g = module.fun_defs[1].name.start;
+        assert_eq!( attrs, vec![("deny", 1, None), ("allow", 2, Some((f, g))), ("warn", 1, Some((f, g)))] );
+
+        Ok(())
+    }
 }
@@ -1,75 +1,147 @@
 
-use parse_input::{Input, ParseError};
-use parse_type::{Type, parse_type};
+use parse_input::{Input, PSym, ParseError};
 
 use super::ast::*;
 
 
+// Once a statement's keyword is there, the input can only be that
+// statement, so its error is the one to report.
 pub fn parse_statement(input : &mut Input) -> Result<Statement, ParseError> {
-    input.choice( &[ parse_let
-                   , parse_if
-                   , parse_elseif
-                   , parse_else
-                   , parse_set
-                   , parse_return 
-                   , parse_yield
-                   , parse_expr_statement
-                   , parse_foreach
-                   , parse_while
-                   , parse_break
-                   ] )
+    let rp = input.create_restore();
+    parse_loop_label(input);
+    let keyword = input.parse_symbol().map(|k| k.value).unwrap_or_default();
+    input.restore(rp);
+    let parser : fn(&mut Input) -> Result<Statement, ParseError> = match keyword.as_str() {
+        "let" => parse_let,
+        "if" => parse_if,
+        "elseif" => parse_elseif,
+        "else" => parse_else,
+        "set" => parse_set,
+        "return" => parse_return,
+        "yield" => parse_yield,
+        "foreach" => parse_foreach,
+        "while" => parse_while,
+        "loop" => parse_loop,
+        "break" => parse_break,
+        "continue" => parse_continue,
+        _ => parse_expr_statement,
+    };
+    parser(input)
+}
+
+// The statements of a block, up to its `}`, or of the whole input.
+pub fn parse_statements(input : &mut Input) -> Result<Vec<Statement>, ParseError> {
+    let mut statements = vec![];
+    loop {
+        let rp = input.create_restore();
+        let done = input.expect("}").is_ok() || input.expect_end().is_ok();
+        input.restore(rp);
+        if done {
+            return Ok(statements);
+        }
+        statements.push(parse_statement(input)?);
+    }
 }
 
 fn parse_elseif(input : &mut Input) -> Result<Statement, ParseError> {
-    input.expect("elseif")?;
+    let keyword = parse_keyword(input, "elseif")?;
     let test = parse_expr(input)?;
     input.expect("{")?;
-    let statements = input.zero_or_more(parse_statement)?;
+    let statements = parse_statements(input)?;
     input.expect("}")?;
-    Ok(Statement::ElseIf { test, statements })
+    Ok(Statement::ElseIf { keyword, test, statements })
 }
 
 fn parse_else(input : &mut Input) -> Result<Statement, ParseError> {
     input.expect("else")?;
     input.expect("{")?;
-    let statements = input.zero_or_more(parse_statement)?;
+    let statements = parse_statements(input)?;
     input.expect("}")?;
     Ok(Statement::Else(statements))
 }
 
 fn parse_if(input : &mut Input) -> Result<Statement, ParseError> {
-    input.expect("if")?;
+    let keyword = parse_keyword(input, "if")?;
     let test = parse_expr(input)?;
     input.expect("{")?;
-    let statements = input.zero_or_more(parse_statement)?;
+    let statements = parse_statements(input)?;
     input.expect("}")?;
-    Ok(Statement::If { test, statements })
+    Ok(Statement::If { keyword, test, statements })
+}
+
+// A keyword, kept for where it is when nothing after it says.
+fn parse_keyword(input : &mut Input, keyword : &str) -> Result<PSym, ParseError> {
+    let rp = input.create_restore();
+    let sym = input.parse_symbol()?;
+    if sym.value == keyword {
+        Ok(sym)
+    }
+    else {
+        input.restore(rp);
+        Err(ParseError::ErrorAt(sym.start, format!("Expected {}", keyword)))
+    }
+}
+
+fn parse_label(input : &mut Input) -> Result<PSym, ParseError> {
+    input.expect("'")?;
+    input.parse_symbol()
+}
+
+fn parse_loop_label(input : &mut Input) -> Option<PSym> {
+    input.maybe(|i| {
+        let label = parse_label(i)?;
+        i.expect(":")?;
+        Ok(label)
+    })
 }
 
 fn parse_foreach(input : &mut Input) -> Result<Statement, ParseError> {
+    let label = parse_loop_label(input);
     input.expect("foreach")?;
-    let var = input.parse_symbol()?;
+    let var = parse_pattern(input)?;
+    let value = input.maybe(|i| {
+        i.expect(",")?;
+        parse_pattern(i)
+    });
     input.expect("in")?;
     let items = parse_expr(input)?;
     input.expect("{")?;
-    let statements = input.zero_or_more(parse_statement)?;
+    let statements = parse_statements(input)?;
     input.expect("}")?;
-    Ok(Statement::Foreach { var, items, statements })
+    Ok(Statement::Foreach { label, var, value, items, statements })
 }
 
 fn parse_while(input : &mut Input) -> Result<Statement, ParseError> {
+    let label = parse_loop_label(input);
     input.expect("while")?;
     let test = parse_expr(input)?;
     input.expect("{")?;
-    let statements = input.zero_or_more(parse_statement)?;
+    let statements = parse_statements(input)?;
+    input.expect("}")?;
+    Ok(Statement::While { label, test, statements })
+}
+
+fn parse_loop(input : &mut Input) -> Result<Statement, ParseError> {
+    let label = parse_loop_label(input);
+    input.expect("loop")?;
+    input.expect("{")?;
+    let statements = parse_statements(input)?;
     input.expect("}")?;
-    Ok(Statement::While { test, statements })
+    Ok(Statement::Loop { label, statements })
 }
 
 fn parse_break(input : &mut Input) -> Result<Statement, ParseError> {
-    input.expect("break")?;
+    let keyword = parse_keyword(input, "break")?;
+    let label = input.maybe(parse_label);
+    input.expect(";")?;
+    Ok(Statement::Break { keyword, label })
+}
+
+fn parse_continue(input : &mut Input) -> Result<Statement, ParseError> {
+    let keyword = parse_keyword(input, "continue")?;
+    let label = input.maybe(parse_label);
     input.expect(";")?;
-    Ok(Statement::Break)
+    Ok(Statement::Continue { keyword, label })
 }
 
 fn parse_set(input : &mut Input) -> Result<Statement, ParseError> {
@@ -83,24 +155,82 @@ fn parse_set(input : &mut Input) -> Result<Statement, ParseError> {
 
 fn parse_let(input : &mut Input) -> Result<Statement, ParseError> {
     input.expect("let")?;
-    let name = input.parse_symbol()?;
+    let pattern = parse_pattern(input)?;
     match input.expect(":") {
         Ok(_) => {
-            let value_type = parse_type(input)?;
+            let value_type = parse_annotation(input)?;
             input.expect("=")?;
             let expr = parse_expr(input)?;
             input.expect(";")?;
-            Ok(Statement::Let { name, value_type, expr })
+            Ok(Statement::Let { pattern, value_type, expr })
         },
         Err(_) => {
             input.expect("=")?;
             let expr = parse_expr(input)?;
             input.expect(";")?;
-            Ok(Statement::Let { name, value_type: Type::Infer, expr })
+            Ok(Statement::Let { pattern, value_type: Type::Infer, expr })
         },
     }
 }
 
+fn parse_pattern(input : &mut Input) -> Result<Pattern, ParseError> {
+    match input.expect("(") {
+        Ok(_) => {
+            let mut ps = input.list(parse_pattern)?;
+            input.expect(")")?;
+            if ps.len() == 1 {
+                Ok(ps.pop().unwrap())
+            }
+            else {
+                Ok(Pattern::Tuple(ps))
+            }
+        },
+        Err(_) => Ok(Pattern::Name(input.parse_symbol()?)),
+    }
+}
+
+// Annotations are names, possibly namespaced, with type arguments in angle
+// brackets, function types like `fun(A, B) -> R`, and tuples `(A, B)`. A
+// function type without an arrow returns nothing, and parentheses around a
+// single annotation only group it.
+pub fn parse_annotation(input : &mut Input) -> Result<Type, ParseError> {
+    if input.expect("(").is_ok() {
+        let mut ts = input.list(parse_annotation)?;
+        input.expect(")")?;
+        return match ts.len() {
+            0 => Ok(Type::Unit),
+            1 => Ok(ts.remove(0)),
+            _ => Ok(Type::Tuple(ts)),
+        };
+    }
+    let mut name = input.parse_symbol()?;
+    if name.value == "fun" {
+        if let Some(params) = input.maybe(|i| { i.expect("(")?; let ps = i.list(parse_annotation)?; i.expect(")")?; Ok(ps) }) {
+            let ret = match input.expect("->") {
+                Ok(_) => parse_annotation(input)?,
+                Err(_) => Type::Unit,
+            };
+            return Ok(Type::Fun(params, Box::new(ret)));
+        }
+    }
+    let mut namespace = vec![];
+    while input.expect("::").is_ok() {
+        namespace.push(name);
+        name = input.parse_symbol()?;
+    }
+    let args = input.maybe(|i| { i.expect("<")?; let ts = i.list(parse_annotation)?; i.expect(">")?; Ok(ts) });
+    let t = match args {
+        Some(args) => Type::Generic(name, args),
+        None => Type::Simple(name),
+    };
+    if namespace.is_empty() {
+        Ok(t)
+    }
+    else {
+        Ok(Type::Namespace(namespace, Box::new(t)))
+    }
+}
+
 fn parse_expr_statement(input : &mut Input) -> Result<Statement, ParseError> {
     let expr = parse_expr(input)?;
     input.expect(";")?;
@@ -121,29 +251,155 @@ fn parse_return(input : &mut Input) -> Result<Statement, ParseError> {
     Ok(Statement::Return(expr))
 }
 
-fn parse_expr(input : &mut Input) -> Result<Expr, ParseError> {
-                  
+pub fn parse_expr(input : &mut Input) -> Result<Expr, ParseError> {
+    let expr = parse_operand(input)?;
+    if input.expect("..").is_err() {
+        return Ok(expr);
+    }
+    let inclusive = input.expect("=").is_ok();
+    let end = parse_operand(input)?;
+    let step = match input.expect("by") {
+        Ok(_) => Some(Box::new(parse_operand(input)?)),
+        Err(_) => None,
+    };
+    Ok(Expr::Range { start: Box::new(expr), end: Box::new(end), inclusive, step })
+}
+
+fn parse_operand(input : &mut Input) -> Result<Expr, ParseError> {
+    // What is wrong inside a string, like an unterminated interpolation, is
+    // the error to report once there is one.
+    let rp = input.create_restore();
+    if input.parse_string().is_ok() {
+        input.restore(rp);
+        let expr = parse_string_expr(input)?;
+        return parse_post_expr(input, expr);
+    }
+
     let expr = input.choice( &[ |input| Ok(Expr::Number(input.parse_number()?))
-                              , |input| Ok(Expr::PString(input.parse_string()?))
                               , parse_bool
                               , parse_lambda
                               , parse_result_cons
+                              , parse_option_cons
                               , parse_struct_cons
                               , parse_variable
                               , parse_list_cons
+                              , parse_map_cons
                               , parse_paren_expr
                               ] )?;
 
     parse_post_expr(input, expr)
 }
 
+// The offset of each character of a string's value, worked out from where
+// the literal's quotes are. Each escape is a byte longer than the character
+// it stands for. Quotes and backslashes are always escaped; the bytes left
+// over are taken to be escaped newlines and tabs, in order. An escape is
+// placed at its backslash.
+fn value_offsets(s : &PSym) -> Vec<(usize, char)> {
+    let written = s.end.saturating_sub(s.start + 1);
+    let required = s.value.chars().filter(|c| matches!(c, '"' | '\\')).count();
+    let mut optional = written.saturating_sub(s.value.len() + required);
+    let mut at = s.start + 1;
+    s.value.chars().map(|c| {
+        let escaped = match c {
+            '"' | '\\' => true,
+            '\n' | '\t' if optional > 0 => {
+                optional -= 1;
+                true
+            },
+            _ => false,
+        };
+        let offset = at;
+        at += c.len_utf8() + usize::from(escaped);
+        (offset, c)
+    }).collect()
+}
+
+// Splits `"a {x} b"` into text and embedded expressions. `{{` and `}}` stand
+// for literal braces.
+fn parse_string_expr(input : &mut Input) -> Result<Expr, ParseError> {
+    let s = input.parse_string()?;
+    let chars = value_offsets(&s);
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut text_start = s.start;
+    let mut i = 0;
+    while i < chars.len() {
+        let (at, c) = chars[i];
+        match c {
+            '{' | '}' if chars.get(i + 1).map(|n| n.1) == Some(c) => {
+                text.push(c);
+                i += 2;
+            },
+            '}' => return Err(ParseError::ErrorAt(at, "Unmatched '}' in string, use '}}'".to_string())),
+            '{' => {
+                let mut depth = 0;
+                let mut in_string = false;
+                let mut end = None;
+                for (j, &(_, c)) in chars.iter().enumerate().skip(i + 1) {
+                    match c {
+                        '"' => in_string = !in_string,
+                        '{' if !in_string => depth += 1,
+                        '}' if !in_string && depth == 0 => { end = Some(j); break; },
+                        '}' if !in_string => depth -= 1,
+                        _ => (),
+                    }
+                }
+                let end = end.ok_or_else(|| ParseError::ErrorAt(at, "Unterminated interpolation in string".to_string()))?;
+                if !text.is_empty() {
+                    parts.push(StringPart::Text(PSym { start: text_start, end: at, value: std::mem::take(&mut text) }));
+                }
+                let mut inner = Input::new(&chars[i + 1..end]);
+                let expr = parse_expr(&mut inner)?;
+                inner.expect_end()?;
+                parts.push(StringPart::Expr(expr));
+                i = end + 1;
+                text_start = chars.get(i).map(|c| c.0).unwrap_or(s.end);
+            },
+            c => {
+                text.push(c);
+                i += 1;
+            },
+        }
+    }
+    if parts.is_empty() {
+        return Ok(Expr::PString(PSym { value: text, ..s }));
+    }
+    if !text.is_empty() {
+        parts.push(StringPart::Text(PSym { start: text_start, end: s.end, value: text }));
+    }
+    Ok(Expr::Interpolate(parts))
+}
+
+// An expression, or `...xs` to splice in the items of a list.
+fn parse_item(input : &mut Input) -> Result<Expr, ParseError> {
+    match input.maybe(|i| i.expect("...")) {
+        Some(_) => Ok(Expr::Spread(Box::new(parse_expr(input)?))),
+        None => parse_expr(input),
+    }
+}
+
 fn parse_list_cons(input : &mut Input) -> Result<Expr, ParseError> {
     input.expect("[")?;
-    let es = input.list(parse_expr)?;
+    let es = input.list(parse_item)?;
     input.expect("]")?;
     Ok(Expr::ListCons(es))
 }
 
+fn parse_map_cons(input : &mut Input) -> Result<Expr, ParseError> {
+    fn parse_map_entry(input : &mut Input) -> Result<MapEntry, ParseError> {
+        let key = parse_expr(input)?;
+        input.expect(":")?;
+        let value = parse_expr(input)?;
+        Ok(MapEntry { key, value })
+    }
+    input.expect("#")?;
+    input.expect("{")?;
+    let entries = input.list(parse_map_entry)?;
+    input.expect("}")?;
+    Ok(Expr::MapCons(entries))
+}
+
 fn parse_result_cons(input : &mut Input) -> Result<Expr, ParseError> {
     match input.expect("Ok") {
         Ok(_) => {
@@ -162,19 +418,71 @@ fn parse_result_cons(input : &mut Input) -> Result<Expr, ParseError> {
     Ok(Expr::ResultCons(ResultValue::Error(Box::new(e))))
 }
 
+fn parse_option_cons(input : &mut Input) -> Result<Expr, ParseError> {
+    let rp = input.create_restore();
+    let name = input.parse_symbol()?;
+    match name.value.as_str() {
+        "None" => Ok(Expr::OptionCons(OptionValue::None)),
+        "Some" if input.expect("(").is_ok() => {
+            let e = parse_expr(input)?;
+            input.expect(")")?;
+            Ok(Expr::OptionCons(OptionValue::Some(Box::new(e))))
+        },
+        _ => {
+            input.restore(rp);
+            Err(ParseError::ErrorAt(name.start, "Expected option".to_string()))
+        },
+    }
+}
+
+// A positional argument, or a named one as `name: value`.
+fn parse_arg(input : &mut Input) -> Result<Result<Expr, NamedArg>, ParseError> {
+    let name = input.maybe(|i| {
+        let name = i.parse_symbol()?;
+        // `a::b` is a path.
+        if i.maybe(|ii| ii.expect("::")).is_some() {
+            return Err(ParseError::ErrorAt(name.start, "expected named argument".to_string()));
+        }
+        i.expect(":")?;
+        Ok(name)
+    });
+    let value = match name {
+        Some(_) => parse_expr(input)?,
+        None => parse_item(input)?,
+    };
+    Ok(match name {
+        Some(name) => Err(NamedArg { name, value }),
+        None => Ok(value),
+    })
+}
+
 fn parse_struct_cons(input : &mut Input) -> Result<Expr, ParseError> {
-    fn parse_struct_slot(input : &mut Input) -> Result<StructSlot, ParseError> {
+    // A slot, or the `..base` that must come after them.
+    fn parse_struct_slot(input : &mut Input) -> Result<Result<StructSlot, Expr>, ParseError> {
+        if input.maybe(|i| i.expect("..")).is_some() {
+            return Ok(Err(parse_expr(input)?));
+        }
         let name = input.parse_symbol()?;
         input.expect(":")?;
         let value = parse_expr(input)?;
-        Ok(StructSlot { name, value })
+        Ok(Ok(StructSlot { name, value }))
     }
     input.expect("new")?;
     let name = input.maybe(|i| i.parse_symbol());
     input.expect("{")?;
-    let slots = input.list(parse_struct_slot)?;
+    let items = input.list(parse_struct_slot)?;
     input.expect("}")?;
-    Ok(Expr::StructCons { name, slots })
+    let mut slots = vec![];
+    let mut base = None;
+    for item in items {
+        match item {
+            Ok(slot) if base.is_none() => slots.push(slot),
+            Err(e) if base.is_none() => base = Some(Box::new(e)),
+            Ok(StructSlot { name, .. }) => return Err(ParseError::ErrorAt(name.start, "..base must come last".to_string())),
+            Err(e) => return Err(ParseError::ErrorAt(e.start().unwrap_or(0), "..base must come last".to_string())),
+        }
+    }
+    Ok(Expr::StructCons { name, slots, base })
 }
 
 fn parse_variable(input : &mut Input) -> Result<Expr, ParseError> {
@@ -188,15 +496,36 @@ fn parse_variable(input : &mut Input) -> Result<Expr, ParseError> {
     Ok(Expr::Variable { namespace, name })
 }
 
+// As the last choice of operand, its error is what a missing operand reports.
 fn parse_paren_expr(input : &mut Input) -> Result<Expr, ParseError> {
-    input.expect("(")?;
+    input.expect("(").map_err(|e| match e {
+        ParseError::ErrorAt(at, _) => ParseError::ErrorAt(at, "Expected expression".to_string()),
+        ParseError::EndOfFile(_) => ParseError::EndOfFile("Expected expression".to_string()),
+    })?;
     let expr = parse_expr(input)?;
+    let mut rest = input.zero_or_more(|i| {
+        i.expect(",")?;
+        parse_expr(i)
+    })?;
     input.expect(")")?;
-    Ok(expr)
+    if rest.is_empty() {
+        Ok(expr)
+    }
+    else {
+        rest.insert(0, expr);
+        Ok(Expr::Tuple(rest))
+    }
 }
 
-// dash call, call, dot, try
+// dash call, call, dot, index, try
 fn parse_post_expr(input : &mut Input, e : Expr) -> Result<Expr, ParseError> {
+    // `..` is a range rather than a slot.
+    let rp = input.create_restore();
+    if input.expect("..").is_ok() {
+        input.restore(rp);
+        return Ok(e);
+    }
+
     match input.expect("-") {
         Ok(_) => {
             let func = input.parse_symbol()?;
@@ -215,15 +544,31 @@ fn parse_post_expr(input : &mut Input, e : Expr) -> Result<Expr, ParseError> {
 
     match input.expect("(") {
         Ok(_) => {
-            let params = input.list(parse_expr)?;
+            let args = input.list(parse_arg)?;
 
             input.expect(")")?; 
+
+            let mut params = vec![];
+            let mut named = vec![];
+            for arg in args {
+                match arg {
+                    Ok(value) if named.is_empty() => params.push(value),
+                    Ok(value) => return Err(ParseError::ErrorAt(value.start().unwrap_or(0), "positional argument after named argument".to_string())),
+                    Err(arg) => named.push(arg),
+                }
+            }
         
-            return parse_post_expr(input, Expr::Call { func: Box::new(e), params });
+            return parse_post_expr(input, Expr::Call { func: Box::new(e), params, named });
         },
         Err(_) => (),
     }
 
+    if input.expect("[").is_ok() {
+        let index = parse_expr(input)?;
+        input.expect("]")?;
+        return parse_post_expr(input, Expr::Index { object: Box::new(e), index: Box::new(index) });
+    }
+
     match input.expect("?") {
         Ok(_) => return parse_post_expr(input, Expr::Try(Box::new(e))),
         Err(_) => (),
@@ -232,48 +577,74 @@ fn parse_post_expr(input : &mut Input, e : Expr) -> Result<Expr, ParseError> {
     Ok(e)
 }
 
+// Only the last parameter can be variadic, and it takes no default.
+pub fn check_variadic(params : &[FunParam]) -> Result<(), ParseError> {
+    for (i, p) in params.iter().enumerate().filter(|(_, p)| p.variadic) {
+        if i + 1 != params.len() {
+            return Err(ParseError::ErrorAt(p.name.start, format!("variadic parameter {} must come last", p.name.value)));
+        }
+        if p.default.is_some() {
+            return Err(ParseError::ErrorAt(p.name.start, format!("variadic parameter {} cannot have a default", p.name.value)));
+        }
+    }
+    Ok(())
+}
+
 fn parse_lambda(input : &mut Input) -> Result<Expr, ParseError> {
-    fn parse_param(input : &mut Input) -> Result<FunParam, ParseError> {
-        let name = input.parse_symbol()?; 
+    fn parse_param(input : &mut Input) -> Result<(Pattern, Type, bool), ParseError> {
+        let variadic = input.maybe(|i| i.expect("...")).is_some();
+        let pattern = if variadic { Pattern::Name(input.parse_symbol()?) } else { parse_pattern(input)? };
         match input.expect(":") {
             Ok(_) => { 
-                let param_type = parse_type(input)?;
-                Ok(FunParam { name, param_type })
+                let param_type = parse_annotation(input)?;
+                Ok((pattern, param_type, variadic))
             },
             Err(_) => {
-                Ok(FunParam { name, param_type: Type::Infer })
+                Ok((pattern, Type::Infer, variadic))
             },
         }
     }
     input.expect("|")?;
-    let params = input.list(parse_param)?;
+    let patterns = input.list(parse_param)?;
     input.expect("|")?;
-    match input.expect("->") {
+
+    // Destructured parameters become a plain parameter and a `let` at the
+    // top of the body.
+    let mut params = vec![];
+    let mut lets = vec![];
+    for (i, (pattern, param_type, variadic)) in patterns.into_iter().enumerate() {
+        match pattern {
+            Pattern::Name(name) => params.push(FunParam { name, param_type, default: None, variadic }),
+            pattern => {
+                let start = pattern.start().unwrap_or_default();
+                let name = PSym { start, end: start, value: format!("__arg{}", i) };
+                let expr = Expr::Variable { namespace: vec![], name: PSym { start, end: start, value: name.value.clone() } };
+                params.push(FunParam { name, param_type, default: None, variadic });
+                lets.push(Statement::Let { pattern, value_type: Type::Infer, expr });
+            },
+        }
+    }
+    check_variadic(&params)?;
+
+    let return_type = match input.expect("->") {
+        Ok(_) => parse_annotation(input)?,
+        Err(_) => Type::Infer,
+    };
+    match input.expect("{") {
         Ok(_) => {
-            let return_type = parse_type(input)?;
-            match input.expect("{") {
-                Ok(_) => {
-                    let definition = input.zero_or_more(parse_statement)?;
-                    input.expect("}")?;
-                    Ok(Expr::StatementLambda { params, return_type, definition })
-                },
-                Err(_) => {
-                    let definition = Box::new(parse_expr(input)?);
-                    Ok(Expr::ExprLambda { params, return_type, definition })
-                },
-            }
+            let definition = parse_statements(input)?;
+            input.expect("}")?;
+            lets.extend(definition);
+            Ok(Expr::StatementLambda { params, return_type, definition: lets })
         },
         Err(_) => {
-            match input.expect("{") {
-                Ok(_) => {
-                    let definition = input.zero_or_more(parse_statement)?;
-                    input.expect("}")?;
-                    Ok(Expr::StatementLambda { params, return_type: Type::Infer, definition })
-                },
-                Err(_) => {
-                    let definition = Box::new(parse_expr(input)?);
-                    Ok(Expr::ExprLambda { params, return_type: Type::Infer, definition })
-                },
+            let definition = parse_expr(input)?;
+            if lets.is_empty() {
+                Ok(Expr::ExprLambda { params, return_type, definition: Box::new(definition) })
+            }
+            else {
+                lets.push(Statement::Return(Some(definition)));
+                Ok(Expr::StatementLambda { params, return_type, definition: lets })
             }
         },
     }
@@ -299,6 +670,22 @@ fn parse_bool(input : &mut Input) -> Result<Expr, ParseError> {
 mod test {
     use super::*;
 
+    #[test]
+    fn should_parse_annotations() -> Result<(), ParseError> {
+        let i = r#"(number, fun(Map<string, a::B<T>>) -> (), (bool, string))"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_annotation(&mut input)?;
+        let ts = match u {
+            Type::Tuple(ts) => ts,
+            t => panic!( "expected Type::Tuple but found {:?}", t ),
+        };
+        assert_eq!( ts.len(), 3 );
+        assert!( matches!( &ts[0], Type::Simple(n) if n.value == "number" ) );
+        assert!( matches!( &ts[1], Type::Fun(ps, r) if matches!( &ps[..], [Type::Generic(m, args)] if m.value == "Map" && matches!( &args[1], Type::Namespace(ns, _) if ns.len() == 1 ) ) && matches!( **r, Type::Unit ) ) );
+        assert!( matches!( &ts[2], Type::Tuple(inner) if inner.len() == 2 ) );
+        Ok(())
+    }
+
     #[test]
     fn should_parse_expr_lambda() -> Result<(), ParseError> {
         let i = r#"|a, b, c| 0"#.char_indices().collect::<Vec<(usize, char)>>();
@@ -364,7 +751,7 @@ mod test {
         let mut input = Input::new(&i);
         let u = parse_expr(&mut input)?;
         let (call, mut params) = match u {
-           Expr::Call { func, params } => (*func, params), 
+           Expr::Call { func, params, .. } => (*func, params), 
            e => panic!("expected call but found {:?}", e),
         };
 
@@ -376,7 +763,7 @@ mod test {
         assert_eq!( var, "b" );
 
         let (call, mut params) = match call {
-           Expr::Call { func, params } => (*func, params), 
+           Expr::Call { func, params, .. } => (*func, params), 
            e => panic!("expected call but found {:?}", e),
         };
 
@@ -440,6 +827,22 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn should_parse_option_cons() -> Result<(), ParseError> {
+        let i = r#"[Some(x), None, Nonempty, Some]"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_expr(&mut input)?;
+        let es = match u {
+            Expr::ListCons(es) => es,
+            e => panic!("Expected ListCons but found {:?}", e),
+        };
+        assert!( matches!( es[0], Expr::OptionCons(OptionValue::Some(_)) ) );
+        assert!( matches!( es[1], Expr::OptionCons(OptionValue::None) ) );
+        assert!( matches!( &es[2], Expr::Variable { name, .. } if name.value == "Nonempty" ) );
+        assert!( matches!( &es[3], Expr::Variable { name, .. } if name.value == "Some" ) );
+        Ok(())
+    }
+
     #[test]
     fn should_parse_list_cons() -> Result<(), ParseError> {
         let i = r#"[1,2,3,4]"#.char_indices().collect::<Vec<(usize, char)>>();
@@ -499,4 +902,239 @@ mod test {
         }
         Ok(())
     }
+
+    #[test]
+    fn should_parse_named_arguments() -> Result<(), ParseError> {
+        let i = r#"connect(a::b, x, timeout: 5, secure: f(y))"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_expr(&mut input)?;
+        match u {
+            Expr::Call { params, named, .. } => {
+                assert_eq!( params.len(), 2 );
+                assert!( matches!( &params[0], Expr::Variable { namespace, .. } if namespace.len() == 1 ) );
+                let names = named.iter().map(|n| n.name.value.as_str()).collect::<Vec<&str>>();
+                assert_eq!( names, vec!["timeout", "secure"] );
+            },
+            e => panic!("Expected call but found {:?}", e),
+        }
+
+        let i = r#"f(a: 1, 2)"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        match parse_expr(&mut input) {
+            Err(ParseError::ErrorAt(_, message)) => assert_eq!( message, "positional argument after named argument" ),
+            r => panic!("Expected error but found {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_struct_update() -> Result<(), ParseError> {
+        let i = r#"new Blah { a: 1, ..f(x) }"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_expr(&mut input)?;
+        match u {
+            Expr::StructCons { slots, base: Some(base), .. } => {
+                assert_eq!( slots.len(), 1 );
+                assert!( matches!( *base, Expr::Call { .. } ) );
+            },
+            e => panic!("Expected struct cons but found {:?}", e),
+        }
+
+        let i = r#"new Blah { ..b, a: 1 }"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        assert!( parse_struct_cons(&mut input).is_err() );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_index_chain() -> Result<(), ParseError> {
+        let i = r#"a.b[0][f(1)].c"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_expr(&mut input)?;
+        let object = match u {
+            Expr::Dot { object, .. } => *object,
+            e => panic!("Expected dot but found {:?}", e),
+        };
+        match object {
+            Expr::Index { object, index } => {
+                assert!( matches!( *object, Expr::Index { .. } ) );
+                assert!( matches!( *index, Expr::Call { .. } ) );
+            },
+            e => panic!("Expected index but found {:?}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_set_index() -> Result<(), ParseError> {
+        let i = r#"set xs[0] = 1;"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_statement(&mut input)?;
+        assert!( matches!( u, Statement::Set { target: Expr::Index { .. }, .. } ) );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_map_cons() -> Result<(), ParseError> {
+        let i = r#"#{ "a": 1, key: [2] }"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_expr(&mut input)?;
+        match u {
+            Expr::MapCons(entries) => assert_eq!( entries.len(), 2 ),
+            e => panic!("Expected map cons but found {:?}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_foreach_with_key_and_value() -> Result<(), ParseError> {
+        let i = r#"foreach k, v in m { }"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_statement(&mut input)?;
+        assert!( matches!( u, Statement::Foreach { value: Some(_), .. } ) );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_tuple_and_paren() -> Result<(), ParseError> {
+        let i = r#"((a), (b, c))"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_expr(&mut input)?;
+        match u {
+            Expr::Tuple(es) => {
+                assert!( matches!( es[0], Expr::Variable { .. } ) );
+                assert!( matches!( es[1], Expr::Tuple(_) ) );
+            },
+            e => panic!("Expected tuple but found {:?}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_let_with_tuple_pattern() -> Result<(), ParseError> {
+        let i = r#"let (a, (b, c)) : (number, (string, bool)) = x;"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_statement(&mut input)?;
+        match u {
+            Statement::Let { pattern, value_type: Type::Tuple(ts), .. } if ts.len() == 2 => {
+                let names = pattern.names().iter().map(|n| n.value.clone()).collect::<Vec<String>>();
+                assert_eq!( names, vec!["a", "b", "c"] );
+            },
+            s => panic!("Expected let but found {:?}", s),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_desugar_destructured_lambda_param() -> Result<(), ParseError> {
+        let i = r#"|(a, b), c| add(a, c)"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_lambda(&mut input)?;
+        match u {
+            Expr::StatementLambda { params, definition, .. } => {
+                assert_eq!( params[0].name.value, "__arg0" );
+                assert_eq!( params[1].name.value, "c" );
+                assert!( matches!( definition[0], Statement::Let { pattern: Pattern::Tuple(_), .. } ) );
+                assert!( matches!( definition[1], Statement::Return(Some(_)) ) );
+            },
+            e => panic!("Expected statement lambda but found {:?}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_interpolated_string() -> Result<(), ParseError> {
+        let i = r#""user {name} has {len(items)} {{items}}""#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_expr(&mut input)?;
+        match u {
+            Expr::Interpolate(parts) => {
+                assert_eq!( parts.len(), 5 );
+                assert!( matches!( &parts[0], StringPart::Text(t) if t.value == "user " ) );
+                assert!( matches!( &parts[1], StringPart::Expr(Expr::Variable { name, .. }) if name.value == "name" ) );
+                assert!( matches!( &parts[2], StringPart::Text(t) if t.value == " has " ) );
+                assert!( matches!( &parts[3], StringPart::Expr(Expr::Call { .. }) ) );
+                assert!( matches!( &parts[4], StringPart::Text(t) if t.value == " {items}" ) );
+            },
+            e => panic!("Expected interpolation but found {:?}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_unescape_braces_in_plain_string() -> Result<(), ParseError> {
+        let i = r#""{{}}""#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_expr(&mut input)?;
+        assert!( matches!( u, Expr::PString(s) if s.value == "{}" ) );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_labeled_loops() -> Result<(), ParseError> {
+        let i = r#"'outer: loop { foreach x in xs { continue 'outer; } break; }"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_statement(&mut input)?;
+        match u {
+            Statement::Loop { label: Some(label), statements } => {
+                assert_eq!( label.value, "outer" );
+                match &statements[0] {
+                    Statement::Foreach { label: None, statements, .. } =>
+                        assert!( matches!( &statements[0], Statement::Continue { label: Some(l), .. } if l.value == "outer" ) ),
+                    s => panic!("Expected foreach but found {:?}", s),
+                }
+                assert!( matches!( statements[1], Statement::Break { label: None, .. } ) );
+            },
+            s => panic!("Expected loop but found {:?}", s),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_ranges() -> Result<(), ParseError> {
+        let i = r#"foreach i in 0..=len(xs) by 2 { }"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_statement(&mut input)?;
+        match u {
+            Statement::Foreach { items: Expr::Range { start, end, inclusive, step }, .. } => {
+                assert!( matches!( *start, Expr::Number(n) if n.value == "0" ) );
+                assert!( matches!( *end, Expr::Call { .. } ) );
+                assert!( inclusive );
+                assert!( matches!( step.as_deref(), Some(Expr::Number(n)) if n.value == "2" ) );
+            },
+            s => panic!("Expected foreach over range but found {:?}", s),
+        }
+        let i = r#"xs.count..n"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_expr(&mut input)?;
+        assert!( matches!( u, Expr::Range { inclusive: false, step: None, .. } ) );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_spreads_and_variadic_lambda() -> Result<(), ParseError> {
+        let i = r#"f(a, ...[1, ...xs], ...ys)"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_expr(&mut input)?;
+        let params = match u {
+            Expr::Call { params, .. } => params,
+            e => panic!("Expected call but found {:?}", e),
+        };
+        assert_eq!( params.len(), 3 );
+        assert!( matches!( &params[1], Expr::Spread(e) if matches!( &**e, Expr::ListCons(es) if matches!( es[1], Expr::Spread(_) ) ) ) );
+        assert!( matches!( &params[2], Expr::Spread(_) ) );
+
+        let i = r#"|a, ...rest : List<number>| rest"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_expr(&mut input)?;
+        assert!( matches!( u, Expr::ExprLambda { params, .. } if !params[0].variadic && params[1].variadic ) );
+
+        let i = r#"|...rest, a| rest"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        match parse_lambda(&mut input) {
+            Err(ParseError::ErrorAt(_, message)) => assert_eq!( message, "variadic parameter rest must come last" ),
+            r => panic!("Expected error but found {:?}", r),
+        }
+        Ok(())
+    }
 }
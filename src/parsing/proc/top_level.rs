@@ -1,39 +1,76 @@
 
 use parse_input::{Input, PSym, ParseError};
-use parse_type::{Type, parse_type};
-use super::statement::parse_statement;
+use super::statement::{parse_statements, parse_annotation, parse_expr, check_variadic};
 use super::ast::*;
 
 
 pub fn parse_top_level(input : &mut Input) -> Result<TopLevel, ParseError> {
-    fn t<T>( v : Option<T> ) -> bool {
-        match v {
-            Some(_) => true,
-            None => false,
-        }
+    // Once an item's keywords are there, the input can only be that item, so
+    // its error is the one to report.
+    fn starts(input : &mut Input, keywords : &[&str]) -> bool {
+        let rp = input.create_restore();
+        let found = keywords.iter().all(|k| matches!(input.parse_symbol(), Ok(s) if s.value == *k));
+        input.restore(rp);
+        found
     }
 
-    match parse_use(input) {
-        Ok(v) => return Ok(TopLevel::Import(v)),
-        Err(_) => { },
+    if starts(input, &["use"]) {
+        return parse_use(input).map(TopLevel::Import);
     }
 
-    let public = input.maybe(|i| i.expect("pub"));
-    
-    match parse_fun_def(input) {
-        Ok(def) => return Ok(TopLevel::FunDef{ def, public: t(public) }),
-        Err(_) => { },
+    if starts(input, &["impl"]) {
+        return parse_impl_def(input).map(TopLevel::ImplDef);
+    }
+
+    let public = input.maybe(|i| i.expect("pub")).is_some();
+
+    if starts(input, &["extern", "fun"]) {
+        return parse_extern_fun_def(input).map(|def| TopLevel::ExternFunDef { def, public });
     }
 
-    match parse_struct_def(input) {
-        Ok(def) => return Ok(TopLevel::StructDef { def, public: t(public) }),
-        Err(_) => { },
+    if starts(input, &["extern", "struct"]) {
+        return parse_extern_struct_def(input).map(|def| TopLevel::ExternStructDef { def, public });
     }
 
-    match parse_enum_def(input) {
-        Ok(def) => Ok(TopLevel::EnumDef { def, public: t(public) }),
-        Err(e) => Err(e),
+    if starts(input, &["type"]) {
+        return parse_type_alias(input).map(|def| TopLevel::TypeAlias { def, public });
     }
+
+    if starts(input, &["const"]) {
+        return parse_const_def(input).map(|def| TopLevel::ConstDef { def, public });
+    }
+
+    if starts(input, &["interface"]) {
+        return parse_interface_def(input).map(|def| TopLevel::InterfaceDef { def, public });
+    }
+
+    if starts(input, &["fun"]) {
+        return parse_fun_def(input).map(|def| TopLevel::FunDef { def, public });
+    }
+
+    if starts(input, &["struct"]) {
+        return parse_struct_def(input).map(|def| TopLevel::StructDef { def, public });
+    }
+
+    if starts(input, &["enum"]) {
+        return parse_enum_def(input).map(|def| TopLevel::EnumDef { def, public });
+    }
+
+    // Nothing here starts an item, which only the end of the input may do
+    // without it being an error.
+    input.expect_end()?;
+    Err(ParseError::EndOfFile("expected item".to_string()))
+}
+
+// `#[level(rule, ...)]`, or `#![level(rule, ...)]` when `inner`.
+pub fn parse_lint_attr(input : &mut Input, inner : bool) -> Result<(PSym, Vec<PSym>), ParseError> {
+    input.expect(if inner { "#![" } else { "#[" })?;
+    let level = input.parse_symbol()?;
+    input.expect("(")?;
+    let rules = input.list(|i| i.parse_symbol())?;
+    input.expect(")")?;
+    input.expect("]")?;
+    Ok((level, rules))
 }
 
 fn parse_use(input : &mut Input) -> Result<Use, ParseError> {
@@ -101,69 +138,226 @@ fn parse_struct_def(input : &mut Input) -> Result<StructDef, ParseError> {
     input.expect("{")?; 
     
     let items = input.list(|i| {
-        let name = i.parse_symbol()?;
+        let mut name = i.parse_symbol()?;
+        let mut public = false;
+        if name.value == "pub" {
+            if let Some(n) = i.maybe(|ii| ii.parse_symbol()) {
+                name = n;
+                public = true;
+            }
+        }
         i.expect(":")?;
-        let item_type = parse_type(i)?;
-        Ok( StructItem { name, item_type } )
+        let item_type = parse_annotation(i)?;
+        let default = match i.maybe(|ii| ii.expect("=")) {
+            Some(_) => Some(parse_expr(i)?),
+            None => None,
+        };
+        Ok( StructItem { name, item_type, public, default } )
     })?;
 
     input.expect("}")?; 
     Ok( StructDef { name, type_params, items } ) 
 }
 
-fn parse_fun_def(input : &mut Input) -> Result<FunDef, ParseError> {
+fn parse_type_params(input : &mut Input) -> Vec<PSym> {
+    let params = input.maybe(|i| {
+        i.expect("<")?;
+        let params = i.list(|ii| ii.parse_symbol())?;
+        i.expect(">")?;
+        Ok(params)
+    });
+    params.unwrap_or_default()
+}
+
+fn parse_extern_fun_def(input : &mut Input) -> Result<ExternFunDef, ParseError> {
     fn parse_param(input : &mut Input) -> Result<FunParam, ParseError> {
-        let name = input.parse_symbol()?; 
+        let variadic = input.maybe(|i| i.expect("...")).is_some();
+        let name = input.parse_symbol()?;
         input.expect(":")?;
-        let param_type = parse_type(input)?;
-        Ok(FunParam { name, param_type })
+        let param_type = parse_annotation(input)?;
+        Ok(FunParam { name, param_type, default: None, variadic })
+    }
+
+    let rp = input.create_restore();
+    match input.expect("extern").and_then(|_| input.expect("fun")) {
+        Ok(_) => (),
+        Err(e) => {
+            input.restore(rp);
+            return Err(e);
+        },
+    }
+
+    let name = input.parse_symbol()?;
+    let type_params = parse_type_params(input);
+    input.expect("(")?;
+    let params = input.list(parse_param)?;
+    check_variadic(&params)?;
+    input.expect(")")?;
+    let return_type = match input.maybe(|i| i.expect("->")) {
+        Some(_) => parse_annotation(input)?,
+        None => Type::Unit,
+    };
+    input.expect("=")?;
+    let path = input.parse_string()?;
+    if !is_lua_path(&path.value) {
+        return Err(ParseError::ErrorAt(path.start, "Expected Lua path".to_string()));
+    }
+    let module = match input.maybe(|i| i.expect("from")) {
+        Some(_) => {
+            let module = input.parse_string()?;
+            if !is_lua_path(&module.value) {
+                return Err(ParseError::ErrorAt(module.start, "Expected Lua module name".to_string()));
+            }
+            Some(module)
+        },
+        None => None,
+    };
+    input.expect(";")?;
+    Ok( ExternFunDef { name, type_params, params, return_type, path, module } )
+}
+
+fn parse_extern_struct_def(input : &mut Input) -> Result<ExternStructDef, ParseError> {
+    let rp = input.create_restore();
+    match input.expect("extern").and_then(|_| input.expect("struct")) {
+        Ok(_) => (),
+        Err(e) => {
+            input.restore(rp);
+            return Err(e);
+        },
     }
 
+    let name = input.parse_symbol()?;
+    let type_params = parse_type_params(input);
+    input.expect(";")?;
+    Ok( ExternStructDef { name, type_params } )
+}
+
+fn parse_type_alias(input : &mut Input) -> Result<TypeAlias, ParseError> {
+    input.expect("type")?;
+    let name = input.parse_symbol()?;
+    let type_params = parse_type_params(input);
+    input.expect("=")?;
+    let aliased = parse_annotation(input)?;
+    input.expect(";")?;
+    Ok( TypeAlias { name, type_params, aliased } )
+}
+
+fn parse_const_def(input : &mut Input) -> Result<ConstDef, ParseError> {
+    input.expect("const")?;
+    let name = input.parse_symbol()?;
+    input.expect(":")?;
+    let const_type = parse_annotation(input)?;
+    input.expect("=")?;
+    let value = parse_expr(input)?;
+    input.expect(";")?;
+    Ok( ConstDef { name, const_type, value } )
+}
+
+fn is_lua_path(path : &str) -> bool {
+    path.split('.').all(|part| {
+        let mut cs = part.chars();
+        match cs.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => cs.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            _ => false,
+        }
+    })
+}
+
+// A bare `self` stands for `self : Self`.
+fn parse_param(input : &mut Input) -> Result<FunParam, ParseError> {
+    let variadic = input.maybe(|i| i.expect("...")).is_some();
+    let name = input.parse_symbol()?; 
+    if !variadic && name.value == "self" && input.maybe(|i| i.expect(":")).is_none() {
+        let param_type = Type::Simple(PSym { value: "Self".to_string(), ..name.clone() });
+        return Ok(FunParam { name, param_type, default: None, variadic });
+    }
+    input.expect(":")?;
+    let param_type = parse_annotation(input)?;
+    let default = match input.maybe(|i| i.expect("=")) {
+        Some(_) => Some(parse_expr(input)?),
+        None => None,
+    };
+    Ok(FunParam { name, param_type, default, variadic })
+}
+
+fn parse_params(input : &mut Input) -> Result<Vec<FunParam>, ParseError> {
+    let params = input.list(parse_param)?;
+    check_variadic(&params)?;
+    Ok(params)
+}
+
+// Type parameters, each with optional bounds: `<T : Ord + Show, U>`.
+fn parse_bounded_type_params(input : &mut Input) -> Result<(Vec<PSym>, Vec<TypeBound>), ParseError> {
+    let params = input.list(|i| {
+        let param = i.parse_symbol()?;
+        let mut interfaces = vec![];
+        if i.expect(":").is_ok() {
+            loop {
+                interfaces.push(i.parse_symbol()?);
+                if i.expect("+").is_err() {
+                    break;
+                }
+            }
+        }
+        Ok((param, interfaces))
+    })?;
+    let mut type_params = vec![];
+    let mut bounds = vec![];
+    for (param, interfaces) in params {
+        for interface in interfaces {
+            bounds.push(TypeBound { param: param.clone(), interface });
+        }
+        type_params.push(param);
+    }
+    Ok((type_params, bounds))
+}
+
+fn parse_fun_def(input : &mut Input) -> Result<FunDef, ParseError> {
     input.expect("fun")?;
     
     let name = input.parse_symbol()?;
 
     match input.maybe(|i| i.expect("<")) {
         Some(_) => {
-            let type_params = input.list(|i| i.parse_symbol())?;
+            let (type_params, bounds) = parse_bounded_type_params(input)?;
             input.expect(">")?;
             input.expect("(")?;
-            let params = input.list(parse_param)?;
+            let params = parse_params(input)?;
             input.expect(")")?;
             match input.expect("->") {
                 Ok(_) => {
-                    let return_type = parse_type(input)?;
+                    let return_type = parse_annotation(input)?;
                     input.expect("{")?;
-                    let definition = input.zero_or_more(parse_statement)?;
+                    let definition = parse_statements(input)?;
                     input.expect("}")?;
-                    Ok( FunDef { name, type_params, params, return_type, definition } )
+                    Ok( FunDef { name, type_params, bounds, params, return_type, definition } )
                 },
                 Err(_) => { 
                     input.expect("{")?;
-                    let definition = input.zero_or_more(parse_statement)?;
+                    let definition = parse_statements(input)?;
                     input.expect("}")?;
-                    Ok( FunDef { name, type_params, params, return_type: Type::Unit, definition } )
+                    Ok( FunDef { name, type_params, bounds, params, return_type: Type::Unit, definition } )
                 }, 
             }
 
         },
         None => {
             input.expect("(")?;
-            let params = input.list(parse_param)?;
+            let params = parse_params(input)?;
             input.expect(")")?;
             match input.expect("->") {
                 Ok(_) => {
-                    let return_type = parse_type(input)?;
+                    let return_type = parse_annotation(input)?;
                     input.expect("{")?;
-                    let definition = input.zero_or_more(parse_statement)?;
+                    let definition = parse_statements(input)?;
                     input.expect("}")?;
-                    Ok( FunDef { name, type_params: vec![], params, return_type, definition } )
+                    Ok( FunDef { name, type_params: vec![], bounds: vec![], params, return_type, definition } )
                 },
                 Err(_) => { 
                     input.expect("{")?;
-                    let definition = input.zero_or_more(parse_statement)?;
+                    let definition = parse_statements(input)?;
                     input.expect("}")?;
-                    Ok( FunDef { name, type_params: vec![], params, return_type: Type::Unit, definition } )
+                    Ok( FunDef { name, type_params: vec![], bounds: vec![], params, return_type: Type::Unit, definition } )
                 }, 
             }
         },
@@ -171,6 +365,42 @@ fn parse_fun_def(input : &mut Input) -> Result<FunDef, ParseError> {
 }
 
 
+fn parse_interface_def(input : &mut Input) -> Result<InterfaceDef, ParseError> {
+    fn parse_method(input : &mut Input) -> Result<InterfaceMethod, ParseError> {
+        input.expect("fun")?;
+        let name = input.parse_symbol()?;
+        input.expect("(")?;
+        let params = parse_params(input)?;
+        input.expect(")")?;
+        let return_type = match input.maybe(|i| i.expect("->")) {
+            Some(_) => parse_annotation(input)?,
+            None => Type::Unit,
+        };
+        input.expect(";")?;
+        Ok( InterfaceMethod { name, params, return_type } )
+    }
+
+    input.expect("interface")?;
+    let name = input.parse_symbol()?;
+    input.expect("{")?;
+    let methods = input.zero_or_more(parse_method)?;
+    input.expect("}")?;
+    Ok( InterfaceDef { name, methods } )
+}
+
+fn parse_impl_def(input : &mut Input) -> Result<ImplDef, ParseError> {
+    input.expect("impl")?;
+    let first = input.parse_symbol()?;
+    let (interface, target) = match input.maybe(|i| i.expect("for")) {
+        Some(_) => (Some(first), input.parse_symbol()?),
+        None => (None, first),
+    };
+    input.expect("{")?;
+    let funs = input.zero_or_more(parse_fun_def)?;
+    input.expect("}")?;
+    Ok( ImplDef { interface, target, funs } )
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -353,6 +583,23 @@ struct blah {
         Ok(())
     }
 
+    #[test]
+    fn should_parse_struct_with_public_slots_and_defaults() -> Result<(), ParseError> {
+        let i = r#"
+struct blah {
+    pub a : b = f(1),
+    pub : d,
+    c : List<d> = []
+}"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let u = parse_struct_def(&mut input)?;
+
+        let items = u.items.iter().map(|i| (i.name.value.as_str(), i.public, i.default.is_some())).collect::<Vec<_>>();
+        assert_eq!( items, vec![("a", true, true), ("pub", false, false), ("c", false, true)] );
+
+        Ok(())
+    }
+
     #[test]
     fn should_parse_struct_with_type_params() -> Result<(), ParseError> {
         let i = r#"
@@ -372,4 +619,136 @@ struct blah<a,b,c> {
 
         Ok(())
     }
+
+    #[test]
+    fn should_parse_extern_fun_def() -> Result<(), ParseError> {
+        let i = r#"extern fun format<T>(fmt : string, arg : T) -> string = "string.format";"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let def = parse_extern_fun_def(&mut input)?;
+        assert_eq!( sym_proj(&def.name), "format" );
+        assert_eq!( def.type_params.len(), 1 );
+        assert_eq!( def.params.len(), 2 );
+        assert_eq!( sym_proj(&def.path), "string.format" );
+        assert!( def.module.is_none() );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_extern_fun_def_from_module() -> Result<(), ParseError> {
+        let i = r#"extern fun encode(value : any) -> string = "encode" from "cjson";"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let def = parse_extern_fun_def(&mut input)?;
+        assert_eq!( sym_proj(&def.path), "encode" );
+        assert_eq!( def.module.as_ref().map(sym_proj), Some("cjson".to_string()) );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_extern_struct_def() -> Result<(), ParseError> {
+        let i = "extern struct File;".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let def = parse_extern_struct_def(&mut input)?;
+        assert_eq!( sym_proj(&def.name), "File" );
+        assert_eq!( def.type_params.len(), 0 );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_type_alias() -> Result<(), ParseError> {
+        let i = "type Pair<A, B> = Tuple<A, B>;".char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let def = parse_type_alias(&mut input)?;
+        assert_eq!( sym_proj(&def.name), "Pair" );
+        assert_eq!( def.type_params.len(), 2 );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_const_def() -> Result<(), ParseError> {
+        let i = r#"const GREETING : string = concat("hello ", NAME);"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let def = parse_const_def(&mut input)?;
+        assert_eq!( sym_proj(&def.name), "GREETING" );
+        assert!( matches!( def.const_type, Type::Simple(_) ) );
+        assert!( matches!( def.value, Expr::Call { .. } ) );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_interface_and_impl() -> Result<(), ParseError> {
+        let i = r#"
+interface Ord {
+    fun compare(self, other : Self) -> number;
+}
+impl Ord for Point {
+    fun compare(self, other : Point) -> number {
+        return sub(self.x, other.x);
+    }
+}
+impl Point {
+    fun norm(self) -> number {
+        return self.x;
+    }
+}
+fun sort<T : Ord + Show, U>(xs : List<T>, reverse : bool = not(true)) -> List<T> {
+    return xs;
+}"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let interface = parse_interface_def(&mut input)?;
+        assert_eq!( sym_proj(&interface.name), "Ord" );
+        assert_eq!( sym_proj(&interface.methods[0].params[0].name), "self" );
+        assert!( matches!( &interface.methods[0].params[0].param_type, Type::Simple(t) if t.value == "Self" ) );
+        let imp = parse_impl_def(&mut input)?;
+        assert_eq!( imp.interface.as_ref().map(sym_proj), Some("Ord".to_string()) );
+        assert_eq!( sym_proj(&imp.target), "Point" );
+        assert_eq!( imp.funs.len(), 1 );
+        let imp = parse_impl_def(&mut input)?;
+        assert!( imp.interface.is_none() );
+        assert_eq!( sym_proj(&imp.target), "Point" );
+        let def = parse_fun_def(&mut input)?;
+        assert_eq!( def.type_params.len(), 2 );
+        assert!( def.params[0].default.is_none() );
+        assert!( matches!( def.params[1].default, Some(Expr::Call { .. }) ) );
+        let bounds = def.bounds.iter().map(|b| (sym_proj(&b.param), sym_proj(&b.interface))).collect::<Vec<_>>();
+        assert_eq!( bounds, vec![ ("T".to_string(), "Ord".to_string()), ("T".to_string(), "Show".to_string()) ] );
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_extern_with_invalid_path() {
+        let i = r#"extern fun f() = "not a path";"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        match parse_extern_fun_def(&mut input) {
+            Err(ParseError::ErrorAt(_, message)) => assert_eq!( message, "Expected Lua path" ),
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn should_parse_variadic_params() -> Result<(), ParseError> {
+        let i = r#"fun log(level : string = "info", ...parts : List<string>) { }"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let def = parse_fun_def(&mut input)?;
+        assert_eq!( def.params.iter().map(|p| p.variadic).collect::<Vec<bool>>(), vec![false, true] );
+
+        let i = r#"extern fun format(fmt : string, ...args : List<any>) -> string = "string.format";"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        let def = parse_extern_fun_def(&mut input)?;
+        assert!( def.params[1].variadic );
+
+        let i = r#"fun f(...xs : List<number>, y : number) { }"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        match parse_fun_def(&mut input) {
+            Err(ParseError::ErrorAt(_, message)) => assert_eq!( message, "variadic parameter xs must come last" ),
+            r => panic!("Expected error but found {:?}", r),
+        }
+
+        let i = r#"fun f(...xs : List<number> = []) { }"#.char_indices().collect::<Vec<(usize, char)>>();
+        let mut input = Input::new(&i);
+        match parse_fun_def(&mut input) {
+            Err(ParseError::ErrorAt(_, message)) => assert_eq!( message, "variadic parameter xs cannot have a default" ),
+            r => panic!("Expected error but found {:?}", r),
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,105 @@
+
+use std::sync::OnceLock;
+
+use crate::lowering::lua::Lowering;
+use crate::parsing::proc::ast::Mod;
+use crate::parsing::proc::parser::parse;
+
+// The runtime prelude. Most of it is written in hyper (prelude.hy) and is
+// loaded by the interpreter and checker and lowered like any other module.
+// The primitives it builds on are NATIVES, implemented in native.lua for
+// compiled code and in eval/builtins.rs for the interpreter.
+//
+// Lists are indexed from 0 in hyper; the Lua side adds 1 when it touches a
+// table.
+
+pub const SOURCE : &str = include_str!("prelude.hy");
+pub const NATIVE_LUA : &str = include_str!("native.lua");
+
+// Compiled modules `require` this name, so `hyper build` writes the
+// prelude next to them as `hyper_prelude.lua`.
+pub const MODULE_NAME : &str = "hyper_prelude";
+
+pub const NATIVES : &[&str] = &[ "iter", "get", "pop", "is_ok", "unwrap", "unwrap_err", "substring", "split"
+                               , "upper", "lower", "trim", "find", "keys", "values", "has_key", "remove_key"
+                               , "is_some", "is_none", "value_or"
+                               ];
+
+// Helpers that lowered code calls but hyper code can't name. Maps are
+// tables tagged with a metatable so that these can tell them from lists
+// when the checker couldn't.
+pub const INTERNALS : &[&str] = &[ "__map", "__key", "__index", "__set_index", "__entries", "__range", "__impl", "__method"
+                                  , "__update", "__splice", "__failed", "__value"
+                                  ];
+
+pub fn module() -> &'static Mod {
+    static MODULE : OnceLock<Mod> = OnceLock::new();
+    MODULE.get_or_init(|| parse(SOURCE).expect("prelude failed to parse"))
+}
+
+pub fn exports() -> Vec<&'static str> {
+    let methods = module().interface_defs.iter()
+                                         .filter(|d| module().interface_exports.contains(&d.name.value))
+                                         .flat_map(|d| d.methods.iter().map(|m| &m.name.value));
+    let exports = module().fun_exports.iter().chain(module().const_exports.iter()).chain(methods);
+    NATIVES.iter().cloned().chain(exports.map(|e| e.as_str())).collect()
+}
+
+// With a target, the prelude first checks that it runs on that version of
+// Lua or a later one.
+pub fn lua(target : Option<&str>) -> String {
+    let mut lowering = Lowering::prelude();
+    lowering.lower_mod(module());
+    let code = lowering.finish().code;
+    match target {
+        Some(version) => {
+            let check = format!("if _VERSION < \"Lua {0}\" then error(\"built for Lua {0} or later, not \" .. _VERSION) end", version);
+            format!("{}\n{}", check, code)
+        },
+        None => code,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::interpreter::Interpreter;
+    use crate::eval::value::Value;
+    use crate::typing::checker::Checker;
+
+    #[test]
+    fn should_check_prelude_without_errors() {
+        let mut checker = Checker::new();
+        checker.check_mod(module());
+        let errors = checker.take_errors().into_iter().map(|e| e.message).collect::<Vec<String>>();
+        assert_eq!( errors, Vec::<String>::new() );
+    }
+
+    #[test]
+    fn should_export_natives_and_hyper_functions() {
+        let code = lua(None);
+        assert!( code.contains("local function get(items, index)") );
+        assert!( code.contains("__module.get = get") );
+        assert!( code.contains("__module.map = map") );
+        assert!( code.contains("for _, item in iter(items) do") );
+        assert!( !code.contains("__prelude") );
+    }
+
+    #[test]
+    fn should_run_prelude_functions() {
+        let program = parse(r#"
+fun main() -> string {
+    let xs = map([1, 2, 3], |x| mul(x, 10));
+    let ys = to_list(take(gen_map(reverse(xs), |x| add(x, 1)), 2));
+    return join(split(concat(to_string(get(ys, 0)), ",", to_string(get(ys, 1))), ","), "-");
+}"#).expect("parse failed");
+        let mut interpreter = Interpreter::new();
+        interpreter.load(&program).expect("load failed");
+        match interpreter.call("main", vec![]) {
+            Ok(Value::String(s)) => assert_eq!( s, "31-21" ),
+            Ok(v) => panic!("unexpected value {}", v),
+            Err(e) => panic!("runtime error: {}", e.message),
+        }
+    }
+}
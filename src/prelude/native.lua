@@ -0,0 +1,281 @@
+local Map = {}
+
+local function __map(t)
+    return setmetatable(t, Map)
+end
+
+-- Reading a key that isn't there, or past the end of a list, is an error
+-- rather than nil, as it is in the interpreter.
+local function __key(t, k)
+    local v = t[k]
+    if v == nil then
+        error("missing key " .. tostring(k), 2)
+    end
+    return v
+end
+
+local function __index(t, k)
+    local v
+    if getmetatable(t) == Map then
+        v = t[k]
+        if v == nil then
+            error("missing key " .. tostring(k), 2)
+        end
+    else
+        v = t[k + 1]
+        if v == nil then
+            error("index " .. k .. " out of range", 2)
+        end
+    end
+    return v
+end
+
+local function __set_index(t, k, v)
+    if getmetatable(t) == Map then
+        t[k] = v
+    else
+        t[k + 1] = v
+    end
+end
+
+-- A map on its own iterates over its keys.
+local function iter(items)
+    if getmetatable(items) == Map then
+        local key = nil
+        return function()
+            key = next(items, key)
+            if key ~= nil then
+                return true, key
+            end
+        end
+    end
+    if type(items) == "function" then
+        return function()
+            local item = items()
+            if item ~= nil then
+                return true, item
+            end
+        end
+    end
+    return ipairs(items)
+end
+
+local function get(items, index)
+    local item = items[index + 1]
+    if item == nil then
+        error("index " .. index .. " out of range", 2)
+    end
+    return item
+end
+
+local function pop(items)
+    if #items == 0 then
+        error("pop from empty list", 2)
+    end
+    return table.remove(items)
+end
+
+local function is_ok(result)
+    return result.is_ok
+end
+
+local function unwrap(result)
+    if not result.is_ok then
+        error("unwrap called on Err", 2)
+    end
+    return result.value
+end
+
+local function unwrap_err(result)
+    if result.is_ok then
+        error("unwrap_err called on Ok", 2)
+    end
+    return result.value
+end
+
+-- Options are nil when absent.
+local function is_some(option)
+    return option ~= nil
+end
+
+local function is_none(option)
+    return option == nil
+end
+
+local function value_or(option, default)
+    if option == nil then
+        return default
+    end
+    return option
+end
+
+local function substring(s, start, stop)
+    return string.sub(s, start + 1, stop)
+end
+
+local function split(s, separator)
+    if separator == "" then
+        error("split with empty separator", 2)
+    end
+    local ret = {}
+    local start = 1
+    while true do
+        local i, j = string.find(s, separator, start, true)
+        if i == nil then
+            table.insert(ret, string.sub(s, start))
+            return ret
+        end
+        table.insert(ret, string.sub(s, start, i - 1))
+        start = j + 1
+    end
+end
+
+local function upper(s)
+    return string.upper(s)
+end
+
+local function lower(s)
+    return string.lower(s)
+end
+
+local function trim(s)
+    return (string.gsub(s, "^%s*(.-)%s*$", "%1"))
+end
+
+local function find(s, part)
+    local i = string.find(s, part, 1, true)
+    if i == nil then
+        return -1
+    end
+    return i - 1
+end
+
+-- Index/item pairs for lists and generators, key/value pairs for maps.
+local function __entries(items)
+    if getmetatable(items) == Map then
+        return next, items, nil
+    end
+    local index = -1
+    if type(items) == "function" then
+        return function()
+            local item = items()
+            if item ~= nil then
+                index = index + 1
+                return index, item
+            end
+        end
+    end
+    return function()
+        index = index + 1
+        local item = items[index + 1]
+        if item ~= nil then
+            return index, item
+        end
+    end
+end
+
+-- Builds the list for a range that isn't iterated directly by foreach.
+local function __range(first, last, step, inclusive)
+    if step == 0 then
+        error("range step cannot be zero")
+    end
+    local items = {}
+    local i = first
+    while (step > 0 and i < last) or (step < 0 and i > last) or (inclusive and i == last) do
+        items[#items + 1] = i
+        i = i + step
+    end
+    return items
+end
+
+-- `new T { ..base }`: a copy of base with some of its slots replaced.
+local function __update(base, slots)
+    local t = {}
+    for k, v in pairs(base) do
+        t[k] = v
+    end
+    for k, v in pairs(slots) do
+        t[k] = v
+    end
+    return setmetatable(t, getmetatable(base))
+end
+
+-- `?` on a value the checker couldn't type. Results are tables with
+-- is_ok, and options are nil or the value itself.
+local function __failed(v)
+    return v == nil or (type(v) == "table" and v.is_ok == false)
+end
+
+local function __value(v)
+    if type(v) == "table" and v.is_ok ~= nil then
+        return v.value
+    end
+    return v
+end
+
+-- `[a, ...xs, b]`: the lists' items in order, in a new list.
+local function __splice(...)
+    local items = {}
+    for _, list in ipairs({ ... }) do
+        for _, item in ipairs(list) do
+            items[#items + 1] = item
+        end
+    end
+    return items
+end
+
+-- Impls by interface and type name. Structs carry a metatable whose
+-- __name is the struct's name, qualified by its module in projects.
+local __impls = {}
+
+local function __type_name(v)
+    local t = type(v)
+    if t == "table" then
+        local mt = getmetatable(v)
+        return mt and mt.__name or "table"
+    end
+    if t == "boolean" then
+        return "bool"
+    end
+    return t
+end
+
+local function __impl(interface, type_name, methods)
+    __impls[interface .. "." .. type_name] = methods
+end
+
+-- An interface method, which dispatches on the type of its first argument.
+local function __method(interface, name)
+    return function(self, ...)
+        local type_name = __type_name(self)
+        local methods = __impls[interface .. "." .. type_name]
+        if methods == nil then
+            error(type_name .. " does not implement " .. interface, 2)
+        end
+        return methods[name](self, ...)
+    end
+end
+
+local function keys(m)
+    local ret = {}
+    for k in pairs(m) do
+        table.insert(ret, k)
+    end
+    return ret
+end
+
+local function values(m)
+    local ret = {}
+    for _, v in pairs(m) do
+        table.insert(ret, v)
+    end
+    return ret
+end
+
+local function has_key(m, k)
+    return m[k] ~= nil
+end
+
+local function remove_key(m, k)
+    m[k] = nil
+end
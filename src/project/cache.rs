@@ -0,0 +1,234 @@
+use std::path::PathBuf;
+
+
+use crate::diagnostic::Diagnostic;
+use crate::json::{self, Json};
+use crate::parsing::proc::ast::*;
+
+// Where a package keeps its cache, next to hyper.toml.
+pub const DIR_NAME : &str = ".hyper-cache";
+
+// What a project build keeps of each module it checks and lowers, so that
+// the next build can skip the module while neither its source nor what it
+// imports has changed. The parsed module is kept too, for loading the
+// module graph without parsing modules whose source is the same.
+pub struct Entry {
+    // Hashes of the module's source and of the signatures of the modules
+    // its uses refer to.
+    pub source : u64,
+    pub imports : u64,
+    pub lua : String,
+    pub map : String,
+    pub warnings : Vec<Diagnostic>,
+    // The parsed module as `stored` writes it, and the hash of the source
+    // alone it was parsed from.
+    pub parsed : u64,
+    pub module : Json,
+}
+
+// Entries are files named after the module's Lua name in the directory.
+pub struct Cache {
+    dir : PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir : PathBuf) -> Self {
+        Cache { dir }
+    }
+
+    fn path(&self, lua_name : &str) -> PathBuf {
+        self.dir.join(format!("{}.json", lua_name))
+    }
+
+    // Anything missing or unreadable is as good as no entry.
+    pub fn load(&self, lua_name : &str) -> Option<Entry> {
+        let json = json::parse(&std::fs::read_to_string(self.path(lua_name)).ok()?).ok()?;
+        let hash = |key : &str| u64::from_str_radix(json.get(key)?.as_str()?, 16).ok();
+        let mut warnings = vec![];
+        for w in json.get("warnings")?.as_array()? {
+            let message = w.get("message")?.as_str()?.to_string();
+            warnings.push(Diagnostic::new(w.get("start").and_then(|s| s.as_usize()), message));
+        }
+        Some(Entry { source: hash("source")?
+                   , imports: hash("imports")?
+                   , lua: json.get("lua")?.as_str()?.to_string()
+                   , map: json.get("map")?.as_str()?.to_string()
+                   , warnings
+                   , parsed: hash("parsed")?
+                   , module: json.get("module")?.clone()
+                   })
+    }
+
+    pub fn store(&self, lua_name : &str, entry : &Entry) -> std::io::Result<()> {
+        let warnings = entry.warnings.iter().map(|w| {
+            let start = w.start.map(|s| Json::Number(s as f64)).unwrap_or(Json::Null);
+            Json::Object(vec![("start".to_string(), start), ("message".to_string(), Json::String(w.message.clone()))])
+        }).collect();
+        let json = Json::Object(vec![ ("source".to_string(), Json::String(format!("{:x}", entry.source)))
+                                    , ("imports".to_string(), Json::String(format!("{:x}", entry.imports)))
+                                    , ("lua".to_string(), Json::String(entry.lua.clone()))
+                                    , ("map".to_string(), Json::String(entry.map.clone()))
+                                    , ("warnings".to_string(), Json::Array(warnings))
+                                    , ("parsed".to_string(), Json::String(format!("{:x}", entry.parsed)))
+                                    , ("module".to_string(), entry.module.clone())
+                                    ]);
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.path(lua_name), json.to_string())
+    }
+}
+
+// Changes whenever lowering or the stored modules do, so that entries from
+// builds that lowered or stored modules differently aren't used. Every
+// release of hyper is taken to have changed them.
+const FORMAT : &str = "hyper-cache 2";
+const VERSION : &str = env!("CARGO_PKG_VERSION");
+
+// FNV-1a, which is the same from one build of hyper to the next, unlike
+// the standard library's hasher, salted with FORMAT and VERSION.
+pub fn hash(parts : &[&str]) -> u64 {
+    let salted = [FORMAT, VERSION].iter().chain(parts.iter()).cloned().collect::<Vec<&str>>();
+    fnv(&salted)
+}
+
+// Each part is preceded by its length so that moving text between parts
+// changes the hash.
+fn fnv(parts : &[&str]) -> u64 {
+    let mut hash : u64 = 0xcbf2_9ce4_8422_2325;
+    let mut feed = |bytes : &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100_0000_01b3);
+        }
+    };
+    for part in parts {
+        feed(&(part.len() as u64).to_le_bytes());
+        feed(part.as_bytes());
+    }
+    hash
+}
+
+fn type_text(t : &Type) -> String {
+    let list = |ts : &[Type]| ts.iter().map(type_text).collect::<Vec<String>>().join(", ");
+    match t {
+        Type::Unit => "()".to_string(),
+        Type::Infer => "_".to_string(),
+        Type::Simple(name) => name.value.clone(),
+        Type::Generic(name, args) => format!("{}<{}>", name.value, list(args)),
+        Type::Fun(params, ret) => format!("fun({}) -> {}", list(params), type_text(ret)),
+        Type::Tuple(ts) => format!("({})", list(ts)),
+        Type::Namespace(path, t) => {
+            let path = path.iter().map(|p| format!("{}::", p.value)).collect::<String>();
+            format!("{}{}", path, type_text(t))
+        },
+    }
+}
+
+fn params_text(params : &[FunParam]) -> String {
+    let params = params.iter().map(|p| {
+        let default = if p.default.is_some() { " = .." } else { "" };
+        let rest = if p.variadic { "..." } else { "" };
+        format!("{}{} : {}{}", rest, p.name.value, type_text(&p.param_type), default)
+    }).collect::<Vec<String>>();
+    params.join(", ")
+}
+
+fn names(syms : &[parse_input::PSym]) -> String {
+    syms.iter().map(|s| s.value.as_str()).collect::<Vec<&str>>().join(", ")
+}
+
+// What other modules can see of a module, which is what checking and
+// lowering them depends on: its exports and all of its impls.
+pub fn signature(module : &Mod) -> String {
+    let mut lines = vec![];
+    for def in module.fun_defs.iter().filter(|d| module.fun_exports.contains(&d.name.value)) {
+        let bounds = def.bounds.iter().map(|b| format!("{} : {}", b.param.value, b.interface.value)).collect::<Vec<String>>();
+        let generator = if contains_yield(&def.definition) { " yields" } else { "" };
+        lines.push(format!( "fun {}<{}; {}>({}) -> {}{}"
+                          , def.name.value, names(&def.type_params), bounds.join(", ")
+                          , params_text(&def.params), type_text(&def.return_type), generator
+                          ));
+    }
+    for def in module.extern_fun_defs.iter().filter(|d| module.fun_exports.contains(&d.name.value)) {
+        lines.push(format!( "extern fun {}<{}>({}) -> {}"
+                          , def.name.value, names(&def.type_params), params_text(&def.params), type_text(&def.return_type)
+                          ));
+    }
+    for def in module.struct_defs.iter().filter(|d| module.struct_exports.contains(&d.name.value)) {
+        let items = def.items.iter().map(|i| {
+            let default = if i.default.is_some() { " = .." } else { "" };
+            format!("{}{} : {}{}", if i.public { "pub " } else { "" }, i.name.value, type_text(&i.item_type), default)
+        });
+        lines.push(format!("struct {}<{}> {{ {} }}", def.name.value, names(&def.type_params), items.collect::<Vec<String>>().join(", ")));
+    }
+    for def in module.extern_struct_defs.iter().filter(|d| module.struct_exports.contains(&d.name.value)) {
+        lines.push(format!("extern struct {}<{}>", def.name.value, names(&def.type_params)));
+    }
+    for def in module.enum_defs.iter().filter(|d| module.enum_exports.contains(&d.name.value)) {
+        lines.push(format!("enum {} {{ {} }}", def.name.value, names(&def.items)));
+    }
+    for def in module.type_aliases.iter().filter(|d| module.type_exports.contains(&d.name.value)) {
+        lines.push(format!("type {}<{}> = {}", def.name.value, names(&def.type_params), type_text(&def.aliased)));
+    }
+    for def in module.const_defs.iter().filter(|d| module.const_exports.contains(&d.name.value)) {
+        lines.push(format!("const {} : {}", def.name.value, type_text(&def.const_type)));
+    }
+    for def in module.interface_defs.iter().filter(|d| module.interface_exports.contains(&d.name.value)) {
+        let methods = def.methods.iter().map(|m| format!("{}({}) -> {}", m.name.value, params_text(&m.params), type_text(&m.return_type)));
+        lines.push(format!("interface {} {{ {} }}", def.name.value, methods.collect::<Vec<String>>().join("; ")));
+    }
+    for def in module.impl_defs.iter() {
+        let interface = def.interface.as_ref().map(|i| format!("{} for ", i.value)).unwrap_or_default();
+        let funs = def.funs.iter().map(|f| format!("{}({}) -> {}", f.name.value, params_text(&f.params), type_text(&f.return_type)));
+        lines.push(format!("impl {}{} {{ {} }}", interface, def.target.value, funs.collect::<Vec<String>>().join("; ")));
+    }
+    lines.join("\n")
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::proc::parser::parse;
+
+    fn signature_of(input : &str) -> String {
+        signature(&parse(input).expect("parse failed"))
+    }
+
+    #[test]
+    fn should_only_change_signature_with_exports() {
+        let before = signature_of("pub fun f(x : number) -> number { return x; }\nfun g() { }");
+        let moved = signature_of("fun g() { print(1); }\n\n\npub fun f(x : number) -> number { return add(x, 1); }");
+        let changed = signature_of("pub fun f(x : string) -> number { return 1; }\nfun g() { }");
+        assert_eq!( before, "fun f<; >(x : number) -> number" );
+        assert_eq!( before, moved );
+        assert_ne!( before, changed );
+        assert_ne!( signature_of("pub struct C { n : number }"), signature_of("pub struct C { n : number = 1 }") );
+        assert_eq!( signature_of("pub struct C { n : number = 1 }"), signature_of("pub struct C { n : number = 2 }") );
+        assert_eq!( type_text(&Type::Simple(parse_input::PSym { start: 4, end: 5, value: "T".to_string() })), "T" );
+        assert_eq!( signature_of("pub const C : a::Map<string, fun(List<number>) -> (bool, _)> = 1;")
+                  , "const C : a::Map<string, fun(List<number>) -> (bool, _)>"
+                  );
+    }
+
+    #[test]
+    fn should_hash_parts_apart_and_the_same_each_build() {
+        assert_eq!( hash(&["ab", "c"]), hash(&["ab", "c"]) );
+        assert_ne!( hash(&["ab", "c"]), hash(&["a", "bc"]) );
+        assert_ne!( hash(&[]), hash(&[""]) );
+        assert_eq!( fnv(&["module"]), 0xa298_7d78_245a_346f );
+    }
+
+    #[test]
+    fn should_store_and_load_entries() {
+        let dir = std::env::temp_dir().join(format!("hyper_cache_{}", std::process::id()));
+        let cache = Cache::new(dir.clone());
+        let entry = Entry { source: u64::MAX, imports: 7, lua: "return {}\n".to_string(), map: "{}".to_string(), warnings: vec![Diagnostic::new(Some(3), "w".to_string()), Diagnostic::new(None, "v".to_string())], parsed: 9, module: Json::Array(vec![]) };
+        cache.store("a.b", &entry).expect("store failed");
+        let loaded = cache.load("a.b").expect("load failed");
+        assert_eq!( (loaded.source, loaded.imports, loaded.lua, loaded.map), (u64::MAX, 7, entry.lua, entry.map) );
+        assert_eq!( (loaded.parsed, loaded.module), (9, entry.module) );
+        assert_eq!( loaded.warnings.iter().map(|w| (w.start, w.message.as_str())).collect::<Vec<_>>(), vec![(Some(3), "w"), (None, "v")] );
+        assert!( cache.load("missing").is_none() );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use parse_input::ParseError;
+
+use crate::diagnostic::Diagnostic;
+use crate::parsing::proc::ast::*;
+use crate::parsing::proc::parser::parse;
+use super::cache::{self, Cache};
+use super::manifest::{self, Manifest};
+use super::stored;
+
+pub struct File {
+    pub path : String,
+    pub source : String,
+}
+
+pub struct Module {
+    pub package : usize,
+    // The module's path within its package, e.g. `["geometry", "shapes"]`
+    // for src/geometry/shapes.hy.
+    pub name : Vec<String>,
+    pub file : usize,
+    pub module : Mod,
+    // The module each `use` refers to, None where it couldn't be loaded.
+    pub uses : Vec<Option<usize>>,
+}
+
+// Modules parsed by an earlier load, by path, with the source they were
+// parsed from.
+pub type Parsed = HashMap<String, (String, Mod)>;
+
+// A package and the modules reachable from its entry through `use`. The
+// first package is the one being built, and the first module its entry.
+//
+// In a `use`, a first name that is one of the package's dependencies
+// refers to that package, to its entry when nothing follows. Anything else
+// is a module of the same package.
+pub struct Graph {
+    pub packages : Vec<Manifest>,
+    pub files : Vec<File>,
+    pub modules : Vec<Module>,
+    // Problems found in the files, by file.
+    pub errors : Vec<(usize, Diagnostic)>,
+    // How many modules had to be parsed, rather than taken from `parsed`
+    // or the build cache.
+    pub reparsed : usize,
+    parsed : Parsed,
+    cache : Cache,
+    loaded : HashMap<(usize, Vec<String>), Option<usize>>,
+    // The modules being loaded, each waiting on the ones after it.
+    loading : Vec<usize>,
+}
+
+impl Graph {
+    // Fails when a package can't be found; problems in files are left in
+    // `errors`. Modules whose source is the same as when they were parsed,
+    // here or by the build that cached them, aren't parsed again.
+    pub fn load(root : Manifest, parsed : Parsed) -> Result<Graph, String> {
+        let mut graph = Graph::with_packages(root, parsed)?;
+        let entry = graph.packages[0].entry.clone();
+        if graph.module(0, entry, None).is_some() {
+            graph.check_imports();
+        }
+        Ok(graph)
+    }
+
+    // Loads what a `use` written outside the package's modules refers to,
+    // as in the REPL, instead of the entry. Gives back the used module
+    // unless it couldn't be loaded.
+    pub fn load_use(root : Manifest, u : &Use) -> Result<(Graph, Option<usize>), String> {
+        let mut graph = Graph::with_packages(root, Parsed::new())?;
+        let (package, name) = graph.target(0, u);
+        let index = graph.module(package, name, None);
+        if index.is_some() {
+            graph.check_imports();
+        }
+        Ok((graph, index))
+    }
+
+    fn with_packages(root : Manifest, parsed : Parsed) -> Result<Graph, String> {
+        let cache = Cache::new(root.root.join(cache::DIR_NAME));
+        let mut graph = Graph { packages: vec![root], files: vec![], modules: vec![], errors: vec![], reparsed: 0, parsed, cache, loaded: HashMap::new(), loading: vec![] };
+        let mut next = 0;
+        while next < graph.packages.len() {
+            for (name, path) in graph.packages[next].dependencies.clone() {
+                graph.add_package(&name, &path)?;
+            }
+            next += 1;
+        }
+        Ok(graph)
+    }
+
+    // Gives the parsed modules back for the next load, along with those
+    // this load didn't get to.
+    pub fn into_parsed(mut self) -> Parsed {
+        let files = &mut self.files;
+        let modules = self.modules.into_iter().map(|m| {
+            let file = &mut files[m.file];
+            (file.path.clone(), (std::mem::take(&mut file.source), m.module))
+        });
+        self.parsed.extend(modules);
+        self.parsed
+    }
+
+    fn add_package(&mut self, name : &str, path : &Path) -> Result<(), String> {
+        let manifest = Manifest::load(&path.join(manifest::FILE_NAME))?;
+        if manifest.name != name {
+            return Err(format!("dependency {} is at {}, whose package is named {}", name, path.display(), manifest.name));
+        }
+        let root = std::fs::canonicalize(&manifest.root).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for package in self.packages.iter() {
+            let same = std::fs::canonicalize(&package.root).map(|r| r == root).unwrap_or(false);
+            match (package.name == name, same) {
+                (true, true) => return Ok(()),
+                (true, false) => return Err(format!("two packages are named {}: {} and {}", name, package.root.display(), path.display())),
+                _ => (),
+            }
+        }
+        self.packages.push(manifest);
+        Ok(())
+    }
+
+    // The name Lua requires the module by, which is also where it's
+    // written in the output. Dependencies go in a directory of their own.
+    pub fn lua_name(&self, index : usize) -> String {
+        let module = &self.modules[index];
+        self.lua_path(module.package, &module.name)
+    }
+
+    fn lua_path(&self, package : usize, name : &[String]) -> String {
+        let path = name.join(".");
+        match package {
+            0 => path,
+            p => format!("{}.{}", self.packages[p].name, path),
+        }
+    }
+
+    pub fn display_name(&self, index : usize) -> String {
+        let module = &self.modules[index];
+        let path = module.name.join("::");
+        match module.package {
+            0 => path,
+            p => format!("{}::{}", self.packages[p].name, path),
+        }
+    }
+
+    fn source_path(&self, package : usize, name : &[String]) -> PathBuf {
+        let mut path = self.packages[package].source.clone();
+        for part in name {
+            path.push(part);
+        }
+        path.with_extension("hy")
+    }
+
+    // Loads a module and the ones it uses. `site` is the `use` that asked
+    // for it, as a file and where in it.
+    fn module(&mut self, package : usize, name : Vec<String>, site : Option<(usize, usize)>) -> Option<usize> {
+        let key = (package, name.clone());
+        if let Some(index) = self.loaded.get(&key).cloned() {
+            if let (Some(index), Some((file, start))) = (index, site) {
+                if let Some(at) = self.loading.iter().position(|m| *m == index) {
+                    let cycle = self.loading[at..].iter().chain(std::iter::once(&index)).map(|m| self.display_name(*m)).collect::<Vec<String>>();
+                    let message = format!("modules can't use each other in a cycle: {}", cycle.join(" -> "));
+                    self.errors.push((file, Diagnostic::new(Some(start), message)));
+                }
+            }
+            return index;
+        }
+
+        let path = self.source_path(package, &name);
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                let message = format!("cannot read module {} at {}: {}", name.join("::"), path.display(), e);
+                match site {
+                    Some((file, start)) => self.errors.push((file, Diagnostic::new(Some(start), message))),
+                    None => {
+                        self.files.push(File { path: path.display().to_string(), source: String::new() });
+                        self.errors.push((self.files.len() - 1, Diagnostic::new(None, message)));
+                    },
+                }
+                self.loaded.insert(key, None);
+                return None;
+            },
+        };
+        let path = path.display().to_string();
+        let previous = match self.parsed.remove(&path) {
+            Some((previous, module)) if previous == source => Some(module),
+            _ => self.cache.load(&self.lua_path(package, &name))
+                           .filter(|e| e.parsed == cache::hash(&[&source]))
+                           .and_then(|e| stored::module_from_json(&e.module)),
+        };
+        self.files.push(File { path, source });
+        let file = self.files.len() - 1;
+        let module = match previous {
+            Some(module) => module,
+            None => {
+                self.reparsed += 1;
+                match parse(&self.files[file].source) {
+                    Ok(module) => module,
+                    Err(e) => {
+                        let diagnostic = match e {
+                            ParseError::ErrorAt(offset, message) => Diagnostic::new(Some(offset), message),
+                            e => Diagnostic::new(None, format!("{:?}", e)),
+                        };
+                        self.errors.push((file, diagnostic));
+                        self.loaded.insert(key, None);
+                        return None;
+                    },
+                }
+            },
+        };
+
+        let targets = module.uses.iter().map(|u| {
+            let (target, name) = self.target(package, u);
+            (target, name, u.namespace[0].start)
+        }).collect::<Vec<_>>();
+
+        let index = self.modules.len();
+        self.modules.push(Module { package, name, file, module, uses: vec![] });
+        self.loaded.insert(key, Some(index));
+        self.loading.push(index);
+        let uses = targets.into_iter().map(|(target, name, start)| self.module(target, name, Some((file, start)))).collect();
+        self.loading.pop();
+        self.modules[index].uses = uses;
+        Some(index)
+    }
+
+    // The package and module a `use` in `package` refers to.
+    fn target(&self, package : usize, u : &Use) -> (usize, Vec<String>) {
+        let names = u.namespace.iter().map(|n| n.value.clone()).collect::<Vec<String>>();
+        let dependency = self.packages[package].dependencies.iter().position(|(d, _)| *d == names[0]);
+        let target = match dependency {
+            Some(d) => self.packages.iter().position(|p| p.name == self.packages[package].dependencies[d].0).unwrap_or(package),
+            None => package,
+        };
+        let name = match (dependency, names.len()) {
+            (Some(_), 1) => self.packages[target].entry.clone(),
+            (Some(_), _) => names[1..].to_vec(),
+            (None, _) => names,
+        };
+        (target, name)
+    }
+
+    // Every name a `use` gives has to be exported by the module.
+    fn check_imports(&mut self) {
+        let mut errors = vec![];
+        for module in self.modules.iter() {
+            for (u, target) in module.module.uses.iter().zip(module.uses.iter()) {
+                let target = match target {
+                    Some(target) => *target,
+                    None => continue,
+                };
+                for import in u.imports.iter() {
+                    if let Import::Item(name) = import {
+                        if !exports(&self.modules[target].module).any(|e| *e == name.value) {
+                            let message = format!("{} has no export {}", self.display_name(target), name.value);
+                            errors.push((module.file, Diagnostic::new(Some(name.start), message)));
+                        }
+                    }
+                }
+            }
+        }
+        self.errors.extend(errors);
+    }
+}
+
+pub fn exports(module : &Mod) -> impl Iterator<Item = &String> {
+    module.fun_exports.iter()
+        .chain(module.struct_exports.iter())
+        .chain(module.enum_exports.iter())
+        .chain(module.type_exports.iter())
+        .chain(module.const_exports.iter())
+        .chain(module.interface_exports.iter())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A package on disk, made of hyper.toml and the given files.
+    fn package(dir : &str, manifest : &str, files : &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("hyper_graph_{}_{}", std::process::id(), dir));
+        let _ = std::fs::remove_dir_all(&root);
+        for (path, contents) in std::iter::once(&(manifest::FILE_NAME, manifest)).chain(files.iter()) {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn should_load_modules_from_uses() -> Result<(), String> {
+        let util = package("util", "[package]\nname = \"util\"\nentry = \"lib\"", &[
+            ("src/lib.hy", "pub fun id(x : number) -> number { return x; }"),
+            ("src/text.hy", "pub fun shout(s : string) -> string { return s; }"),
+        ]);
+        let app = package("app", &format!("[package]\nname = \"app\"\n[dependencies]\nutil = {{ path = {:?} }}", util.display().to_string()), &[
+            ("src/main.hy", "use geometry::shapes::{area};\nuse util::{id};\nuse util::text::{*};\nfun main() { }"),
+            ("src/geometry/shapes.hy", "use util::{id};\npub fun area() -> number { return id(1); }"),
+        ]);
+
+        let graph = Graph::load(Manifest::load(&app.join(manifest::FILE_NAME))?, Parsed::new())?;
+
+        assert_eq!( graph.errors.len(), 0 );
+        let names = (0..graph.modules.len()).map(|m| graph.lua_name(m)).collect::<Vec<String>>();
+        assert_eq!( names, vec!["main", "geometry.shapes", "util.lib", "util.text"] );
+        assert_eq!( graph.modules[0].uses, vec![Some(1), Some(2), Some(3)] );
+        assert_eq!( graph.modules[1].uses, vec![Some(2)] );
+        assert_eq!( graph.reparsed, 4 );
+
+        std::fs::write(app.join("src/geometry/shapes.hy"), "use util::{id};\npub fun area() -> number { return id(2); }").unwrap();
+        let graph = Graph::load(Manifest::load(&app.join(manifest::FILE_NAME))?, graph.into_parsed())?;
+        assert_eq!( graph.errors.len(), 0 );
+        assert_eq!( graph.modules.len(), 4 );
+        assert_eq!( graph.reparsed, 1 );
+        Ok(())
+    }
+
+    #[test]
+    fn should_take_modules_the_build_cached() -> Result<(), String> {
+        let source = "fun main() { }";
+        let app = package("cached", "[package]\nname = \"app\"", &[("src/main.hy", source)]);
+        let module = parse(source).map_err(|e| format!("{:?}", e))?;
+        let entry = cache::Entry { source: 0, imports: 0, lua: String::new(), map: String::new(), warnings: vec![], parsed: cache::hash(&[source]), module: stored::module_json(&module) };
+        Cache::new(app.join(cache::DIR_NAME)).store("main", &entry).map_err(|e| e.to_string())?;
+
+        let graph = Graph::load(Manifest::load(&app.join(manifest::FILE_NAME))?, Parsed::new())?;
+        assert_eq!( (graph.errors.len(), graph.modules.len(), graph.reparsed), (0, 1, 0) );
+
+        std::fs::write(app.join("src/main.hy"), "fun main() { print(1); }").unwrap();
+        let graph = Graph::load(Manifest::load(&app.join(manifest::FILE_NAME))?, Parsed::new())?;
+        assert_eq!( graph.reparsed, 1 );
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_missing_modules_exports_and_cycles() -> Result<(), String> {
+        let app = package("cycle", "[package]\nname = \"app\"", &[
+            ("src/main.hy", "use a::{f, g};\nuse missing::{*};\nfun main() { }"),
+            ("src/a.hy", "use main::{*};\npub fun f() { }\nfun g() { }"),
+        ]);
+
+        let graph = Graph::load(Manifest::load(&app.join(manifest::FILE_NAME))?, Parsed::new())?;
+
+        let errors = graph.errors.iter().map(|(f, e)| (graph.files[*f].path.ends_with("main.hy"), e.message.as_str())).collect::<Vec<_>>();
+        assert_eq!( errors.len(), 3 );
+        assert_eq!( errors[0], (false, "modules can't use each other in a cycle: main -> a -> main") );
+        assert!( errors[1].0 && errors[1].1.starts_with("cannot read module missing at ") );
+        assert_eq!( errors[2], (true, "a has no export g") );
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use std::path::{Path, PathBuf};
+
+use crate::toml::{self, Toml};
+
+pub const FILE_NAME : &str = "hyper.toml";
+
+// Lowered code uses goto, which Lua has had since 5.2.
+pub const LUA_VERSIONS : &[&str] = &["5.2", "5.3", "5.4"];
+
+// A package, described by the hyper.toml at its root:
+//
+//     [package]
+//     name = "app"
+//     source = "src"
+//     entry = "main"
+//     output = "out"
+//     lua = "5.2"
+//
+//     [dependencies]
+//     util = { path = "../util" }
+//
+// Everything but the name has the default shown. Dependencies are other
+// packages on disk, which `use` reaches by their name.
+#[derive(Debug)]
+pub struct Manifest {
+    pub name : String,
+    // The directory hyper.toml is in, which the other paths are relative to.
+    pub root : PathBuf,
+    pub source : PathBuf,
+    pub entry : Vec<String>,
+    pub output : PathBuf,
+    pub lua : String,
+    pub dependencies : Vec<(String, PathBuf)>,
+}
+
+// The closest hyper.toml in the directory, or the file's directory, or
+// above it.
+pub fn find(start : &Path) -> Option<PathBuf> {
+    let start = std::fs::canonicalize(start).ok()?;
+    start.ancestors().map(|d| d.join(FILE_NAME)).find(|m| m.is_file())
+}
+
+fn is_name(s : &str) -> bool {
+    !s.is_empty() && !s.starts_with(|c : char| c.is_ascii_digit()) && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Manifest {
+    pub fn load(path : &Path) -> Result<Manifest, String> {
+        let located = |e : String| format!("{}: {}", path.display(), e);
+        let source = std::fs::read_to_string(path).map_err(|e| located(e.to_string()))?;
+        let toml = toml::parse(&source).map_err(located)?;
+        let root = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        Manifest::new(root, &toml).map_err(located)
+    }
+
+    pub fn new(root : PathBuf, toml : &Toml) -> Result<Manifest, String> {
+        let package = toml.get("package").ok_or("missing [package]")?;
+        let text = |key : &str, default : &str| -> Result<String, String> {
+            match package.get(key) {
+                Some(value) => value.as_str().map(|s| s.to_string()).ok_or(format!("package {} should be a string", key)),
+                None => Ok(default.to_string()),
+            }
+        };
+
+        let name = text("name", "")?;
+        if !is_name(&name) {
+            return Err("package name should be a name made of letters, digits and _".to_string());
+        }
+        let entry = text("entry", "main")?.split("::").map(|s| s.to_string()).collect::<Vec<String>>();
+        if !entry.iter().all(|s| is_name(s)) {
+            return Err(format!("entry should name a module, e.g. main or app::main, not {}", entry.join("::")));
+        }
+        let lua = text("lua", "5.2")?;
+        if !LUA_VERSIONS.contains(&lua.as_str()) {
+            return Err(format!("lua should be one of {}, not {}", LUA_VERSIONS.join(", "), lua));
+        }
+
+        let mut dependencies = vec![];
+        if let Some(deps) = toml.get("dependencies") {
+            for (dep, value) in deps.as_table().ok_or("dependencies should be a table")? {
+                let path = value.get("path")
+                                .and_then(|p| p.as_str())
+                                .ok_or(format!("dependency {} should be {{ path = \"...\" }}", dep))?;
+                dependencies.push((dep.clone(), root.join(path)));
+            }
+        }
+
+        Ok(Manifest { name
+                    , source: root.join(text("source", "src")?)
+                    , entry
+                    , output: root.join(text("output", "out")?)
+                    , lua
+                    , dependencies
+                    , root
+                    })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn manifest(input : &str) -> Result<Manifest, String> {
+        Manifest::new(PathBuf::from("app"), &toml::parse(input)?)
+    }
+
+    #[test]
+    fn should_fill_in_defaults() -> Result<(), String> {
+        let m = manifest("[package]\nname = \"app\"\n\n[dependencies]\nutil = { path = \"../util\" }")?;
+        assert_eq!( m.source, PathBuf::from("app/src") );
+        assert_eq!( m.entry, vec!["main"] );
+        assert_eq!( m.output, PathBuf::from("app/out") );
+        assert_eq!( m.lua, "5.2" );
+        assert_eq!( m.dependencies, vec![("util".to_string(), PathBuf::from("app/../util"))] );
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_bad_settings() {
+        let error = |input : &str| manifest(input).err().unwrap_or_default();
+        assert_eq!( error("[lints]"), "missing [package]" );
+        assert_eq!( error("[package]\nname = \"my-app\""), "package name should be a name made of letters, digits and _" );
+        assert_eq!( error("[package]\nname = \"app\"\nlua = \"5.1\""), "lua should be one of 5.2, 5.3, 5.4, not 5.1" );
+        assert_eq!( error("[package]\nname = \"app\"\n[dependencies]\nutil = \"../util\""), "dependency util should be { path = \"...\" }" );
+    }
+}
@@ -0,0 +1,6 @@
+
+pub mod manifest;
+pub mod graph;
+pub mod cache;
+pub mod stored;
+pub mod watch;
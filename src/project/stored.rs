@@ -0,0 +1,481 @@
+use parse_input::PSym;
+
+use crate::json::Json;
+use crate::parsing::proc::ast::*;
+
+// Parsed modules as the cache keeps them. Each node is an array of its
+// fields in order, led by the variant's name for enums. Anything that
+// doesn't read back is as good as nothing kept, and the module is parsed
+// again.
+
+pub fn module_json(m : &Mod) -> Json {
+    Json::Array(vec![ list(&m.fun_defs, fun_def)
+                    , list(&m.struct_defs, struct_def)
+                    , list(&m.enum_defs, |d| Json::Array(vec![sym(&d.name), list(&d.items, sym)]))
+                    , strings(&m.fun_exports)
+                    , strings(&m.struct_exports)
+                    , strings(&m.enum_exports)
+                    , list(&m.extern_fun_defs, extern_fun_def)
+                    , list(&m.extern_struct_defs, |d| Json::Array(vec![sym(&d.name), list(&d.type_params, sym)]))
+                    , list(&m.type_aliases, |d| Json::Array(vec![sym(&d.name), list(&d.type_params, sym), ty(&d.aliased)]))
+                    , strings(&m.type_exports)
+                    , list(&m.const_defs, |d| Json::Array(vec![sym(&d.name), ty(&d.const_type), expr(&d.value)]))
+                    , strings(&m.const_exports)
+                    , list(&m.interface_defs, interface_def)
+                    , strings(&m.interface_exports)
+                    , list(&m.impl_defs, |d| Json::Array(vec![opt(&d.interface, sym), sym(&d.target), list(&d.funs, fun_def)]))
+                    , list(&m.uses, use_json)
+                    , list(&m.lint_attrs, lint_attr)
+                    ])
+}
+
+pub fn module_from_json(j : &Json) -> Option<Mod> {
+    match j.as_array()? {
+        [ fun_defs, struct_defs, enum_defs, fun_exports, struct_exports, enum_exports, extern_fun_defs
+        , extern_struct_defs, type_aliases, type_exports, const_defs, const_exports, interface_defs
+        , interface_exports, impl_defs, uses, lint_attrs ] =>
+            Some(Mod { fun_defs: read_list(fun_defs, read_fun_def)?
+                     , struct_defs: read_list(struct_defs, read_struct_def)?
+                     , enum_defs: read_list(enum_defs, |j| match j.as_array()? {
+                           [name, items] => Some(EnumDef { name: read_sym(name)?, items: read_list(items, read_sym)? }),
+                           _ => None,
+                       })?
+                     , fun_exports: read_strings(fun_exports)?
+                     , struct_exports: read_strings(struct_exports)?
+                     , enum_exports: read_strings(enum_exports)?
+                     , extern_fun_defs: read_list(extern_fun_defs, read_extern_fun_def)?
+                     , extern_struct_defs: read_list(extern_struct_defs, |j| match j.as_array()? {
+                           [name, type_params] => Some(ExternStructDef { name: read_sym(name)?, type_params: read_list(type_params, read_sym)? }),
+                           _ => None,
+                       })?
+                     , type_aliases: read_list(type_aliases, |j| match j.as_array()? {
+                           [name, type_params, aliased] =>
+                               Some(TypeAlias { name: read_sym(name)?, type_params: read_list(type_params, read_sym)?, aliased: read_ty(aliased)? }),
+                           _ => None,
+                       })?
+                     , type_exports: read_strings(type_exports)?
+                     , const_defs: read_list(const_defs, |j| match j.as_array()? {
+                           [name, const_type, value] =>
+                               Some(ConstDef { name: read_sym(name)?, const_type: read_ty(const_type)?, value: read_expr(value)? }),
+                           _ => None,
+                       })?
+                     , const_exports: read_strings(const_exports)?
+                     , interface_defs: read_list(interface_defs, read_interface_def)?
+                     , interface_exports: read_strings(interface_exports)?
+                     , impl_defs: read_list(impl_defs, |j| match j.as_array()? {
+                           [interface, target, funs] =>
+                               Some(ImplDef { interface: read_opt(interface, read_sym)?, target: read_sym(target)?, funs: read_list(funs, read_fun_def)? }),
+                           _ => None,
+                       })?
+                     , uses: read_list(uses, read_use)?
+                     , lint_attrs: read_list(lint_attrs, read_lint_attr)?
+                     }),
+        _ => None,
+    }
+}
+
+fn list<T>(items : &[T], f : impl Fn(&T) -> Json) -> Json {
+    Json::Array(items.iter().map(f).collect())
+}
+
+fn opt<T>(item : &Option<T>, f : impl Fn(&T) -> Json) -> Json {
+    item.as_ref().map(f).unwrap_or(Json::Null)
+}
+
+fn strings(items : &[String]) -> Json {
+    list(items, |s| Json::String(s.clone()))
+}
+
+fn number(n : usize) -> Json {
+    Json::Number(n as f64)
+}
+
+fn tagged(tag : &str, fields : Vec<Json>) -> Json {
+    Json::Array(std::iter::once(Json::String(tag.to_string())).chain(fields).collect())
+}
+
+fn sym(s : &PSym) -> Json {
+    Json::Array(vec![number(s.start), number(s.end), Json::String(s.value.clone())])
+}
+
+fn ty(t : &Type) -> Json {
+    match t {
+        Type::Unit => tagged("Unit", vec![]),
+        Type::Infer => tagged("Infer", vec![]),
+        Type::Simple(name) => tagged("Simple", vec![sym(name)]),
+        Type::Generic(name, args) => tagged("Generic", vec![sym(name), list(args, ty)]),
+        Type::Fun(params, ret) => tagged("Fun", vec![list(params, ty), ty(ret)]),
+        Type::Tuple(ts) => tagged("Tuple", vec![list(ts, ty)]),
+        Type::Namespace(path, t) => tagged("Namespace", vec![list(path, sym), ty(t)]),
+    }
+}
+
+fn param(p : &FunParam) -> Json {
+    Json::Array(vec![sym(&p.name), ty(&p.param_type), opt(&p.default, expr), Json::Bool(p.variadic)])
+}
+
+fn pattern(p : &Pattern) -> Json {
+    match p {
+        Pattern::Name(name) => tagged("Name", vec![sym(name)]),
+        Pattern::Tuple(ps) => tagged("Tuple", vec![list(ps, pattern)]),
+    }
+}
+
+fn expr(e : &Expr) -> Json {
+    match e {
+        Expr::Number(n) => tagged("Number", vec![sym(n)]),
+        Expr::PString(s) => tagged("PString", vec![sym(s)]),
+        Expr::Interpolate(parts) => tagged("Interpolate", vec![list(parts, |p| match p {
+            StringPart::Text(s) => tagged("Text", vec![sym(s)]),
+            StringPart::Expr(e) => tagged("Expr", vec![expr(e)]),
+        })]),
+        Expr::Bool(b) => tagged("Bool", vec![Json::Bool(*b)]),
+        Expr::Variable { namespace, name } => tagged("Variable", vec![list(namespace, sym), sym(name)]),
+        Expr::StatementLambda { params, return_type, definition } =>
+            tagged("StatementLambda", vec![list(params, param), ty(return_type), list(definition, statement)]),
+        Expr::ExprLambda { params, return_type, definition } =>
+            tagged("ExprLambda", vec![list(params, param), ty(return_type), expr(definition)]),
+        Expr::Call { func, params, named } =>
+            tagged("Call", vec![expr(func), list(params, expr), list(named, |a| Json::Array(vec![sym(&a.name), expr(&a.value)]))]),
+        Expr::Try(e) => tagged("Try", vec![expr(e)]),
+        Expr::Dot { object, slot } => tagged("Dot", vec![expr(object), sym(slot)]),
+        Expr::Dash { object, func } => tagged("Dash", vec![expr(object), sym(func)]),
+        Expr::Index { object, index } => tagged("Index", vec![expr(object), expr(index)]),
+        Expr::StructCons { name, slots, base } =>
+            tagged("StructCons", vec![opt(name, sym), list(slots, |s| Json::Array(vec![sym(&s.name), expr(&s.value)])), opt(base, |b| expr(b))]),
+        Expr::ListCons(es) => tagged("ListCons", vec![list(es, expr)]),
+        Expr::Spread(e) => tagged("Spread", vec![expr(e)]),
+        Expr::MapCons(entries) => tagged("MapCons", vec![list(entries, |e| Json::Array(vec![expr(&e.key), expr(&e.value)]))]),
+        Expr::Tuple(es) => tagged("Tuple", vec![list(es, expr)]),
+        Expr::Range { start, end, inclusive, step } =>
+            tagged("Range", vec![expr(start), expr(end), Json::Bool(*inclusive), opt(step, |s| expr(s))]),
+        Expr::ResultCons(ResultValue::Okay(e)) => tagged("Okay", vec![expr(e)]),
+        Expr::ResultCons(ResultValue::Error(e)) => tagged("Error", vec![expr(e)]),
+        Expr::OptionCons(OptionValue::Some(e)) => tagged("Some", vec![expr(e)]),
+        Expr::OptionCons(OptionValue::None) => tagged("None", vec![]),
+    }
+}
+
+fn statement(s : &Statement) -> Json {
+    match s {
+        Statement::Expr(e) => tagged("Expr", vec![expr(e)]),
+        Statement::Return(e) => tagged("Return", vec![opt(e, expr)]),
+        Statement::Yield(e) => tagged("Yield", vec![opt(e, expr)]),
+        Statement::Let { pattern: p, value_type, expr: e } => tagged("Let", vec![pattern(p), ty(value_type), expr(e)]),
+        Statement::Set { target, new_value } => tagged("Set", vec![expr(target), expr(new_value)]),
+        Statement::Break { keyword, label } => tagged("Break", vec![sym(keyword), opt(label, sym)]),
+        Statement::Continue { keyword, label } => tagged("Continue", vec![sym(keyword), opt(label, sym)]),
+        Statement::While { label, test, statements } => tagged("While", vec![opt(label, sym), expr(test), list(statements, statement)]),
+        Statement::Foreach { label, var, value, items, statements } =>
+            tagged("Foreach", vec![opt(label, sym), pattern(var), opt(value, pattern), expr(items), list(statements, statement)]),
+        Statement::Loop { label, statements } => tagged("Loop", vec![opt(label, sym), list(statements, statement)]),
+        Statement::If { keyword, test, statements } => tagged("If", vec![sym(keyword), expr(test), list(statements, statement)]),
+        Statement::ElseIf { keyword, test, statements } => tagged("ElseIf", vec![sym(keyword), expr(test), list(statements, statement)]),
+        Statement::Else(statements) => tagged("Else", vec![list(statements, statement)]),
+    }
+}
+
+fn fun_def(d : &FunDef) -> Json {
+    Json::Array(vec![ sym(&d.name)
+                    , list(&d.type_params, sym)
+                    , list(&d.bounds, |b| Json::Array(vec![sym(&b.param), sym(&b.interface)]))
+                    , list(&d.params, param)
+                    , ty(&d.return_type)
+                    , list(&d.definition, statement)
+                    ])
+}
+
+fn struct_def(d : &StructDef) -> Json {
+    let item = |i : &StructItem| Json::Array(vec![sym(&i.name), ty(&i.item_type), Json::Bool(i.public), opt(&i.default, expr)]);
+    Json::Array(vec![sym(&d.name), list(&d.type_params, sym), list(&d.items, item)])
+}
+
+fn extern_fun_def(d : &ExternFunDef) -> Json {
+    Json::Array(vec![ sym(&d.name)
+                    , list(&d.type_params, sym)
+                    , list(&d.params, param)
+                    , ty(&d.return_type)
+                    , sym(&d.path)
+                    , opt(&d.module, sym)
+                    ])
+}
+
+fn interface_def(d : &InterfaceDef) -> Json {
+    let method = |m : &InterfaceMethod| Json::Array(vec![sym(&m.name), list(&m.params, param), ty(&m.return_type)]);
+    Json::Array(vec![sym(&d.name), list(&d.methods, method)])
+}
+
+fn use_json(u : &Use) -> Json {
+    let import = |i : &Import| match i {
+        Import::Everything => tagged("Everything", vec![]),
+        Import::Item(name) => tagged("Item", vec![sym(name)]),
+    };
+    Json::Array(vec![list(&u.namespace, sym), list(&u.imports, import)])
+}
+
+fn lint_attr(a : &LintAttr) -> Json {
+    let item = |(start, end) : &(usize, usize)| Json::Array(vec![number(*start), number(*end)]);
+    Json::Array(vec![sym(&a.level), list(&a.rules, sym), opt(&a.item, item)])
+}
+
+fn read_list<T>(j : &Json, f : impl Fn(&Json) -> Option<T>) -> Option<Vec<T>> {
+    j.as_array()?.iter().map(f).collect()
+}
+
+// Some(None) for null, None for anything that isn't a T either.
+fn read_opt<T>(j : &Json, f : impl Fn(&Json) -> Option<T>) -> Option<Option<T>> {
+    match j {
+        Json::Null => Some(None),
+        j => f(j).map(Some),
+    }
+}
+
+fn read_strings(j : &Json) -> Option<Vec<String>> {
+    read_list(j, |s| s.as_str().map(|s| s.to_string()))
+}
+
+fn read_bool(j : &Json) -> Option<bool> {
+    match j {
+        Json::Bool(b) => Some(*b),
+        _ => None,
+    }
+}
+
+fn read_tagged(j : &Json) -> Option<(&str, &[Json])> {
+    let items = j.as_array()?;
+    Some((items.first()?.as_str()?, &items[1..]))
+}
+
+fn read_sym(j : &Json) -> Option<PSym> {
+    match j.as_array()? {
+        [start, end, value] => Some(PSym { start: start.as_usize()?, end: end.as_usize()?, value: value.as_str()?.to_string() }),
+        _ => None,
+    }
+}
+
+fn read_boxed(j : &Json) -> Option<Box<Expr>> {
+    read_expr(j).map(Box::new)
+}
+
+fn read_ty(j : &Json) -> Option<Type> {
+    match read_tagged(j)? {
+        ("Unit", []) => Some(Type::Unit),
+        ("Infer", []) => Some(Type::Infer),
+        ("Simple", [name]) => Some(Type::Simple(read_sym(name)?)),
+        ("Generic", [name, args]) => Some(Type::Generic(read_sym(name)?, read_list(args, read_ty)?)),
+        ("Fun", [params, ret]) => Some(Type::Fun(read_list(params, read_ty)?, Box::new(read_ty(ret)?))),
+        ("Tuple", [ts]) => Some(Type::Tuple(read_list(ts, read_ty)?)),
+        ("Namespace", [path, t]) => Some(Type::Namespace(read_list(path, read_sym)?, Box::new(read_ty(t)?))),
+        _ => None,
+    }
+}
+
+fn read_param(j : &Json) -> Option<FunParam> {
+    match j.as_array()? {
+        [name, param_type, default, variadic] =>
+            Some(FunParam { name: read_sym(name)?, param_type: read_ty(param_type)?, default: read_opt(default, read_expr)?, variadic: read_bool(variadic)? }),
+        _ => None,
+    }
+}
+
+fn read_pattern(j : &Json) -> Option<Pattern> {
+    match read_tagged(j)? {
+        ("Name", [name]) => Some(Pattern::Name(read_sym(name)?)),
+        ("Tuple", [ps]) => Some(Pattern::Tuple(read_list(ps, read_pattern)?)),
+        _ => None,
+    }
+}
+
+fn read_expr(j : &Json) -> Option<Expr> {
+    let e = match read_tagged(j)? {
+        ("Number", [n]) => Expr::Number(read_sym(n)?),
+        ("PString", [s]) => Expr::PString(read_sym(s)?),
+        ("Interpolate", [parts]) => Expr::Interpolate(read_list(parts, |p| match read_tagged(p)? {
+            ("Text", [s]) => Some(StringPart::Text(read_sym(s)?)),
+            ("Expr", [e]) => Some(StringPart::Expr(read_expr(e)?)),
+            _ => None,
+        })?),
+        ("Bool", [b]) => Expr::Bool(read_bool(b)?),
+        ("Variable", [namespace, name]) => Expr::Variable { namespace: read_list(namespace, read_sym)?, name: read_sym(name)? },
+        ("StatementLambda", [params, return_type, definition]) =>
+            Expr::StatementLambda { params: read_list(params, read_param)?, return_type: read_ty(return_type)?, definition: read_list(definition, read_statement)? },
+        ("ExprLambda", [params, return_type, definition]) =>
+            Expr::ExprLambda { params: read_list(params, read_param)?, return_type: read_ty(return_type)?, definition: read_boxed(definition)? },
+        ("Call", [func, params, named]) => Expr::Call { func: read_boxed(func)?
+                                                      , params: read_list(params, read_expr)?
+                                                      , named: read_list(named, |a| match a.as_array()? {
+                                                            [name, value] => Some(NamedArg { name: read_sym(name)?, value: read_expr(value)? }),
+                                                            _ => None,
+                                                        })?
+                                                      },
+        ("Try", [e]) => Expr::Try(read_boxed(e)?),
+        ("Dot", [object, slot]) => Expr::Dot { object: read_boxed(object)?, slot: read_sym(slot)? },
+        ("Dash", [object, func]) => Expr::Dash { object: read_boxed(object)?, func: read_sym(func)? },
+        ("Index", [object, index]) => Expr::Index { object: read_boxed(object)?, index: read_boxed(index)? },
+        ("StructCons", [name, slots, base]) => Expr::StructCons { name: read_opt(name, read_sym)?
+                                                                , slots: read_list(slots, |s| match s.as_array()? {
+                                                                      [name, value] => Some(StructSlot { name: read_sym(name)?, value: read_expr(value)? }),
+                                                                      _ => None,
+                                                                  })?
+                                                                , base: read_opt(base, read_boxed)?
+                                                                },
+        ("ListCons", [es]) => Expr::ListCons(read_list(es, read_expr)?),
+        ("Spread", [e]) => Expr::Spread(read_boxed(e)?),
+        ("MapCons", [entries]) => Expr::MapCons(read_list(entries, |e| match e.as_array()? {
+            [key, value] => Some(MapEntry { key: read_expr(key)?, value: read_expr(value)? }),
+            _ => None,
+        })?),
+        ("Tuple", [es]) => Expr::Tuple(read_list(es, read_expr)?),
+        ("Range", [start, end, inclusive, step]) =>
+            Expr::Range { start: read_boxed(start)?, end: read_boxed(end)?, inclusive: read_bool(inclusive)?, step: read_opt(step, read_boxed)? },
+        ("Okay", [e]) => Expr::ResultCons(ResultValue::Okay(read_boxed(e)?)),
+        ("Error", [e]) => Expr::ResultCons(ResultValue::Error(read_boxed(e)?)),
+        ("Some", [e]) => Expr::OptionCons(OptionValue::Some(read_boxed(e)?)),
+        ("None", []) => Expr::OptionCons(OptionValue::None),
+        _ => return None,
+    };
+    Some(e)
+}
+
+fn read_statement(j : &Json) -> Option<Statement> {
+    let statements = |j : &Json| read_list(j, read_statement);
+    let s = match read_tagged(j)? {
+        ("Expr", [e]) => Statement::Expr(read_expr(e)?),
+        ("Return", [e]) => Statement::Return(read_opt(e, read_expr)?),
+        ("Yield", [e]) => Statement::Yield(read_opt(e, read_expr)?),
+        ("Let", [pattern, value_type, expr]) => Statement::Let { pattern: read_pattern(pattern)?, value_type: read_ty(value_type)?, expr: read_expr(expr)? },
+        ("Set", [target, new_value]) => Statement::Set { target: read_expr(target)?, new_value: read_expr(new_value)? },
+        ("Break", [keyword, label]) => Statement::Break { keyword: read_sym(keyword)?, label: read_opt(label, read_sym)? },
+        ("Continue", [keyword, label]) => Statement::Continue { keyword: read_sym(keyword)?, label: read_opt(label, read_sym)? },
+        ("While", [label, test, body]) => Statement::While { label: read_opt(label, read_sym)?, test: read_expr(test)?, statements: statements(body)? },
+        ("Foreach", [label, var, value, items, body]) => Statement::Foreach { label: read_opt(label, read_sym)?
+                                                                            , var: read_pattern(var)?
+                                                                            , value: read_opt(value, read_pattern)?
+                                                                            , items: read_expr(items)?
+                                                                            , statements: statements(body)?
+                                                                            },
+        ("Loop", [label, body]) => Statement::Loop { label: read_opt(label, read_sym)?, statements: statements(body)? },
+        ("If", [keyword, test, body]) => Statement::If { keyword: read_sym(keyword)?, test: read_expr(test)?, statements: statements(body)? },
+        ("ElseIf", [keyword, test, body]) => Statement::ElseIf { keyword: read_sym(keyword)?, test: read_expr(test)?, statements: statements(body)? },
+        ("Else", [body]) => Statement::Else(statements(body)?),
+        _ => return None,
+    };
+    Some(s)
+}
+
+fn read_fun_def(j : &Json) -> Option<FunDef> {
+    match j.as_array()? {
+        [name, type_params, bounds, params, return_type, definition] =>
+            Some(FunDef { name: read_sym(name)?
+                        , type_params: read_list(type_params, read_sym)?
+                        , bounds: read_list(bounds, |b| match b.as_array()? {
+                              [param, interface] => Some(TypeBound { param: read_sym(param)?, interface: read_sym(interface)? }),
+                              _ => None,
+                          })?
+                        , params: read_list(params, read_param)?
+                        , return_type: read_ty(return_type)?
+                        , definition: read_list(definition, read_statement)?
+                        }),
+        _ => None,
+    }
+}
+
+fn read_struct_def(j : &Json) -> Option<StructDef> {
+    let item = |j : &Json| match j.as_array()? {
+        [name, item_type, public, default] =>
+            Some(StructItem { name: read_sym(name)?, item_type: read_ty(item_type)?, public: read_bool(public)?, default: read_opt(default, read_expr)? }),
+        _ => None,
+    };
+    match j.as_array()? {
+        [name, type_params, items] => Some(StructDef { name: read_sym(name)?, type_params: read_list(type_params, read_sym)?, items: read_list(items, item)? }),
+        _ => None,
+    }
+}
+
+fn read_extern_fun_def(j : &Json) -> Option<ExternFunDef> {
+    match j.as_array()? {
+        [name, type_params, params, return_type, path, module] =>
+            Some(ExternFunDef { name: read_sym(name)?
+                              , type_params: read_list(type_params, read_sym)?
+                              , params: read_list(params, read_param)?
+                              , return_type: read_ty(return_type)?
+                              , path: read_sym(path)?
+                              , module: read_opt(module, read_sym)?
+                              }),
+        _ => None,
+    }
+}
+
+fn read_interface_def(j : &Json) -> Option<InterfaceDef> {
+    let method = |j : &Json| match j.as_array()? {
+        [name, params, return_type] => Some(InterfaceMethod { name: read_sym(name)?, params: read_list(params, read_param)?, return_type: read_ty(return_type)? }),
+        _ => None,
+    };
+    match j.as_array()? {
+        [name, methods] => Some(InterfaceDef { name: read_sym(name)?, methods: read_list(methods, method)? }),
+        _ => None,
+    }
+}
+
+fn read_use(j : &Json) -> Option<Use> {
+    let import = |j : &Json| match read_tagged(j)? {
+        ("Everything", []) => Some(Import::Everything),
+        ("Item", [name]) => Some(Import::Item(read_sym(name)?)),
+        _ => None,
+    };
+    match j.as_array()? {
+        [namespace, imports] => Some(Use { namespace: read_list(namespace, read_sym)?, imports: read_list(imports, import)? }),
+        _ => None,
+    }
+}
+
+fn read_lint_attr(j : &Json) -> Option<LintAttr> {
+    let item = |j : &Json| match j.as_array()? {
+        [start, end] => Some((start.as_usize()?, end.as_usize()?)),
+        _ => None,
+    };
+    match j.as_array()? {
+        [level, rules, attr_item] => Some(LintAttr { level: read_sym(level)?, rules: read_list(rules, read_sym)?, item: read_opt(attr_item, item)? }),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::proc::parser::parse;
+
+    #[test]
+    fn should_read_back_what_it_writes() {
+        let source = r#"
+#![deny(unused_let)]
+use geo::shapes::{area, *};
+pub struct Pair<T> { pub first : T, second : List<Option<T>> = [] }
+enum Color { Red, Green }
+extern fun format(f : string, ...args : List<any>) -> string = "string.format";
+extern fun encode(v : any) -> string = "encode" from "cjson";
+extern struct Handle;
+pub type Table<V> = Map<string, fun(V) -> (V, geo::Unit)>;
+pub const LIMIT : number = 10;
+interface Show { fun show(self) -> string; }
+impl Show for Pair { fun show(self) -> string { return "pair"; } }
+#[allow(unused_param)]
+pub fun f<T : Show>(x : T, y : number = 1, ...rest : List<number>) -> Result<number, string> {
+    let (a, b) = (x, #{ "k": [1, ...rest] });
+    set b["k"] = [];
+    'outer: foreach i, v in 0..=10 by 2 {
+        if eq(i, 1) { break 'outer; } elseif neq(i, 2) { continue; } else { yield; }
+    }
+    while true { loop { return Error("{a.show()}\t{y}"); } }
+    let g = |z : number| -> number add(z, y);
+    let h = || { print(Some(x)?, None, new Pair { first: 1, ..p }, x.first, x-show()); };
+    return Okay(g(y: 2));
+}"#;
+        let module = parse(source).expect("parse failed");
+        let json = module_json(&module);
+        let read = module_from_json(&crate::json::parse(&json.to_string()).expect("json failed")).expect("read failed");
+        assert_eq!( format!("{:?}", read), format!("{:?}", module) );
+        assert!( module_from_json(&Json::Array(vec![])).is_none() );
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// When each watched file was last changed, and its size, so that comparing
+// two snapshots tells whether anything changed in between. Files that
+// don't exist are in it too, for noticing when they're created.
+#[derive(PartialEq, Debug)]
+pub struct Snapshot(Vec<(PathBuf, Option<SystemTime>, u64)>);
+
+// Takes in the given files, and the modules anywhere under the given
+// directories.
+pub fn snapshot(paths : &[PathBuf]) -> Snapshot {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            modules(path, &mut files);
+        }
+        else {
+            stamp(path, &mut files);
+        }
+    }
+    Snapshot(files)
+}
+
+fn stamp(path : &Path, files : &mut Vec<(PathBuf, Option<SystemTime>, u64)>) {
+    let metadata = std::fs::metadata(path).ok();
+    let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+    files.push((path.to_path_buf(), modified, metadata.map(|m| m.len()).unwrap_or(0)));
+}
+
+// Hidden entries are left out, which keeps editors' swap files and the
+// like from counting as changes.
+fn modules(dir : &Path, files : &mut Vec<(PathBuf, Option<SystemTime>, u64)>) {
+    let mut entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect::<Vec<PathBuf>>(),
+        Err(_) => return,
+    };
+    entries.sort();
+    for path in entries {
+        if path.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            modules(&path, files);
+        }
+        else if path.extension().is_some_and(|e| e == "hy") {
+            stamp(&path, files);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_notice_changed_added_and_removed_modules() {
+        let root = std::env::temp_dir().join(format!("hyper_watch_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("src/a")).unwrap();
+        std::fs::write(root.join("src/main.hy"), "fun main() { }").unwrap();
+        std::fs::write(root.join("src/a/.main.hy.swp"), "").unwrap();
+        let watched = vec![root.join("hyper.toml"), root.join("src")];
+
+        let before = snapshot(&watched);
+        assert_eq!( before.0.iter().map(|(p, m, _)| (p.strip_prefix(&root).unwrap().to_path_buf(), m.is_some())).collect::<Vec<_>>()
+                  , vec![(PathBuf::from("hyper.toml"), false), (PathBuf::from("src/main.hy"), true)]
+                  );
+        assert_eq!( before, snapshot(&watched) );
+
+        std::fs::write(root.join("src/main.hy"), "fun main() { print(1); }").unwrap();
+        let changed = snapshot(&watched);
+        assert_ne!( before, changed );
+        std::fs::write(root.join("src/a/b.hy"), "").unwrap();
+        let added = snapshot(&watched);
+        assert_ne!( changed, added );
+        std::fs::remove_file(root.join("src/a/b.hy")).unwrap();
+        assert_eq!( changed, snapshot(&watched) );
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,309 @@
+
+// The part of TOML that project files need: tables, strings, numbers,
+// booleans, arrays and inline tables. Arrays of tables and dates aren't
+// supported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Toml {
+    String(String),
+    Number(f64),
+    Bool(bool),
+    Array(Vec<Toml>),
+    Table(Vec<(String, Toml)>),
+}
+
+impl Toml {
+    pub fn get(&self, key : &str) -> Option<&Toml> {
+        match self {
+            Toml::Table(items) => items.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Toml::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&[(String, Toml)]> {
+        match self {
+            Toml::Table(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(s : &str) -> Result<Toml, String> {
+    let cs = s.chars().collect::<Vec<char>>();
+    let mut index = 0;
+    let mut root = vec![];
+    let mut table : Vec<String> = vec![];
+    loop {
+        skip_blank(&cs, &mut index);
+        match cs.get(index) {
+            None => return Ok(Toml::Table(root)),
+            Some('[') => {
+                index += 1;
+                if cs.get(index) == Some(&'[') {
+                    return Err(format!("arrays of tables aren't supported on line {}", line(&cs, index)));
+                }
+                table = vec![];
+                loop {
+                    skip_space(&cs, &mut index);
+                    table.push(parse_key(&cs, &mut index)?);
+                    skip_space(&cs, &mut index);
+                    match cs.get(index) {
+                        Some('.') => index += 1,
+                        Some(']') => { index += 1; break; },
+                        _ => return Err(format!("expected ] on line {}", line(&cs, index))),
+                    }
+                }
+                // Asking for a table makes it exist, even when it's empty.
+                table_mut(&mut root, &table, line(&cs, index))?;
+            },
+            Some(_) => {
+                let key = parse_key(&cs, &mut index)?;
+                expect(&cs, &mut index, '=')?;
+                let value = parse_value(&cs, &mut index)?;
+                let at = line(&cs, index);
+                let items = table_mut(&mut root, &table, at)?;
+                if items.iter().any(|(k, _)| *k == key) {
+                    return Err(format!("{} is given twice on line {}", key, at));
+                }
+                items.push((key, value));
+            },
+        }
+        skip_space(&cs, &mut index);
+        skip_comment(&cs, &mut index);
+        match cs.get(index) {
+            None | Some('\n') => (),
+            Some('\r') if cs.get(index + 1) == Some(&'\n') => (),
+            _ => return Err(format!("expected the end of line {}", line(&cs, index))),
+        }
+    }
+}
+
+fn line(cs : &[char], index : usize) -> usize {
+    cs[..index.min(cs.len())].iter().filter(|c| **c == '\n').count() + 1
+}
+
+fn table_mut<'a>(root : &'a mut Vec<(String, Toml)>, path : &[String], at : usize) -> Result<&'a mut Vec<(String, Toml)>, String> {
+    let mut items = root;
+    for key in path {
+        let index = match items.iter().position(|(k, _)| k == key) {
+            Some(index) => index,
+            None => {
+                items.push((key.clone(), Toml::Table(vec![])));
+                items.len() - 1
+            },
+        };
+        items = match &mut items[index].1 {
+            Toml::Table(inner) => inner,
+            _ => return Err(format!("{} is not a table on line {}", key, at)),
+        };
+    }
+    Ok(items)
+}
+
+fn skip_space(cs : &[char], index : &mut usize) {
+    while *index < cs.len() && (cs[*index] == ' ' || cs[*index] == '\t') {
+        *index += 1;
+    }
+}
+
+fn skip_comment(cs : &[char], index : &mut usize) {
+    if cs.get(*index) == Some(&'#') {
+        while *index < cs.len() && cs[*index] != '\n' {
+            *index += 1;
+        }
+    }
+}
+
+// Skips whitespace, newlines and comments.
+fn skip_blank(cs : &[char], index : &mut usize) {
+    loop {
+        while *index < cs.len() && cs[*index].is_whitespace() {
+            *index += 1;
+        }
+        match cs.get(*index) {
+            Some('#') => skip_comment(cs, index),
+            _ => return,
+        }
+    }
+}
+
+fn expect(cs : &[char], index : &mut usize, c : char) -> Result<(), String> {
+    skip_space(cs, index);
+    if cs.get(*index) == Some(&c) {
+        *index += 1;
+        Ok(())
+    }
+    else {
+        Err(format!("expected {} on line {}", c, line(cs, *index)))
+    }
+}
+
+fn parse_key(cs : &[char], index : &mut usize) -> Result<String, String> {
+    match cs.get(*index) {
+        Some('"') | Some('\'') => parse_string(cs, index),
+        _ => {
+            let start = *index;
+            while *index < cs.len() && (cs[*index].is_ascii_alphanumeric() || cs[*index] == '_' || cs[*index] == '-') {
+                *index += 1;
+            }
+            if start == *index {
+                return Err(format!("expected a key on line {}", line(cs, *index)));
+            }
+            Ok(cs[start..*index].iter().collect())
+        },
+    }
+}
+
+fn parse_value(cs : &[char], index : &mut usize) -> Result<Toml, String> {
+    skip_space(cs, index);
+    match cs.get(*index) {
+        Some('"') | Some('\'') => Ok(Toml::String(parse_string(cs, index)?)),
+        // Arrays can span lines, inline tables can't.
+        Some('[') => {
+            *index += 1;
+            let mut items = vec![];
+            loop {
+                skip_blank(cs, index);
+                if cs.get(*index) == Some(&']') {
+                    *index += 1;
+                    return Ok(Toml::Array(items));
+                }
+                items.push(parse_value(cs, index)?);
+                skip_blank(cs, index);
+                match cs.get(*index) {
+                    Some(',') => *index += 1,
+                    Some(']') => (),
+                    _ => return Err(format!("expected , or ] on line {}", line(cs, *index))),
+                }
+            }
+        },
+        Some('{') => {
+            *index += 1;
+            let mut items : Vec<(String, Toml)> = vec![];
+            skip_space(cs, index);
+            if cs.get(*index) == Some(&'}') {
+                *index += 1;
+                return Ok(Toml::Table(items));
+            }
+            loop {
+                skip_space(cs, index);
+                let key = parse_key(cs, index)?;
+                if items.iter().any(|(k, _)| *k == key) {
+                    return Err(format!("{} is given twice on line {}", key, line(cs, *index)));
+                }
+                expect(cs, index, '=')?;
+                items.push((key, parse_value(cs, index)?));
+                skip_space(cs, index);
+                match cs.get(*index) {
+                    Some(',') => *index += 1,
+                    Some('}') => { *index += 1; return Ok(Toml::Table(items)); },
+                    _ => return Err(format!("expected , or }} on line {}", line(cs, *index))),
+                }
+            }
+        },
+        Some(c) if *c == '-' || *c == '+' || c.is_ascii_digit() => {
+            let start = *index;
+            while *index < cs.len() && (cs[*index].is_ascii_digit() || "+-._eE".contains(cs[*index])) {
+                *index += 1;
+            }
+            let n = cs[start..*index].iter().filter(|c| **c != '_').collect::<String>();
+            n.parse::<f64>().map(Toml::Number).map_err(|_| format!("invalid number {} on line {}", n, line(cs, start)))
+        },
+        _ => {
+            for (word, value) in [("true", Toml::Bool(true)), ("false", Toml::Bool(false))] {
+                if cs[*index..].starts_with(&word.chars().collect::<Vec<char>>()) {
+                    *index += word.len();
+                    return Ok(value);
+                }
+            }
+            Err(format!("expected a value on line {}", line(cs, *index)))
+        },
+    }
+}
+
+// Basic strings in double quotes take escapes, literal ones in single
+// quotes don't.
+fn parse_string(cs : &[char], index : &mut usize) -> Result<String, String> {
+    let quote = cs[*index];
+    let start = *index;
+    *index += 1;
+    let mut ret = String::new();
+    while *index < cs.len() && cs[*index] != '\n' {
+        let c = cs[*index];
+        *index += 1;
+        match c {
+            c if c == quote => return Ok(ret),
+            '\\' if quote == '"' => {
+                let e = cs.get(*index).ok_or("unterminated string")?;
+                *index += 1;
+                match e {
+                    'n' => ret.push('\n'),
+                    'r' => ret.push('\r'),
+                    't' => ret.push('\t'),
+                    '"' | '\\' => ret.push(*e),
+                    'u' => {
+                        let hex = cs.get(*index..*index + 4).ok_or("invalid escape")?.iter().collect::<String>();
+                        *index += 4;
+                        let code = u32::from_str_radix(&hex, 16).map_err(|_| "invalid escape".to_string())?;
+                        ret.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                    },
+                    e => return Err(format!("invalid escape \\{} on line {}", e, line(cs, *index))),
+                }
+            },
+            c => ret.push(c),
+        }
+    }
+    Err(format!("unterminated string on line {}", line(cs, start)))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_tables() -> Result<(), String> {
+        let input = r#"
+# A project.
+[package]
+name = "app"   # trailing comment
+version = 1.5
+
+[lints]
+unused_let = 'deny'
+
+[dependencies.util]
+path = "../util"
+
+[build]
+targets = [
+    "5.3",
+    "5.4",
+]
+deps = { strict = true, depth = 2 }
+"#;
+        let toml = parse(input)?;
+        assert_eq!( toml.get("package").and_then(|t| t.get("name")).and_then(|n| n.as_str()), Some("app") );
+        assert_eq!( toml.get("package").and_then(|t| t.get("version")), Some(&Toml::Number(1.5)) );
+        assert_eq!( toml.get("lints").and_then(|t| t.as_table()).map(|t| t.len()), Some(1) );
+        assert_eq!( toml.get("dependencies").and_then(|t| t.get("util")).and_then(|t| t.get("path")).and_then(|p| p.as_str()), Some("../util") );
+        let build = toml.get("build").expect("missing build");
+        assert_eq!( build.get("targets"), Some(&Toml::Array(vec![Toml::String("5.3".into()), Toml::String("5.4".into())])) );
+        assert_eq!( build.get("deps").and_then(|d| d.get("strict")), Some(&Toml::Bool(true)) );
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_errors_with_lines() {
+        assert_eq!( parse("a = 1\na = 2"), Err("a is given twice on line 2".to_string()) );
+        assert_eq!( parse("[a]\nb = 1 c"), Err("expected the end of line 2".to_string()) );
+        assert_eq!( parse("a = \"x"), Err("unterminated string on line 1".to_string()) );
+        assert_eq!( parse("a = 1\n[a]"), Err("a is not a table on line 2".to_string()) );
+    }
+}
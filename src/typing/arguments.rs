@@ -0,0 +1,75 @@
+use parse_input::PSym;
+
+use crate::parsing::proc::ast::NamedArg;
+
+// What is wrong with the arguments of a call. Errors about the call as a
+// whole are placed at its start.
+pub enum ArgError<'a> {
+    TooMany { expected : usize, found : usize, start : Option<usize> },
+    Unknown(&'a PSym),
+    Twice(&'a PSym),
+    Missing { name : String, start : Option<usize> },
+}
+
+impl<'a> ArgError<'a> {
+    pub fn message(&self) -> String {
+        match self {
+            ArgError::TooMany { expected, found, .. } => format!("expected {} arguments but found {}", expected, found),
+            ArgError::Unknown(name) => format!("unknown argument {}", name.value),
+            ArgError::Twice(name) => format!("argument {} is given twice", name.value),
+            ArgError::Missing { name, .. } => format!("missing argument {}", name),
+        }
+    }
+
+    pub fn start(&self) -> Option<usize> {
+        match self {
+            ArgError::Unknown(name) | ArgError::Twice(name) => Some(name.start),
+            ArgError::TooMany { start, .. } | ArgError::Missing { start, .. } => *start,
+        }
+    }
+}
+
+// Matches the arguments of a call to the parameters, given as names and
+// whether they have a default. Each parameter gets the index of its
+// argument among the positional arguments followed by the named ones, or
+// None when its default is used. `start` is where the call is.
+pub fn arrange<'a>(params : &[(&str, bool)], positional : usize, named : &'a [NamedArg], start : Option<usize>) -> Result<Vec<Option<usize>>, ArgError<'a>> {
+    if positional > params.len() {
+        return Err(ArgError::TooMany { expected: params.len(), found: positional + named.len(), start });
+    }
+    let mut slots = (0..params.len()).map(|i| if i < positional { Some(i) } else { None }).collect::<Vec<Option<usize>>>();
+    for (i, arg) in named.iter().enumerate() {
+        match params.iter().position(|(n, _)| *n == arg.name.value) {
+            None => return Err(ArgError::Unknown(&arg.name)),
+            Some(p) if slots[p].is_some() => return Err(ArgError::Twice(&arg.name)),
+            Some(p) => slots[p] = Some(positional + i),
+        }
+    }
+    for (slot, (name, has_default)) in slots.iter().zip(params) {
+        if slot.is_none() && !has_default {
+            return Err(ArgError::Missing { name: name.to_string(), start });
+        }
+    }
+    Ok(slots)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::proc::ast::Expr;
+
+    fn sym(start : usize, value : &str) -> PSym {
+        PSym { start, end: start + value.len() - 1, value: value.to_string() }
+    }
+
+    #[test]
+    fn should_place_errors_about_the_whole_call_at_its_start() {
+        let params = [("a", false), ("b", true), ("c", false)];
+        let named = vec![NamedArg { name: sym(9, "b"), value: Expr::Bool(true) }];
+        assert!( matches!( arrange(&params, 1, &named, Some(4)), Err(ArgError::Missing { ref name, start: Some(4) }) if name == "c" ) );
+        assert!( matches!( arrange(&params, 4, &[], Some(4)), Err(ArgError::TooMany { expected: 3, found: 4, start: Some(4) }) ) );
+        assert_eq!( arrange(&params, 2, &named, Some(4)).map_err(|e| (e.start(), e.message())).err(), Some((Some(9), "argument b is given twice".to_string())) );
+        assert_eq!( arrange(&params, 3, &[], None).ok(), Some(vec![Some(0), Some(1), Some(2)]) );
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+
+use parse_input::PSym;
+
+use crate::diagnostic::Diagnostic;
+use crate::parsing::proc::ast::*;
+
+// A local, tagged with the function it belongs to, counting lambdas from
+// the outermost `fun`, and how many loops were around its declaration.
+struct Binding {
+    name : String,
+    fun : usize,
+    loops : usize,
+    // Set for the names a `foreach` binds without destructuring.
+    loop_var : Option<*const PSym>,
+}
+
+// A lambda being walked and its free variables, with where each is first
+// used.
+struct Frame {
+    captured : Vec<(usize, Option<usize>)>,
+}
+
+// A loop being walked.
+struct Loop {
+    fun : usize,
+    // Bindings the loop sets outside of lambdas.
+    sets : HashSet<usize>,
+    // Bindings declared outside the loop that lambdas made in it capture.
+    captured : Vec<(usize, Option<usize>)>,
+}
+
+// Lambdas share the variables they capture with the functions around them,
+// in Lua as in hyper, so captures need no help to become upvalues. What
+// needs help is a loop variable that is set, whether by the loop or by a
+// lambda capturing it, since Lua hands it back to the iterator.
+pub struct Captures {
+    pub assigned : HashSet<*const PSym>,
+    // Each lambda's free variables, with where it first uses each. Nothing
+    // but the tests reads them yet.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub captured : HashMap<*const Expr, Vec<(String, Option<usize>)>>,
+    pub warnings : Vec<Diagnostic>,
+}
+
+struct Walker {
+    bindings : Vec<Binding>,
+    scopes : Vec<Vec<usize>>,
+    frames : Vec<Frame>,
+    loops : Vec<Loop>,
+    assigned : HashSet<*const PSym>,
+    captured : HashMap<*const Expr, Vec<(String, Option<usize>)>>,
+    warned : HashSet<(usize, Option<usize>)>,
+    warnings : Vec<Diagnostic>,
+}
+
+pub fn captures(module : &Mod) -> Captures {
+    let mut walker = Walker { bindings: vec![]
+                            , scopes: vec![]
+                            , frames: vec![]
+                            , loops: vec![]
+                            , assigned: HashSet::new()
+                            , captured: HashMap::new()
+                            , warned: HashSet::new()
+                            , warnings: vec![]
+                            };
+    for def in module.fun_defs.iter().chain(module.impl_defs.iter().flat_map(|d| d.funs.iter())) {
+        walker.function(&def.params, &def.definition);
+    }
+    for def in module.const_defs.iter() {
+        walker.expr(&def.value);
+    }
+    for item in module.struct_defs.iter().flat_map(|d| d.items.iter()) {
+        if let Some(default) = &item.default {
+            walker.expr(default);
+        }
+    }
+    Captures { assigned: walker.assigned, captured: walker.captured, warnings: walker.warnings }
+}
+
+impl Walker {
+    fn function(&mut self, params : &[FunParam], definition : &[Statement]) {
+        self.scopes.push(vec![]);
+        for param in params {
+            self.bind(&param.name.value);
+        }
+        for default in params.iter().filter_map(|p| p.default.as_ref()) {
+            self.expr(default);
+        }
+        self.statements(definition);
+        self.scopes.pop();
+    }
+
+    fn lambda(&mut self, lambda : &Expr, params : &[FunParam], body : Result<&[Statement], &Expr>) {
+        self.frames.push(Frame { captured: vec![] });
+        self.scopes.push(vec![]);
+        for param in params {
+            self.bind(&param.name.value);
+        }
+        match body {
+            Ok(statements) => self.statements(statements),
+            Err(expr) => self.expr(expr),
+        }
+        self.scopes.pop();
+        // Captures from further out are seen again when the lambda around
+        // this one ends.
+        let frame = self.frames.pop().unwrap();
+        let fun = self.frames.len();
+        let names = frame.captured.iter().map(|(id, start)| (self.bindings[*id].name.clone(), *start)).collect();
+        self.captured.insert(lambda as *const Expr, names);
+        for (id, start) in frame.captured {
+            let declared_in = match &self.bindings[id] {
+                b if b.fun == fun => b.loops,
+                _ => continue,
+            };
+            for l in self.loops.iter_mut().skip(declared_in).filter(|l| l.fun == fun) {
+                l.captured.push((id, start));
+            }
+        }
+    }
+
+    fn bind(&mut self, name : &str) {
+        self.bind_var(name, None);
+    }
+
+    fn bind_var(&mut self, name : &str, loop_var : Option<*const PSym>) {
+        self.bindings.push(Binding { name: name.to_string(), fun: self.frames.len(), loops: self.loops.len(), loop_var });
+        let id = self.bindings.len() - 1;
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(id);
+        }
+    }
+
+    fn lookup(&self, name : &str) -> Option<usize> {
+        self.scopes.iter().rev().flat_map(|s| s.iter().rev()).find(|b| self.bindings[**b].name == name).cloned()
+    }
+
+    fn reference(&mut self, name : &str, start : Option<usize>) -> Option<usize> {
+        // Globals aren't captured.
+        let id = self.lookup(name)?;
+        for frame in self.frames.iter_mut().skip(self.bindings[id].fun) {
+            if !frame.captured.iter().any(|(c, _)| *c == id) {
+                frame.captured.push((id, start));
+            }
+        }
+        Some(id)
+    }
+
+    fn set(&mut self, name : &PSym) {
+        let id = match self.reference(&name.value, Some(name.start)) {
+            Some(id) => id,
+            None => return,
+        };
+        if let Some(var) = self.bindings[id].loop_var {
+            self.assigned.insert(var);
+        }
+        // Lambdas setting it don't change it between the ones a loop makes.
+        if self.bindings[id].fun != self.frames.len() {
+            return;
+        }
+        let declared_in = self.bindings[id].loops;
+        for l in self.loops.iter_mut().skip(declared_in) {
+            l.sets.insert(id);
+        }
+    }
+
+    fn in_loop(&mut self, f : impl FnOnce(&mut Self)) {
+        self.loops.push(Loop { fun: self.frames.len(), sets: HashSet::new(), captured: vec![] });
+        self.scopes.push(vec![]);
+        f(self);
+        self.scopes.pop();
+        let l = self.loops.pop().unwrap();
+        for (id, start) in l.captured {
+            if l.sets.contains(&id) && self.warned.insert((id, start)) {
+                let name = &self.bindings[id].name;
+                let message = format!("lambda captures {0}, which the loop around it sets, so every lambda made by the loop sees the latest {0}", name);
+                self.warnings.push(Diagnostic::new(start, message));
+            }
+        }
+    }
+
+    fn statements(&mut self, statements : &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn block(&mut self, statements : &[Statement]) {
+        self.scopes.push(vec![]);
+        self.statements(statements);
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, statement : &Statement) {
+        match statement {
+            Statement::Expr(e) | Statement::Return(Some(e)) | Statement::Yield(Some(e)) => self.expr(e),
+            Statement::Return(None) | Statement::Yield(None) | Statement::Break { .. } | Statement::Continue { .. } => (),
+            Statement::Let { pattern, expr, .. } => {
+                self.expr(expr);
+                for name in pattern.names() {
+                    self.bind(&name.value);
+                }
+            },
+            Statement::Set { target, new_value } => {
+                match target {
+                    Expr::Variable { namespace, name } if namespace.is_empty() => self.set(name),
+                    target => self.expr(target),
+                }
+                self.expr(new_value);
+            },
+            Statement::While { test, statements, .. } => self.in_loop(|w| {
+                w.expr(test);
+                w.statements(statements);
+            }),
+            Statement::Loop { statements, .. } => self.in_loop(|w| w.statements(statements)),
+            Statement::Foreach { var, value, items, statements, .. } => {
+                self.expr(items);
+                self.in_loop(|w| {
+                    for p in std::iter::once(var).chain(value.iter()) {
+                        match p {
+                            Pattern::Name(name) => w.bind_var(&name.value, Some(name as *const PSym)),
+                            p => p.names().into_iter().for_each(|name| w.bind(&name.value)),
+                        }
+                    }
+                    w.statements(statements);
+                });
+            },
+            Statement::If { test, statements, .. } | Statement::ElseIf { test, statements, .. } => {
+                self.expr(test);
+                self.block(statements);
+            },
+            Statement::Else(statements) => self.block(statements),
+        }
+    }
+
+    fn expr(&mut self, e : &Expr) {
+        match e {
+            Expr::Number(_) | Expr::PString(_) | Expr::Bool(_) | Expr::OptionCons(OptionValue::None) => (),
+            Expr::Interpolate(parts) => {
+                for part in parts {
+                    if let StringPart::Expr(e) = part {
+                        self.expr(e);
+                    }
+                }
+            },
+            Expr::Variable { namespace, name } if namespace.is_empty() => {
+                self.reference(&name.value, Some(name.start));
+            },
+            Expr::Variable { .. } => (),
+            Expr::StatementLambda { params, definition, .. } => self.lambda(e, params, Ok(definition)),
+            Expr::ExprLambda { params, definition, .. } => self.lambda(e, params, Err(definition)),
+            Expr::Call { func, params, named } => {
+                self.expr(func);
+                for p in params {
+                    self.expr(p);
+                }
+                for arg in named {
+                    self.expr(&arg.value);
+                }
+            },
+            // `a-f` calls whatever `f` names, which may be a local.
+            Expr::Dash { object, func } => {
+                self.expr(object);
+                self.reference(&func.value, Some(func.start));
+            },
+            Expr::Try(e) | Expr::Spread(e) | Expr::Dot { object: e, .. } => self.expr(e),
+            Expr::ResultCons(ResultValue::Okay(e)) | Expr::ResultCons(ResultValue::Error(e)) | Expr::OptionCons(OptionValue::Some(e)) => self.expr(e),
+            Expr::Index { object, index } => {
+                self.expr(object);
+                self.expr(index);
+            },
+            Expr::StructCons { slots, base, .. } => {
+                for slot in slots {
+                    self.expr(&slot.value);
+                }
+                if let Some(base) = base {
+                    self.expr(base);
+                }
+            },
+            Expr::ListCons(es) | Expr::Tuple(es) => {
+                for e in es {
+                    self.expr(e);
+                }
+            },
+            Expr::MapCons(entries) => {
+                for entry in entries {
+                    self.expr(&entry.key);
+                    self.expr(&entry.value);
+                }
+            },
+            Expr::Range { start, end, step, .. } => {
+                self.expr(start);
+                self.expr(end);
+                if let Some(step) = step {
+                    self.expr(step);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::proc::parser::parse;
+
+    fn warnings(input : &str) -> Vec<String> {
+        let module = parse(input).expect("parse failed");
+        captures(&module).warnings.into_iter().map(|w| w.message).collect()
+    }
+
+    #[test]
+    fn should_warn_about_variables_shared_by_lambdas_in_loops() {
+        let found = warnings(r#"
+fun main() {
+    let fs = [];
+    let i = 0;
+    while lt(i, 3) {
+        push(fs, || i);
+        push(fs, || |x : number| add(x, i));
+        set i = add(i, 1);
+    }
+}"#);
+        assert_eq!( found.len(), 2, "{:?}", found );
+        assert!( found[0].starts_with("lambda captures i, which the loop around it sets") );
+    }
+
+    #[test]
+    fn should_not_warn_about_variables_each_iteration_has_its_own() {
+        let found = warnings(r#"
+fun main() {
+    let fs = [];
+    let total = 0;
+    foreach x in [1, 2] {
+        let y = mul(x, 2);
+        push(fs, || add(x, y));
+        push(fs, |n : number| { set total = add(total, n); });
+        set y = add(y, 1);
+    }
+    let n = 0;
+    let g = || {
+        while lt(n, 3) {
+            set n = add(n, 1);
+        }
+    };
+}"#);
+        assert_eq!( found.len(), 0, "{:?}", found );
+    }
+
+    #[test]
+    fn should_list_the_free_variables_of_each_lambda() {
+        let module = parse(r#"
+fun main(a : number) {
+    let b = 1;
+    let f = |c : number| {
+        let d = c;
+        let g = || add(add(a, b), d);
+        return add(b, g());
+    };
+}"#).expect("parse failed");
+        let captured = captures(&module).captured;
+        let (outer, inner) = match &module.fun_defs[0].definition[1] {
+            Statement::Let { expr: outer @ Expr::StatementLambda { definition, .. }, .. } => match &definition[1] {
+                Statement::Let { expr: inner, .. } => (outer, inner),
+                s => panic!("Expected let but found {:?}", s),
+            },
+            s => panic!("Expected let of a lambda but found {:?}", s),
+        };
+        let names = |e : &Expr| captured[&(e as *const Expr)].iter().map(|(n, _)| n.as_str()).collect::<Vec<&str>>();
+        assert_eq!( names(outer), vec!["a", "b"] );
+        assert_eq!( names(inner), vec!["a", "b", "d"] );
+        assert_eq!( captured.len(), 2 );
+    }
+
+    #[test]
+    fn should_find_loop_variables_that_are_set() {
+        let module = parse(r#"
+fun main() {
+    foreach k, v in #{ "a": 1 } {
+        set k = concat(k, "!");
+    }
+    foreach x in [1] {
+        let f = || { set x = 2; };
+    }
+    foreach y in [1] {
+        let y = 2;
+        set y = 3;
+    }
+}"#).expect("parse failed");
+        let assigned = captures(&module).assigned;
+        let names = |s : &Statement| match s {
+            Statement::Foreach { var: Pattern::Name(var), value, .. } => {
+                let value = match value {
+                    Some(Pattern::Name(value)) => assigned.contains(&(value as *const PSym)),
+                    _ => false,
+                };
+                (assigned.contains(&(var as *const PSym)), value)
+            },
+            s => panic!("Expected foreach but found {:?}", s),
+        };
+        let statements = &module.fun_defs[0].definition;
+        assert_eq!( names(&statements[0]), (true, false) );
+        assert_eq!( names(&statements[1]), (true, false) );
+        assert_eq!( names(&statements[2]), (false, false) );
+    }
+}
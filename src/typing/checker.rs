@@ -0,0 +1,2652 @@
+
+use std::collections::{HashMap, HashSet};
+
+use parse_input::PSym;
+
+use crate::diagnostic::Diagnostic;
+use crate::parsing::proc::ast::*;
+use crate::prelude;
+use super::types::*;
+use super::arguments::arrange;
+use super::captures::captures;
+use super::flow::check_flow;
+
+#[derive(Clone)]
+pub struct StructInfo {
+    pub params : Vec<String>,
+    pub items : Vec<(String, Ty)>,
+    // Slots that `new` may leave out.
+    pub defaults : HashSet<String>,
+    // Slots without pub, which only the struct's own module can see.
+    pub private : HashSet<String>,
+    // Extern structs are Lua tables that hyper can pass around but not
+    // build or look into.
+    pub opaque : bool,
+}
+
+// A type that must implement an interface, checked once inference is done.
+#[derive(Clone)]
+struct Obligation {
+    ty : Ty,
+    interface : String,
+    start : Option<usize>,
+    // Bounds of the function the obligation comes from.
+    bounds : Vec<(String, String)>,
+}
+
+// A type used where the interpreter and Lua only agree on some types,
+// checked once inference is done. In a generic function the type may be one
+// of its parameters, whose instantiations are then checked instead.
+#[derive(Clone)]
+struct Requirement {
+    ty : Ty,
+    start : Option<usize>,
+    place : &'static str,
+    // The generic function the type comes from.
+    owner : Option<String>,
+}
+
+// A use of a generic function, with the types its parameters stand for.
+#[derive(Clone)]
+struct Instance {
+    fun : String,
+    args : HashMap<String, Ty>,
+    start : Option<usize>,
+    owner : Option<String>,
+}
+
+// Parameter names of a function and whether each has a default, for
+// checking named and left out arguments.
+type Signature = Vec<(String, bool)>;
+
+// A variadic parameter isn't part of it, as it can't be named.
+fn signature(params : &[FunParam]) -> Signature {
+    params.iter().filter(|p| !p.variadic).map(|p| (p.name.value.clone(), p.default.is_some())).collect()
+}
+
+// Inside the function, a variadic parameter is a plain list.
+fn bound_type(t : Ty) -> Ty {
+    match t {
+        Ty::Rest(list) => *list,
+        t => t,
+    }
+}
+
+// Whether the interpreter compares values of the type as Lua does.
+fn comparable(t : &Ty) -> bool {
+    match t {
+        Ty::List(_) | Ty::Tuple(_) | Ty::Result(_, _) => false,
+        Ty::Option(t) => comparable(t),
+        _ => true,
+    }
+}
+
+#[derive(Clone)]
+struct FunContext {
+    ret : Ty,
+    yields : Option<Ty>,
+    // Whether a plain `return` is an error, which the flow check reports.
+    returns_value : bool,
+}
+
+#[derive(Clone)]
+pub struct Checker {
+    vars : Vec<Option<Ty>>,
+    globals : HashMap<String, Scheme>,
+    structs : HashMap<String, StructInfo>,
+    enums : HashMap<String, Vec<String>>,
+    // Alias names to their type parameters and the annotation they stand for.
+    aliases : HashMap<String, (Vec<String>, Type)>,
+    consts : HashSet<String>,
+    // Interface names to their methods, typed in terms of `Self`.
+    interfaces : HashMap<String, Vec<(String, Ty)>>,
+    // Interface and type name pairs.
+    impls : HashSet<(String, String)>,
+    // Struct names to the methods of their `impl` blocks.
+    methods : HashMap<String, HashMap<String, (Scheme, Signature)>>,
+    // Globals declared with `fun` or `extern fun`.
+    signatures : HashMap<String, Signature>,
+    obligations : Vec<Obligation>,
+    // Bounds on the type parameters of the function being checked.
+    bounds : Vec<(String, String)>,
+    self_type : Option<Ty>,
+    // Consts not yet defined at the const being checked.
+    pending_consts : HashSet<String>,
+    scopes : Vec<HashMap<String, Ty>>,
+    type_params : Vec<String>,
+    funs : Vec<FunContext>,
+    // Labels of the loops around the statement being checked, within the
+    // innermost function.
+    loops : Vec<Option<String>>,
+    // Set by `use a::{*}`; names that aren't found may come from there.
+    open_imports : bool,
+    // Every type name declared or imported so far, along with those the
+    // module being checked declares further down, and whatever a `use`
+    // whose module wasn't found names.
+    type_names : HashSet<String>,
+    // The modules uses name, by their path and by its last part, with the
+    // types they export where the module was found.
+    namespaces : HashMap<String, Option<HashSet<String>>>,
+    // The uses whose module was found; see `declare_imports`.
+    resolved : HashSet<*const Use>,
+    // Types of indexed and iterated expressions, for lowering.
+    collections : Vec<(*const Expr, Ty)>,
+    // Calls of the form `a.f(...)` and the type of `a`, for lowering.
+    member_calls : Vec<(*const Expr, Ty, String)>,
+    // Calls whose arguments aren't simply the parameters in order.
+    arrangements : Vec<(*const Expr, Vec<Option<usize>>)>,
+    // Operand types of `?`, for lowering.
+    tries : Vec<(*const Expr, Ty)>,
+    // Types of values that can't be nil in Lua, which is what None lowers
+    // to, and where they go.
+    present : Vec<Requirement>,
+    // Arguments of eq, neq, to_string and print, interpolated values and map
+    // keys, with the builtin they're passed to, "a string" or "a map key".
+    // Lua compares and prints lists, tuples, results and structs as the
+    // tables they are, where the interpreter looks at their items.
+    compared : Vec<Requirement>,
+    // The generic function being declared or checked.
+    generic : Option<String>,
+    // Where the statement being checked starts, for values like `[]` and
+    // `None` that have no location of their own.
+    statement_start : Option<usize>,
+    // Uses of generic functions declared with `fun`.
+    instances : Vec<Instance>,
+    // How many of the instances an earlier `settle` has seen.
+    settled_instances : usize,
+    // Type parameters of generic functions, with where their values go.
+    param_uses : HashSet<(String, String, &'static str)>,
+    // Loop variables that are set, for lowering; see `captures`.
+    assigned : HashSet<*const PSym>,
+    errors : Vec<Diagnostic>,
+    warnings : Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Collection {
+    List,
+    Map,
+    Generator,
+}
+
+// What `?` propagates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TryKind {
+    Result,
+    Option,
+}
+
+fn builtins() -> Vec<(&'static str, Scheme)> {
+    fn fun(params : Vec<Ty>, ret : Ty) -> Ty {
+        Ty::Fun(params, Box::new(ret))
+    }
+    fn generic(ty : Ty) -> Scheme {
+        Scheme::poly(vec!["T".to_string()], ty)
+    }
+    fn result_generic(ty : Ty) -> Scheme {
+        Scheme::poly(vec!["T".to_string(), "E".to_string()], ty)
+    }
+    let t = || Ty::Param("T".to_string());
+    let result = || Ty::Result(Box::new(t()), Box::new(Ty::Param("E".to_string())));
+    let option = || Ty::Option(Box::new(t()));
+    fn map_generic(ty : Ty) -> Scheme {
+        Scheme::poly(vec!["K".to_string(), "V".to_string()], ty)
+    }
+    let k = || Ty::Param("K".to_string());
+    let v = || Ty::Param("V".to_string());
+    let map = || Ty::Map(Box::new(k()), Box::new(v()));
+    let strings = |n : usize, ret : Ty| Scheme::mono(fun(vec![Ty::String; n], ret));
+    let arith = || Scheme::mono(fun(vec![Ty::Number, Ty::Number], Ty::Number));
+    let compare = || Scheme::mono(fun(vec![Ty::Number, Ty::Number], Ty::Bool));
+    let logic = || Scheme::mono(fun(vec![Ty::Bool, Ty::Bool], Ty::Bool));
+
+    vec![ ("print", Scheme::mono(Ty::Any))
+        , ("add", arith())
+        , ("sub", arith())
+        , ("mul", arith())
+        , ("div", arith())
+        , ("mod", arith())
+        , ("eq", generic(fun(vec![t(), t()], Ty::Bool)))
+        , ("neq", generic(fun(vec![t(), t()], Ty::Bool)))
+        , ("lt", compare())
+        , ("le", compare())
+        , ("gt", compare())
+        , ("ge", compare())
+        , ("not", Scheme::mono(fun(vec![Ty::Bool], Ty::Bool)))
+        , ("and", logic())
+        , ("or", logic())
+        , ("concat", Scheme::mono(Ty::Any))
+        , ("to_string", generic(fun(vec![t()], Ty::String)))
+        , ("len", generic(fun(vec![t()], Ty::Number)))
+        , ("push", generic(fun(vec![Ty::List(Box::new(t())), t()], Ty::Unit)))
+        , ("iter", generic(fun(vec![Ty::List(Box::new(t()))], Ty::Generator(Box::new(t())))))
+        , ("get", generic(fun(vec![Ty::List(Box::new(t())), Ty::Number], t())))
+        , ("pop", generic(fun(vec![Ty::List(Box::new(t()))], t())))
+        , ("is_ok", result_generic(fun(vec![result()], Ty::Bool)))
+        , ("unwrap", result_generic(fun(vec![result()], t())))
+        , ("unwrap_err", result_generic(fun(vec![result()], Ty::Param("E".to_string()))))
+        , ("is_some", generic(fun(vec![option()], Ty::Bool)))
+        , ("is_none", generic(fun(vec![option()], Ty::Bool)))
+        , ("value_or", generic(fun(vec![option(), t()], t())))
+        , ("substring", Scheme::mono(fun(vec![Ty::String, Ty::Number, Ty::Number], Ty::String)))
+        , ("split", strings(2, Ty::List(Box::new(Ty::String))))
+        , ("upper", strings(1, Ty::String))
+        , ("lower", strings(1, Ty::String))
+        , ("trim", strings(1, Ty::String))
+        , ("find", strings(2, Ty::Number))
+        , ("keys", map_generic(fun(vec![map()], Ty::List(Box::new(k())))))
+        , ("values", map_generic(fun(vec![map()], Ty::List(Box::new(v())))))
+        , ("has_key", map_generic(fun(vec![map(), k()], Ty::Bool)))
+        , ("remove_key", map_generic(fun(vec![map(), k()], Ty::Unit)))
+        ]
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        let globals = builtins().into_iter().map(|(n, s)| (n.to_string(), s)).collect();
+        let mut checker = Checker { vars: vec![]
+                , globals
+                , structs: HashMap::new()
+                , enums: HashMap::new()
+                , aliases: HashMap::new()
+                , consts: HashSet::new()
+                , interfaces: HashMap::new()
+                , impls: HashSet::new()
+                , methods: HashMap::new()
+                , signatures: HashMap::new()
+                , obligations: vec![]
+                , bounds: vec![]
+                , self_type: None
+                , pending_consts: HashSet::new()
+                , scopes: vec![HashMap::new()]
+                , type_params: vec![]
+                , funs: vec![]
+                , loops: vec![]
+                , open_imports: false
+                , type_names: HashSet::new()
+                , namespaces: HashMap::new()
+                , resolved: HashSet::new()
+                , collections: vec![]
+                , member_calls: vec![]
+                , arrangements: vec![]
+                , tries: vec![]
+                , present: vec![]
+                , compared: vec![]
+                , generic: None
+                , statement_start: None
+                , instances: vec![]
+                , settled_instances: 0
+                , param_uses: HashSet::new()
+                , assigned: HashSet::new()
+                , errors: vec![]
+                , warnings: vec![]
+                };
+        checker.check_mod(prelude::module());
+        checker.take_errors();
+        checker.take_warnings();
+        checker
+    }
+
+    pub fn check_mod(&mut self, module : &Mod) {
+        for u in module.uses.iter() {
+            self.declare_use(u);
+        }
+        let names = module.type_aliases.iter().map(|d| &d.name)
+            .chain(module.extern_struct_defs.iter().map(|d| &d.name))
+            .chain(module.struct_defs.iter().map(|d| &d.name))
+            .chain(module.enum_defs.iter().map(|d| &d.name))
+            .chain(module.interface_defs.iter().map(|d| &d.name));
+        self.type_names.extend(names.map(|n| n.value.clone()));
+        for def in module.type_aliases.iter() {
+            self.declare_type_alias(def);
+        }
+        for def in module.extern_struct_defs.iter() {
+            self.declare_extern_struct(def);
+        }
+        for def in module.struct_defs.iter() {
+            self.declare_struct(def);
+        }
+        for def in module.enum_defs.iter() {
+            self.declare_enum(def);
+        }
+        for def in module.interface_defs.iter() {
+            self.declare_interface(def);
+        }
+        for def in module.impl_defs.iter() {
+            self.declare_impl(def);
+        }
+        for def in module.extern_fun_defs.iter() {
+            self.declare_extern_fun(def);
+        }
+        for def in module.fun_defs.iter() {
+            self.declare_fun(def);
+        }
+        for def in module.const_defs.iter() {
+            self.declare_const(def);
+        }
+        for def in module.struct_defs.iter() {
+            self.check_struct_defaults(def);
+            if module.struct_exports.contains(&def.name.value) {
+                continue;
+            }
+            for item in def.items.iter().filter(|i| i.public) {
+                self.error(Some(item.name.start), format!("slot {} is pub but struct {} is not", item.name.value, def.name.value));
+            }
+        }
+        for def in module.fun_defs.iter() {
+            self.check_fun_def(def);
+        }
+        for def in module.impl_defs.iter() {
+            self.check_impl(def);
+        }
+        self.pending_consts = module.const_defs.iter().map(|d| d.name.value.clone()).collect();
+        for def in module.const_defs.iter() {
+            self.check_const_def(def);
+            self.pending_consts.remove(&def.name.value);
+        }
+        let found = captures(module);
+        self.assigned.extend(found.assigned);
+        self.warnings.extend(found.warnings);
+    }
+
+    pub fn take_errors(&mut self) -> Vec<Diagnostic> {
+        self.settle();
+        std::mem::take(&mut self.errors)
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // Checks the bounds collected so far, now that their types are known.
+    // Types that are still unknown are left to dispatch at runtime.
+    fn settle(&mut self) {
+        for o in std::mem::take(&mut self.obligations) {
+            let name = match self.resolve(&o.ty) {
+                Ty::Any | Ty::Var(_) => continue,
+                Ty::Param(p) => {
+                    if !o.bounds.iter().any(|(b, i)| *b == p && *i == o.interface) {
+                        self.error(o.start, format!("type parameter {} is not bounded by {}", p, o.interface));
+                    }
+                    continue;
+                },
+                Ty::Number => "number".to_string(),
+                Ty::String => "string".to_string(),
+                Ty::Bool => "bool".to_string(),
+                Ty::Named(n, _) => n,
+                t => {
+                    self.error(o.start, format!("{} does not implement {}", t, o.interface));
+                    continue;
+                },
+            };
+            if !self.impls.contains(&(o.interface.clone(), name.clone())) {
+                self.error(o.start, format!("{} does not implement {}", name, o.interface));
+            }
+        }
+        let mut requirements = std::mem::take(&mut self.present);
+        requirements.append(&mut self.compared);
+        for (t, start, place) in self.instantiated(requirements) {
+            match place {
+                "to_string" if !self.printable(&t) => self.error(start, format!("cannot convert {} to a string", t)),
+                "print" if !self.printable(&t) => self.error(start, format!("cannot print {}", t)),
+                "a string" if !self.printable(&t) => self.error(start, format!("cannot interpolate {} into a string", t)),
+                "to_string" | "print" | "a string" => (),
+                "a map key" if !comparable(&t) => self.error(start, format!("{} cannot be a map key", t)),
+                "eq" | "neq" if !comparable(&t) => self.error(start, format!("cannot compare {} with {}", t, place)),
+                "a map key" | "eq" | "neq" => (),
+                place => {
+                    if let Ty::Option(_) = t {
+                        self.error(start, format!("{} cannot go in {}", t, place));
+                    }
+                },
+            }
+        }
+    }
+
+    // Resolves the types of requirements. Those that are a type parameter of
+    // a generic function become requirements on what each use of the
+    // function passes for it, which may in turn be a parameter of the
+    // function it's used in.
+    fn instantiated(&mut self, mut requirements : Vec<Requirement>) -> Vec<(Ty, Option<usize>, &'static str)> {
+        let instances = self.instances[self.settled_instances..].to_vec();
+        self.settled_instances = self.instances.len();
+        for instance in instances {
+            for (fun, param, place) in self.param_uses.iter() {
+                if *fun == instance.fun {
+                    requirements.push(Requirement { ty: instance.args[param].clone(), start: instance.start, place, owner: instance.owner.clone() });
+                }
+            }
+        }
+        let mut ret = vec![];
+        let mut i = 0;
+        while i < requirements.len() {
+            let r = requirements[i].clone();
+            i += 1;
+            let mut t = self.resolve(&r.ty);
+            // Options compare as what they hold.
+            while let (Ty::Option(inner), "eq" | "neq" | "a map key") = (&t, r.place) {
+                t = *inner.clone();
+            }
+            match (t, r.owner) {
+                (Ty::Param(param), Some(owner)) => {
+                    if self.param_uses.insert((owner.clone(), param.clone(), r.place)) {
+                        for instance in self.instances.iter().filter(|i| i.fun == owner) {
+                            requirements.push(Requirement { ty: instance.args[&param].clone(), start: instance.start, place: r.place, owner: instance.owner.clone() });
+                        }
+                    }
+                },
+                (t, _) => ret.push((t, r.start, r.place)),
+            }
+        }
+        ret
+    }
+
+    pub fn collections(&self) -> HashMap<*const Expr, Collection> {
+        let mut ret = HashMap::new();
+        for (e, t) in self.collections.iter() {
+            let c = match self.resolve(t) {
+                Ty::List(_) => Collection::List,
+                Ty::Map(_, _) => Collection::Map,
+                Ty::Generator(_) => Collection::Generator,
+                _ => continue,
+            };
+            ret.insert(*e, c);
+        }
+        ret
+    }
+
+    // The `a.f(...)` calls that call a method rather than a function in a
+    // slot. When the checker doesn't know what `a` is, any name that is a
+    // method of some struct and a slot of none is taken to be a method.
+    pub fn method_calls(&self) -> HashSet<*const Expr> {
+        let mut ret = HashSet::new();
+        for (e, t, name) in self.member_calls.iter() {
+            let is_method = match self.resolve(t) {
+                Ty::Named(n, _) => self.methods.get(&n).is_some_and(|m| m.contains_key(name)),
+                Ty::Any | Ty::Var(_) => {
+                    self.methods.values().any(|m| m.contains_key(name))
+                    && !self.structs.values().any(|s| s.items.iter().any(|(n, _)| n == name))
+                },
+                _ => false,
+            };
+            if is_method {
+                ret.insert(*e);
+            }
+        }
+        ret
+    }
+
+    // For each call that names or leaves out arguments, where each
+    // parameter's argument is; see `arrange`.
+    pub fn arrangements(&self) -> HashMap<*const Expr, Vec<Option<usize>>> {
+        self.arrangements.iter().cloned().collect()
+    }
+
+    pub fn try_kinds(&self) -> HashMap<*const Expr, TryKind> {
+        let mut ret = HashMap::new();
+        for (e, t) in self.tries.iter() {
+            let kind = match self.resolve(t) {
+                Ty::Result(_, _) => TryKind::Result,
+                Ty::Option(_) => TryKind::Option,
+                _ => continue,
+            };
+            ret.insert(*e, kind);
+        }
+        ret
+    }
+
+    pub fn assigned_loop_vars(&self) -> HashSet<*const PSym> {
+        self.assigned.clone()
+    }
+
+    pub fn global(&self, name : &str) -> Option<&Scheme> {
+        self.globals.get(name)
+    }
+
+    pub fn local(&self, name : &str) -> Option<Ty> {
+        self.scopes.iter().rev().find_map(|s| s.get(name)).map(|t| self.resolve(t))
+    }
+
+    pub fn declare_use(&mut self, u : &Use) {
+        if self.resolved.contains(&(u as *const Use)) {
+            return;
+        }
+        self.name_module(u, None);
+        for import in u.imports.iter() {
+            match import {
+                Import::Everything => self.open_imports = true,
+                Import::Item(name) => {
+                    self.globals.insert(name.value.clone(), Scheme::mono(Ty::Any));
+                    self.signatures.remove(&name.value);
+                    self.type_names.insert(name.value.clone());
+                },
+            }
+        }
+    }
+
+    fn name_module(&mut self, u : &Use, types : Option<HashSet<String>>) {
+        let path = u.namespace.iter().map(|n| n.value.as_str()).collect::<Vec<&str>>();
+        for key in [path.join("::"), path[path.len() - 1].to_string()] {
+            self.namespaces.entry(key).or_insert_with(|| types.clone());
+        }
+    }
+
+    // Declares what a `use` brings in from `target`, the module it refers
+    // to. Anything wrong with the declarations is reported when the target
+    // itself is checked.
+    pub fn declare_imports(&mut self, u : &Use, target : &Mod) {
+        let imported = |name : &String, exports : &[String]| exports.contains(name) && u.imports.iter().any(|i| match i {
+            Import::Everything => true,
+            Import::Item(n) => n.value == *name,
+        });
+        let errors = self.errors.len();
+        for def in target.type_aliases.iter().filter(|d| imported(&d.name.value, &target.type_exports)) {
+            self.declare_type_alias(def);
+        }
+        for def in target.extern_struct_defs.iter().filter(|d| imported(&d.name.value, &target.struct_exports)) {
+            self.declare_extern_struct(def);
+        }
+        for def in target.struct_defs.iter().filter(|d| imported(&d.name.value, &target.struct_exports)) {
+            self.declare_struct(def);
+            // Defaults are checked and lowered in the declaring module, so
+            // others only need to know which slots have them.
+            if let Some(info) = self.structs.get_mut(&def.name.value) {
+                info.private = def.items.iter().filter(|i| !i.public).map(|i| i.name.value.clone()).collect();
+            }
+        }
+        for def in target.enum_defs.iter().filter(|d| imported(&d.name.value, &target.enum_exports)) {
+            self.declare_enum(def);
+        }
+        for def in target.interface_defs.iter().filter(|d| imported(&d.name.value, &target.interface_exports)) {
+            self.declare_interface(def);
+        }
+        // Impls of anything that wasn't imported fail to declare, and are
+        // dropped with the errors.
+        for def in target.impl_defs.iter() {
+            self.declare_impl(def);
+        }
+        for def in target.extern_fun_defs.iter().filter(|d| imported(&d.name.value, &target.fun_exports)) {
+            self.declare_extern_fun(def);
+        }
+        for def in target.fun_defs.iter().filter(|d| imported(&d.name.value, &target.fun_exports)) {
+            self.declare_fun(def);
+        }
+        for def in target.const_defs.iter().filter(|d| imported(&d.name.value, &target.const_exports)) {
+            self.declare_const(def);
+        }
+        self.errors.truncate(errors);
+        let types = target.struct_exports.iter()
+            .chain(target.enum_exports.iter())
+            .chain(target.type_exports.iter())
+            .chain(target.interface_exports.iter());
+        self.name_module(u, Some(types.cloned().collect()));
+        self.resolved.insert(u as *const Use);
+    }
+
+    pub fn declare_struct(&mut self, def : &StructDef) {
+        // Register the name first so that recursive structs resolve.
+        self.type_names.insert(def.name.value.clone());
+        let params = def.type_params.iter().map(|p| p.value.clone()).collect::<Vec<String>>();
+        self.structs.insert(def.name.value.clone(), StructInfo { params: params.clone(), items: vec![], defaults: HashSet::new(), private: HashSet::new(), opaque: false });
+        self.type_params = params.clone();
+        let items = def.items.iter().map(|item| (item.name.value.clone(), self.convert(&item.item_type))).collect();
+        self.type_params.clear();
+        let defaults = def.items.iter().filter(|i| i.default.is_some()).map(|i| i.name.value.clone()).collect();
+        self.structs.insert(def.name.value.clone(), StructInfo { params, items, defaults, private: HashSet::new(), opaque: false });
+    }
+
+    // Defaults are evaluated where the struct is built, but can only see
+    // what is global.
+    pub fn check_struct_defaults(&mut self, def : &StructDef) {
+        let arity = def.type_params.len();
+        let args = (0..arity).map(|_| self.fresh()).collect::<Vec<Ty>>();
+        let items = match self.struct_items(&def.name.value, &args) {
+            Some(items) => items,
+            None => return,
+        };
+        let scopes = std::mem::replace(&mut self.scopes, vec![HashMap::new()]);
+        for item in def.items.iter() {
+            let default = match &item.default {
+                Some(e) => e,
+                None => continue,
+            };
+            let t = self.check_expr(default);
+            if let Some((_, expected)) = items.iter().find(|(n, _)| *n == item.name.value) {
+                self.unify(expected, &t, default.start());
+            }
+        }
+        self.scopes = scopes;
+    }
+
+    pub fn declare_enum(&mut self, def : &EnumDef) {
+        let cases = def.items.iter().map(|i| i.value.clone()).collect();
+        self.enums.insert(def.name.value.clone(), cases);
+    }
+
+    pub fn declare_fun(&mut self, def : &FunDef) {
+        self.check_not_method(&def.name, "function");
+        self.type_params = def.type_params.iter().map(|p| p.value.clone()).collect();
+        self.generic = Some(def.name.value.clone()).filter(|_| !def.type_params.is_empty());
+        let params = self.param_types(&def.params);
+        let mut ret = self.convert(&def.return_type);
+        if contains_yield(&def.definition) {
+            ret = Ty::Generator(Box::new(ret));
+        }
+        let type_params = std::mem::take(&mut self.type_params);
+        self.generic = None;
+        let mut bounds = vec![];
+        for bound in def.bounds.iter() {
+            if !type_params.contains(&bound.param.value) {
+                self.error(Some(bound.param.start), format!("unknown type parameter {}", bound.param.value));
+            }
+            else if !self.interfaces.contains_key(&bound.interface.value) {
+                self.error(Some(bound.interface.start), format!("unknown interface {}", bound.interface.value));
+            }
+            else {
+                bounds.push((bound.param.value.clone(), bound.interface.value.clone()));
+            }
+        }
+        let scheme = Scheme { params: type_params, bounds, ty: Ty::Fun(params, Box::new(ret)) };
+        self.globals.insert(def.name.value.clone(), scheme);
+        self.signatures.insert(def.name.value.clone(), signature(&def.params));
+    }
+
+    // Each method becomes a global whose first parameter picks the impl.
+    pub fn declare_interface(&mut self, def : &InterfaceDef) {
+        self.type_names.insert(def.name.value.clone());
+        let mut methods = vec![];
+        self.type_params = vec!["Self".to_string()];
+        for method in def.methods.iter() {
+            match method.params.first() {
+                Some(p) if p.name.value == "self" => (),
+                _ => {
+                    self.error(Some(method.name.start), format!("{} must take self as its first parameter", method.name.value));
+                    continue;
+                },
+            }
+            // Methods are globals, so another one of the same name would
+            // replace it.
+            match self.interface_of(&method.name.value) {
+                Some(other) if *other != def.name.value => {
+                    let message = format!("method {} of {} is already a method of {}", method.name.value, def.name.value, other);
+                    self.error(Some(method.name.start), message);
+                },
+                _ if self.signatures.contains_key(&method.name.value) => {
+                    self.error(Some(method.name.start), format!("method {} of {} clashes with function {}", method.name.value, def.name.value, method.name.value));
+                },
+                _ => (),
+            }
+            let params = self.param_types(&method.params);
+            let ret = self.convert(&method.return_type);
+            let ty = Ty::Fun(params, Box::new(ret));
+            let scheme = Scheme { params: vec!["Self".to_string()]
+                                , bounds: vec![("Self".to_string(), def.name.value.clone())]
+                                , ty: ty.clone()
+                                };
+            self.globals.insert(method.name.value.clone(), scheme);
+            self.signatures.remove(&method.name.value);
+            methods.push((method.name.value.clone(), ty));
+        }
+        self.type_params.clear();
+        self.interfaces.insert(def.name.value.clone(), methods);
+    }
+
+    fn interface_of(&self, method : &str) -> Option<&String> {
+        self.interfaces.iter().filter(|(_, methods)| methods.iter().any(|(m, _)| m == method)).map(|(name, _)| name).min()
+    }
+
+    fn check_not_method(&mut self, name : &PSym, what : &str) {
+        if let Some(interface) = self.interface_of(&name.value) {
+            let message = format!("{} {} clashes with method {} of {}", what, name.value, name.value, interface);
+            self.error(Some(name.start), message);
+        }
+    }
+
+    pub fn declare_impl(&mut self, def : &ImplDef) {
+        let interface = match &def.interface {
+            Some(interface) => interface,
+            None => return self.declare_methods(def),
+        };
+        if !self.interfaces.contains_key(&interface.value) {
+            self.error(Some(interface.start), format!("unknown interface {}", interface.value));
+            return;
+        }
+        if self.impl_target(&def.target.value).is_none() {
+            self.error(Some(def.target.start), format!("cannot implement {} for {}", interface.value, def.target.value));
+            return;
+        }
+        let key = (interface.value.clone(), def.target.value.clone());
+        if !self.impls.insert(key) {
+            self.error(Some(def.target.start), format!("{} is already implemented for {}", interface.value, def.target.value));
+        }
+    }
+
+    pub fn check_impl(&mut self, def : &ImplDef) {
+        let interface = match &def.interface {
+            Some(interface) => interface,
+            None => return self.check_methods(def),
+        };
+        let methods = match self.interfaces.get(&interface.value) {
+            Some(methods) => methods.clone(),
+            None => return,
+        };
+        let target = match self.impl_target(&def.target.value) {
+            Some(t) => t,
+            None => return,
+        };
+        for (name, _) in methods.iter() {
+            if !def.funs.iter().any(|f| f.name.value == *name) {
+                self.error(Some(def.target.start), format!("impl {} for {} is missing {}", interface.value, def.target.value, name));
+            }
+        }
+        let self_map = std::iter::once(("Self".to_string(), target.clone())).collect();
+        self.self_type = Some(target);
+        for fun in def.funs.iter() {
+            let expected = match methods.iter().find(|(name, _)| *name == fun.name.value) {
+                Some((_, t)) => t.substitute(&self_map),
+                None => {
+                    self.error(Some(fun.name.start), format!("{} is not a method of {}", fun.name.value, interface.value));
+                    continue;
+                },
+            };
+            self.type_params = fun.type_params.iter().map(|p| p.value.clone()).collect();
+            let params = self.param_types(&fun.params);
+            let ret = self.convert(&fun.return_type);
+            self.unify(&expected, &Ty::Fun(params.clone(), Box::new(ret.clone())), Some(fun.name.start));
+            self.check_body(&fun.params, params, ret, &fun.definition, Some(fun.name.start), &format!("function {}", fun.name.value));
+            self.type_params.clear();
+        }
+        self.self_type = None;
+    }
+
+    fn declare_methods(&mut self, def : &ImplDef) {
+        let target = match self.impl_target(&def.target.value) {
+            Some(t @ Ty::Named(_, _)) => t,
+            _ => {
+                self.error(Some(def.target.start), format!("cannot add methods to {}", def.target.value));
+                return;
+            },
+        };
+        let slots = self.structs[&def.target.value].items.iter().map(|(n, _)| n.clone()).collect::<HashSet<String>>();
+        self.self_type = Some(target);
+        for fun in def.funs.iter() {
+            match fun.params.first() {
+                Some(p) if p.name.value == "self" => (),
+                _ => {
+                    self.error(Some(fun.name.start), format!("{} must take self as its first parameter", fun.name.value));
+                    continue;
+                },
+            }
+            if slots.contains(&fun.name.value) {
+                self.error(Some(fun.name.start), format!("{} has both a slot and a method named {}", def.target.value, fun.name.value));
+                continue;
+            }
+            if self.methods.get(&def.target.value).is_some_and(|m| m.contains_key(&fun.name.value)) {
+                self.error(Some(fun.name.start), format!("{} already has a method {}", def.target.value, fun.name.value));
+                continue;
+            }
+            self.type_params = fun.type_params.iter().map(|p| p.value.clone()).collect();
+            let params = self.param_types(&fun.params);
+            let mut ret = self.convert(&fun.return_type);
+            if contains_yield(&fun.definition) {
+                ret = Ty::Generator(Box::new(ret));
+            }
+            let scheme = Scheme::poly(std::mem::take(&mut self.type_params), Ty::Fun(params, Box::new(ret)));
+            self.methods.entry(def.target.value.clone()).or_default().insert(fun.name.value.clone(), (scheme, signature(&fun.params)));
+        }
+        self.self_type = None;
+    }
+
+    fn check_methods(&mut self, def : &ImplDef) {
+        let target = match self.impl_target(&def.target.value) {
+            Some(t @ Ty::Named(_, _)) => t,
+            _ => return,
+        };
+        self.self_type = Some(target);
+        let mut seen = HashSet::new();
+        for fun in def.funs.iter() {
+            // Duplicates were reported when they were declared.
+            if !seen.insert(&fun.name.value) {
+                continue;
+            }
+            let scheme = match self.methods.get(&def.target.value).and_then(|m| m.get(&fun.name.value)) {
+                Some((scheme, _)) => scheme.clone(),
+                None => continue,
+            };
+            let (params, ret) = match scheme.ty {
+                Ty::Fun(params, ret) => (params, *ret),
+                _ => continue,
+            };
+            self.type_params = scheme.params;
+            self.check_defaults(&fun.params, &params);
+            self.check_body(&fun.params, params, ret, &fun.definition, Some(fun.name.start), &format!("function {}", fun.name.value));
+            self.type_params.clear();
+        }
+        self.self_type = None;
+    }
+
+    // Defaults are evaluated on entry to the function, so they see the
+    // parameters before them.
+    fn check_defaults(&mut self, params : &[FunParam], tys : &[Ty]) {
+        self.scopes.push(HashMap::new());
+        for (param, t) in params.iter().zip(tys) {
+            if let Some(default) = &param.default {
+                let found = self.check_expr(default);
+                let start = default.start().or(Some(param.name.start));
+                self.unify(t, &found, start);
+                // Lua fills in the default for nil.
+                self.keep_present(found, start, "a parameter with a default");
+            }
+            self.bind(&param.name.value, t.clone());
+        }
+        self.scopes.pop();
+    }
+
+    // The method that `object.name(...)` calls, if the object is a struct
+    // with one.
+    fn method(&self, object : &Ty, name : &str) -> Option<(Scheme, Signature)> {
+        match self.resolve(object) {
+            Ty::Named(n, _) => self.methods.get(&n)?.get(name).cloned(),
+            _ => None,
+        }
+    }
+
+    // Impls dispatch on the runtime type, so they can only be for
+    // primitives and structs declared in hyper.
+    fn impl_target(&self, name : &str) -> Option<Ty> {
+        match name {
+            "number" => Some(Ty::Number),
+            "string" => Some(Ty::String),
+            "bool" => Some(Ty::Bool),
+            _ => match self.structs.get(name) {
+                Some(info) if !info.opaque => Some(Ty::Named(name.to_string(), vec![Ty::Any; info.params.len()])),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn declare_type_alias(&mut self, def : &TypeAlias) {
+        // Aliases expand wherever they're named, so one that leads back to
+        // itself through any part of any other alias would never finish.
+        if self.alias_refers_to(&def.aliased, &def.name.value, &mut vec![]) {
+            self.error(Some(def.name.start), format!("type alias {} refers to itself", def.name.value));
+            return;
+        }
+        self.type_names.insert(def.name.value.clone());
+        let params = def.type_params.iter().map(|p| p.value.clone()).collect();
+        self.aliases.insert(def.name.value.clone(), (params, def.aliased.clone()));
+    }
+
+    fn alias_refers_to(&self, t : &Type, alias : &str, seen : &mut Vec<String>) -> bool {
+        let mut named = |n : &PSym| {
+            if n.value == alias {
+                return true;
+            }
+            if seen.contains(&n.value) {
+                return false;
+            }
+            seen.push(n.value.clone());
+            match self.aliases.get(&n.value) {
+                Some((_, t)) => self.alias_refers_to(t, alias, seen),
+                None => false,
+            }
+        };
+        match t {
+            Type::Unit | Type::Infer | Type::Namespace(..) => false,
+            Type::Simple(n) => named(n),
+            Type::Generic(n, args) => named(n) || args.iter().any(|a| self.alias_refers_to(a, alias, seen)),
+            Type::Fun(params, ret) => params.iter().chain(std::iter::once(&**ret)).any(|p| self.alias_refers_to(p, alias, seen)),
+            Type::Tuple(ts) => ts.iter().any(|t| self.alias_refers_to(t, alias, seen)),
+        }
+    }
+
+    pub fn declare_const(&mut self, def : &ConstDef) {
+        self.check_not_method(&def.name, "const");
+        let t = self.convert(&def.const_type);
+        self.globals.insert(def.name.value.clone(), Scheme::mono(t));
+        self.signatures.remove(&def.name.value);
+        self.consts.insert(def.name.value.clone());
+    }
+
+    pub fn check_const_def(&mut self, def : &ConstDef) {
+        let declared = match self.globals.get(&def.name.value) {
+            Some(scheme) => scheme.ty.clone(),
+            None => return,
+        };
+        let t = self.check_expr(&def.value);
+        self.unify(&declared, &t, def.value.start());
+    }
+
+    pub fn declare_extern_struct(&mut self, def : &ExternStructDef) {
+        self.type_names.insert(def.name.value.clone());
+        let params = def.type_params.iter().map(|p| p.value.clone()).collect();
+        self.structs.insert(def.name.value.clone(), StructInfo { params, items: vec![], defaults: HashSet::new(), private: HashSet::new(), opaque: true });
+    }
+
+    // Extern signatures can't be checked against Lua, so they are taken
+    // as declared.
+    pub fn declare_extern_fun(&mut self, def : &ExternFunDef) {
+        self.check_not_method(&def.name, "function");
+        self.type_params = def.type_params.iter().map(|p| p.value.clone()).collect();
+        let params = self.param_types(&def.params);
+        let ret = self.convert(&def.return_type);
+        let type_params = std::mem::take(&mut self.type_params);
+        let scheme = Scheme::poly(type_params, Ty::Fun(params, Box::new(ret)));
+        self.globals.insert(def.name.value.clone(), scheme);
+        self.signatures.insert(def.name.value.clone(), signature(&def.params));
+    }
+
+    pub fn check_fun_def(&mut self, def : &FunDef) {
+        let scheme = match self.globals.get(&def.name.value) {
+            Some(scheme) => scheme.clone(),
+            None => return,
+        };
+        let (params, ret) = match scheme.ty {
+            Ty::Fun(params, ret) => (params, *ret),
+            _ => return,
+        };
+        self.generic = Some(def.name.value.clone()).filter(|_| !def.type_params.is_empty());
+        self.type_params = scheme.params;
+        self.bounds = scheme.bounds;
+        self.check_defaults(&def.params, &params);
+        self.check_body(&def.params, params, ret, &def.definition, Some(def.name.start), &format!("function {}", def.name.value));
+        self.bounds.clear();
+        self.type_params.clear();
+        self.generic = None;
+    }
+
+    pub fn check_top_statement(&mut self, statement : &Statement) {
+        self.check_statement(statement);
+    }
+
+    pub fn infer(&mut self, expr : &Expr) -> Ty {
+        let t = self.check_expr(expr);
+        self.resolve(&t)
+    }
+
+    pub fn resolve(&self, t : &Ty) -> Ty {
+        match t {
+            Ty::Var(v) => match &self.vars[*v] {
+                Some(t) => self.resolve(t),
+                None => t.clone(),
+            },
+            Ty::List(t) => Ty::List(Box::new(self.resolve(t))),
+            Ty::Map(k, v) => Ty::Map(Box::new(self.resolve(k)), Box::new(self.resolve(v))),
+            Ty::Tuple(ts) => Ty::Tuple(ts.iter().map(|t| self.resolve(t)).collect()),
+            Ty::Result(t, e) => Ty::Result(Box::new(self.resolve(t)), Box::new(self.resolve(e))),
+            Ty::Option(t) => Ty::Option(Box::new(self.resolve(t))),
+            Ty::Generator(t) => Ty::Generator(Box::new(self.resolve(t))),
+            Ty::Rest(t) => Ty::Rest(Box::new(self.resolve(t))),
+            Ty::Fun(ps, r) => Ty::Fun(ps.iter().map(|p| self.resolve(p)).collect(), Box::new(self.resolve(r))),
+            Ty::Named(n, args) => Ty::Named(n.clone(), args.iter().map(|a| self.resolve(a)).collect()),
+            Ty::Anon(slots) => Ty::Anon(slots.iter().map(|(n, t)| (n.clone(), self.resolve(t))).collect()),
+            t => t.clone(),
+        }
+    }
+
+    fn error(&mut self, start : Option<usize>, message : String) {
+        self.errors.push(Diagnostic::new(start, message));
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.vars.push(None);
+        Ty::Var(self.vars.len() - 1)
+    }
+
+    fn instantiate(&mut self, scheme : &Scheme, start : Option<usize>) -> Ty {
+        self.instantiate_args(scheme, start).0
+    }
+
+    fn instantiate_args(&mut self, scheme : &Scheme, start : Option<usize>) -> (Ty, HashMap<String, Ty>) {
+        let mut map = HashMap::new();
+        for param in scheme.params.iter() {
+            let t = self.fresh();
+            map.insert(param.clone(), t);
+        }
+        for (param, interface) in scheme.bounds.iter() {
+            let obligation = Obligation { ty: map[param].clone()
+                                        , interface: interface.clone()
+                                        , start
+                                        , bounds: self.bounds.clone()
+                                        };
+            self.obligations.push(obligation);
+        }
+        (scheme.ty.substitute(&map), map)
+    }
+
+    fn convert(&mut self, t : &Type) -> Ty {
+        match t {
+            Type::Unit => Ty::Unit,
+            Type::Infer => self.fresh(),
+            Type::Simple(name) => self.named_type(name),
+            Type::Generic(name, args) => self.generic_type(name, args),
+            Type::Fun(params, ret) => Ty::Fun(params.iter().map(|p| self.convert(p)).collect(), Box::new(self.convert(ret))),
+            Type::Tuple(ts) => Ty::Tuple(ts.iter().map(|t| self.convert(t)).collect()),
+            // Types from other modules are unknown to the checker, beyond
+            // whether their module exports them.
+            Type::Namespace(namespace, t) => {
+                let name = match &**t {
+                    Type::Simple(name) => name,
+                    Type::Generic(name, args) => {
+                        args.iter().for_each(|a| { self.convert(a); });
+                        name
+                    },
+                    _ => return Ty::Any,
+                };
+                let path = namespace.iter().map(|n| n.value.as_str()).collect::<Vec<&str>>().join("::");
+                match self.namespaces.get(&path) {
+                    Some(Some(types)) if !types.contains(&name.value) => {
+                        self.error(Some(name.start), format!("{} has no exported type {}", path, name.value));
+                    },
+                    Some(_) => (),
+                    None => self.error(Some(namespace[0].start), format!("unknown module {}", path)),
+                }
+                Ty::Any
+            },
+        }
+    }
+
+    fn generic_type(&mut self, name : &PSym, args : &[Type]) -> Ty {
+        let mut args = args.iter().map(|a| self.convert(a)).collect::<Vec<Ty>>();
+        if let Some((params, aliased)) = self.aliases.get(&name.value).cloned() {
+            if args.len() != params.len() {
+                self.error(Some(name.start), format!("expected {} type arguments for {} but found {}", params.len(), name.value, args.len()));
+                return Ty::Any;
+            }
+            return self.expand_alias(params, &aliased, args);
+        }
+        let arity = match name.value.as_str() {
+            "List" | "Option" | "Generator" => 1,
+            "Map" | "Result" => 2,
+            n => match self.structs.get(n) {
+                Some(info) => info.params.len(),
+                None => return self.unknown_type(name),
+            },
+        };
+        if args.len() != arity {
+            self.error(Some(name.start), format!("expected {} type arguments for {} but found {}", arity, name.value, args.len()));
+            return Ty::Any;
+        }
+        let place = match name.value.as_str() {
+            "List" => "a list",
+            "Option" => "an Option",
+            "Generator" => "a generator",
+            _ => "a map",
+        };
+        if let "List" | "Option" | "Generator" | "Map" = name.value.as_str() {
+            for t in args.iter() {
+                self.keep_present(t.clone(), Some(name.start), place);
+            }
+        }
+        match name.value.as_str() {
+            "List" => Ty::List(Box::new(args.remove(0))),
+            "Option" => Ty::Option(Box::new(args.remove(0))),
+            "Generator" => Ty::Generator(Box::new(args.remove(0))),
+            "Map" => {
+                self.compare(args[0].clone(), Some(name.start), "a map key");
+                Ty::Map(Box::new(args.remove(0)), Box::new(args.remove(0)))
+            },
+            "Result" => Ty::Result(Box::new(args.remove(0)), Box::new(args.remove(0))),
+            n => Ty::Named(n.to_string(), args),
+        }
+    }
+
+    fn param_types(&mut self, params : &[FunParam]) -> Vec<Ty> {
+        let mut tys = vec![];
+        for param in params {
+            let t = self.convert(&param.param_type);
+            if param.variadic {
+                let list = Ty::List(Box::new(self.fresh()));
+                self.unify(&list, &t, Some(param.name.start));
+                tys.push(Ty::Rest(Box::new(t)));
+            }
+            else {
+                tys.push(t);
+            }
+        }
+        tys
+    }
+
+    fn named_type(&mut self, sym : &PSym) -> Ty {
+        let name = sym.value.as_str();
+        if let (Some(t), "Self") = (&self.self_type, name) {
+            return t.clone();
+        }
+        match name {
+            "number" => return Ty::Number,
+            "string" => return Ty::String,
+            "bool" => return Ty::Bool,
+            "any" => return Ty::Any,
+            _ => (),
+        }
+        if self.type_params.iter().any(|p| p == name) {
+            return Ty::Param(name.to_string());
+        }
+        if self.enums.contains_key(name) {
+            return Ty::Named(name.to_string(), vec![]);
+        }
+        if let Some((params, aliased)) = self.aliases.get(name).cloned() {
+            let args = params.iter().map(|_| self.fresh()).collect();
+            return self.expand_alias(params, &aliased, args);
+        }
+        let arity = match self.structs.get(name) {
+            Some(info) => info.params.len(),
+            None => return self.unknown_type(sym),
+        };
+        let args = (0..arity).map(|_| self.fresh()).collect();
+        Ty::Named(name.to_string(), args)
+    }
+
+    // A type that isn't one of the module's own yet. Types from other
+    // modules and interfaces are unknown to the checker, but anything else
+    // is misspelled or missing.
+    fn unknown_type(&mut self, name : &PSym) -> Ty {
+        if !self.type_names.contains(&name.value) && !self.open_imports {
+            self.error(Some(name.start), format!("unknown type {}", name.value));
+        }
+        Ty::Any
+    }
+
+    fn expand_alias(&mut self, params : Vec<String>, aliased : &Type, args : Vec<Ty>) -> Ty {
+        let outer = std::mem::replace(&mut self.type_params, params.clone());
+        let t = self.convert(aliased);
+        self.type_params = outer;
+        t.substitute(&params.into_iter().zip(args).collect())
+    }
+
+    fn shallow(&self, t : &Ty) -> Ty {
+        match t {
+            Ty::Var(v) => match &self.vars[*v] {
+                Some(t) => self.shallow(t),
+                None => t.clone(),
+            },
+            t => t.clone(),
+        }
+    }
+
+    fn occurs(&self, var : usize, t : &Ty) -> bool {
+        match self.shallow(t) {
+            Ty::Var(v) => v == var,
+            Ty::List(t) | Ty::Generator(t) | Ty::Rest(t) | Ty::Option(t) => self.occurs(var, &t),
+            Ty::Result(t, e) | Ty::Map(t, e) => self.occurs(var, &t) || self.occurs(var, &e),
+            Ty::Fun(ps, r) => ps.iter().any(|p| self.occurs(var, p)) || self.occurs(var, &r),
+            Ty::Named(_, args) | Ty::Tuple(args) => args.iter().any(|a| self.occurs(var, a)),
+            Ty::Anon(slots) => slots.iter().any(|(_, t)| self.occurs(var, t)),
+            _ => false,
+        }
+    }
+
+    fn unify(&mut self, expected : &Ty, found : &Ty, start : Option<usize>) {
+        if !self.unify_inner(expected, found) {
+            let message = format!("expected {} but found {}", self.resolve(expected), self.resolve(found));
+            self.error(start, message);
+        }
+    }
+
+    fn unify_inner(&mut self, a : &Ty, b : &Ty) -> bool {
+        let a = self.shallow(a);
+        let b = self.shallow(b);
+        match (&a, &b) {
+            (Ty::Any, _) | (_, Ty::Any) => true,
+            (Ty::Var(x), Ty::Var(y)) if x == y => true,
+            (Ty::Var(x), t) | (t, Ty::Var(x)) => {
+                if self.occurs(*x, t) {
+                    false
+                }
+                else {
+                    self.vars[*x] = Some(t.clone());
+                    true
+                }
+            },
+            (Ty::Unit, Ty::Unit) | (Ty::Number, Ty::Number) | (Ty::String, Ty::String) | (Ty::Bool, Ty::Bool) => true,
+            (Ty::List(x), Ty::List(y)) | (Ty::Generator(x), Ty::Generator(y)) | (Ty::Rest(x), Ty::Rest(y)) | (Ty::Option(x), Ty::Option(y)) =>
+                self.unify_inner(x, y),
+            (Ty::Result(t1, e1), Ty::Result(t2, e2)) | (Ty::Map(t1, e1), Ty::Map(t2, e2)) =>
+                self.unify_inner(t1, t2) && self.unify_inner(e1, e2),
+            (Ty::Fun(p1, r1), Ty::Fun(p2, r2)) => {
+                p1.len() == p2.len()
+                && p1.iter().zip(p2.iter()).all(|(x, y)| self.unify_inner(x, y))
+                && self.unify_inner(r1, r2)
+            },
+            (Ty::Tuple(t1), Ty::Tuple(t2)) => {
+                t1.len() == t2.len()
+                && t1.iter().zip(t2.iter()).all(|(x, y)| self.unify_inner(x, y))
+            },
+            (Ty::Named(n1, a1), Ty::Named(n2, a2)) => {
+                n1 == n2
+                && a1.len() == a2.len()
+                && a1.iter().zip(a2.iter()).all(|(x, y)| self.unify_inner(x, y))
+            },
+            (Ty::Anon(s1), Ty::Anon(s2)) => {
+                s1.len() == s2.len()
+                && s1.iter().zip(s2.iter()).all(|((n1, x), (n2, y))| n1 == n2 && self.unify_inner(x, y))
+            },
+            (Ty::Param(x), Ty::Param(y)) => x == y,
+            _ => false,
+        }
+    }
+
+    fn lookup(&mut self, namespace : &[PSym], name : &PSym) -> Ty {
+        if let Some(last) = namespace.last() {
+            if let Some(cases) = self.enums.get(&last.value) {
+                if cases.contains(&name.value) {
+                    return Ty::Named(last.value.clone(), vec![]);
+                }
+                self.error(Some(name.start), format!("enum {} has no case {}", last.value, name.value));
+                return Ty::Any;
+            }
+            // Items from other modules are unknown to the checker.
+            return Ty::Any;
+        }
+        if let Some(t) = self.scopes.iter().rev().find_map(|s| s.get(&name.value)) {
+            return t.clone();
+        }
+        if self.pending_consts.contains(&name.value) {
+            self.error(Some(name.start), format!("const {} is used before it is defined", name.value));
+        }
+        if let Some(scheme) = self.globals.get(&name.value).cloned() {
+            let (t, args) = self.instantiate_args(&scheme, Some(name.start));
+            if !args.is_empty() && self.signatures.contains_key(&name.value) {
+                let owner = self.generic.clone();
+                self.instances.push(Instance { fun: name.value.clone(), args, start: Some(name.start), owner });
+            }
+            return t;
+        }
+        if self.open_imports {
+            return Ty::Any;
+        }
+        self.error(Some(name.start), format!("unknown variable {}", name.value));
+        Ty::Any
+    }
+
+    // Whether `name` is something other than a builtin.
+    fn is_defined(&self, name : &str) -> bool {
+        self.scopes.iter().any(|s| s.contains_key(name)) || self.signatures.contains_key(name) || self.consts.contains(name)
+    }
+
+    fn bind(&mut self, name : &str, t : Ty) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), t);
+    }
+
+    // `what` names the function for errors found at `start`.
+    fn check_body(&mut self, params : &[FunParam], param_tys : Vec<Ty>, ret : Ty, definition : &[Statement], start : Option<usize>, what : &str) {
+        self.scopes.push(HashMap::new());
+        for (param, t) in params.iter().zip(param_tys) {
+            self.bind(&param.name.value, bound_type(t));
+        }
+        // Lambdas without a return type may return nothing.
+        let returns_value = !contains_yield(definition) && !matches!(self.shallow(&ret), Ty::Unit | Ty::Var(_));
+        let context = match self.shallow(&ret) {
+            Ty::Generator(t) if contains_yield(definition) => FunContext { ret: Ty::Unit, yields: Some(*t), returns_value },
+            _ => FunContext { ret, yields: None, returns_value },
+        };
+        self.funs.push(context);
+        let loops = std::mem::take(&mut self.loops);
+        self.check_block(definition);
+        self.loops = loops;
+        self.funs.pop();
+        self.scopes.pop();
+
+        let flow = check_flow(definition, returns_value, start);
+        self.errors.extend(flow.errors);
+        if returns_value && flow.falls_through {
+            self.error(start, format!("{} can end without returning a value", what));
+        }
+    }
+
+    fn check_block(&mut self, statements : &[Statement]) {
+        self.scopes.push(HashMap::new());
+        for statement in statements {
+            self.check_statement(statement);
+        }
+        self.scopes.pop();
+    }
+
+    fn check_test(&mut self, test : &Expr) {
+        let t = self.check_expr(test);
+        self.unify(&Ty::Bool, &t, test.start());
+    }
+
+    fn check_statement(&mut self, statement : &Statement) {
+        self.statement_start = statement.start().or(self.statement_start);
+        match statement {
+            Statement::Expr(e) => { self.check_expr(e); },
+            Statement::Return(e) => {
+                let (t, start) = match e {
+                    Some(e) => (self.check_expr(e), e.start()),
+                    None => (Ty::Unit, None),
+                };
+                match self.funs.last() {
+                    // Reported by the flow check.
+                    Some(context) if e.is_none() && context.returns_value => (),
+                    Some(context) if e.is_some() && context.yields.is_some() => (),
+                    Some(context) => {
+                        let ret = context.ret.clone();
+                        self.unify(&ret, &t, start);
+                    },
+                    None => self.error(start, "return outside of function".to_string()),
+                }
+            },
+            Statement::Yield(e) => {
+                let (t, start) = match e {
+                    Some(e) => (self.check_expr(e), e.start()),
+                    None => (Ty::Unit, None),
+                };
+                self.keep_present(t.clone(), start, "a generator");
+                match self.funs.last().and_then(|c| c.yields.clone()) {
+                    Some(yields) => self.unify(&yields, &t, start),
+                    None => self.error(start, "yield outside of function".to_string()),
+                }
+            },
+            Statement::Let { pattern, value_type, expr } => {
+                let declared = self.convert(value_type);
+                let t = self.check_expr(expr);
+                self.unify(&declared, &t, expr.start());
+                self.bind_pattern(pattern, declared);
+            },
+            Statement::Set { target, new_value } => {
+                if let Expr::Variable { namespace, name } = target {
+                    let local = self.scopes.iter().any(|s| s.contains_key(&name.value));
+                    if namespace.is_empty() && !local && self.consts.contains(&name.value) {
+                        self.error(Some(name.start), format!("cannot assign to const {}", name.value));
+                    }
+                }
+                let target_type = match target {
+                    Expr::Variable { .. } | Expr::Dot { .. } | Expr::Index { .. } => self.check_expr(target),
+                    _ => {
+                        self.error(target.start(), "invalid set target".to_string());
+                        Ty::Any
+                    },
+                };
+                let t = self.check_expr(new_value);
+                self.unify(&target_type, &t, new_value.start());
+                if let Expr::Index { object, .. } = target {
+                    let place = match self.collections.iter().rev().find(|(e, _)| std::ptr::eq(*e, &**object)).map(|(_, t)| self.shallow(t)) {
+                        Some(Ty::Map(_, _)) => "a map",
+                        _ => "a list",
+                    };
+                    self.keep_present(t, target.start(), place);
+                }
+            },
+            Statement::Break { keyword, label } | Statement::Continue { keyword, label } => self.check_loop_control(keyword, label),
+            Statement::While { label, test, statements } => {
+                self.check_test(test);
+                self.check_loop_body(label, statements);
+            },
+            Statement::Loop { label, statements } => self.check_loop_body(label, statements),
+            Statement::Foreach { label, var, value, items, statements } => {
+                let items_type = self.check_expr(items);
+                self.collections.push((items as *const Expr, items_type.clone()));
+                self.scopes.push(HashMap::new());
+                match value {
+                    Some(value) => {
+                        let (key_type, value_type) = self.entry_types(&items_type, items.start());
+                        self.bind_pattern(var, key_type);
+                        self.bind_pattern(value, value_type);
+                    },
+                    None => {
+                        let item_type = self.item_type(&items_type, items.start());
+                        self.bind_pattern(var, item_type);
+                    },
+                }
+                self.check_loop_body(label, statements);
+                self.scopes.pop();
+            },
+            Statement::If { test, statements, .. } | Statement::ElseIf { test, statements, .. } => {
+                self.check_test(test);
+                self.check_block(statements);
+            },
+            Statement::Else(statements) => self.check_block(statements),
+        }
+    }
+
+    fn check_loop_body(&mut self, label : &Option<PSym>, statements : &[Statement]) {
+        self.loops.push(label.as_ref().map(|l| l.value.clone()));
+        self.check_block(statements);
+        self.loops.pop();
+    }
+
+    fn check_loop_control(&mut self, keyword : &PSym, label : &Option<PSym>) {
+        match label {
+            _ if self.loops.is_empty() => self.error(Some(keyword.start), format!("{} outside of loop", keyword.value)),
+            Some(label) if !self.loops.contains(&Some(label.value.clone())) =>
+                self.error(Some(label.start), format!("unknown loop label '{}", label.value)),
+            _ => (),
+        }
+    }
+
+    fn bind_pattern(&mut self, pattern : &Pattern, t : Ty) {
+        let ps = match pattern {
+            Pattern::Name(name) => return self.bind(&name.value, t),
+            Pattern::Tuple(ps) => ps,
+        };
+        let ts = match self.shallow(&t) {
+            Ty::Tuple(ts) if ts.len() == ps.len() => ts,
+            Ty::Any => ps.iter().map(|_| Ty::Any).collect(),
+            Ty::Var(_) => {
+                let ts = ps.iter().map(|_| self.fresh()).collect::<Vec<Ty>>();
+                self.unify(&Ty::Tuple(ts.clone()), &t, pattern.start());
+                ts
+            },
+            found => {
+                let message = format!("cannot destructure {} into {} values", self.resolve(&found), ps.len());
+                self.error(pattern.start(), message);
+                ps.iter().map(|_| Ty::Any).collect()
+            },
+        };
+        for (p, t) in ps.iter().zip(ts) {
+            self.bind_pattern(p, t);
+        }
+    }
+
+    // A map on its own iterates over its keys.
+    fn item_type(&mut self, items : &Ty, start : Option<usize>) -> Ty {
+        match self.shallow(items) {
+            Ty::List(t) | Ty::Generator(t) => *t,
+            Ty::Map(k, _) => *k,
+            Ty::Any => Ty::Any,
+            t @ Ty::Var(_) => {
+                let item = self.fresh();
+                self.unify(&Ty::List(Box::new(item.clone())), &t, start);
+                item
+            },
+            t => {
+                let message = format!("cannot iterate over {}", self.resolve(&t));
+                self.error(start, message);
+                Ty::Any
+            },
+        }
+    }
+
+    fn entry_types(&mut self, items : &Ty, start : Option<usize>) -> (Ty, Ty) {
+        match self.shallow(items) {
+            Ty::Map(k, v) => (*k, *v),
+            Ty::Any => (Ty::Any, Ty::Any),
+            t => (Ty::Number, self.item_type(&t, start)),
+        }
+    }
+
+    fn index_type(&mut self, object : &Ty, index : &Expr) -> Ty {
+        let t = self.check_expr(index);
+        match self.shallow(object) {
+            Ty::List(item) => {
+                self.unify(&Ty::Number, &t, index.start());
+                *item
+            },
+            Ty::Map(k, v) => {
+                self.unify(&k, &t, index.start());
+                *v
+            },
+            Ty::Any => Ty::Any,
+            object @ Ty::Var(_) => {
+                self.unify(&Ty::Number, &t, index.start());
+                let item = self.fresh();
+                self.unify(&Ty::List(Box::new(item.clone())), &object, index.start());
+                item
+            },
+            t => {
+                let message = format!("cannot index {}", self.resolve(&t));
+                self.error(index.start(), message);
+                Ty::Any
+            },
+        }
+    }
+
+    fn check_lambda(&mut self, start : Option<usize>, params : &[FunParam], return_type : &Type, definition : Result<&[Statement], &Expr>) -> Ty {
+        let param_tys = self.param_types(params);
+        let mut ret = self.convert(return_type);
+        match definition {
+            Ok(statements) => {
+                if contains_yield(statements) {
+                    ret = Ty::Generator(Box::new(ret));
+                }
+                self.check_body(params, param_tys.clone(), ret.clone(), statements, start, "lambda");
+            },
+            Err(expr) => {
+                self.scopes.push(HashMap::new());
+                for (param, t) in params.iter().zip(param_tys.iter()) {
+                    self.bind(&param.name.value, bound_type(t.clone()));
+                }
+                // Lambda bodies are not inside the enclosing function.
+                self.funs.push(FunContext { ret: ret.clone(), yields: None, returns_value: false });
+                let t = self.check_expr(expr);
+                self.funs.pop();
+                self.scopes.pop();
+                self.unify(&ret, &t, expr.start());
+            },
+        }
+        Ty::Fun(param_tys, Box::new(ret))
+    }
+
+    fn check_call(&mut self, func : Ty, args : Vec<(Ty, Option<usize>)>, start : Option<usize>) -> Ty {
+        match self.shallow(&func) {
+            Ty::Fun(params, ret) => {
+                let (fixed, rest) = match params.split_last() {
+                    Some((Ty::Rest(list), fixed)) => (fixed, Some(&**list)),
+                    _ => (&params[..], None),
+                };
+                if rest.is_some() && args.len() < fixed.len() {
+                    self.error(start, format!("expected at least {} arguments but found {}", fixed.len(), args.len()));
+                    return *ret;
+                }
+                if rest.is_none() && params.len() != args.len() {
+                    self.error(start, format!("expected {} arguments but found {}", params.len(), args.len()));
+                    return *ret;
+                }
+                for (i, (arg, arg_start)) in args.iter().enumerate() {
+                    match (fixed.get(i), self.shallow(arg), rest) {
+                        (_, Ty::Rest(list), Some(rest)) if i >= fixed.len() => self.unify(rest, &list, *arg_start),
+                        (_, Ty::Rest(_), _) => self.error(*arg_start, "spread argument must fill a variadic parameter".to_string()),
+                        (Some(param), _, _) => self.unify(param, arg, *arg_start),
+                        (None, _, Some(rest)) => {
+                            let item = self.item_type(rest, *arg_start);
+                            self.unify(&item, arg, *arg_start);
+                            self.keep_present(arg.clone(), *arg_start, "a variadic parameter");
+                        },
+                        (None, _, None) => (),
+                    }
+                }
+                *ret
+            },
+            Ty::Any => Ty::Any,
+            t @ Ty::Var(_) => {
+                let ret = self.fresh();
+                let params = args.into_iter().map(|(t, _)| t).collect();
+                self.unify(&t, &Ty::Fun(params, Box::new(ret.clone())), start);
+                ret
+            },
+            t => {
+                let message = format!("cannot call {}", self.resolve(&t));
+                self.error(start, message);
+                Ty::Any
+            },
+        }
+    }
+
+    // Puts the arguments in parameter order, with fresh types standing in
+    // for the defaults, which were checked with the function.
+    fn check_arranged_call(&mut self, call : &Expr, func : Ty, signature : &Signature, args : Vec<(Ty, Option<usize>)>, named : &[NamedArg]) -> Ty {
+        let params = signature.iter().map(|(n, d)| (n.as_str(), *d)).collect::<Vec<(&str, bool)>>();
+        let positional = args.len() - named.len();
+        // Positional arguments past the last parameter go to a variadic one.
+        let extra = match self.shallow(&func) {
+            Ty::Fun(ps, _) if matches!(ps.last(), Some(Ty::Rest(_))) => positional.saturating_sub(params.len()),
+            _ => 0,
+        };
+        let positional = positional - extra;
+        let slots = match arrange(&params, positional, named, call.start()) {
+            Ok(slots) => slots,
+            Err(e) => {
+                self.error(e.start(), e.message());
+                return match self.shallow(&func) {
+                    Ty::Fun(_, ret) => *ret,
+                    _ => Ty::Any,
+                };
+            },
+        };
+        if !named.is_empty() || slots.len() != positional {
+            self.arrangements.push((call as *const Expr, slots.clone()));
+        }
+        let mut arranged = vec![];
+        for slot in slots {
+            match slot {
+                Some(i) => arranged.push(args[i].clone()),
+                None => arranged.push((self.fresh(), None)),
+            }
+        }
+        arranged.extend(args[positional..positional + extra].iter().cloned());
+        self.check_call(func, arranged, call.start())
+    }
+
+    // The signature of a global function that no local shadows.
+    fn signature(&self, name : &str) -> Option<Signature> {
+        if self.scopes.iter().any(|s| s.contains_key(name)) {
+            return None;
+        }
+        self.signatures.get(name).cloned()
+    }
+
+    fn check_spread(&mut self, e : &Expr) -> Ty {
+        let t = self.check_expr(e);
+        let list = Ty::List(Box::new(self.fresh()));
+        self.unify(&list, &t, e.start());
+        list
+    }
+
+    // Only values that print the same in the interpreter and in Lua can go
+    // into a string; tables would show up as addresses.
+    fn check_interpolated(&mut self, e : &Expr) {
+        let t = self.check_expr(e);
+        let t = self.shallow(&t);
+        if let Ty::Var(_) | Ty::Param(_) = t {
+            self.compare(t, e.start(), "a string");
+        }
+        else if !self.printable(&t) {
+            let message = format!("cannot interpolate {} into a string", self.resolve(&t));
+            self.error(e.start(), message);
+        }
+    }
+
+    fn compare(&mut self, ty : Ty, start : Option<usize>, place : &'static str) {
+        let owner = self.generic.clone();
+        self.compared.push(Requirement { ty, start, place, owner });
+    }
+
+    fn keep_present(&mut self, ty : Ty, start : Option<usize>, place : &'static str) {
+        let owner = self.generic.clone();
+        let start = start.or(self.statement_start);
+        self.present.push(Requirement { ty, start, place, owner });
+    }
+
+    // Whether Lua's tostring gives what the interpreter prints.
+    fn printable(&self, t : &Ty) -> bool {
+        match t {
+            Ty::Any | Ty::Number | Ty::String | Ty::Bool | Ty::Param(_) | Ty::Var(_) => true,
+            Ty::Named(name, _) => self.enums.contains_key(name),
+            _ => false,
+        }
+    }
+
+    fn check_expr(&mut self, expr : &Expr) -> Ty {
+        match expr {
+            Expr::Number(_) => Ty::Number,
+            Expr::PString(_) => Ty::String,
+            Expr::Interpolate(parts) => {
+                for part in parts {
+                    if let StringPart::Expr(e) = part {
+                        self.check_interpolated(e);
+                    }
+                }
+                Ty::String
+            },
+            Expr::Bool(_) => Ty::Bool,
+            Expr::Variable { namespace, name } => self.lookup(namespace, name),
+            Expr::StatementLambda { params, return_type, definition } =>
+                self.check_lambda(expr.start(), params, return_type, Ok(definition)),
+            Expr::ExprLambda { params, return_type, definition } =>
+                self.check_lambda(expr.start(), params, return_type, Err(definition)),
+            Expr::Call { func, params, named } => {
+                let (func_type, mut args, signature) = match &**func {
+                    Expr::Dash { object, func } => {
+                        let object_type = self.check_expr(object);
+                        let signature = self.signature(&func.value);
+                        (self.lookup(&[], func), vec![(object_type, object.start())], signature)
+                    },
+                    Expr::Dot { object, slot } => {
+                        let object_type = self.check_expr(object);
+                        self.member_calls.push((expr as *const Expr, object_type.clone(), slot.value.clone()));
+                        match self.method(&object_type, &slot.value) {
+                            Some((scheme, signature)) => {
+                                let t = self.instantiate(&scheme, Some(slot.start));
+                                (t, vec![(object_type, object.start())], Some(signature))
+                            },
+                            None => (self.slot_type(&object_type, slot), vec![], None),
+                        }
+                    },
+                    Expr::Variable { namespace, name } if namespace.is_empty() => {
+                        let signature = self.signature(&name.value);
+                        (self.check_expr(func), vec![], signature)
+                    },
+                    func => (self.check_expr(func), vec![], None),
+                };
+                for param in params {
+                    let t = match param {
+                        Expr::Spread(e) => Ty::Rest(Box::new(self.check_spread(e))),
+                        param => self.check_expr(param),
+                    };
+                    args.push((t, param.start()));
+                }
+                for arg in named {
+                    args.push((self.check_expr(&arg.value), arg.value.start()));
+                }
+                if let Expr::Variable { namespace, name } = &**func {
+                    if let (true, Some(builtin)) = (namespace.is_empty(), ["eq", "neq", "to_string", "print"].iter().find(|b| **b == name.value)) {
+                        if !self.is_defined(&name.value) {
+                            // Both of eq's arguments have the same type.
+                            let checked = if *builtin == "print" { args.len() } else { 1 };
+                            for (t, start) in args.iter().take(checked).cloned().collect::<Vec<_>>() {
+                                let t = match t {
+                                    Ty::Rest(list) => self.item_type(&list, start),
+                                    t => t,
+                                };
+                                self.compare(t, start, builtin);
+                            }
+                        }
+                    }
+                }
+                match signature {
+                    Some(signature) => self.check_arranged_call(expr, func_type, &signature, args, named),
+                    None => {
+                        if let Some(arg) = named.first() {
+                            self.error(Some(arg.name.start), "only functions declared with fun take named arguments".to_string());
+                        }
+                        self.check_call(func_type, args, expr.start())
+                    },
+                }
+            },
+            Expr::Dash { object, func } => {
+                let object_type = self.check_expr(object);
+                let func_type = self.lookup(&[], func);
+                self.check_call(func_type, vec![(object_type, object.start())], expr.start())
+            },
+            Expr::Try(e) => {
+                let t = self.check_expr(e);
+                self.tries.push((expr as *const Expr, t.clone()));
+                if let Ty::Option(item) = self.shallow(&t) {
+                    match self.funs.last() {
+                        Some(context) => {
+                            let ret = context.ret.clone();
+                            let propagated = Ty::Option(Box::new(self.fresh()));
+                            self.unify(&ret, &propagated, e.start());
+                        },
+                        None => self.error(e.start(), "? outside of function".to_string()),
+                    }
+                    return *item;
+                }
+                let (ok, err) = (self.fresh(), self.fresh());
+                self.unify(&Ty::Result(Box::new(ok.clone()), Box::new(err.clone())), &t, e.start());
+                match self.funs.last() {
+                    Some(context) => {
+                        let ret = context.ret.clone();
+                        let propagated = Ty::Result(Box::new(self.fresh()), Box::new(err));
+                        self.unify(&ret, &propagated, e.start());
+                    },
+                    None => self.error(e.start(), "? outside of function".to_string()),
+                }
+                ok
+            },
+            Expr::Index { object, index } => {
+                let t = self.check_expr(object);
+                self.collections.push((&**object as *const Expr, t.clone()));
+                self.index_type(&t, index)
+            },
+            Expr::Tuple(es) => Ty::Tuple(es.iter().map(|e| self.check_expr(e)).collect()),
+            Expr::Range { start, end, step, .. } => {
+                for e in std::iter::once(start).chain(std::iter::once(end)).chain(step) {
+                    let t = self.check_expr(e);
+                    self.unify(&Ty::Number, &t, e.start());
+                }
+                Ty::List(Box::new(Ty::Number))
+            },
+            Expr::MapCons(entries) => {
+                let (k, v) = (self.fresh(), self.fresh());
+                if let Some(entry) = entries.first() {
+                    self.compare(k.clone(), entry.key.start(), "a map key");
+                    self.keep_present(k.clone(), entry.key.start(), "a map");
+                    self.keep_present(v.clone(), entry.value.start(), "a map");
+                }
+                for entry in entries {
+                    let t = self.check_expr(&entry.key);
+                    self.unify(&k, &t, entry.key.start());
+                    let t = self.check_expr(&entry.value);
+                    self.unify(&v, &t, entry.value.start());
+                }
+                Ty::Map(Box::new(k), Box::new(v))
+            },
+            Expr::Dot { object, slot } => {
+                let t = self.check_expr(object);
+                self.slot_type(&t, slot)
+            },
+            Expr::StructCons { name: None, slots, base } => {
+                let slots = slots.iter().map(|s| (s.name.value.clone(), self.check_expr(&s.value))).collect();
+                if let Some(base) = base {
+                    self.check_expr(base);
+                    self.error(base.start(), "..base needs a named struct".to_string());
+                }
+                Ty::Anon(slots)
+            },
+            Expr::StructCons { name: Some(name), slots, base } => self.check_struct_cons(name, slots, base.as_deref()),
+            Expr::ListCons(es) => {
+                let item = self.fresh();
+                self.keep_present(item.clone(), expr.start(), "a list");
+                for e in es {
+                    match e {
+                        Expr::Spread(spread) => {
+                            let t = self.check_spread(spread);
+                            self.unify(&Ty::List(Box::new(item.clone())), &t, spread.start());
+                        },
+                        e => {
+                            let t = self.check_expr(e);
+                            self.unify(&item, &t, e.start());
+                        },
+                    }
+                }
+                Ty::List(Box::new(item))
+            },
+            Expr::Spread(e) => {
+                self.check_expr(e);
+                self.error(e.start(), "...spread is only allowed in calls and lists".to_string());
+                Ty::Any
+            },
+            Expr::ResultCons(ResultValue::Okay(e)) => {
+                let t = self.check_expr(e);
+                Ty::Result(Box::new(t), Box::new(self.fresh()))
+            },
+            Expr::ResultCons(ResultValue::Error(e)) => {
+                let t = self.check_expr(e);
+                Ty::Result(Box::new(self.fresh()), Box::new(t))
+            },
+            Expr::OptionCons(OptionValue::Some(e)) => {
+                let t = self.check_expr(e);
+                self.keep_present(t.clone(), e.start(), "an Option");
+                Ty::Option(Box::new(t))
+            },
+            Expr::OptionCons(OptionValue::None) => Ty::Option(Box::new(self.fresh())),
+        }
+    }
+
+    fn struct_items(&mut self, name : &str, args : &[Ty]) -> Option<Vec<(String, Ty)>> {
+        let info = self.structs.get(name)?;
+        let map = info.params.iter().cloned().zip(args.iter().cloned()).collect::<HashMap<String, Ty>>();
+        Some(info.items.iter().map(|(n, t)| (n.clone(), t.substitute(&map))).collect())
+    }
+
+    // Reports a slot of an imported struct that its module keeps to itself.
+    fn private_slot(&mut self, name : &str, slot : &PSym) -> bool {
+        let private = self.structs.get(name).is_some_and(|info| info.private.contains(&slot.value));
+        if private {
+            self.error(Some(slot.start), format!("slot {} of {} is private", slot.value, name));
+        }
+        private
+    }
+
+    fn slot_type(&mut self, t : &Ty, slot : &PSym) -> Ty {
+        let slots = match self.shallow(t) {
+            Ty::Named(name, args) => match self.struct_items(&name, &args) {
+                Some(_) if self.private_slot(&name, slot) => return Ty::Any,
+                Some(items) => items,
+                None => {
+                    self.error(Some(slot.start), format!("{} has no slot {}", name, slot.value));
+                    return Ty::Any;
+                },
+            },
+            Ty::Anon(slots) => slots,
+            // Without an annotation the object's struct is unknown.
+            Ty::Any | Ty::Var(_) => return Ty::Any,
+            t => {
+                let message = format!("{} has no slot {}", self.resolve(&t), slot.value);
+                self.error(Some(slot.start), message);
+                return Ty::Any;
+            },
+        };
+        match slots.into_iter().find(|(n, _)| *n == slot.value) {
+            Some((_, t)) => t,
+            None => {
+                let message = format!("{} has no slot {}", self.resolve(t), slot.value);
+                self.error(Some(slot.start), message);
+                Ty::Any
+            },
+        }
+    }
+
+    fn check_struct_cons(&mut self, name : &PSym, slots : &[StructSlot], base : Option<&Expr>) -> Ty {
+        let (arity, defaults) = match self.structs.get(&name.value) {
+            Some(info) if info.opaque => {
+                self.error(Some(name.start), format!("cannot construct extern struct {}", name.value));
+                for e in slots.iter().map(|s| &s.value).chain(base) {
+                    self.check_expr(e);
+                }
+                return Ty::Any;
+            },
+            Some(info) => (info.params.len(), info.defaults.clone()),
+            None => {
+                self.error(Some(name.start), format!("unknown struct {}", name.value));
+                for e in slots.iter().map(|s| &s.value).chain(base) {
+                    self.check_expr(e);
+                }
+                return Ty::Any;
+            },
+        };
+        let args = (0..arity).map(|_| self.fresh()).collect::<Vec<Ty>>();
+        let items = self.struct_items(&name.value, &args).unwrap_or_default();
+        let mut given = HashSet::new();
+        for slot in slots {
+            let t = self.check_expr(&slot.value);
+            if !given.insert(slot.name.value.as_str()) {
+                self.error(Some(slot.name.start), format!("slot {} is given twice", slot.name.value));
+            }
+            match items.iter().find(|(n, _)| *n == slot.name.value) {
+                Some(_) if self.private_slot(&name.value, &slot.name) => (),
+                Some((_, expected)) => self.unify(expected, &t, slot.value.start()),
+                None => self.error(Some(slot.name.start), format!("{} has no slot {}", name.value, slot.name.value)),
+            }
+        }
+        let t = Ty::Named(name.value.clone(), args);
+        match base {
+            Some(base) => {
+                let base_type = self.check_expr(base);
+                self.unify(&t, &base_type, base.start());
+            },
+            None => {
+                let missing = items.iter().map(|(n, _)| n.as_str())
+                                          .filter(|n| !given.contains(n) && !defaults.contains(*n))
+                                          .collect::<Vec<&str>>();
+                if !missing.is_empty() {
+                    self.error(Some(name.start), format!("new {} is missing {}", name.value, missing.join(", ")));
+                }
+            },
+        }
+        t
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::proc::parser::parse;
+
+    fn check(input : &str) -> Vec<String> {
+        let module = parse(input).expect("parse failed");
+        let mut checker = Checker::new();
+        checker.check_mod(&module);
+        checker.take_errors().into_iter().map(|e| e.message).collect()
+    }
+
+    #[test]
+    fn should_accept_well_typed_module() {
+        let errors = check(r#"
+struct Point { x : number, y : number }
+
+fun norm(p : Point) -> number {
+    return add(mul(p.x, p.x), mul(p.y, p.y));
+}
+
+fun main() {
+    let total = norm(new Point { x: 1, y: 2 });
+    print(total);
+}"#);
+        assert_eq!( errors.len(), 0, "{:?}", errors );
+    }
+
+    #[test]
+    fn should_report_return_mismatch() {
+        let errors = check(r#"
+fun f() -> number {
+    return "x";
+}"#);
+        assert_eq!( errors, vec!["expected number but found string"] );
+    }
+
+    #[test]
+    fn should_infer_list_item_type_in_foreach() {
+        let errors = check(r#"
+fun f() {
+    foreach x in ["a", "b"] {
+        let y : number = x;
+    }
+}"#);
+        assert_eq!( errors, vec!["expected number but found string"] );
+    }
+
+    #[test]
+    fn should_report_unknown_slot() {
+        let errors = check(r#"
+struct Point { x : number }
+
+fun f(p : Point) -> number {
+    return p.z;
+}"#);
+        assert_eq!( errors, vec!["Point has no slot z"] );
+    }
+
+    #[test]
+    fn should_require_result_return_type_for_try() {
+        let errors = check(r#"
+fun g() -> Result<number, string> {
+    return Err("bad");
+}
+
+fun f() -> number {
+    let x = g()?;
+    return x;
+}"#);
+        assert_eq!( errors, vec!["expected number but found Result<_, string>"] );
+    }
+
+    #[test]
+    fn should_trust_extern_declarations() {
+        let errors = check(r#"
+extern struct File;
+extern fun open(path : string, mode : string) -> File = "io.open";
+extern fun format(fmt : string, arg : number) -> string = "string.format";
+fun f() -> string {
+    let file = open("x", "r");
+    let bad = new File { handle: 1 };
+    return format("%d", "one");
+}"#);
+        assert_eq!( errors, vec![ "cannot construct extern struct File"
+                                , "expected number but found string"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_index_types() {
+        let errors = check(r#"
+fun f(flag : bool) -> number {
+    let xs = [1, 2];
+    set xs[0] = "three";
+    let y = flag[0];
+    return xs["one"];
+}"#);
+        assert_eq!( errors, vec![ "expected number but found string"
+                                , "cannot index bool"
+                                , "expected number but found string"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_map_types() {
+        let errors = check(r#"
+fun f() -> number {
+    let m = #{ "a": 1, "b": true };
+    set m[1] = 2;
+    foreach k, v in m {
+        let x = add(k, v);
+    }
+    return m["a"];
+}"#);
+        assert_eq!( errors, vec![ "expected number but found bool"
+                                , "expected string but found number"
+                                , "expected number but found string"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_map_annotations() {
+        let errors = check(r#"
+fun g(m : Map<string, number>) -> string {
+    return m["a"];
+}
+fun h() {
+    let s = g(#{ 1: "x" });
+    let m : Map<string, bool> = #{ "a": true };
+}"#);
+        assert_eq!( errors, vec![ "expected string but found number"
+                                , "expected Map<string, number> but found Map<number, string>"
+                                ] );
+    }
+
+    #[test]
+    fn should_infer_tuple_returns() {
+        let errors = check(r#"
+fun pair() -> (number, string) {
+    return (1, "one");
+}
+fun f() -> number {
+    let (n, s) = pair();
+    let (a, b, c) = pair();
+    return s;
+}"#);
+        assert_eq!( errors, vec![ "cannot destructure (number, string) into 3 values"
+                                , "expected number but found string"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_tuple_annotations() {
+        let errors = check(r#"
+fun pair() -> (number, string) {
+    return 5;
+}
+fun f() {
+    let t : (number, string) = (1, 2, 3);
+    let u : (number, (bool, string)) = (1, (true, "x"));
+}"#);
+        assert_eq!( errors, vec![ "expected (number, string) but found number"
+                                , "expected (number, string) but found (number, number, number)"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_generic_and_function_annotations() {
+        let errors = check(r#"
+fun f(x : Option<number>) -> number {
+    return add(x, 1);
+}
+fun g(xs : List<string>, r : Result<number, string>, h : fun(number) -> string) -> number {
+    let n : number = unwrap(r);
+    return h(n);
+}
+fun k(o : Option<number, string>) { }"#);
+        assert_eq!( errors, vec![ "expected 1 type arguments for Option but found 2"
+                                , "expected number but found Option<number>"
+                                , "expected number but found string"
+                                ] );
+    }
+
+    #[test]
+    fn should_only_compare_and_print_what_lua_does_alike() {
+        let errors = check(r#"
+fun same<T>(a : T, b : T) -> bool {
+    return eq(a, b);
+}
+fun f(ok : Result<number, string>, o : Option<number>) -> string {
+    let a = eq([1], [1]);
+    let b = neq((1, "a"), (1, "a"));
+    let c = eq(ok, ok);
+    let d = eq(o, None);
+    let m = #{ (1, 2): "pair" };
+    let n : Map<List<number>, bool> = #{};
+    return to_string([1, 2]);
+}
+fun g() {
+    let to_string = |x : List<number>| "list";
+    let s = to_string([1]);
+}
+enum Color { Red }
+struct Point { x : number }
+fun show<T>(x : T) {
+    print("x is", x);
+}
+fun describe<T>(x : T) -> string {
+    return "it is {x}";
+}
+fun h(xs : List<number>) {
+    print(1, "a", Color::Red, xs, new Point { x: 1 });
+    print(...xs);
+    same(1, 2);
+    same(Some([1]), None);
+    show(Color::Red);
+    show((1, 2));
+    describe(xs);
+    contains(xs, 1);
+    contains([xs], xs);
+}"#);
+        assert_eq!( errors, vec![ "cannot compare List<number> with eq"
+                                , "cannot compare (number, string) with neq"
+                                , "cannot compare Result<number, string> with eq"
+                                , "(number, number) cannot be a map key"
+                                , "List<number> cannot be a map key"
+                                , "cannot convert List<number> to a string"
+                                , "cannot print List<number>"
+                                , "cannot print Point"
+                                , "cannot compare List<number> with eq"
+                                , "cannot compare List<number> with eq"
+                                , "cannot print (number, number)"
+                                , "cannot interpolate List<number> into a string"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_interpolated_strings() {
+        let errors = check(r#"
+fun f(name : string, count : number) -> string {
+    let ok = "user {name} has {count} items";
+    return "items {[1, 2]} {add(name, 1)}";
+}"#);
+        assert_eq!( errors, vec![ "cannot interpolate List<number> into a string"
+                                , "expected number but found string"
+                                ] );
+    }
+
+    #[test]
+    fn should_reject_loop_control_outside_loops() {
+        let errors = check(r#"
+fun f(xs : List<number>) {
+    'outer: foreach x in xs {
+        let g = || { break; };
+        loop {
+            if eq(x, 0) { continue 'outer; }
+            break 'inner;
+        }
+    }
+    continue;
+}"#);
+        assert_eq!( errors, vec![ "break outside of loop"
+                                , "unknown loop label 'inner"
+                                , "continue outside of loop"
+                                ] );
+        let source = "fun f() {\n    break;\n}\nfun g() {\n    continue;\n}";
+        let mut checker = Checker::new();
+        checker.check_mod(&parse(source).expect("parse failed"));
+        let starts = checker.take_errors().into_iter().map(|e| e.start).collect::<Vec<Option<usize>>>();
+        assert_eq!( starts, vec![source.find("break"), source.find("continue")] );
+    }
+
+    #[test]
+    fn should_check_ranges() {
+        let errors = check(r#"
+fun f(n : number) -> List<number> {
+    foreach i in 0..n by 2 {
+        let s : string = i;
+    }
+    return 0..="ten";
+}"#);
+        assert_eq!( errors, vec![ "expected string but found number"
+                                , "expected number but found string"
+                                ] );
+    }
+
+    #[test]
+    fn should_expand_type_aliases() {
+        let errors = check(r#"
+type Id = number;
+type Key = Id;
+type Loop = Loop;
+struct Box<T> { item : T }
+type Boxed = Box;
+type Callback<T> = fun(T) -> string;
+type P = Map<string, bool>;
+type Pair<A> = (A, A);
+type Tree = List<(number, Tree)>;
+type Even = fun(Odd) -> bool;
+type Odd = List<Even>;
+fun f(id : Key, b : Boxed) -> string {
+    let s : string = b.item;
+    return id;
+}
+fun h(p : P, c : Callback<number>, q : Pair<string>) -> number {
+    let s : string = c("x");
+    let (a, b) = q;
+    let n : number = a;
+    let w : Callback<number, string> = c;
+    return p;
+}"#);
+        assert_eq!( errors, vec![ "type alias Loop refers to itself"
+                                , "type alias Tree refers to itself"
+                                , "type alias Odd refers to itself"
+                                , "expected string but found number"
+                                , "expected number but found string"
+                                , "expected number but found string"
+                                , "expected 1 type arguments for Callback but found 2"
+                                , "expected number but found Map<string, bool>"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_consts() {
+        let errors = check(r#"
+const LIMIT : number = mul(60, 60);
+const NAME : string = LIMIT;
+const EARLY : number = LATE;
+const LATE : number = LATE;
+fun f() -> number {
+    set LIMIT = 1;
+    let NAME = 2;
+    set NAME = 3;
+    return LIMIT;
+}"#);
+        assert_eq!( errors, vec![ "cannot assign to const LIMIT"
+                                , "expected string but found number"
+                                , "const LATE is used before it is defined"
+                                , "const LATE is used before it is defined"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_interfaces_and_bounds() {
+        let errors = check(r#"
+interface Ord {
+    fun compare(self, other : Self) -> number;
+}
+struct Point { x : number }
+struct Line { a : Point }
+impl Ord for Point {
+    fun compare(self, other : Self) -> number {
+        return sub(self.x, other.x);
+    }
+}
+impl Ord for number {
+    fun compare(self, other : string) -> number {
+        return 0;
+    }
+}
+impl Ord for bool {
+    fun size(self) -> number {
+        return 0;
+    }
+}
+impl Eq for Point {
+}
+fun max<T : Ord>(a : T, b : T) -> T {
+    if gt(compare(a, b), 0) {
+        return a;
+    }
+    return b;
+}
+fun first<T>(a : T, b : T) -> number {
+    return compare(a, b);
+}
+fun main() {
+    let p = max(new Point { x: 1 }, new Point { x: 2 });
+    let l = new Line { a: p };
+    max(l, l);
+    compare("a", "b");
+}"#);
+        assert_eq!( errors, vec![ "unknown interface Eq"
+                                , "expected fun(number, number) -> number but found fun(number, string) -> number"
+                                , "impl Ord for bool is missing compare"
+                                , "size is not a method of Ord"
+                                , "type parameter T is not bounded by Ord"
+                                , "Line does not implement Ord"
+                                , "string does not implement Ord"
+                                ] );
+    }
+
+    #[test]
+    fn should_reject_methods_that_clash() {
+        let errors = check(r#"
+fun label(x : number) -> string { return "number"; }
+interface Named { fun name(self) -> string; }
+interface Titled { fun name(self) -> string; }
+interface Labelled { fun label(self) -> string; }
+interface Mapped { fun map(self) -> number; }
+interface Sized { fun size(self) -> number; }
+fun size(x : number) -> number { return x; }
+const name : string = "x";"#);
+        assert_eq!( errors, vec![ "method name of Titled is already a method of Named"
+                                , "method map of Mapped clashes with function map"
+                                , "function label clashes with method label of Labelled"
+                                , "function size clashes with method size of Sized"
+                                , "const name clashes with method name of Named"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_methods() {
+        let errors = check(r#"
+struct Point { x : number }
+impl Point {
+    fun scale(self, k : number) -> Point {
+        return new Point { x: mul(self.x, k) };
+    }
+    fun x(self) -> number {
+        return self.x;
+    }
+    fun origin() -> Point {
+        return new Point { x: 0 };
+    }
+    fun scale(self) -> number {
+        return 0;
+    }
+}
+impl number {
+    fun double(self) -> number {
+        return mul(self, 2);
+    }
+}
+fun main() {
+    let p = new Point { x: 1 };
+    let n : number = p.scale(2).x;
+    p.scale("2");
+    p.norm();
+}"#);
+        assert_eq!( errors, vec![ "Point has both a slot and a method named x"
+                                , "origin must take self as its first parameter"
+                                , "Point already has a method scale"
+                                , "cannot add methods to number"
+                                , "expected number but found string"
+                                , "Point has no slot norm"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_struct_defaults_and_updates() {
+        let errors = check(r#"
+struct Config { name : string, retries : number = 3, verbose : bool = "no", level : number }
+pub struct Public { pub a : number, b : number }
+struct Private { pub a : number }
+fun main() {
+    let a = new Config { name: "a", level: 1 };
+    let b = new Config { retries: 5, ..a };
+    let c = new Config { retries: 1 };
+    let d = new Config { name: "d", name: "e", level: 2, colour: 1 };
+    let e = new Config { ..1 };
+    let f = new { x: 1, ..a };
+}"#);
+        assert_eq!( errors, vec![ "expected bool but found string"
+                                , "slot a is pub but struct Private is not"
+                                , "new Config is missing name, level"
+                                , "slot name is given twice"
+                                , "Config has no slot colour"
+                                , "expected Config but found number"
+                                , "..base needs a named struct"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_named_and_default_arguments() {
+        let errors = check(r#"
+fun connect(host : string, port : number = 80, secure : bool = "no") -> number {
+    return port;
+}
+fun main() {
+    connect("a");
+    connect(host: "a", secure: true);
+    connect("a", port: "80");
+    connect("a", colour: 1);
+    connect("a", host: "b");
+    connect(port: 1);
+    connect("a", 1, true, 2);
+    let f = |x : number| x;
+    f(x: 1);
+    let connect = |x : number| x;
+    connect(x: 1);
+}"#);
+        assert_eq!( errors, vec![ "expected bool but found string"
+                                , "expected number but found string"
+                                , "unknown argument colour"
+                                , "argument host is given twice"
+                                , "missing argument host"
+                                , "expected 3 arguments but found 4"
+                                , "only functions declared with fun take named arguments"
+                                , "only functions declared with fun take named arguments"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_variadic_calls_and_spreads() {
+        let errors = check(r#"
+fun log(level : string, ...parts : List<string>) -> string {
+    return level;
+}
+fun pair(a : number, b : number) -> number {
+    return a;
+}
+fun bad(...xs : number) {
+}
+fun main() {
+    let xs = [1, 2];
+    log("a");
+    log("a", "b", ...["c"], "d");
+    log();
+    log("a", "b", level: "x");
+    pair(1, ...xs);
+    let ys = [0, ...xs, ...3];
+    let g = |a : number, ...rest| rest;
+    g(1, "x", 2);
+    g();
+}"#);
+        assert_eq!( errors, vec![ "expected List<_> but found number"
+                                , "missing argument level"
+                                , "argument level is given twice"
+                                , "spread argument must fill a variadic parameter"
+                                , "expected List<_> but found number"
+                                , "expected string but found number"
+                                , "expected at least 1 arguments but found 0"
+                                ] );
+    }
+
+    #[test]
+    fn should_keep_options_from_being_used_as_present() {
+        let errors = check(r#"
+fun d(x : number, y : Option<number> = None) {
+}
+fun main() {
+    let o = Some(1);
+    add(o, 1);
+    let n = value_or(o, 0);
+    value_or(o, "x");
+    let xs = [Some(1)];
+    let nested = Some(None);
+    let s = "{o}";
+    let f = || {
+        let x = o?;
+        return Some(add(x, n));
+    };
+    let g = || {
+        let x = o?;
+        return x;
+    };
+    let h = || {
+        let x = Ok(1)?;
+        return Err(x);
+    };
+    if and(is_some(o), is_none(f())) {
+    }
+}"#);
+        assert_eq!( errors, vec![ "expected number but found Option<number>"
+                                , "expected number but found string"
+                                , "cannot interpolate Option<number> into a string"
+                                , "expected Option<_> but found number"
+                                , "Option<number> cannot go in a parameter with a default"
+                                , "Option<number> cannot go in a list"
+                                , "Option<_> cannot go in an Option"
+                                ] );
+    }
+
+    #[test]
+    fn should_report_flow_errors() {
+        let errors = check(r#"
+fun sign(x : number) -> number {
+    if gt(x, 0) {
+        return 1;
+    }
+    elseif lt(x, 0) {
+        return -1;
+    }
+}
+fun first(xs : List<number>) -> number {
+    foreach x in xs {
+        return x;
+        print(x);
+    }
+    return;
+}
+fun count() -> number {
+    yield 1;
+    return 2;
+}
+fun forever() -> number {
+    let f = |x : number| -> number { print(x); };
+    loop { }
+}"#);
+        assert_eq!( errors, vec![ "function sign can end without returning a value"
+                                , "unreachable statement"
+                                , "return without a value in a function that returns one"
+                                , "a generator cannot return a value"
+                                , "lambda can end without returning a value"
+                                ] );
+    }
+
+    #[test]
+    fn should_check_against_imported_declarations() {
+        let target = parse(r#"
+pub struct Circle { pub r : number, pub label : string = "circle" }
+pub fun area(c : Circle) -> number { return c.r; }
+fun hidden() -> number { return 1; }
+pub interface Show { fun show(self) -> string; }
+impl Show for Circle {
+    fun show(self) -> string { return self.label; }
+}"#).expect("parse failed");
+        let module = parse(r#"
+use shapes::{Circle, area, Show, hidden};
+fun main() {
+    let c = new Circle { r: 1 };
+    let a : string = area(c);
+    print(show(c), hidden());
+}"#).expect("parse failed");
+        let mut checker = Checker::new();
+        checker.declare_imports(&module.uses[0], &target);
+        checker.check_mod(&module);
+        let errors = checker.take_errors().into_iter().map(|e| e.message).collect::<Vec<String>>();
+        assert_eq!( errors, vec![ "expected string but found number"
+                                , "unknown variable hidden"
+                                ] );
+    }
+
+    #[test]
+    fn should_keep_options_out_of_generics_annotations_and_assignments() {
+        let module = parse(r#"
+fun g<T>(x : T) -> Option<T> {
+    return Some(x);
+}
+fun h<T>(x : T) -> Option<T> {
+    return g(x);
+}
+fun d(x : number = None) {
+}
+fun main(m : Map<string, Option<number>>, l : List<Option<bool>>) {
+    let a = is_some(g(None));
+    let b = h(Some(1));
+    let n = #{};
+    set n["a"] = None;
+    let xs = [];
+    push(xs, None);
+    let ys = [1];
+    set ys[0] = 2;
+}"#).expect("parse failed");
+        let mut checker = Checker::new();
+        checker.check_mod(&module);
+        let errors = checker.take_errors();
+        assert!( errors.iter().all(|e| e.start.is_some()) );
+        let messages = errors.into_iter().map(|e| e.message).collect::<Vec<String>>();
+        assert_eq!( messages, vec![ "expected number but found Option<_>"
+                                  , "Option<number> cannot go in a map"
+                                  , "Option<bool> cannot go in a list"
+                                  , "Option<_> cannot go in a parameter with a default"
+                                  , "Option<_> cannot go in a map"
+                                  , "Option<_> cannot go in a list"
+                                  , "Option<_> cannot go in an Option"
+                                  , "Option<number> cannot go in an Option"
+                                  ] );
+    }
+
+    #[test]
+    fn should_hide_private_slots_of_imported_structs() {
+        let target = parse(r#"
+pub struct Account { pub owner : string, balance : number = 0 }
+pub fun open(owner : string) -> Account { return new Account { owner: owner, balance: 1 }; }
+fun peek(a : Account) -> number { return a.balance; }"#).expect("parse failed");
+        let module = parse(r#"
+use bank::{*};
+fun main() {
+    let a = open("me");
+    let b = new Account { owner: "you", balance: 5 };
+    set a.balance = 10;
+    set a.owner = "them";
+    let c = (a.owner, a.balance, new Account { owner: "x", ..a });
+}"#).expect("parse failed");
+        let mut checker = Checker::new();
+        checker.declare_imports(&module.uses[0], &target);
+        checker.check_mod(&module);
+        let errors = checker.take_errors().into_iter().map(|e| e.message).collect::<Vec<String>>();
+        assert_eq!( errors, vec![ "slot balance of Account is private"
+                                , "slot balance of Account is private"
+                                , "slot balance of Account is private"
+                                ] );
+    }
+
+    #[test]
+    fn should_report_unknown_types() {
+        let target = parse("pub struct Square { side : number }\nstruct Hidden { side : number }").expect("parse failed");
+        let module = parse(r#"
+use geo::shapes::{Square};
+use text::{Padding};
+struct Pair { first : Later, second : Shape, third : Square, fourth : Padding }
+enum Later { Soon }
+interface Shape { fun size(self) -> number; }
+fun f(a : Strng, b : Lst<number>, c : shapes::Sq, d : geo::shapes::Square, e : other::T, g : shapes::Hidden) { }"#).expect("parse failed");
+        let mut checker = Checker::new();
+        checker.declare_imports(&module.uses[0], &target);
+        checker.check_mod(&module);
+        let errors = checker.take_errors().into_iter().map(|e| e.message).collect::<Vec<String>>();
+        assert_eq!( errors, vec![ "unknown type Strng"
+                                , "unknown type Lst"
+                                , "shapes has no exported type Sq"
+                                , "unknown module other"
+                                , "shapes has no exported type Hidden"
+                                ] );
+    }
+}
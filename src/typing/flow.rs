@@ -0,0 +1,158 @@
+use parse_input::PSym;
+
+use crate::diagnostic::Diagnostic;
+use crate::parsing::proc::ast::*;
+
+// What the control flow of a function body says about it. Lambdas in the
+// body have flows of their own.
+pub struct BodyFlow {
+    // Whether running the body can reach its end.
+    pub falls_through : bool,
+    pub errors : Vec<Diagnostic>,
+}
+
+struct Walker {
+    // Labels of the loops around the statement being walked, and whether
+    // anything breaks out of each.
+    loops : Vec<(Option<String>, bool)>,
+    generator : bool,
+    returns_value : bool,
+    // Where to report statements that don't say where they are.
+    start : Option<usize>,
+    errors : Vec<Diagnostic>,
+}
+
+// Checks the body of a function, which returns a value when
+// `returns_value` is set, and finds whether it can end without a return.
+// Loop control outside of loops is left to the checker.
+pub fn check_flow(definition : &[Statement], returns_value : bool, start : Option<usize>) -> BodyFlow {
+    let mut walker = Walker { loops: vec![], generator: contains_yield(definition), returns_value, start, errors: vec![] };
+    let falls_through = walker.block(definition);
+    BodyFlow { falls_through, errors: walker.errors }
+}
+
+impl Walker {
+    fn error(&mut self, start : Option<usize>, message : &str) {
+        self.errors.push(Diagnostic::new(start.or(self.start), message.to_string()));
+    }
+
+    // Returns whether the end of the block can be reached. Only the first
+    // statement that can't be is reported.
+    fn block(&mut self, statements : &[Statement]) -> bool {
+        let mut index = 0;
+        while index < statements.len() {
+            let falls_through = match &statements[index] {
+                Statement::If { .. } => {
+                    let (falls_through, next) = self.if_chain(statements, index);
+                    index = next;
+                    falls_through
+                },
+                statement => {
+                    index += 1;
+                    self.statement(statement)
+                },
+            };
+            if !falls_through {
+                if let Some(next) = statements.get(index) {
+                    self.error(next.start(), "unreachable statement");
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    // Walks an if and the elseif/else branches after it, returning whether
+    // any of them falls through and the index of the first statement after
+    // the chain.
+    fn if_chain(&mut self, statements : &[Statement], mut index : usize) -> (bool, usize) {
+        let mut falls_through = false;
+        let mut has_else = false;
+        if let Statement::If { statements: body, .. } = &statements[index] {
+            falls_through |= self.block(body);
+        }
+        index += 1;
+        while let Some(statement) = statements.get(index) {
+            match statement {
+                Statement::ElseIf { statements: body, .. } => falls_through |= self.block(body),
+                Statement::Else(body) => {
+                    falls_through |= self.block(body);
+                    has_else = true;
+                },
+                _ => break,
+            }
+            index += 1;
+        }
+        (falls_through || !has_else, index)
+    }
+
+    // Walks a loop body, returning whether anything breaks out of the loop.
+    fn loop_body(&mut self, label : &Option<PSym>, statements : &[Statement]) -> bool {
+        self.loops.push((label.as_ref().map(|l| l.value.clone()), false));
+        self.block(statements);
+        self.loops.pop().unwrap().1
+    }
+
+    fn statement(&mut self, statement : &Statement) -> bool {
+        match statement {
+            Statement::Expr(_) | Statement::Let { .. } | Statement::Set { .. } | Statement::Yield(_) => true,
+            Statement::Return(e) => {
+                match e {
+                    Some(e) if self.generator => self.error(e.start(), "a generator cannot return a value"),
+                    None if self.returns_value => self.error(None, "return without a value in a function that returns one"),
+                    _ => (),
+                }
+                false
+            },
+            Statement::Break { label, .. } => {
+                let target = match label {
+                    Some(label) => self.loops.iter_mut().rev().find(|(l, _)| l.as_ref() == Some(&label.value)),
+                    None => self.loops.last_mut(),
+                };
+                if let Some((_, broken)) = target {
+                    *broken = true;
+                }
+                false
+            },
+            Statement::Continue { .. } => false,
+            Statement::While { label, test, statements } => {
+                let broken = self.loop_body(label, statements);
+                broken || !matches!(test, Expr::Bool(true))
+            },
+            Statement::Loop { label, statements } => self.loop_body(label, statements),
+            Statement::Foreach { label, statements, .. } => {
+                self.loop_body(label, statements);
+                true
+            },
+            // Only an if starts a chain; see `if_chain`.
+            Statement::If { statements, .. } | Statement::ElseIf { statements, .. } | Statement::Else(statements) => {
+                self.block(statements);
+                true
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::proc::parser::parse;
+
+    fn falls_through(input : &str) -> bool {
+        let module = parse(input).expect("parse failed");
+        let flow = check_flow(&module.fun_defs[0].definition, true, None);
+        assert_eq!( flow.errors.len(), 0 );
+        flow.falls_through
+    }
+
+    #[test]
+    fn should_follow_loops_out() {
+        assert!( !falls_through("fun f() -> number { loop { } }") );
+        assert!( !falls_through("fun f() -> number { while true { if g() { continue; } } }") );
+        assert!( falls_through("fun f() -> number { while true { if g() { break; } } }") );
+        assert!( falls_through("fun f() -> number { while g() { return 1; } }") );
+        assert!( falls_through("fun f() -> number { 'a: loop { loop { break 'a; } } }") );
+        assert!( !falls_through("fun f() -> number { loop { 'a: loop { break 'a; } } }") );
+        assert!( !falls_through("fun f() -> number { if g() { return 1; } else { return 2; } }") );
+    }
+}
@@ -0,0 +1,6 @@
+
+pub mod types;
+pub mod checker;
+pub mod captures;
+pub mod flow;
+pub mod arguments;
@@ -0,0 +1,125 @@
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ty {
+    // Unknown to the checker; compatible with everything.
+    Any,
+    Unit,
+    Number,
+    String,
+    Bool,
+    List(Box<Ty>),
+    Map(Box<Ty>, Box<Ty>),
+    Tuple(Vec<Ty>),
+    Result(Box<Ty>, Box<Ty>),
+    Option(Box<Ty>),
+    Generator(Box<Ty>),
+    Fun(Vec<Ty>, Box<Ty>),
+    // The list taken by a variadic last parameter, or spread into a call.
+    Rest(Box<Ty>),
+    // Struct or enum declared in the module.
+    Named(String, Vec<Ty>),
+    Anon(Vec<(String, Ty)>),
+    // Type parameter of the definition being checked.
+    Param(String),
+    Var(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Scheme {
+    pub params : Vec<String>,
+    // Interfaces that each instantiation of a parameter must implement.
+    pub bounds : Vec<(String, String)>,
+    pub ty : Ty,
+}
+
+impl Scheme {
+    pub fn mono(ty : Ty) -> Self {
+        Scheme { params: vec![], bounds: vec![], ty }
+    }
+
+    pub fn poly(params : Vec<String>, ty : Ty) -> Self {
+        Scheme { params, bounds: vec![], ty }
+    }
+}
+
+impl Ty {
+    pub fn substitute(&self, map : &HashMap<String, Ty>) -> Ty {
+        let sub = |t : &Ty| t.substitute(map);
+        match self {
+            Ty::Param(name) => match map.get(name) {
+                Some(t) => t.clone(),
+                None => self.clone(),
+            },
+            Ty::List(t) => Ty::List(Box::new(sub(t))),
+            Ty::Map(k, v) => Ty::Map(Box::new(sub(k)), Box::new(sub(v))),
+            Ty::Tuple(ts) => Ty::Tuple(ts.iter().map(sub).collect()),
+            Ty::Result(t, e) => Ty::Result(Box::new(sub(t)), Box::new(sub(e))),
+            Ty::Option(t) => Ty::Option(Box::new(sub(t))),
+            Ty::Generator(t) => Ty::Generator(Box::new(sub(t))),
+            Ty::Rest(t) => Ty::Rest(Box::new(sub(t))),
+            Ty::Fun(ps, r) => Ty::Fun(ps.iter().map(sub).collect(), Box::new(sub(r))),
+            Ty::Named(n, args) => Ty::Named(n.clone(), args.iter().map(sub).collect()),
+            Ty::Anon(slots) => Ty::Anon(slots.iter().map(|(n, t)| (n.clone(), sub(t))).collect()),
+            t => t.clone(),
+        }
+    }
+}
+
+fn comma_list(f : &mut fmt::Formatter, ts : &[Ty]) -> fmt::Result {
+    for (i, t) in ts.iter().enumerate() {
+        if i != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", t)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ty::Any => write!(f, "any"),
+            Ty::Unit => write!(f, "()"),
+            Ty::Number => write!(f, "number"),
+            Ty::String => write!(f, "string"),
+            Ty::Bool => write!(f, "bool"),
+            Ty::List(t) => write!(f, "List<{}>", t),
+            Ty::Map(k, v) => write!(f, "Map<{}, {}>", k, v),
+            Ty::Tuple(ts) => {
+                write!(f, "(")?;
+                comma_list(f, ts)?;
+                write!(f, ")")
+            },
+            Ty::Result(t, e) => write!(f, "Result<{}, {}>", t, e),
+            Ty::Option(t) => write!(f, "Option<{}>", t),
+            Ty::Generator(t) => write!(f, "Generator<{}>", t),
+            Ty::Rest(t) => write!(f, "...{}", t),
+            Ty::Fun(ps, r) => {
+                write!(f, "fun(")?;
+                comma_list(f, ps)?;
+                write!(f, ") -> {}", r)
+            },
+            Ty::Named(n, args) if args.is_empty() => write!(f, "{}", n),
+            Ty::Named(n, args) => {
+                write!(f, "{}<", n)?;
+                comma_list(f, args)?;
+                write!(f, ">")
+            },
+            Ty::Anon(slots) => {
+                write!(f, "new {{ ")?;
+                for (i, (n, t)) in slots.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} : {}", n, t)?;
+                }
+                write!(f, " }}")
+            },
+            Ty::Param(n) => write!(f, "{}", n),
+            Ty::Var(_) => write!(f, "_"),
+        }
+    }
+}